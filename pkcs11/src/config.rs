@@ -0,0 +1,83 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The module's configuration file.
+//!
+//! The file is named by the `YUBIHSM_PKCS11_CONF` environment variable and uses the same
+//! `key = value` format as the vendor module's `yubihsm_pkcs11.conf`. Each `connector` line adds a
+//! slot for the connector at that URL, and `cacert` and `proxy` apply to every connector. Other
+//! settings understood by the vendor module are ignored. Without a configuration file there is a
+//! single slot for a connector on `http://127.0.0.1:12345`.
+
+use types::*;
+
+use std::env;
+use std::fs;
+
+pub const DEFAULT_CONNECTOR: &str = "http://127.0.0.1:12345";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlotConfig {
+    pub connector: String,
+    pub cacert: Option<String>,
+    pub proxy: Option<String>,
+}
+
+/// Load the configuration named by `YUBIHSM_PKCS11_CONF`, or the default configuration.
+pub fn load() -> Result<Vec<SlotConfig>, CK_RV> {
+    match env::var_os("YUBIHSM_PKCS11_CONF") {
+        Some(path) => {
+            let contents = fs::read_to_string(path).map_err(|_| CKR_GENERAL_ERROR)?;
+            Ok(parse(&contents))
+        }
+        None => Ok(parse(&format!("connector = {}", DEFAULT_CONNECTOR))),
+    }
+}
+
+/// Parse a configuration file. Flags without a value, such as the vendor module's `debug`, are
+/// ignored.
+pub fn parse(contents: &str) -> Vec<SlotConfig> {
+    let mut connectors = Vec::new();
+    let mut cacert = None;
+    let mut proxy = None;
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut parts = line.splitn(2, '=');
+        let key = parts.next().unwrap().trim();
+        let value = match parts.next() {
+            Some(value) => value.trim().to_string(),
+            None => continue,
+        };
+        match key {
+            "connector" => connectors.push(value),
+            "cacert" => cacert = Some(value),
+            "proxy" => proxy = Some(value),
+            _ => (),
+        }
+    }
+
+    connectors
+        .into_iter()
+        .map(|connector| SlotConfig {
+            connector,
+            cacert: cacert.clone(),
+            proxy: proxy.clone(),
+        })
+        .collect()
+}
@@ -0,0 +1,1006 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A PKCS#11 v2.40 module for the YubiHSM2, built on `libyubihsm`.
+//!
+//! Each configured connector (see the `config` module) is a slot, and logging in to a slot creates
+//! a `Session` with an AuthKey. The PIN is the AuthKey's ID as four hex digits followed by its
+//! password, as with the vendor's module, so `0001password` logs in with the default AuthKey.
+//!
+//! Asymmetric keys appear as a private key and a public key object with the same `CKA_ID` (the
+//! object ID), X.509 certificates stored as opaque objects appear as certificates, and other opaque
+//! objects as data objects. The module can sign with RSA (PKCS#1 v1.5 and PSS), ECDSA and EdDSA
+//! keys, decrypt with RSA keys (PKCS#1 v1.5 and OAEP), generate key pairs, destroy objects and
+//! generate random data; other functions return `CKR_FUNCTION_NOT_SUPPORTED`.
+
+// Every exported function is unsafe because it's called through the C ABI with raw pointers, and
+// their safety requirements are the PKCS#11 specification's.
+#![allow(clippy::missing_safety_doc)]
+
+extern crate failure;
+extern crate libyubihsm;
+extern crate sha1;
+extern crate sha2;
+
+mod config;
+mod mechanisms;
+mod objects;
+mod state;
+mod types;
+
+#[cfg(test)]
+mod tests;
+
+use mechanisms::{DecryptMechanism, SignMechanism, MECHANISMS};
+use objects::{AttributeValue, Object, ObjectHandle, ObjectKind};
+use state::{DecryptOperation, P11Session, SignOperation};
+use types::*;
+
+use libyubihsm::Session;
+
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+/// Run `f`, converting its result (or a panic, which mustn't unwind into C) to a return value.
+fn guard<F: FnOnce() -> Result<(), CK_RV>>(f: F) -> CK_RV {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => CKR_OK,
+        Ok(Err(rv)) => rv,
+        Err(_) => CKR_GENERAL_ERROR,
+    }
+}
+
+unsafe fn out<'a, T>(p: *mut T) -> Result<&'a mut T, CK_RV> {
+    p.as_mut().ok_or(CKR_ARGUMENTS_BAD)
+}
+
+unsafe fn input<'a>(data: CK_BYTE_PTR, len: CK_ULONG) -> Result<&'a [u8], CK_RV> {
+    if len == 0 {
+        Ok(&[])
+    } else if data.is_null() {
+        Err(CKR_ARGUMENTS_BAD)
+    } else {
+        Ok(slice::from_raw_parts(data, len as usize))
+    }
+}
+
+unsafe fn read_template(
+    template: CK_ATTRIBUTE_PTR,
+    count: CK_ULONG,
+) -> Result<Vec<(CK_ATTRIBUTE_TYPE, Vec<u8>)>, CK_RV> {
+    if count == 0 {
+        return Ok(Vec::new());
+    } else if template.is_null() {
+        return Err(CKR_ARGUMENTS_BAD);
+    }
+
+    slice::from_raw_parts(template, count as usize)
+        .iter()
+        .map(|attribute| {
+            let value = input(attribute.pValue as CK_BYTE_PTR, attribute.ulValueLen)?;
+            Ok((attribute.type_, value.to_vec()))
+        })
+        .collect()
+}
+
+/// Write `data` to a caller's buffer: a null buffer asks for the length, and a buffer that's too
+/// small fails with `CKR_BUFFER_TOO_SMALL`.
+unsafe fn write_output(data: &[u8], buffer: CK_BYTE_PTR, len: &mut CK_ULONG) -> Result<(), CK_RV> {
+    if !buffer.is_null() {
+        if (*len as usize) < data.len() {
+            *len = data.len() as CK_ULONG;
+            return Err(CKR_BUFFER_TOO_SMALL);
+        }
+        ptr::copy_nonoverlapping(data.as_ptr(), buffer, data.len());
+    }
+
+    *len = data.len() as CK_ULONG;
+    Ok(())
+}
+
+/// `write_output` for lists of slots and mechanisms.
+unsafe fn write_list<T: Copy>(items: &[T], list: *mut T, count: CK_ULONG_PTR) -> Result<(), CK_RV> {
+    let count = out(count)?;
+    if !list.is_null() {
+        if (*count as usize) < items.len() {
+            *count = items.len() as CK_ULONG;
+            return Err(CKR_BUFFER_TOO_SMALL);
+        }
+        ptr::copy_nonoverlapping(items.as_ptr(), list, items.len());
+    }
+
+    *count = items.len() as CK_ULONG;
+    Ok(())
+}
+
+/// Copy `s` into a blank-padded, unterminated PKCS#11 string field.
+fn pad(field: &mut [u8], s: &str) {
+    for b in field.iter_mut() {
+        *b = b' ';
+    }
+    let len = s.len().min(field.len());
+    field[..len].copy_from_slice(&s.as_bytes()[..len]);
+}
+
+fn require_rw(session: &P11Session) -> Result<(), CK_RV> {
+    if session.flags & CKF_RW_SESSION == 0 {
+        return Err(CKR_SESSION_READ_ONLY);
+    }
+    Ok(())
+}
+
+/// Load a private key for a signing or decryption operation.
+fn load_private_key(device: &Session, key: CK_OBJECT_HANDLE) -> Result<Object, CK_RV> {
+    let handle = ObjectHandle::from_handle(key).map_err(|_| CKR_KEY_HANDLE_INVALID)?;
+    if handle.kind != ObjectKind::PrivateKey {
+        return Err(CKR_KEY_TYPE_INCONSISTENT);
+    }
+
+    Object::load(device, handle).map_err(|_| CKR_KEY_HANDLE_INVALID)
+}
+
+const MANUFACTURER: &str = "Yubico (www.yubico.com)";
+
+#[no_mangle]
+pub unsafe extern "C" fn C_GetFunctionList(list: *mut *const CK_FUNCTION_LIST) -> CK_RV {
+    guard(|| {
+        *out(list)? = &FUNCTION_LIST;
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_Initialize(args: CK_VOID_PTR) -> CK_RV {
+    guard(|| {
+        if let Some(args) = (args as *const CK_C_INITIALIZE_ARGS).as_ref() {
+            if !args.pReserved.is_null() {
+                return Err(CKR_ARGUMENTS_BAD);
+            }
+
+            // The module uses its own locking, which is only acceptable if the application allows
+            // native locking when it supplies its own functions.
+            let has_mutex_functions = !args.CreateMutex.is_null();
+            if has_mutex_functions && args.flags & CKF_OS_LOCKING_OK == 0 {
+                return Err(CKR_CANT_LOCK);
+            }
+        }
+
+        state::initialize(config::load()?)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_Finalize(reserved: CK_VOID_PTR) -> CK_RV {
+    guard(|| {
+        if !reserved.is_null() {
+            return Err(CKR_ARGUMENTS_BAD);
+        }
+        state::finalize()
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_GetInfo(info: *mut CK_INFO) -> CK_RV {
+    guard(|| {
+        let info = out(info)?;
+        state::with_module(|_| Ok(()))?;
+
+        info.cryptokiVersion = CK_VERSION {
+            major: 2,
+            minor: 40,
+        };
+        pad(&mut info.manufacturerID, MANUFACTURER);
+        info.flags = 0;
+        pad(&mut info.libraryDescription, "YubiHSM PKCS#11 module");
+        info.libraryVersion = CK_VERSION {
+            major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
+            minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
+        };
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_GetSlotList(
+    _token_present: CK_BBOOL,
+    list: CK_SLOT_ID_PTR,
+    count: CK_ULONG_PTR,
+) -> CK_RV {
+    guard(|| {
+        let slots = state::with_module(|module| {
+            Ok((0..module.slot_count() as CK_SLOT_ID).collect::<Vec<_>>())
+        })?;
+        write_list(&slots, list, count)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_GetSlotInfo(slot_id: CK_SLOT_ID, info: *mut CK_SLOT_INFO) -> CK_RV {
+    guard(|| {
+        let info = out(info)?;
+        state::with_module(|module| {
+            let slot = module.slot(slot_id)?;
+            pad(
+                &mut info.slotDescription,
+                &format!("YubiHSM Connector {}", slot.config.connector),
+            );
+            pad(&mut info.manufacturerID, MANUFACTURER);
+            info.flags = CKF_TOKEN_PRESENT | CKF_HW_SLOT;
+            info.hardwareVersion = CK_VERSION::default();
+            info.firmwareVersion = CK_VERSION::default();
+            Ok(())
+        })
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_GetTokenInfo(slot_id: CK_SLOT_ID, info: *mut CK_TOKEN_INFO) -> CK_RV {
+    guard(|| {
+        let info = out(info)?;
+        let device_info = state::connector(slot_id)?
+            .get_device_info()
+            .map_err(|_| CKR_DEVICE_ERROR)?;
+        state::with_module(|module| {
+            let (sessions, rw_sessions) = module.session_count(slot_id);
+
+            pad(&mut info.label, "YubiHSM");
+            pad(&mut info.manufacturerID, MANUFACTURER);
+            pad(&mut info.model, "YubiHSM");
+            pad(
+                &mut info.serialNumber,
+                &format!("{:08}", device_info.serial),
+            );
+            info.flags =
+                CKF_RNG | CKF_LOGIN_REQUIRED | CKF_USER_PIN_INITIALIZED | CKF_TOKEN_INITIALIZED;
+            info.ulMaxSessionCount = CK_EFFECTIVELY_INFINITE;
+            info.ulSessionCount = sessions as CK_ULONG;
+            info.ulMaxRwSessionCount = CK_EFFECTIVELY_INFINITE;
+            info.ulRwSessionCount = rw_sessions as CK_ULONG;
+            // Four hex digits of AuthKey ID, and a password of 8 to 64 characters.
+            info.ulMaxPinLen = 68;
+            info.ulMinPinLen = 12;
+            info.ulTotalPublicMemory = CK_UNAVAILABLE_INFORMATION;
+            info.ulFreePublicMemory = CK_UNAVAILABLE_INFORMATION;
+            info.ulTotalPrivateMemory = CK_UNAVAILABLE_INFORMATION;
+            info.ulFreePrivateMemory = CK_UNAVAILABLE_INFORMATION;
+            info.hardwareVersion = CK_VERSION::default();
+            info.firmwareVersion = CK_VERSION {
+                major: device_info.major_version,
+                minor: device_info.minor_version,
+            };
+            pad(&mut info.utcTime, "");
+            Ok(())
+        })
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_GetMechanismList(
+    slot_id: CK_SLOT_ID,
+    list: CK_MECHANISM_TYPE_PTR,
+    count: CK_ULONG_PTR,
+) -> CK_RV {
+    guard(|| {
+        state::with_module(|module| module.slot(slot_id).map(|_| ()))?;
+        let mechanisms = MECHANISMS.iter().map(|m| m.0).collect::<Vec<_>>();
+        write_list(&mechanisms, list, count)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_GetMechanismInfo(
+    slot_id: CK_SLOT_ID,
+    mechanism: CK_MECHANISM_TYPE,
+    info: *mut CK_MECHANISM_INFO,
+) -> CK_RV {
+    guard(|| {
+        let info = out(info)?;
+        state::with_module(|module| module.slot(slot_id).map(|_| ()))?;
+
+        let &(_, min, max, flags) = MECHANISMS
+            .iter()
+            .find(|m| m.0 == mechanism)
+            .ok_or(CKR_MECHANISM_INVALID)?;
+        info.ulMinKeySize = min;
+        info.ulMaxKeySize = max;
+        info.flags = flags;
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_OpenSession(
+    slot_id: CK_SLOT_ID,
+    flags: CK_FLAGS,
+    _application: CK_VOID_PTR,
+    _notify: CK_NOTIFY,
+    handle: CK_SESSION_HANDLE_PTR,
+) -> CK_RV {
+    guard(|| {
+        let handle = out(handle)?;
+        if flags & CKF_SERIAL_SESSION == 0 {
+            return Err(CKR_SESSION_PARALLEL_NOT_SUPPORTED);
+        }
+
+        state::connector(slot_id)?;
+        *handle = state::with_module(|module| module.open_session(slot_id, flags))?;
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_CloseSession(handle: CK_SESSION_HANDLE) -> CK_RV {
+    guard(|| state::with_module(|module| module.close_session(handle)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_CloseAllSessions(slot_id: CK_SLOT_ID) -> CK_RV {
+    guard(|| state::with_module(|module| module.close_all_sessions(slot_id)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_GetSessionInfo(
+    handle: CK_SESSION_HANDLE,
+    info: *mut CK_SESSION_INFO,
+) -> CK_RV {
+    guard(|| {
+        let info = out(info)?;
+        state::with_module(|module| {
+            let logged_in = module.is_logged_in(handle)?;
+            let session = module.session(handle)?;
+            let rw = session.flags & CKF_RW_SESSION != 0;
+
+            info.slotID = session.slot_id;
+            info.state = match (rw, logged_in) {
+                (false, false) => CKS_RO_PUBLIC_SESSION,
+                (false, true) => CKS_RO_USER_FUNCTIONS,
+                (true, false) => CKS_RW_PUBLIC_SESSION,
+                (true, true) => CKS_RW_USER_FUNCTIONS,
+            };
+            info.flags = session.flags;
+            info.ulDeviceError = 0;
+            Ok(())
+        })
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_Login(
+    handle: CK_SESSION_HANDLE,
+    user_type: CK_USER_TYPE,
+    pin: CK_UTF8CHAR_PTR,
+    pin_len: CK_ULONG,
+) -> CK_RV {
+    guard(|| {
+        // The device has no distinction between the SO and users; both log in with an AuthKey.
+        if user_type != CKU_USER && user_type != CKU_SO {
+            return Err(CKR_USER_TYPE_INVALID);
+        }
+
+        let pin = input(pin, pin_len)?;
+        state::login(handle, pin)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_Logout(handle: CK_SESSION_HANDLE) -> CK_RV {
+    guard(|| state::with_module(|module| module.logout(handle)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_DestroyObject(
+    handle: CK_SESSION_HANDLE,
+    object: CK_OBJECT_HANDLE,
+) -> CK_RV {
+    guard(|| {
+        let device = state::with_module(|module| {
+            let (session, device) = module.device_session(handle)?;
+            require_rw(session)?;
+            Ok(device)
+        })?;
+
+        let object = ObjectHandle::from_handle(object)?;
+        // The public key is part of the device's key pair object, so deleting it would also
+        // delete the private key.
+        if object.kind == ObjectKind::PublicKey {
+            return Err(CKR_ACTION_PROHIBITED);
+        }
+
+        state::with_device(&device, |device| {
+            // Checks that a certificate handle refers to a certificate, and a data handle to data.
+            Object::load(device, object)?;
+            device
+                .delete_object(object.id, object.object_type())
+                .map_err(|_| CKR_OBJECT_HANDLE_INVALID)
+        })
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_GetAttributeValue(
+    handle: CK_SESSION_HANDLE,
+    object: CK_OBJECT_HANDLE,
+    template: CK_ATTRIBUTE_PTR,
+    count: CK_ULONG,
+) -> CK_RV {
+    guard(|| {
+        if template.is_null() && count != 0 {
+            return Err(CKR_ARGUMENTS_BAD);
+        }
+
+        // No objects are visible until the slot is logged in.
+        let device = state::with_module(|module| {
+            let (_, device) = module.device_session(handle).map_err(|rv| match rv {
+                CKR_USER_NOT_LOGGED_IN => CKR_OBJECT_HANDLE_INVALID,
+                rv => rv,
+            })?;
+            Ok(device)
+        })?;
+
+        state::with_device(&device, |device| {
+            let mut object = Object::load(device, ObjectHandle::from_handle(object)?)?;
+            // `template` may be null when there are no attributes.
+            if count == 0 {
+                return Ok(());
+            }
+
+            // Every attribute is processed even if an earlier one fails, as the specification
+            // requires.
+            let mut rv = CKR_OK;
+            for attribute in slice::from_raw_parts_mut(template, count as usize) {
+                match object.attribute(device, attribute.type_)? {
+                    None => {
+                        attribute.ulValueLen = CK_UNAVAILABLE_INFORMATION;
+                        rv = CKR_ATTRIBUTE_TYPE_INVALID;
+                    }
+                    Some(AttributeValue::Sensitive) => {
+                        attribute.ulValueLen = CK_UNAVAILABLE_INFORMATION;
+                        rv = CKR_ATTRIBUTE_SENSITIVE;
+                    }
+                    Some(AttributeValue::Value(value)) => {
+                        let buffer = attribute.pValue as CK_BYTE_PTR;
+                        if write_output(&value, buffer, &mut attribute.ulValueLen).is_err() {
+                            attribute.ulValueLen = CK_UNAVAILABLE_INFORMATION;
+                            rv = CKR_BUFFER_TOO_SMALL;
+                        }
+                    }
+                }
+            }
+
+            if rv == CKR_OK {
+                Ok(())
+            } else {
+                Err(rv)
+            }
+        })
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_FindObjectsInit(
+    handle: CK_SESSION_HANDLE,
+    template: CK_ATTRIBUTE_PTR,
+    count: CK_ULONG,
+) -> CK_RV {
+    guard(|| {
+        let template = read_template(template, count)?;
+
+        let device = state::with_module(|module| {
+            if module.session(handle)?.find.is_some() {
+                return Err(CKR_OPERATION_ACTIVE);
+            }
+
+            // Before logging in there are no objects to find.
+            if !module.is_logged_in(handle)? {
+                module.session(handle)?.find = Some(Vec::new());
+                return Ok(None);
+            }
+
+            Ok(Some(module.device_session(handle)?.1))
+        })?;
+        let device = match device {
+            Some(device) => device,
+            None => return Ok(()),
+        };
+
+        let mut found = state::with_device(&device, |device| {
+            let mut found = Vec::new();
+            for object in objects::list(device)? {
+                let mut object = Object::load(device, object)?;
+                if object.matches(device, &template)? {
+                    found.push(object.handle().to_handle());
+                }
+            }
+            Ok(found)
+        })?;
+
+        // Results are handed out from the end.
+        found.reverse();
+        state::with_module(|module| {
+            let session = module.session(handle)?;
+            if session.find.is_some() {
+                return Err(CKR_OPERATION_ACTIVE);
+            }
+            session.find = Some(found);
+            Ok(())
+        })
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_FindObjects(
+    handle: CK_SESSION_HANDLE,
+    objects: CK_OBJECT_HANDLE_PTR,
+    max_count: CK_ULONG,
+    count: CK_ULONG_PTR,
+) -> CK_RV {
+    guard(|| {
+        let count = out(count)?;
+        if objects.is_null() && max_count != 0 {
+            return Err(CKR_ARGUMENTS_BAD);
+        }
+
+        state::with_module(|module| {
+            let found = module
+                .session(handle)?
+                .find
+                .as_mut()
+                .ok_or(CKR_OPERATION_NOT_INITIALIZED)?;
+
+            let mut n = 0;
+            while n < max_count as usize {
+                match found.pop() {
+                    Some(object) => *objects.add(n) = object,
+                    None => break,
+                }
+                n += 1;
+            }
+
+            *count = n as CK_ULONG;
+            Ok(())
+        })
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_FindObjectsFinal(handle: CK_SESSION_HANDLE) -> CK_RV {
+    guard(|| {
+        state::with_module(|module| {
+            module
+                .session(handle)?
+                .find
+                .take()
+                .ok_or(CKR_OPERATION_NOT_INITIALIZED)?;
+            Ok(())
+        })
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_SignInit(
+    handle: CK_SESSION_HANDLE,
+    mechanism: CK_MECHANISM_PTR,
+    key: CK_OBJECT_HANDLE,
+) -> CK_RV {
+    guard(|| {
+        let mechanism = *out(mechanism)?;
+
+        let device = state::with_module(|module| {
+            let (session, device) = module.device_session(handle)?;
+            if session.sign.is_some() {
+                return Err(CKR_OPERATION_ACTIVE);
+            }
+            Ok(device)
+        })?;
+
+        let (key_id, public_key) = state::with_device(&device, |device| {
+            let mut key = load_private_key(device, key)?;
+            Ok((key.handle().id, key.public_key(device)?.clone()))
+        })?;
+        let operation = SignOperation {
+            key_id,
+            mechanism: SignMechanism::new(&mechanism, &public_key)?,
+            public_key,
+            data: Vec::new(),
+        };
+
+        state::with_module(|module| {
+            let session = module.session(handle)?;
+            if session.sign.is_some() {
+                return Err(CKR_OPERATION_ACTIVE);
+            }
+            session.sign = Some(operation);
+            Ok(())
+        })
+    })
+}
+
+/// Finish a signing operation over `data`, or over the data passed to `C_SignUpdate` if `data` is
+/// `None`, keeping the operation if the caller is asking for the signature's length.
+unsafe fn finish_sign(
+    handle: CK_SESSION_HANDLE,
+    data: Option<&[u8]>,
+    signature: CK_BYTE_PTR,
+    signature_len: &mut CK_ULONG,
+) -> Result<(), CK_RV> {
+    let operation = state::with_module(|module| {
+        let (session, device) = module.device_session(handle)?;
+        let operation = session.sign.take().ok_or(CKR_OPERATION_NOT_INITIALIZED)?;
+
+        let len = SignMechanism::signature_len(&operation.public_key)?;
+        if signature.is_null() || (*signature_len as usize) < len {
+            let result = write_output(&vec![0; len], signature, signature_len);
+            session.sign = Some(operation);
+            return result.map(|()| None);
+        }
+
+        Ok(Some((operation, device)))
+    })?;
+    let (operation, device) = match operation {
+        Some(operation) => operation,
+        None => return Ok(()),
+    };
+
+    let data = data.unwrap_or(&operation.data);
+    let result = state::with_device(&device, |device| {
+        operation
+            .mechanism
+            .sign(device, operation.key_id, &operation.public_key, data)
+    })?;
+    write_output(&result, signature, signature_len)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_Sign(
+    handle: CK_SESSION_HANDLE,
+    data: CK_BYTE_PTR,
+    data_len: CK_ULONG,
+    signature: CK_BYTE_PTR,
+    signature_len: CK_ULONG_PTR,
+) -> CK_RV {
+    guard(|| {
+        let data = input(data, data_len)?;
+        let signature_len = out(signature_len)?;
+
+        finish_sign(handle, Some(data), signature, signature_len)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_SignUpdate(
+    handle: CK_SESSION_HANDLE,
+    data: CK_BYTE_PTR,
+    data_len: CK_ULONG,
+) -> CK_RV {
+    guard(|| {
+        let data = input(data, data_len)?;
+
+        state::with_module(|module| {
+            let (session, _) = module.device_session(handle)?;
+            let operation = session.sign.as_mut().ok_or(CKR_OPERATION_NOT_INITIALIZED)?;
+            operation.data.extend_from_slice(data);
+            Ok(())
+        })
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_SignFinal(
+    handle: CK_SESSION_HANDLE,
+    signature: CK_BYTE_PTR,
+    signature_len: CK_ULONG_PTR,
+) -> CK_RV {
+    guard(|| {
+        let signature_len = out(signature_len)?;
+
+        finish_sign(handle, None, signature, signature_len)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_DecryptInit(
+    handle: CK_SESSION_HANDLE,
+    mechanism: CK_MECHANISM_PTR,
+    key: CK_OBJECT_HANDLE,
+) -> CK_RV {
+    guard(|| {
+        let mechanism = *out(mechanism)?;
+
+        let device = state::with_module(|module| {
+            let (session, device) = module.device_session(handle)?;
+            if session.decrypt.is_some() {
+                return Err(CKR_OPERATION_ACTIVE);
+            }
+            Ok(device)
+        })?;
+
+        let operation = state::with_device(&device, |device| {
+            let mut key = load_private_key(device, key)?;
+            Ok(DecryptOperation {
+                key_id: key.handle().id,
+                mechanism: DecryptMechanism::new(&mechanism, key.public_key(device)?)?,
+                plaintext: None,
+            })
+        })?;
+
+        state::with_module(|module| {
+            let session = module.session(handle)?;
+            if session.decrypt.is_some() {
+                return Err(CKR_OPERATION_ACTIVE);
+            }
+            session.decrypt = Some(operation);
+            Ok(())
+        })
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_Decrypt(
+    handle: CK_SESSION_HANDLE,
+    data: CK_BYTE_PTR,
+    data_len: CK_ULONG,
+    plaintext: CK_BYTE_PTR,
+    plaintext_len: CK_ULONG_PTR,
+) -> CK_RV {
+    guard(|| {
+        let data = input(data, data_len)?;
+        let plaintext_len = out(plaintext_len)?;
+
+        let (mut operation, device) = state::with_module(|module| {
+            let (session, device) = module.device_session(handle)?;
+            let operation = session
+                .decrypt
+                .take()
+                .ok_or(CKR_OPERATION_NOT_INITIALIZED)?;
+            Ok((operation, device))
+        })?;
+
+        let result = match operation.plaintext.take() {
+            Some(result) => result,
+            None => state::with_device(&device, |device| {
+                operation.mechanism.decrypt(device, operation.key_id, data)
+            })?,
+        };
+
+        let keep = |mut operation: DecryptOperation, result| {
+            operation.plaintext = Some(result);
+            state::with_module(|module| {
+                module.session(handle)?.decrypt = Some(operation);
+                Ok(())
+            })
+        };
+        match write_output(&result, plaintext, plaintext_len) {
+            Err(CKR_BUFFER_TOO_SMALL) => {
+                keep(operation, result)?;
+                Err(CKR_BUFFER_TOO_SMALL)
+            }
+            Ok(()) if plaintext.is_null() => keep(operation, result),
+            result => result,
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_GenerateKeyPair(
+    handle: CK_SESSION_HANDLE,
+    mechanism: CK_MECHANISM_PTR,
+    public_template: CK_ATTRIBUTE_PTR,
+    public_count: CK_ULONG,
+    private_template: CK_ATTRIBUTE_PTR,
+    private_count: CK_ULONG,
+    public_key: CK_OBJECT_HANDLE_PTR,
+    private_key: CK_OBJECT_HANDLE_PTR,
+) -> CK_RV {
+    guard(|| {
+        let mechanism = out(mechanism)?.mechanism;
+        let public_template = read_template(public_template, public_count)?;
+        let private_template = read_template(private_template, private_count)?;
+        let public_key = out(public_key)?;
+        let private_key = out(private_key)?;
+
+        let device = state::with_module(|module| {
+            let (session, device) = module.device_session(handle)?;
+            require_rw(session)?;
+            Ok(device)
+        })?;
+
+        state::with_device(&device, |device| {
+            let id = mechanisms::generate_key_pair(
+                device,
+                mechanism,
+                &public_template,
+                &private_template,
+            )?;
+            *public_key = ObjectHandle {
+                kind: ObjectKind::PublicKey,
+                id,
+            }
+            .to_handle();
+            *private_key = ObjectHandle {
+                kind: ObjectKind::PrivateKey,
+                id,
+            }
+            .to_handle();
+            Ok(())
+        })
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_SeedRandom(
+    handle: CK_SESSION_HANDLE,
+    _seed: CK_BYTE_PTR,
+    _seed_len: CK_ULONG,
+) -> CK_RV {
+    guard(|| {
+        state::with_module(|module| module.session(handle).map(|_| ()))?;
+        Err(CKR_RANDOM_SEED_NOT_SUPPORTED)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn C_GenerateRandom(
+    handle: CK_SESSION_HANDLE,
+    data: CK_BYTE_PTR,
+    len: CK_ULONG,
+) -> CK_RV {
+    guard(|| {
+        if data.is_null() && len != 0 {
+            return Err(CKR_ARGUMENTS_BAD);
+        }
+
+        let device = state::with_module(|module| Ok(module.device_session(handle)?.1))?;
+        // `data` may be null when no bytes are asked for.
+        if len == 0 {
+            return Ok(());
+        }
+
+        state::with_device(&device, |device| {
+            let random = device
+                .get_random(len as usize)
+                .map_err(|_| CKR_DEVICE_ERROR)?;
+            ptr::copy_nonoverlapping(random.as_ptr(), data, random.len());
+            Ok(())
+        })
+    })
+}
+
+macro_rules! not_supported {
+    ($($name:ident($($arg:ty),*);)*) => {
+        $(
+            #[no_mangle]
+            pub unsafe extern "C" fn $name($(_: $arg),*) -> CK_RV {
+                CKR_FUNCTION_NOT_SUPPORTED
+            }
+        )*
+    };
+}
+
+not_supported! {
+    C_InitToken(CK_SLOT_ID, CK_UTF8CHAR_PTR, CK_ULONG, CK_UTF8CHAR_PTR);
+    C_InitPIN(CK_SESSION_HANDLE, CK_UTF8CHAR_PTR, CK_ULONG);
+    C_SetPIN(CK_SESSION_HANDLE, CK_UTF8CHAR_PTR, CK_ULONG, CK_UTF8CHAR_PTR, CK_ULONG);
+    C_GetOperationState(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG_PTR);
+    C_SetOperationState(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG, CK_OBJECT_HANDLE, CK_OBJECT_HANDLE);
+    C_CreateObject(CK_SESSION_HANDLE, CK_ATTRIBUTE_PTR, CK_ULONG, CK_OBJECT_HANDLE_PTR);
+    C_CopyObject(CK_SESSION_HANDLE, CK_OBJECT_HANDLE, CK_ATTRIBUTE_PTR, CK_ULONG, CK_OBJECT_HANDLE_PTR);
+    C_GetObjectSize(CK_SESSION_HANDLE, CK_OBJECT_HANDLE, CK_ULONG_PTR);
+    C_SetAttributeValue(CK_SESSION_HANDLE, CK_OBJECT_HANDLE, CK_ATTRIBUTE_PTR, CK_ULONG);
+    C_EncryptInit(CK_SESSION_HANDLE, CK_MECHANISM_PTR, CK_OBJECT_HANDLE);
+    C_Encrypt(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG, CK_BYTE_PTR, CK_ULONG_PTR);
+    C_EncryptUpdate(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG, CK_BYTE_PTR, CK_ULONG_PTR);
+    C_EncryptFinal(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG_PTR);
+    C_DecryptUpdate(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG, CK_BYTE_PTR, CK_ULONG_PTR);
+    C_DecryptFinal(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG_PTR);
+    C_DigestInit(CK_SESSION_HANDLE, CK_MECHANISM_PTR);
+    C_Digest(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG, CK_BYTE_PTR, CK_ULONG_PTR);
+    C_DigestUpdate(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG);
+    C_DigestKey(CK_SESSION_HANDLE, CK_OBJECT_HANDLE);
+    C_DigestFinal(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG_PTR);
+    C_SignRecoverInit(CK_SESSION_HANDLE, CK_MECHANISM_PTR, CK_OBJECT_HANDLE);
+    C_SignRecover(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG, CK_BYTE_PTR, CK_ULONG_PTR);
+    C_VerifyInit(CK_SESSION_HANDLE, CK_MECHANISM_PTR, CK_OBJECT_HANDLE);
+    C_Verify(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG, CK_BYTE_PTR, CK_ULONG);
+    C_VerifyUpdate(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG);
+    C_VerifyFinal(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG);
+    C_VerifyRecoverInit(CK_SESSION_HANDLE, CK_MECHANISM_PTR, CK_OBJECT_HANDLE);
+    C_VerifyRecover(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG, CK_BYTE_PTR, CK_ULONG_PTR);
+    C_DigestEncryptUpdate(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG, CK_BYTE_PTR, CK_ULONG_PTR);
+    C_DecryptDigestUpdate(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG, CK_BYTE_PTR, CK_ULONG_PTR);
+    C_SignEncryptUpdate(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG, CK_BYTE_PTR, CK_ULONG_PTR);
+    C_DecryptVerifyUpdate(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG, CK_BYTE_PTR, CK_ULONG_PTR);
+    C_GenerateKey(CK_SESSION_HANDLE, CK_MECHANISM_PTR, CK_ATTRIBUTE_PTR, CK_ULONG, CK_OBJECT_HANDLE_PTR);
+    C_WrapKey(CK_SESSION_HANDLE, CK_MECHANISM_PTR, CK_OBJECT_HANDLE, CK_OBJECT_HANDLE, CK_BYTE_PTR, CK_ULONG_PTR);
+    C_UnwrapKey(CK_SESSION_HANDLE, CK_MECHANISM_PTR, CK_OBJECT_HANDLE, CK_BYTE_PTR, CK_ULONG, CK_ATTRIBUTE_PTR, CK_ULONG, CK_OBJECT_HANDLE_PTR);
+    C_DeriveKey(CK_SESSION_HANDLE, CK_MECHANISM_PTR, CK_OBJECT_HANDLE, CK_ATTRIBUTE_PTR, CK_ULONG, CK_OBJECT_HANDLE_PTR);
+    C_GetFunctionStatus(CK_SESSION_HANDLE);
+    C_CancelFunction(CK_SESSION_HANDLE);
+    C_WaitForSlotEvent(CK_FLAGS, CK_SLOT_ID_PTR, CK_VOID_PTR);
+}
+
+static FUNCTION_LIST: CK_FUNCTION_LIST = CK_FUNCTION_LIST {
+    version: CK_VERSION {
+        major: 2,
+        minor: 40,
+    },
+    C_Initialize: Some(C_Initialize),
+    C_Finalize: Some(C_Finalize),
+    C_GetInfo: Some(C_GetInfo),
+    C_GetFunctionList: Some(C_GetFunctionList),
+    C_GetSlotList: Some(C_GetSlotList),
+    C_GetSlotInfo: Some(C_GetSlotInfo),
+    C_GetTokenInfo: Some(C_GetTokenInfo),
+    C_GetMechanismList: Some(C_GetMechanismList),
+    C_GetMechanismInfo: Some(C_GetMechanismInfo),
+    C_InitToken: Some(C_InitToken),
+    C_InitPIN: Some(C_InitPIN),
+    C_SetPIN: Some(C_SetPIN),
+    C_OpenSession: Some(C_OpenSession),
+    C_CloseSession: Some(C_CloseSession),
+    C_CloseAllSessions: Some(C_CloseAllSessions),
+    C_GetSessionInfo: Some(C_GetSessionInfo),
+    C_GetOperationState: Some(C_GetOperationState),
+    C_SetOperationState: Some(C_SetOperationState),
+    C_Login: Some(C_Login),
+    C_Logout: Some(C_Logout),
+    C_CreateObject: Some(C_CreateObject),
+    C_CopyObject: Some(C_CopyObject),
+    C_DestroyObject: Some(C_DestroyObject),
+    C_GetObjectSize: Some(C_GetObjectSize),
+    C_GetAttributeValue: Some(C_GetAttributeValue),
+    C_SetAttributeValue: Some(C_SetAttributeValue),
+    C_FindObjectsInit: Some(C_FindObjectsInit),
+    C_FindObjects: Some(C_FindObjects),
+    C_FindObjectsFinal: Some(C_FindObjectsFinal),
+    C_EncryptInit: Some(C_EncryptInit),
+    C_Encrypt: Some(C_Encrypt),
+    C_EncryptUpdate: Some(C_EncryptUpdate),
+    C_EncryptFinal: Some(C_EncryptFinal),
+    C_DecryptInit: Some(C_DecryptInit),
+    C_Decrypt: Some(C_Decrypt),
+    C_DecryptUpdate: Some(C_DecryptUpdate),
+    C_DecryptFinal: Some(C_DecryptFinal),
+    C_DigestInit: Some(C_DigestInit),
+    C_Digest: Some(C_Digest),
+    C_DigestUpdate: Some(C_DigestUpdate),
+    C_DigestKey: Some(C_DigestKey),
+    C_DigestFinal: Some(C_DigestFinal),
+    C_SignInit: Some(C_SignInit),
+    C_Sign: Some(C_Sign),
+    C_SignUpdate: Some(C_SignUpdate),
+    C_SignFinal: Some(C_SignFinal),
+    C_SignRecoverInit: Some(C_SignRecoverInit),
+    C_SignRecover: Some(C_SignRecover),
+    C_VerifyInit: Some(C_VerifyInit),
+    C_Verify: Some(C_Verify),
+    C_VerifyUpdate: Some(C_VerifyUpdate),
+    C_VerifyFinal: Some(C_VerifyFinal),
+    C_VerifyRecoverInit: Some(C_VerifyRecoverInit),
+    C_VerifyRecover: Some(C_VerifyRecover),
+    C_DigestEncryptUpdate: Some(C_DigestEncryptUpdate),
+    C_DecryptDigestUpdate: Some(C_DecryptDigestUpdate),
+    C_SignEncryptUpdate: Some(C_SignEncryptUpdate),
+    C_DecryptVerifyUpdate: Some(C_DecryptVerifyUpdate),
+    C_GenerateKey: Some(C_GenerateKey),
+    C_GenerateKeyPair: Some(C_GenerateKeyPair),
+    C_WrapKey: Some(C_WrapKey),
+    C_UnwrapKey: Some(C_UnwrapKey),
+    C_DeriveKey: Some(C_DeriveKey),
+    C_SeedRandom: Some(C_SeedRandom),
+    C_GenerateRandom: Some(C_GenerateRandom),
+    C_GetFunctionStatus: Some(C_GetFunctionStatus),
+    C_CancelFunction: Some(C_CancelFunction),
+    C_WaitForSlotEvent: Some(C_WaitForSlotEvent),
+};
@@ -0,0 +1,496 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The supported mechanisms, and how signing, decryption and key generation map onto the
+//! device's operations.
+
+use libyubihsm::{
+    Algorithm, Capability, EcdsaSignature, ObjectType, PublicKey, Session, SignatureScheme,
+};
+use objects::{curve_from_params, field_len, parse_boolean, parse_ulong, rsa_modulus_len};
+use types::*;
+
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+use std::mem;
+use std::ptr;
+use std::slice;
+
+const RSA_FLAGS: CK_FLAGS = CKF_HW | CKF_SIGN;
+const EC_FLAGS: CK_FLAGS = CKF_HW | CKF_SIGN | CKF_EC_F_P | CKF_EC_NAMEDCURVE | CKF_EC_UNCOMPRESS;
+
+/// The supported mechanisms, with their minimum and maximum key sizes (in bits) and flags.
+pub const MECHANISMS: &[(CK_MECHANISM_TYPE, CK_ULONG, CK_ULONG, CK_FLAGS)] = &[
+    (
+        CKM_RSA_PKCS_KEY_PAIR_GEN,
+        2048,
+        4096,
+        CKF_HW | CKF_GENERATE_KEY_PAIR,
+    ),
+    (CKM_RSA_PKCS, 2048, 4096, RSA_FLAGS | CKF_DECRYPT),
+    (CKM_SHA1_RSA_PKCS, 2048, 4096, RSA_FLAGS),
+    (CKM_SHA256_RSA_PKCS, 2048, 4096, RSA_FLAGS),
+    (CKM_SHA384_RSA_PKCS, 2048, 4096, RSA_FLAGS),
+    (CKM_SHA512_RSA_PKCS, 2048, 4096, RSA_FLAGS),
+    (CKM_RSA_PKCS_PSS, 2048, 4096, RSA_FLAGS),
+    (CKM_SHA1_RSA_PKCS_PSS, 2048, 4096, RSA_FLAGS),
+    (CKM_SHA256_RSA_PKCS_PSS, 2048, 4096, RSA_FLAGS),
+    (CKM_SHA384_RSA_PKCS_PSS, 2048, 4096, RSA_FLAGS),
+    (CKM_SHA512_RSA_PKCS_PSS, 2048, 4096, RSA_FLAGS),
+    (CKM_RSA_PKCS_OAEP, 2048, 4096, CKF_HW | CKF_DECRYPT),
+    (
+        CKM_EC_KEY_PAIR_GEN,
+        224,
+        521,
+        EC_FLAGS & !CKF_SIGN | CKF_GENERATE_KEY_PAIR,
+    ),
+    (CKM_ECDSA, 224, 521, EC_FLAGS),
+    (CKM_ECDSA_SHA1, 224, 521, EC_FLAGS),
+    (CKM_ECDSA_SHA256, 224, 521, EC_FLAGS),
+    (CKM_ECDSA_SHA384, 224, 521, EC_FLAGS),
+    (CKM_ECDSA_SHA512, 224, 521, EC_FLAGS),
+    (
+        CKM_EC_EDWARDS_KEY_PAIR_GEN,
+        255,
+        255,
+        CKF_HW | CKF_GENERATE_KEY_PAIR,
+    ),
+    (CKM_EDDSA, 255, 255, CKF_HW | CKF_SIGN),
+];
+
+/// A digest named by a `CKM_SHA*` mechanism in mechanism parameters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hash {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl Hash {
+    fn from_mechanism(mechanism: CK_MECHANISM_TYPE) -> Result<Hash, CK_RV> {
+        match mechanism {
+            CKM_SHA_1 => Ok(Hash::Sha1),
+            CKM_SHA256 => Ok(Hash::Sha256),
+            CKM_SHA384 => Ok(Hash::Sha384),
+            CKM_SHA512 => Ok(Hash::Sha512),
+            _ => Err(CKR_MECHANISM_PARAM_INVALID),
+        }
+    }
+
+    pub fn digest(&self, data: &[u8]) -> Vec<u8> {
+        match *self {
+            Hash::Sha1 => Sha1::digest(data).to_vec(),
+            Hash::Sha256 => Sha256::digest(data).to_vec(),
+            Hash::Sha384 => Sha384::digest(data).to_vec(),
+            Hash::Sha512 => Sha512::digest(data).to_vec(),
+        }
+    }
+}
+
+/// The device's MGF1 algorithm for a `CKG_MGF1_*` value.
+fn mgf1_algorithm(mgf: CK_RSA_PKCS_MGF_TYPE) -> Result<Algorithm, CK_RV> {
+    match mgf {
+        CKG_MGF1_SHA1 => Ok(Algorithm::Mgf1Sha1),
+        CKG_MGF1_SHA256 => Ok(Algorithm::Mgf1Sha256),
+        CKG_MGF1_SHA384 => Ok(Algorithm::Mgf1Sha384),
+        CKG_MGF1_SHA512 => Ok(Algorithm::Mgf1Sha512),
+        _ => Err(CKR_MECHANISM_PARAM_INVALID),
+    }
+}
+
+/// Read the mechanism's parameter structure.
+unsafe fn parameters<T: Copy>(mechanism: &CK_MECHANISM) -> Result<T, CK_RV> {
+    if mechanism.pParameter.is_null() || mechanism.ulParameterLen as usize != mem::size_of::<T>() {
+        return Err(CKR_MECHANISM_PARAM_INVALID);
+    }
+
+    Ok(ptr::read_unaligned(mechanism.pParameter as *const T))
+}
+
+#[derive(Clone, Debug)]
+pub enum SignMechanism {
+    /// `CKM_RSA_PKCS`: the input (usually a DigestInfo) is padded and signed as-is.
+    RsaPkcs,
+    /// `CKM_SHA*_RSA_PKCS` and `CKM_ECDSA_SHA*`: the input is hashed by `Session::sign_message`.
+    Message(SignatureScheme),
+    /// `CKM_RSA_PKCS_PSS`, which signs a digest, and `CKM_SHA*_RSA_PKCS_PSS`, which hash the input
+    /// first.
+    RsaPss {
+        hash: Option<Hash>,
+        mgf1_algorithm: Algorithm,
+        salt_len: usize,
+    },
+    /// `CKM_ECDSA`: the input is a digest.
+    Ecdsa,
+    Eddsa,
+}
+
+impl SignMechanism {
+    pub unsafe fn new(
+        mechanism: &CK_MECHANISM,
+        public_key: &PublicKey,
+    ) -> Result<SignMechanism, CK_RV> {
+        let (sign_mechanism, key_matches) = match mechanism.mechanism {
+            CKM_RSA_PKCS => (SignMechanism::RsaPkcs, is_rsa(public_key)),
+            CKM_SHA1_RSA_PKCS => (
+                SignMechanism::Message(SignatureScheme::RsaPkcs1v15Sha1),
+                is_rsa(public_key),
+            ),
+            CKM_SHA256_RSA_PKCS => (
+                SignMechanism::Message(SignatureScheme::RsaPkcs1v15Sha256),
+                is_rsa(public_key),
+            ),
+            CKM_SHA384_RSA_PKCS => (
+                SignMechanism::Message(SignatureScheme::RsaPkcs1v15Sha384),
+                is_rsa(public_key),
+            ),
+            CKM_SHA512_RSA_PKCS => (
+                SignMechanism::Message(SignatureScheme::RsaPkcs1v15Sha512),
+                is_rsa(public_key),
+            ),
+            CKM_RSA_PKCS_PSS
+            | CKM_SHA1_RSA_PKCS_PSS
+            | CKM_SHA256_RSA_PKCS_PSS
+            | CKM_SHA384_RSA_PKCS_PSS
+            | CKM_SHA512_RSA_PKCS_PSS => {
+                let params: CK_RSA_PKCS_PSS_PARAMS = parameters(mechanism)?;
+                let hash = Hash::from_mechanism(params.hashAlg)?;
+
+                // The hashing variants must name their own digest in the parameters.
+                let mechanism_hash = match mechanism.mechanism {
+                    CKM_SHA1_RSA_PKCS_PSS => Some(Hash::Sha1),
+                    CKM_SHA256_RSA_PKCS_PSS => Some(Hash::Sha256),
+                    CKM_SHA384_RSA_PKCS_PSS => Some(Hash::Sha384),
+                    CKM_SHA512_RSA_PKCS_PSS => Some(Hash::Sha512),
+                    _ => None,
+                };
+                if mechanism_hash.is_some() && mechanism_hash != Some(hash) {
+                    return Err(CKR_MECHANISM_PARAM_INVALID);
+                }
+
+                (
+                    SignMechanism::RsaPss {
+                        hash: mechanism_hash,
+                        mgf1_algorithm: mgf1_algorithm(params.mgf)?,
+                        salt_len: params.sLen as usize,
+                    },
+                    is_rsa(public_key),
+                )
+            }
+            CKM_ECDSA => (SignMechanism::Ecdsa, is_ecdsa(public_key)),
+            CKM_ECDSA_SHA1 => (
+                SignMechanism::Message(SignatureScheme::EcdsaSha1),
+                is_ecdsa(public_key),
+            ),
+            CKM_ECDSA_SHA256 => (
+                SignMechanism::Message(SignatureScheme::EcdsaSha256),
+                is_ecdsa(public_key),
+            ),
+            CKM_ECDSA_SHA384 => (
+                SignMechanism::Message(SignatureScheme::EcdsaSha384),
+                is_ecdsa(public_key),
+            ),
+            CKM_ECDSA_SHA512 => (
+                SignMechanism::Message(SignatureScheme::EcdsaSha512),
+                is_ecdsa(public_key),
+            ),
+            CKM_EDDSA => (SignMechanism::Eddsa, is_eddsa(public_key)),
+            _ => return Err(CKR_MECHANISM_INVALID),
+        };
+
+        if !key_matches {
+            return Err(CKR_KEY_TYPE_INCONSISTENT);
+        }
+
+        Ok(sign_mechanism)
+    }
+
+    /// The length of signatures made with `public_key`'s key.
+    pub fn signature_len(public_key: &PublicKey) -> Result<usize, CK_RV> {
+        match *public_key {
+            PublicKey::Rsa(algorithm, _) => Ok(rsa_modulus_len(algorithm)? as usize),
+            PublicKey::Ecc(curve, _, _) => Ok(field_len(curve)? * 2),
+            PublicKey::Edc(_, _) => Ok(64),
+        }
+    }
+
+    /// Sign `data` with the key `key_id`, returning the signature in PKCS#11's format (`r || s`
+    /// for ECDSA).
+    pub fn sign(
+        &self,
+        session: &Session,
+        key_id: u16,
+        public_key: &PublicKey,
+        data: &[u8],
+    ) -> Result<Vec<u8>, CK_RV> {
+        let signature = match *self {
+            SignMechanism::RsaPkcs => session.sign_pkcs1v1_5(key_id, false, data),
+            SignMechanism::Message(scheme) => session.sign_message(key_id, scheme, data),
+            SignMechanism::RsaPss {
+                hash,
+                mgf1_algorithm,
+                salt_len,
+            } => {
+                let digest = match hash {
+                    Some(hash) => hash.digest(data),
+                    None => data.to_vec(),
+                };
+                session.sign_pss(key_id, salt_len, mgf1_algorithm, digest)
+            }
+            SignMechanism::Ecdsa => {
+                // Digests longer than the curve order are truncated, as in FIPS 186-4.
+                let curve = public_key.algorithm();
+                let data = &data[..data.len().min(field_len(curve)?)];
+                session.sign_ecdsa(key_id, data)
+            }
+            SignMechanism::Eddsa => session.sign_eddsa(key_id, data),
+        }
+        .map_err(|_| CKR_FUNCTION_FAILED)?;
+
+        match *public_key {
+            PublicKey::Ecc(curve, _, _) => EcdsaSignature::from_der(curve, signature)
+                .map(|signature| signature.to_bytes())
+                .map_err(|_| CKR_DEVICE_ERROR),
+            _ => Ok(signature),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum DecryptMechanism {
+    RsaPkcs,
+    RsaOaep {
+        label_hash: Vec<u8>,
+        mgf1_algorithm: Algorithm,
+    },
+}
+
+impl DecryptMechanism {
+    pub unsafe fn new(
+        mechanism: &CK_MECHANISM,
+        public_key: &PublicKey,
+    ) -> Result<DecryptMechanism, CK_RV> {
+        let decrypt_mechanism = match mechanism.mechanism {
+            CKM_RSA_PKCS => DecryptMechanism::RsaPkcs,
+            CKM_RSA_PKCS_OAEP => {
+                let params: CK_RSA_PKCS_OAEP_PARAMS = parameters(mechanism)?;
+                let hash = Hash::from_mechanism(params.hashAlg)?;
+
+                let label = if params.pSourceData.is_null() || params.ulSourceDataLen == 0 {
+                    &[][..]
+                } else if params.source == CKZ_DATA_SPECIFIED {
+                    slice::from_raw_parts(
+                        params.pSourceData as *const u8,
+                        params.ulSourceDataLen as usize,
+                    )
+                } else {
+                    return Err(CKR_MECHANISM_PARAM_INVALID);
+                };
+
+                // The device takes the label's digest rather than the label.
+                DecryptMechanism::RsaOaep {
+                    label_hash: hash.digest(label),
+                    mgf1_algorithm: mgf1_algorithm(params.mgf)?,
+                }
+            }
+            _ => return Err(CKR_MECHANISM_INVALID),
+        };
+
+        if !is_rsa(public_key) {
+            return Err(CKR_KEY_TYPE_INCONSISTENT);
+        }
+
+        Ok(decrypt_mechanism)
+    }
+
+    pub fn decrypt(&self, session: &Session, key_id: u16, data: &[u8]) -> Result<Vec<u8>, CK_RV> {
+        match *self {
+            DecryptMechanism::RsaPkcs => session.decrypt_pkcs1v1_5(key_id, data),
+            DecryptMechanism::RsaOaep {
+                ref label_hash,
+                mgf1_algorithm,
+            } => session.decrypt_oaep(key_id, label_hash, mgf1_algorithm, data),
+        }
+        .map_err(|_| CKR_ENCRYPTED_DATA_INVALID)
+    }
+}
+
+fn is_rsa(public_key: &PublicKey) -> bool {
+    matches!(*public_key, PublicKey::Rsa(_, _))
+}
+
+fn is_ecdsa(public_key: &PublicKey) -> bool {
+    matches!(*public_key, PublicKey::Ecc(_, _, _))
+}
+
+fn is_eddsa(public_key: &PublicKey) -> bool {
+    matches!(*public_key, PublicKey::Edc(_, _))
+}
+
+/// An attribute template, as `(type, value)` pairs.
+pub type Template = [(CK_ATTRIBUTE_TYPE, Vec<u8>)];
+
+fn find(template: &Template, attribute: CK_ATTRIBUTE_TYPE) -> Option<&[u8]> {
+    template
+        .iter()
+        .find(|&&(a, _)| a == attribute)
+        .map(|(_, value)| value.as_slice())
+}
+
+fn flag(template: &Template, attribute: CK_ATTRIBUTE_TYPE, default: bool) -> Result<bool, CK_RV> {
+    find(template, attribute).map_or(Ok(default), parse_boolean)
+}
+
+/// Generate a key pair as described by `mechanism` and the templates, returning its object ID.
+///
+/// The key is labeled with `CKA_LABEL` and given the ID in `CKA_ID` if they are set (in either
+/// template), or else the lowest free ID. Its capabilities follow `CKA_SIGN` (which defaults to
+/// true), `CKA_DECRYPT`, `CKA_DERIVE` and `CKA_EXTRACTABLE` in the private key template, and it
+/// is placed in all the domains of the session's AuthKey.
+pub fn generate_key_pair(
+    session: &Session,
+    mechanism: CK_MECHANISM_TYPE,
+    public_template: &Template,
+    private_template: &Template,
+) -> Result<u16, CK_RV> {
+    let algorithm = match mechanism {
+        CKM_RSA_PKCS_KEY_PAIR_GEN => {
+            let bits = find(public_template, CKA_MODULUS_BITS).ok_or(CKR_TEMPLATE_INCOMPLETE)?;
+            if let Some(exponent) = find(public_template, CKA_PUBLIC_EXPONENT) {
+                let skip = exponent.iter().take_while(|&&b| b == 0).count();
+                if exponent[skip..] != [0x01, 0x00, 0x01] {
+                    return Err(CKR_TEMPLATE_INCONSISTENT);
+                }
+            }
+
+            match parse_ulong(bits)? {
+                2048 => Algorithm::Rsa2048,
+                3072 => Algorithm::Rsa3072,
+                4096 => Algorithm::Rsa4096,
+                _ => return Err(CKR_ATTRIBUTE_VALUE_INVALID),
+            }
+        }
+        CKM_EC_KEY_PAIR_GEN => {
+            let params = find(public_template, CKA_EC_PARAMS).ok_or(CKR_TEMPLATE_INCOMPLETE)?;
+            match curve_from_params(params)? {
+                Algorithm::EcEd25519 => return Err(CKR_TEMPLATE_INCONSISTENT),
+                curve => curve,
+            }
+        }
+        CKM_EC_EDWARDS_KEY_PAIR_GEN => {
+            if let Some(params) = find(public_template, CKA_EC_PARAMS) {
+                if curve_from_params(params)? != Algorithm::EcEd25519 {
+                    return Err(CKR_TEMPLATE_INCONSISTENT);
+                }
+            }
+            Algorithm::EcEd25519
+        }
+        _ => return Err(CKR_MECHANISM_INVALID),
+    };
+
+    let sign = flag(private_template, CKA_SIGN, true)?;
+    let decrypt = flag(private_template, CKA_DECRYPT, false)?;
+    let derive = flag(private_template, CKA_DERIVE, false)?;
+    let extractable = flag(private_template, CKA_EXTRACTABLE, false)?;
+
+    let mut capabilities = Vec::new();
+    match mechanism {
+        CKM_RSA_PKCS_KEY_PAIR_GEN => {
+            if derive {
+                return Err(CKR_TEMPLATE_INCONSISTENT);
+            }
+            if sign {
+                capabilities.push(Capability::AsymmetricSignPkcs);
+                capabilities.push(Capability::AsymmetricSignPss);
+            }
+            if decrypt {
+                capabilities.push(Capability::AsymmetricDecryptPkcs);
+                capabilities.push(Capability::AsymmetricDecryptOaep);
+            }
+        }
+        CKM_EC_KEY_PAIR_GEN => {
+            if decrypt {
+                return Err(CKR_TEMPLATE_INCONSISTENT);
+            }
+            if sign {
+                capabilities.push(Capability::AsymmetricSignEcdsa);
+            }
+            if derive {
+                capabilities.push(Capability::AsymmetricDecryptEcdh);
+            }
+        }
+        _ => {
+            if decrypt || derive {
+                return Err(CKR_TEMPLATE_INCONSISTENT);
+            }
+            if sign {
+                capabilities.push(Capability::AsymmetricSignEddsa);
+            }
+        }
+    }
+    if extractable {
+        capabilities.push(Capability::ExportUnderWrap);
+    }
+
+    let label = find(private_template, CKA_LABEL)
+        .or_else(|| find(public_template, CKA_LABEL))
+        .unwrap_or(&[]);
+    let label = String::from_utf8(label.to_vec()).map_err(|_| CKR_ATTRIBUTE_VALUE_INVALID)?;
+    if label.len() > 40 {
+        return Err(CKR_ATTRIBUTE_VALUE_INVALID);
+    }
+
+    let key_id = match find(private_template, CKA_ID).or_else(|| find(public_template, CKA_ID)) {
+        Some(&[id]) => u16::from(id),
+        Some(&[high, low]) => u16::from_be_bytes([high, low]),
+        Some(_) => return Err(CKR_ATTRIBUTE_VALUE_INVALID),
+        None => free_key_id(session)?,
+    };
+
+    let domains = session
+        .get_object_info(session.auth_key_id(), ObjectType::AuthKey)
+        .map_err(|_| CKR_DEVICE_ERROR)?
+        .domains;
+
+    match algorithm {
+        Algorithm::Rsa2048 | Algorithm::Rsa3072 | Algorithm::Rsa4096 => {
+            session.generate_key_rsa(key_id, &label, &domains, &capabilities, algorithm)
+        }
+        Algorithm::EcEd25519 => {
+            session.generate_key_ed(key_id, &label, &domains, &capabilities, algorithm)
+        }
+        _ => session.generate_key_ec(key_id, &label, &domains, &capabilities, algorithm),
+    }
+    .map_err(|_| CKR_FUNCTION_FAILED)?;
+
+    Ok(key_id)
+}
+
+/// The lowest ID not used by an asymmetric key.
+fn free_key_id(session: &Session) -> Result<u16, CK_RV> {
+    let used: Vec<u16> = session
+        .list_objects()
+        .object_type(ObjectType::Asymmetric)
+        .execute()
+        .map_err(|_| CKR_DEVICE_ERROR)?
+        .iter()
+        .map(|object| object.id)
+        .collect();
+
+    (1..=u16::MAX)
+        .find(|id| !used.contains(id))
+        .ok_or(CKR_FUNCTION_FAILED)
+}
@@ -0,0 +1,418 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Mapping of device objects to PKCS#11 objects and their attributes.
+//!
+//! Each asymmetric key appears as both a private key and a public key object with the same
+//! `CKA_ID`, so that applications can find one from the other. Opaque objects stored with
+//! `Algorithm::OpaqueX509Cert` appear as certificates, and other opaque objects as data objects.
+//! Object handles encode the kind of object and its ID, so they stay valid across sessions.
+
+use libyubihsm::{Algorithm, Capability, ObjectInfo, ObjectType, PublicKey, Session};
+use types::*;
+
+use std::mem;
+
+/// Set in `ObjectInfo::origin` for objects generated on the device.
+const ORIGIN_GENERATED: u8 = 0x01;
+
+const RSA_PUBLIC_EXPONENT: &[u8] = &[0x01, 0x00, 0x01];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObjectKind {
+    PrivateKey,
+    PublicKey,
+    Certificate,
+    Data,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ObjectHandle {
+    pub kind: ObjectKind,
+    pub id: u16,
+}
+
+impl ObjectHandle {
+    pub fn from_handle(handle: CK_OBJECT_HANDLE) -> Result<ObjectHandle, CK_RV> {
+        let kind = match handle >> 16 {
+            1 => ObjectKind::PrivateKey,
+            2 => ObjectKind::PublicKey,
+            3 => ObjectKind::Certificate,
+            4 => ObjectKind::Data,
+            _ => return Err(CKR_OBJECT_HANDLE_INVALID),
+        };
+
+        Ok(ObjectHandle {
+            kind,
+            id: handle as u16,
+        })
+    }
+
+    pub fn to_handle(self) -> CK_OBJECT_HANDLE {
+        let kind = match self.kind {
+            ObjectKind::PrivateKey => 1,
+            ObjectKind::PublicKey => 2,
+            ObjectKind::Certificate => 3,
+            ObjectKind::Data => 4,
+        };
+
+        kind << 16 | CK_OBJECT_HANDLE::from(self.id)
+    }
+
+    pub fn object_type(&self) -> ObjectType {
+        match self.kind {
+            ObjectKind::PrivateKey | ObjectKind::PublicKey => ObjectType::Asymmetric,
+            ObjectKind::Certificate | ObjectKind::Data => ObjectType::Opaque,
+        }
+    }
+}
+
+/// The value of an attribute, or a marker that it exists but can't be revealed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AttributeValue {
+    Value(Vec<u8>),
+    Sensitive,
+}
+
+/// A device object loaded for reading its attributes.
+#[derive(Debug)]
+pub struct Object {
+    handle: ObjectHandle,
+    info: ObjectInfo,
+    public_key: Option<PublicKey>,
+    value: Option<Vec<u8>>,
+}
+
+/// List every object visible to the session.
+pub fn list(session: &Session) -> Result<Vec<ObjectHandle>, CK_RV> {
+    let mut handles = Vec::new();
+
+    let keys = session
+        .list_objects()
+        .object_type(ObjectType::Asymmetric)
+        .execute()
+        .map_err(|_| CKR_DEVICE_ERROR)?;
+    for key in keys {
+        for &kind in &[ObjectKind::PrivateKey, ObjectKind::PublicKey] {
+            handles.push(ObjectHandle { kind, id: key.id });
+        }
+    }
+
+    let opaques = session
+        .list_objects()
+        .object_type(ObjectType::Opaque)
+        .execute()
+        .map_err(|_| CKR_DEVICE_ERROR)?;
+    for opaque in opaques {
+        // Listing objects doesn't return their algorithms.
+        let info = session
+            .get_object_info(opaque.id, ObjectType::Opaque)
+            .map_err(|_| CKR_DEVICE_ERROR)?;
+        let kind = if info.algorithm == Some(Algorithm::OpaqueX509Cert) {
+            ObjectKind::Certificate
+        } else {
+            ObjectKind::Data
+        };
+        handles.push(ObjectHandle {
+            kind,
+            id: opaque.id,
+        });
+    }
+
+    Ok(handles)
+}
+
+impl Object {
+    pub fn load(session: &Session, handle: ObjectHandle) -> Result<Object, CK_RV> {
+        let info = session
+            .get_object_info(handle.id, handle.object_type())
+            .map_err(|_| CKR_OBJECT_HANDLE_INVALID)?;
+
+        // A certificate handle mustn't be usable to read other opaque objects, and vice versa.
+        let is_certificate = info.algorithm == Some(Algorithm::OpaqueX509Cert);
+        match handle.kind {
+            ObjectKind::Certificate if !is_certificate => return Err(CKR_OBJECT_HANDLE_INVALID),
+            ObjectKind::Data if is_certificate => return Err(CKR_OBJECT_HANDLE_INVALID),
+            _ => (),
+        }
+
+        Ok(Object {
+            handle,
+            info,
+            public_key: None,
+            value: None,
+        })
+    }
+
+    pub fn handle(&self) -> ObjectHandle {
+        self.handle
+    }
+
+    /// The object's public key, fetched from the device on first use.
+    pub fn public_key(&mut self, session: &Session) -> Result<&PublicKey, CK_RV> {
+        if self.public_key.is_none() {
+            if self.handle.object_type() != ObjectType::Asymmetric {
+                return Err(CKR_KEY_HANDLE_INVALID);
+            }
+
+            let public_key = session
+                .get_pubkey(self.handle.id)
+                .map_err(|_| CKR_DEVICE_ERROR)?;
+            self.public_key = Some(public_key);
+        }
+
+        Ok(self.public_key.as_ref().unwrap())
+    }
+
+    fn value(&mut self, session: &Session) -> Result<Vec<u8>, CK_RV> {
+        if self.value.is_none() {
+            let value = session
+                .get_opaque_object(self.handle.id)
+                .map_err(|_| CKR_DEVICE_ERROR)?;
+            self.value = Some(value);
+        }
+
+        Ok(self.value.clone().unwrap())
+    }
+
+    fn has_capability(&self, capabilities: &[Capability]) -> bool {
+        self.info
+            .capabilities
+            .iter()
+            .any(|c| capabilities.contains(c))
+    }
+
+    /// The value of `attribute`, or `None` if the object doesn't have it.
+    pub fn attribute(
+        &mut self,
+        session: &Session,
+        attribute: CK_ATTRIBUTE_TYPE,
+    ) -> Result<Option<AttributeValue>, CK_RV> {
+        let kind = self.handle.kind;
+        let is_key = kind == ObjectKind::PrivateKey || kind == ObjectKind::PublicKey;
+        let can_sign = self.has_capability(&[
+            Capability::AsymmetricSignPkcs,
+            Capability::AsymmetricSignPss,
+            Capability::AsymmetricSignEcdsa,
+            Capability::AsymmetricSignEddsa,
+        ]);
+        let can_decrypt = self.has_capability(&[
+            Capability::AsymmetricDecryptPkcs,
+            Capability::AsymmetricDecryptOaep,
+        ]);
+        let extractable = self.has_capability(&[Capability::ExportUnderWrap]);
+
+        let value = match attribute {
+            CKA_CLASS => ulong(match kind {
+                ObjectKind::PrivateKey => CKO_PRIVATE_KEY,
+                ObjectKind::PublicKey => CKO_PUBLIC_KEY,
+                ObjectKind::Certificate => CKO_CERTIFICATE,
+                ObjectKind::Data => CKO_DATA,
+            }),
+            CKA_TOKEN => boolean(true),
+            CKA_PRIVATE => boolean(kind == ObjectKind::PrivateKey),
+            CKA_MODIFIABLE => boolean(false),
+            CKA_LABEL => self.info.label.as_bytes().to_vec(),
+            CKA_ID => self.handle.id.to_be_bytes().to_vec(),
+
+            CKA_KEY_TYPE if is_key => ulong(match *self.public_key(session)? {
+                PublicKey::Rsa(_, _) => CKK_RSA,
+                PublicKey::Ecc(_, _, _) => CKK_EC,
+                PublicKey::Edc(_, _) => CKK_EC_EDWARDS,
+            }),
+            CKA_LOCAL if is_key => boolean(self.info.origin & ORIGIN_GENERATED != 0),
+            CKA_MODULUS if is_key => match *self.public_key(session)? {
+                PublicKey::Rsa(_, ref n) => n.clone(),
+                _ => return Ok(None),
+            },
+            CKA_MODULUS_BITS if is_key => match *self.public_key(session)? {
+                PublicKey::Rsa(algorithm, _) => ulong(rsa_modulus_len(algorithm)? * 8),
+                _ => return Ok(None),
+            },
+            CKA_PUBLIC_EXPONENT if is_key => match *self.public_key(session)? {
+                PublicKey::Rsa(_, _) => RSA_PUBLIC_EXPONENT.to_vec(),
+                _ => return Ok(None),
+            },
+            CKA_EC_PARAMS if is_key => match *self.public_key(session)? {
+                PublicKey::Rsa(_, _) => return Ok(None),
+                ref public_key => curve_oid(public_key.algorithm())?.to_vec(),
+            },
+            CKA_EC_POINT if is_key => match *self.public_key(session)? {
+                PublicKey::Rsa(_, _) => return Ok(None),
+                PublicKey::Edc(_, ref a) => octet_string(a),
+                ref public_key => {
+                    octet_string(&public_key.ec_point().map_err(|_| CKR_GENERAL_ERROR)?)
+                }
+            },
+
+            CKA_SENSITIVE | CKA_ALWAYS_SENSITIVE if kind == ObjectKind::PrivateKey => boolean(true),
+            CKA_EXTRACTABLE if kind == ObjectKind::PrivateKey => boolean(extractable),
+            CKA_NEVER_EXTRACTABLE if kind == ObjectKind::PrivateKey => boolean(!extractable),
+            CKA_SIGN if kind == ObjectKind::PrivateKey => boolean(can_sign),
+            CKA_DECRYPT if kind == ObjectKind::PrivateKey => boolean(can_decrypt),
+            CKA_DERIVE if kind == ObjectKind::PrivateKey => {
+                boolean(self.has_capability(&[Capability::AsymmetricDecryptEcdh]))
+            }
+            CKA_SIGN_RECOVER | CKA_UNWRAP | CKA_ALWAYS_AUTHENTICATE
+                if kind == ObjectKind::PrivateKey =>
+            {
+                boolean(false)
+            }
+            CKA_PRIVATE_EXPONENT | CKA_VALUE if kind == ObjectKind::PrivateKey => {
+                return Ok(Some(AttributeValue::Sensitive))
+            }
+
+            CKA_VERIFY if kind == ObjectKind::PublicKey => boolean(can_sign),
+            CKA_ENCRYPT if kind == ObjectKind::PublicKey => boolean(can_decrypt),
+            CKA_DERIVE | CKA_WRAP | CKA_VERIFY_RECOVER if kind == ObjectKind::PublicKey => {
+                boolean(false)
+            }
+
+            CKA_CERTIFICATE_TYPE if kind == ObjectKind::Certificate => ulong(CKC_X_509),
+            CKA_APPLICATION if kind == ObjectKind::Data => Vec::new(),
+            CKA_VALUE if !is_key => self.value(session)?,
+
+            _ => return Ok(None),
+        };
+
+        Ok(Some(AttributeValue::Value(value)))
+    }
+
+    /// Whether every attribute in `template` is present with the same value.
+    pub fn matches(
+        &mut self,
+        session: &Session,
+        template: &[(CK_ATTRIBUTE_TYPE, Vec<u8>)],
+    ) -> Result<bool, CK_RV> {
+        for &(attribute, ref expected) in template {
+            match self.attribute(session, attribute)? {
+                Some(AttributeValue::Value(ref value)) if value == expected => (),
+                _ => return Ok(false),
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+/// Encode a `CK_ULONG` attribute value.
+pub fn ulong(value: CK_ULONG) -> Vec<u8> {
+    value.to_ne_bytes().to_vec()
+}
+
+/// Decode a `CK_ULONG` attribute value.
+pub fn parse_ulong(value: &[u8]) -> Result<CK_ULONG, CK_RV> {
+    let mut bytes = [0; mem::size_of::<CK_ULONG>()];
+    if value.len() != bytes.len() {
+        return Err(CKR_ATTRIBUTE_VALUE_INVALID);
+    }
+
+    bytes.copy_from_slice(value);
+    Ok(CK_ULONG::from_ne_bytes(bytes))
+}
+
+/// Encode a `CK_BBOOL` attribute value.
+pub fn boolean(value: bool) -> Vec<u8> {
+    vec![if value { CK_TRUE } else { CK_FALSE }]
+}
+
+/// Decode a `CK_BBOOL` attribute value.
+pub fn parse_boolean(value: &[u8]) -> Result<bool, CK_RV> {
+    match *value {
+        [b] => Ok(b != CK_FALSE),
+        _ => Err(CKR_ATTRIBUTE_VALUE_INVALID),
+    }
+}
+
+/// The DER-encoded OID naming the curve of an EC or EdDSA key, as used in `CKA_EC_PARAMS`.
+pub fn curve_oid(algorithm: Algorithm) -> Result<&'static [u8], CK_RV> {
+    Ok(match algorithm {
+        Algorithm::EcP224 => &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x21],
+        Algorithm::EcP256 => &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07],
+        Algorithm::EcP384 => &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x22],
+        Algorithm::EcP521 => &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x23],
+        Algorithm::EcK256 => &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x0a],
+        Algorithm::EcBp256 => &[
+            0x06, 0x09, 0x2b, 0x24, 0x03, 0x03, 0x02, 0x08, 0x01, 0x01, 0x07,
+        ],
+        Algorithm::EcBp384 => &[
+            0x06, 0x09, 0x2b, 0x24, 0x03, 0x03, 0x02, 0x08, 0x01, 0x01, 0x0b,
+        ],
+        Algorithm::EcBp512 => &[
+            0x06, 0x09, 0x2b, 0x24, 0x03, 0x03, 0x02, 0x08, 0x01, 0x01, 0x0d,
+        ],
+        Algorithm::EcEd25519 => &[0x06, 0x03, 0x2b, 0x65, 0x70],
+        _ => return Err(CKR_KEY_TYPE_INCONSISTENT),
+    })
+}
+
+/// The curve named by a `CKA_EC_PARAMS` value. Ed25519 may also be named by the PrintableString
+/// `edwards25519`, as in PKCS#11 v3.0.
+pub fn curve_from_params(params: &[u8]) -> Result<Algorithm, CK_RV> {
+    if params == b"\x13\x0cedwards25519" {
+        return Ok(Algorithm::EcEd25519);
+    }
+
+    let curves = [
+        Algorithm::EcP224,
+        Algorithm::EcP256,
+        Algorithm::EcP384,
+        Algorithm::EcP521,
+        Algorithm::EcK256,
+        Algorithm::EcBp256,
+        Algorithm::EcBp384,
+        Algorithm::EcBp512,
+        Algorithm::EcEd25519,
+    ];
+    curves
+        .iter()
+        .find(|&&curve| curve_oid(curve).ok() == Some(params))
+        .cloned()
+        .ok_or(CKR_ATTRIBUTE_VALUE_INVALID)
+}
+
+/// The length in bytes of a field element of the curve of an EC key.
+pub fn field_len(algorithm: Algorithm) -> Result<usize, CK_RV> {
+    match algorithm {
+        Algorithm::EcP224 => Ok(28),
+        Algorithm::EcP256 | Algorithm::EcK256 | Algorithm::EcBp256 => Ok(32),
+        Algorithm::EcP384 | Algorithm::EcBp384 => Ok(48),
+        Algorithm::EcBp512 => Ok(64),
+        Algorithm::EcP521 => Ok(66),
+        _ => Err(CKR_KEY_TYPE_INCONSISTENT),
+    }
+}
+
+/// The length in bytes of the modulus of an RSA key.
+pub fn rsa_modulus_len(algorithm: Algorithm) -> Result<CK_ULONG, CK_RV> {
+    match algorithm {
+        Algorithm::Rsa2048 => Ok(256),
+        Algorithm::Rsa3072 => Ok(384),
+        Algorithm::Rsa4096 => Ok(512),
+        _ => Err(CKR_KEY_TYPE_INCONSISTENT),
+    }
+}
+
+/// Wrap `data` in a DER OCTET STRING, as `CKA_EC_POINT` values are.
+pub fn octet_string(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x04];
+    if data.len() < 0x80 {
+        out.push(data.len() as u8);
+    } else {
+        // EC points are at most 133 bytes long.
+        out.extend_from_slice(&[0x81, data.len() as u8]);
+    }
+    out.extend_from_slice(data);
+    out
+}
@@ -0,0 +1,310 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The module's global state: a slot per configured connector, and the PKCS#11 sessions open on
+//! them.
+//!
+//! The device authenticates a connection rather than an application session, so logging in is
+//! per slot, as PKCS#11 expects: every PKCS#11 session on a slot shares the slot's `Session`, which
+//! is closed when the last of them is.
+//!
+//! The module lock is only held to look at or change this state. Talking to a device happens
+//! outside it, holding only the slot's own lock (see `with_device`), so a slow or hung connector
+//! only holds up its own slot.
+
+use config::SlotConfig;
+use libyubihsm::{Connector, PublicKey, Session, Yubihsm};
+use mechanisms::{DecryptMechanism, SignMechanism};
+use types::*;
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+static MODULE: Mutex<Option<Module>> = Mutex::new(None);
+
+pub struct Module {
+    yubihsm: Yubihsm,
+    slots: Vec<Slot>,
+    sessions: BTreeMap<CK_SESSION_HANDLE, P11Session>,
+    next_session: CK_SESSION_HANDLE,
+}
+
+pub struct Slot {
+    pub config: SlotConfig,
+    connector: Option<Connector>,
+    session: Option<Device>,
+}
+
+/// A slot's logged-in device session. Its commands are serialized by its own lock, since the
+/// session's secure channel carries one command at a time.
+pub type Device = Arc<Mutex<Session>>;
+
+pub struct P11Session {
+    pub slot_id: CK_SLOT_ID,
+    pub flags: CK_FLAGS,
+    pub find: Option<Vec<CK_OBJECT_HANDLE>>,
+    pub sign: Option<SignOperation>,
+    pub decrypt: Option<DecryptOperation>,
+}
+
+pub struct SignOperation {
+    pub key_id: u16,
+    pub public_key: PublicKey,
+    pub mechanism: SignMechanism,
+    /// The input accumulated by `C_SignUpdate`.
+    pub data: Vec<u8>,
+}
+
+pub struct DecryptOperation {
+    pub key_id: u16,
+    pub mechanism: DecryptMechanism,
+    /// The plaintext of a decryption whose output buffer was too small, kept for the retry since
+    /// the length can't be known without decrypting.
+    pub plaintext: Option<Vec<u8>>,
+}
+
+/// Lock the module. A panic while it was locked leaves at worst an operation or search dropped, so
+/// the lock is taken even if it's poisoned rather than failing every later call.
+fn lock() -> MutexGuard<'static, Option<Module>> {
+    MODULE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn initialize(slots: Vec<SlotConfig>) -> Result<(), CK_RV> {
+    let mut module = lock();
+    if module.is_some() {
+        return Err(CKR_CRYPTOKI_ALREADY_INITIALIZED);
+    }
+
+    let yubihsm = Yubihsm::new().map_err(|_| CKR_GENERAL_ERROR)?;
+    *module = Some(Module {
+        yubihsm,
+        slots: slots
+            .into_iter()
+            .map(|config| Slot {
+                config,
+                connector: None,
+                session: None,
+            })
+            .collect(),
+        sessions: BTreeMap::new(),
+        next_session: 1,
+    });
+
+    Ok(())
+}
+
+pub fn finalize() -> Result<(), CK_RV> {
+    let mut module = lock();
+    module.take().ok_or(CKR_CRYPTOKI_NOT_INITIALIZED)?;
+    Ok(())
+}
+
+/// Run `f` with the module locked. `f` mustn't talk to the device; take what it needs out of the
+/// module and use `with_device` once the lock is released.
+pub fn with_module<T, F>(f: F) -> Result<T, CK_RV>
+where
+    F: FnOnce(&mut Module) -> Result<T, CK_RV>,
+{
+    f(lock().as_mut().ok_or(CKR_CRYPTOKI_NOT_INITIALIZED)?)
+}
+
+/// Run `f` with a slot's device session, waiting for any other command on the slot to finish.
+pub fn with_device<T, F>(device: &Device, f: F) -> Result<T, CK_RV>
+where
+    F: FnOnce(&Session) -> Result<T, CK_RV>,
+{
+    f(&device.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// The slot's connector, connecting on first use.
+pub fn connector(slot_id: CK_SLOT_ID) -> Result<Connector, CK_RV> {
+    let (yubihsm, connector, config) = with_module(|module| {
+        let slot = module.slot(slot_id)?;
+        Ok((module.yubihsm, slot.connector.clone(), slot.config.clone()))
+    })?;
+    if let Some(connector) = connector {
+        return Ok(connector);
+    }
+
+    let mut builder = yubihsm.connector();
+    if let Some(cacert) = config.cacert {
+        builder = builder.with_https_ca(cacert);
+    }
+    if let Some(proxy) = config.proxy {
+        builder = builder.with_proxy_server(proxy);
+    }
+    let connector = builder
+        .connect(&config.connector)
+        .map_err(|_| CKR_TOKEN_NOT_PRESENT)?;
+
+    with_module(|module| {
+        let slot = module
+            .slots
+            .get_mut(slot_id as usize)
+            .ok_or(CKR_SLOT_ID_INVALID)?;
+        // Another thread may have connected in the meantime.
+        Ok(slot.connector.get_or_insert(connector).clone())
+    })
+}
+
+/// Log the session's slot in with `pin`.
+pub fn login(handle: CK_SESSION_HANDLE, pin: &[u8]) -> Result<(), CK_RV> {
+    let slot_id = with_module(|module| module.logged_out_slot(handle))?;
+    let (auth_key_id, password) = parse_pin(pin)?;
+    let session = connector(slot_id)?
+        .create_session_from_password(auth_key_id, password, true)
+        .map_err(|_| CKR_PIN_INCORRECT)?;
+
+    with_module(|module| {
+        // Another thread may have logged the slot in, or closed the session, in the meantime.
+        let slot_id = module.logged_out_slot(handle)?;
+        module.slots[slot_id as usize].session = Some(Arc::new(Mutex::new(session)));
+        Ok(())
+    })
+}
+
+/// Parse a PIN: the AuthKey's ID as four hex digits, followed by its password.
+pub fn parse_pin(pin: &[u8]) -> Result<(u16, &str), CK_RV> {
+    let pin = ::std::str::from_utf8(pin).map_err(|_| CKR_PIN_INCORRECT)?;
+    if pin.len() < 5 || !pin.is_char_boundary(4) {
+        return Err(CKR_PIN_LEN_RANGE);
+    }
+
+    let auth_key_id = u16::from_str_radix(&pin[..4], 16).map_err(|_| CKR_PIN_INCORRECT)?;
+    Ok((auth_key_id, &pin[4..]))
+}
+
+impl Module {
+    pub fn slot_count(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn slot(&self, slot_id: CK_SLOT_ID) -> Result<&Slot, CK_RV> {
+        self.slots.get(slot_id as usize).ok_or(CKR_SLOT_ID_INVALID)
+    }
+
+    /// The number of sessions open on the slot, and how many of them are read/write.
+    pub fn session_count(&self, slot_id: CK_SLOT_ID) -> (usize, usize) {
+        let sessions = self.sessions.values().filter(|s| s.slot_id == slot_id);
+        let rw = sessions
+            .clone()
+            .filter(|s| s.flags & CKF_RW_SESSION != 0)
+            .count();
+        (sessions.count(), rw)
+    }
+
+    /// Open a session on a slot whose connector is already connected; see `connector`.
+    pub fn open_session(
+        &mut self,
+        slot_id: CK_SLOT_ID,
+        flags: CK_FLAGS,
+    ) -> Result<CK_SESSION_HANDLE, CK_RV> {
+        self.slot(slot_id)?;
+
+        let handle = self.next_session;
+        self.next_session += 1;
+        self.sessions.insert(
+            handle,
+            P11Session {
+                slot_id,
+                flags,
+                find: None,
+                sign: None,
+                decrypt: None,
+            },
+        );
+
+        Ok(handle)
+    }
+
+    pub fn close_session(&mut self, handle: CK_SESSION_HANDLE) -> Result<(), CK_RV> {
+        let session = self
+            .sessions
+            .remove(&handle)
+            .ok_or(CKR_SESSION_HANDLE_INVALID)?;
+
+        if self.session_count(session.slot_id).0 == 0 {
+            self.slots[session.slot_id as usize].session = None;
+        }
+
+        Ok(())
+    }
+
+    pub fn close_all_sessions(&mut self, slot_id: CK_SLOT_ID) -> Result<(), CK_RV> {
+        self.slot(slot_id)?;
+        self.sessions
+            .retain(|_, session| session.slot_id != slot_id);
+        self.slots[slot_id as usize].session = None;
+        Ok(())
+    }
+
+    pub fn session(&mut self, handle: CK_SESSION_HANDLE) -> Result<&mut P11Session, CK_RV> {
+        self.sessions
+            .get_mut(&handle)
+            .ok_or(CKR_SESSION_HANDLE_INVALID)
+    }
+
+    /// Whether the session's slot is logged in.
+    pub fn is_logged_in(&self, handle: CK_SESSION_HANDLE) -> Result<bool, CK_RV> {
+        let session = self
+            .sessions
+            .get(&handle)
+            .ok_or(CKR_SESSION_HANDLE_INVALID)?;
+        Ok(self.slots[session.slot_id as usize].session.is_some())
+    }
+
+    /// The session, and the device session of its slot, which must be logged in.
+    pub fn device_session(
+        &mut self,
+        handle: CK_SESSION_HANDLE,
+    ) -> Result<(&mut P11Session, Device), CK_RV> {
+        let session = self
+            .sessions
+            .get_mut(&handle)
+            .ok_or(CKR_SESSION_HANDLE_INVALID)?;
+        let device = self.slots[session.slot_id as usize]
+            .session
+            .clone()
+            .ok_or(CKR_USER_NOT_LOGGED_IN)?;
+        Ok((session, device))
+    }
+
+    /// The session's slot, which must not be logged in.
+    fn logged_out_slot(&mut self, handle: CK_SESSION_HANDLE) -> Result<CK_SLOT_ID, CK_RV> {
+        let slot_id = self.session(handle)?.slot_id;
+        if self.slots[slot_id as usize].session.is_some() {
+            return Err(CKR_USER_ALREADY_LOGGED_IN);
+        }
+        Ok(slot_id)
+    }
+
+    pub fn logout(&mut self, handle: CK_SESSION_HANDLE) -> Result<(), CK_RV> {
+        let slot_id = self.session(handle)?.slot_id;
+        self.slots[slot_id as usize]
+            .session
+            .take()
+            .ok_or(CKR_USER_NOT_LOGGED_IN)?;
+
+        // Operations started while logged in can't continue without the device session.
+        for session in self.sessions.values_mut() {
+            if session.slot_id == slot_id {
+                session.find = None;
+                session.sign = None;
+                session.decrypt = None;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use config::{self, SlotConfig};
+use libyubihsm::Algorithm;
+use objects::{self, ObjectHandle, ObjectKind};
+use state;
+use types::*;
+
+use std::panic;
+use std::ptr;
+
+#[test]
+fn object_handles() {
+    for &kind in &[
+        ObjectKind::PrivateKey,
+        ObjectKind::PublicKey,
+        ObjectKind::Certificate,
+        ObjectKind::Data,
+    ] {
+        let handle = ObjectHandle { kind, id: 0x1234 };
+        assert_eq!(ObjectHandle::from_handle(handle.to_handle()), Ok(handle));
+    }
+
+    assert!(ObjectHandle::from_handle(CK_INVALID_HANDLE).is_err());
+    assert!(ObjectHandle::from_handle(0x0009_0001).is_err());
+}
+
+#[test]
+fn attribute_encodings() {
+    assert_eq!(
+        objects::parse_ulong(&objects::ulong(CKO_PRIVATE_KEY)),
+        Ok(CKO_PRIVATE_KEY)
+    );
+    assert!(objects::parse_ulong(&[1, 2, 3]).is_err());
+    assert_eq!(objects::parse_boolean(&objects::boolean(true)), Ok(true));
+    assert_eq!(objects::parse_boolean(&[CK_FALSE]), Ok(false));
+
+    for &algorithm in &[
+        Algorithm::EcP256,
+        Algorithm::EcP384,
+        Algorithm::EcP521,
+        Algorithm::EcK256,
+        Algorithm::EcEd25519,
+    ] {
+        let params = objects::curve_oid(algorithm).unwrap();
+        assert_eq!(objects::curve_from_params(params), Ok(algorithm));
+    }
+    assert_eq!(
+        objects::curve_from_params(b"\x13\x0cedwards25519"),
+        Ok(Algorithm::EcEd25519)
+    );
+
+    assert_eq!(
+        objects::octet_string(&[0xaa; 3]),
+        vec![0x04, 0x03, 0xaa, 0xaa, 0xaa]
+    );
+    assert_eq!(&objects::octet_string(&[0; 200])[..3], &[0x04, 0x81, 200]);
+}
+
+#[test]
+fn module_config() {
+    let slots = config::parse(
+        "# two connectors\n\
+         connector = http://127.0.0.1:12345\n\
+         connector=https://hsm.example.com:8443\n\
+         cacert = /etc/ssl/hsm.pem\n\
+         debug\n\
+         timeout = 5\n",
+    );
+    assert_eq!(
+        slots,
+        vec![
+            SlotConfig {
+                connector: "http://127.0.0.1:12345".to_string(),
+                cacert: Some("/etc/ssl/hsm.pem".to_string()),
+                proxy: None,
+            },
+            SlotConfig {
+                connector: "https://hsm.example.com:8443".to_string(),
+                cacert: Some("/etc/ssl/hsm.pem".to_string()),
+                proxy: None,
+            },
+        ]
+    );
+
+    assert!(config::parse("# no connectors\n").is_empty());
+
+    assert_eq!(state::parse_pin(b"0001password"), Ok((1, "password")));
+    assert_eq!(state::parse_pin(b"00a2x"), Ok((0xa2, "x")));
+    assert_eq!(state::parse_pin(b"0001"), Err(CKR_PIN_LEN_RANGE));
+    assert_eq!(state::parse_pin(b"zz01password"), Err(CKR_PIN_INCORRECT));
+}
+
+#[test]
+fn function_list() {
+    unsafe {
+        let mut list = ptr::null();
+        assert_eq!(::C_GetFunctionList(&mut list), CKR_OK);
+        let list = &*list;
+        assert_eq!((list.version.major, list.version.minor), (2, 40));
+
+        // Nothing works before C_Initialize.
+        let mut count = 0;
+        let get_slot_list = list.C_GetSlotList.unwrap();
+        assert_eq!(
+            get_slot_list(CK_TRUE, ptr::null_mut(), &mut count),
+            CKR_CRYPTOKI_NOT_INITIALIZED
+        );
+
+        let mut handle = 0;
+        assert_eq!(
+            (list.C_OpenSession.unwrap())(0, CKF_RW_SESSION, ptr::null_mut(), None, &mut handle),
+            CKR_SESSION_PARALLEL_NOT_SUPPORTED
+        );
+        assert_eq!(
+            (list.C_DigestInit.unwrap())(1, ptr::null_mut()),
+            CKR_FUNCTION_NOT_SUPPORTED
+        );
+
+        // A panic with the module locked doesn't break later calls.
+        state::initialize(vec![]).unwrap();
+        assert!(panic::catch_unwind(|| state::with_module::<(), _>(|_| panic!())).is_err());
+        assert_eq!(get_slot_list(CK_TRUE, ptr::null_mut(), &mut count), CKR_OK);
+        assert_eq!(count, 0);
+        assert_eq!((list.C_Finalize.unwrap())(ptr::null_mut()), CKR_OK);
+    }
+}
@@ -0,0 +1,648 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The subset of the PKCS#11 v2.40 types and constants (from `pkcs11t.h` and `pkcs11f.h`) used by
+//! the module, with the specification's names. Structures use the platform's default packing, as
+//! on every Unix platform; Windows' 1-byte packing is not supported.
+
+#![allow(non_camel_case_types, non_snake_case, dead_code)]
+
+use std::os::raw::{c_uchar, c_ulong, c_void};
+
+pub type CK_BYTE = c_uchar;
+pub type CK_CHAR = CK_BYTE;
+pub type CK_UTF8CHAR = CK_BYTE;
+pub type CK_BBOOL = CK_BYTE;
+pub type CK_ULONG = c_ulong;
+pub type CK_FLAGS = CK_ULONG;
+pub type CK_RV = CK_ULONG;
+pub type CK_SLOT_ID = CK_ULONG;
+pub type CK_SESSION_HANDLE = CK_ULONG;
+pub type CK_OBJECT_HANDLE = CK_ULONG;
+pub type CK_OBJECT_CLASS = CK_ULONG;
+pub type CK_KEY_TYPE = CK_ULONG;
+pub type CK_CERTIFICATE_TYPE = CK_ULONG;
+pub type CK_ATTRIBUTE_TYPE = CK_ULONG;
+pub type CK_MECHANISM_TYPE = CK_ULONG;
+pub type CK_USER_TYPE = CK_ULONG;
+pub type CK_STATE = CK_ULONG;
+pub type CK_NOTIFICATION = CK_ULONG;
+pub type CK_RSA_PKCS_MGF_TYPE = CK_ULONG;
+pub type CK_RSA_PKCS_OAEP_SOURCE_TYPE = CK_ULONG;
+
+pub type CK_VOID_PTR = *mut c_void;
+pub type CK_BYTE_PTR = *mut CK_BYTE;
+pub type CK_UTF8CHAR_PTR = *mut CK_UTF8CHAR;
+pub type CK_ULONG_PTR = *mut CK_ULONG;
+pub type CK_SLOT_ID_PTR = *mut CK_SLOT_ID;
+pub type CK_SESSION_HANDLE_PTR = *mut CK_SESSION_HANDLE;
+pub type CK_OBJECT_HANDLE_PTR = *mut CK_OBJECT_HANDLE;
+pub type CK_MECHANISM_TYPE_PTR = *mut CK_MECHANISM_TYPE;
+pub type CK_ATTRIBUTE_PTR = *mut CK_ATTRIBUTE;
+pub type CK_MECHANISM_PTR = *mut CK_MECHANISM;
+pub type CK_NOTIFY =
+    Option<unsafe extern "C" fn(CK_SESSION_HANDLE, CK_NOTIFICATION, CK_VOID_PTR) -> CK_RV>;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CK_VERSION {
+    pub major: CK_BYTE,
+    pub minor: CK_BYTE,
+}
+
+#[repr(C)]
+pub struct CK_INFO {
+    pub cryptokiVersion: CK_VERSION,
+    pub manufacturerID: [CK_UTF8CHAR; 32],
+    pub flags: CK_FLAGS,
+    pub libraryDescription: [CK_UTF8CHAR; 32],
+    pub libraryVersion: CK_VERSION,
+}
+
+#[repr(C)]
+pub struct CK_SLOT_INFO {
+    pub slotDescription: [CK_UTF8CHAR; 64],
+    pub manufacturerID: [CK_UTF8CHAR; 32],
+    pub flags: CK_FLAGS,
+    pub hardwareVersion: CK_VERSION,
+    pub firmwareVersion: CK_VERSION,
+}
+
+#[repr(C)]
+pub struct CK_TOKEN_INFO {
+    pub label: [CK_UTF8CHAR; 32],
+    pub manufacturerID: [CK_UTF8CHAR; 32],
+    pub model: [CK_UTF8CHAR; 16],
+    pub serialNumber: [CK_CHAR; 16],
+    pub flags: CK_FLAGS,
+    pub ulMaxSessionCount: CK_ULONG,
+    pub ulSessionCount: CK_ULONG,
+    pub ulMaxRwSessionCount: CK_ULONG,
+    pub ulRwSessionCount: CK_ULONG,
+    pub ulMaxPinLen: CK_ULONG,
+    pub ulMinPinLen: CK_ULONG,
+    pub ulTotalPublicMemory: CK_ULONG,
+    pub ulFreePublicMemory: CK_ULONG,
+    pub ulTotalPrivateMemory: CK_ULONG,
+    pub ulFreePrivateMemory: CK_ULONG,
+    pub hardwareVersion: CK_VERSION,
+    pub firmwareVersion: CK_VERSION,
+    pub utcTime: [CK_CHAR; 16],
+}
+
+#[repr(C)]
+pub struct CK_SESSION_INFO {
+    pub slotID: CK_SLOT_ID,
+    pub state: CK_STATE,
+    pub flags: CK_FLAGS,
+    pub ulDeviceError: CK_ULONG,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CK_ATTRIBUTE {
+    pub type_: CK_ATTRIBUTE_TYPE,
+    pub pValue: CK_VOID_PTR,
+    pub ulValueLen: CK_ULONG,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CK_MECHANISM {
+    pub mechanism: CK_MECHANISM_TYPE,
+    pub pParameter: CK_VOID_PTR,
+    pub ulParameterLen: CK_ULONG,
+}
+
+#[repr(C)]
+pub struct CK_MECHANISM_INFO {
+    pub ulMinKeySize: CK_ULONG,
+    pub ulMaxKeySize: CK_ULONG,
+    pub flags: CK_FLAGS,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CK_C_INITIALIZE_ARGS {
+    pub CreateMutex: CK_VOID_PTR,
+    pub DestroyMutex: CK_VOID_PTR,
+    pub LockMutex: CK_VOID_PTR,
+    pub UnlockMutex: CK_VOID_PTR,
+    pub flags: CK_FLAGS,
+    pub pReserved: CK_VOID_PTR,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CK_RSA_PKCS_PSS_PARAMS {
+    pub hashAlg: CK_MECHANISM_TYPE,
+    pub mgf: CK_RSA_PKCS_MGF_TYPE,
+    pub sLen: CK_ULONG,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CK_RSA_PKCS_OAEP_PARAMS {
+    pub hashAlg: CK_MECHANISM_TYPE,
+    pub mgf: CK_RSA_PKCS_MGF_TYPE,
+    pub source: CK_RSA_PKCS_OAEP_SOURCE_TYPE,
+    pub pSourceData: CK_VOID_PTR,
+    pub ulSourceDataLen: CK_ULONG,
+}
+
+pub const CK_TRUE: CK_BBOOL = 1;
+pub const CK_FALSE: CK_BBOOL = 0;
+pub const CK_UNAVAILABLE_INFORMATION: CK_ULONG = !0;
+pub const CK_EFFECTIVELY_INFINITE: CK_ULONG = 0;
+pub const CK_INVALID_HANDLE: CK_ULONG = 0;
+
+// Return values.
+pub const CKR_OK: CK_RV = 0x0;
+pub const CKR_HOST_MEMORY: CK_RV = 0x2;
+pub const CKR_SLOT_ID_INVALID: CK_RV = 0x3;
+pub const CKR_GENERAL_ERROR: CK_RV = 0x5;
+pub const CKR_FUNCTION_FAILED: CK_RV = 0x6;
+pub const CKR_ARGUMENTS_BAD: CK_RV = 0x7;
+pub const CKR_CANT_LOCK: CK_RV = 0xa;
+pub const CKR_ATTRIBUTE_SENSITIVE: CK_RV = 0x11;
+pub const CKR_ATTRIBUTE_TYPE_INVALID: CK_RV = 0x12;
+pub const CKR_ATTRIBUTE_VALUE_INVALID: CK_RV = 0x13;
+pub const CKR_ACTION_PROHIBITED: CK_RV = 0x1b;
+pub const CKR_DATA_LEN_RANGE: CK_RV = 0x21;
+pub const CKR_DEVICE_ERROR: CK_RV = 0x30;
+pub const CKR_ENCRYPTED_DATA_INVALID: CK_RV = 0x40;
+pub const CKR_FUNCTION_NOT_SUPPORTED: CK_RV = 0x54;
+pub const CKR_KEY_HANDLE_INVALID: CK_RV = 0x60;
+pub const CKR_KEY_TYPE_INCONSISTENT: CK_RV = 0x63;
+pub const CKR_KEY_FUNCTION_NOT_PERMITTED: CK_RV = 0x68;
+pub const CKR_MECHANISM_INVALID: CK_RV = 0x70;
+pub const CKR_MECHANISM_PARAM_INVALID: CK_RV = 0x71;
+pub const CKR_OBJECT_HANDLE_INVALID: CK_RV = 0x82;
+pub const CKR_OPERATION_ACTIVE: CK_RV = 0x90;
+pub const CKR_OPERATION_NOT_INITIALIZED: CK_RV = 0x91;
+pub const CKR_PIN_INCORRECT: CK_RV = 0xa0;
+pub const CKR_PIN_LEN_RANGE: CK_RV = 0xa2;
+pub const CKR_SESSION_HANDLE_INVALID: CK_RV = 0xb3;
+pub const CKR_SESSION_PARALLEL_NOT_SUPPORTED: CK_RV = 0xb4;
+pub const CKR_SESSION_READ_ONLY: CK_RV = 0xb5;
+pub const CKR_TEMPLATE_INCOMPLETE: CK_RV = 0xd0;
+pub const CKR_TEMPLATE_INCONSISTENT: CK_RV = 0xd1;
+pub const CKR_TOKEN_NOT_PRESENT: CK_RV = 0xe0;
+pub const CKR_USER_ALREADY_LOGGED_IN: CK_RV = 0x100;
+pub const CKR_USER_NOT_LOGGED_IN: CK_RV = 0x101;
+pub const CKR_USER_TYPE_INVALID: CK_RV = 0x103;
+pub const CKR_RANDOM_SEED_NOT_SUPPORTED: CK_RV = 0x120;
+pub const CKR_BUFFER_TOO_SMALL: CK_RV = 0x150;
+pub const CKR_CRYPTOKI_NOT_INITIALIZED: CK_RV = 0x190;
+pub const CKR_CRYPTOKI_ALREADY_INITIALIZED: CK_RV = 0x191;
+
+// Flags for CK_C_INITIALIZE_ARGS.
+pub const CKF_LIBRARY_CANT_CREATE_OS_THREADS: CK_FLAGS = 0x1;
+pub const CKF_OS_LOCKING_OK: CK_FLAGS = 0x2;
+
+// Slot flags.
+pub const CKF_TOKEN_PRESENT: CK_FLAGS = 0x1;
+pub const CKF_REMOVABLE_DEVICE: CK_FLAGS = 0x2;
+pub const CKF_HW_SLOT: CK_FLAGS = 0x4;
+
+// Token flags.
+pub const CKF_RNG: CK_FLAGS = 0x1;
+pub const CKF_LOGIN_REQUIRED: CK_FLAGS = 0x4;
+pub const CKF_USER_PIN_INITIALIZED: CK_FLAGS = 0x8;
+pub const CKF_TOKEN_INITIALIZED: CK_FLAGS = 0x400;
+
+// Session flags.
+pub const CKF_RW_SESSION: CK_FLAGS = 0x2;
+pub const CKF_SERIAL_SESSION: CK_FLAGS = 0x4;
+
+// Mechanism flags.
+pub const CKF_HW: CK_FLAGS = 0x1;
+pub const CKF_ENCRYPT: CK_FLAGS = 0x100;
+pub const CKF_DECRYPT: CK_FLAGS = 0x200;
+pub const CKF_SIGN: CK_FLAGS = 0x800;
+pub const CKF_VERIFY: CK_FLAGS = 0x2000;
+pub const CKF_GENERATE_KEY_PAIR: CK_FLAGS = 0x10000;
+pub const CKF_EC_F_P: CK_FLAGS = 0x100000;
+pub const CKF_EC_NAMEDCURVE: CK_FLAGS = 0x800000;
+pub const CKF_EC_UNCOMPRESS: CK_FLAGS = 0x1000000;
+
+// User types.
+pub const CKU_SO: CK_USER_TYPE = 0;
+pub const CKU_USER: CK_USER_TYPE = 1;
+
+// Session states.
+pub const CKS_RO_PUBLIC_SESSION: CK_STATE = 0;
+pub const CKS_RO_USER_FUNCTIONS: CK_STATE = 1;
+pub const CKS_RW_PUBLIC_SESSION: CK_STATE = 2;
+pub const CKS_RW_USER_FUNCTIONS: CK_STATE = 3;
+
+// Object classes.
+pub const CKO_DATA: CK_OBJECT_CLASS = 0x0;
+pub const CKO_CERTIFICATE: CK_OBJECT_CLASS = 0x1;
+pub const CKO_PUBLIC_KEY: CK_OBJECT_CLASS = 0x2;
+pub const CKO_PRIVATE_KEY: CK_OBJECT_CLASS = 0x3;
+
+// Key and certificate types.
+pub const CKK_RSA: CK_KEY_TYPE = 0x0;
+pub const CKK_EC: CK_KEY_TYPE = 0x3;
+pub const CKK_EC_EDWARDS: CK_KEY_TYPE = 0x40;
+pub const CKC_X_509: CK_CERTIFICATE_TYPE = 0x0;
+
+// Attributes.
+pub const CKA_CLASS: CK_ATTRIBUTE_TYPE = 0x0;
+pub const CKA_TOKEN: CK_ATTRIBUTE_TYPE = 0x1;
+pub const CKA_PRIVATE: CK_ATTRIBUTE_TYPE = 0x2;
+pub const CKA_LABEL: CK_ATTRIBUTE_TYPE = 0x3;
+pub const CKA_APPLICATION: CK_ATTRIBUTE_TYPE = 0x10;
+pub const CKA_VALUE: CK_ATTRIBUTE_TYPE = 0x11;
+pub const CKA_CERTIFICATE_TYPE: CK_ATTRIBUTE_TYPE = 0x80;
+pub const CKA_KEY_TYPE: CK_ATTRIBUTE_TYPE = 0x100;
+pub const CKA_ID: CK_ATTRIBUTE_TYPE = 0x102;
+pub const CKA_SENSITIVE: CK_ATTRIBUTE_TYPE = 0x103;
+pub const CKA_ENCRYPT: CK_ATTRIBUTE_TYPE = 0x104;
+pub const CKA_DECRYPT: CK_ATTRIBUTE_TYPE = 0x105;
+pub const CKA_WRAP: CK_ATTRIBUTE_TYPE = 0x106;
+pub const CKA_UNWRAP: CK_ATTRIBUTE_TYPE = 0x107;
+pub const CKA_SIGN: CK_ATTRIBUTE_TYPE = 0x108;
+pub const CKA_SIGN_RECOVER: CK_ATTRIBUTE_TYPE = 0x109;
+pub const CKA_VERIFY: CK_ATTRIBUTE_TYPE = 0x10a;
+pub const CKA_VERIFY_RECOVER: CK_ATTRIBUTE_TYPE = 0x10b;
+pub const CKA_DERIVE: CK_ATTRIBUTE_TYPE = 0x10c;
+pub const CKA_MODULUS: CK_ATTRIBUTE_TYPE = 0x120;
+pub const CKA_MODULUS_BITS: CK_ATTRIBUTE_TYPE = 0x121;
+pub const CKA_PUBLIC_EXPONENT: CK_ATTRIBUTE_TYPE = 0x122;
+pub const CKA_PRIVATE_EXPONENT: CK_ATTRIBUTE_TYPE = 0x123;
+pub const CKA_EXTRACTABLE: CK_ATTRIBUTE_TYPE = 0x162;
+pub const CKA_LOCAL: CK_ATTRIBUTE_TYPE = 0x163;
+pub const CKA_NEVER_EXTRACTABLE: CK_ATTRIBUTE_TYPE = 0x164;
+pub const CKA_ALWAYS_SENSITIVE: CK_ATTRIBUTE_TYPE = 0x165;
+pub const CKA_MODIFIABLE: CK_ATTRIBUTE_TYPE = 0x170;
+pub const CKA_EC_PARAMS: CK_ATTRIBUTE_TYPE = 0x180;
+pub const CKA_EC_POINT: CK_ATTRIBUTE_TYPE = 0x181;
+pub const CKA_ALWAYS_AUTHENTICATE: CK_ATTRIBUTE_TYPE = 0x202;
+
+// Mechanisms.
+pub const CKM_RSA_PKCS_KEY_PAIR_GEN: CK_MECHANISM_TYPE = 0x0;
+pub const CKM_RSA_PKCS: CK_MECHANISM_TYPE = 0x1;
+pub const CKM_SHA1_RSA_PKCS: CK_MECHANISM_TYPE = 0x6;
+pub const CKM_RSA_PKCS_OAEP: CK_MECHANISM_TYPE = 0x9;
+pub const CKM_RSA_PKCS_PSS: CK_MECHANISM_TYPE = 0xd;
+pub const CKM_SHA1_RSA_PKCS_PSS: CK_MECHANISM_TYPE = 0xe;
+pub const CKM_SHA256_RSA_PKCS: CK_MECHANISM_TYPE = 0x40;
+pub const CKM_SHA384_RSA_PKCS: CK_MECHANISM_TYPE = 0x41;
+pub const CKM_SHA512_RSA_PKCS: CK_MECHANISM_TYPE = 0x42;
+pub const CKM_SHA256_RSA_PKCS_PSS: CK_MECHANISM_TYPE = 0x43;
+pub const CKM_SHA384_RSA_PKCS_PSS: CK_MECHANISM_TYPE = 0x44;
+pub const CKM_SHA512_RSA_PKCS_PSS: CK_MECHANISM_TYPE = 0x45;
+pub const CKM_SHA_1: CK_MECHANISM_TYPE = 0x220;
+pub const CKM_SHA256: CK_MECHANISM_TYPE = 0x250;
+pub const CKM_SHA384: CK_MECHANISM_TYPE = 0x260;
+pub const CKM_SHA512: CK_MECHANISM_TYPE = 0x270;
+pub const CKM_EC_KEY_PAIR_GEN: CK_MECHANISM_TYPE = 0x1040;
+pub const CKM_ECDSA: CK_MECHANISM_TYPE = 0x1041;
+pub const CKM_ECDSA_SHA1: CK_MECHANISM_TYPE = 0x1042;
+pub const CKM_ECDSA_SHA256: CK_MECHANISM_TYPE = 0x1044;
+pub const CKM_ECDSA_SHA384: CK_MECHANISM_TYPE = 0x1045;
+pub const CKM_ECDSA_SHA512: CK_MECHANISM_TYPE = 0x1046;
+pub const CKM_EC_EDWARDS_KEY_PAIR_GEN: CK_MECHANISM_TYPE = 0x1055;
+pub const CKM_EDDSA: CK_MECHANISM_TYPE = 0x1057;
+
+// MGF1 functions and OAEP label sources.
+pub const CKG_MGF1_SHA1: CK_RSA_PKCS_MGF_TYPE = 0x1;
+pub const CKG_MGF1_SHA256: CK_RSA_PKCS_MGF_TYPE = 0x2;
+pub const CKG_MGF1_SHA384: CK_RSA_PKCS_MGF_TYPE = 0x3;
+pub const CKG_MGF1_SHA512: CK_RSA_PKCS_MGF_TYPE = 0x4;
+pub const CKZ_DATA_SPECIFIED: CK_RSA_PKCS_OAEP_SOURCE_TYPE = 0x1;
+
+/// The PKCS#11 function list, in the order defined by `pkcs11f.h`.
+#[repr(C)]
+pub struct CK_FUNCTION_LIST {
+    pub version: CK_VERSION,
+    pub C_Initialize: Option<unsafe extern "C" fn(CK_VOID_PTR) -> CK_RV>,
+    pub C_Finalize: Option<unsafe extern "C" fn(CK_VOID_PTR) -> CK_RV>,
+    pub C_GetInfo: Option<unsafe extern "C" fn(*mut CK_INFO) -> CK_RV>,
+    pub C_GetFunctionList: Option<unsafe extern "C" fn(*mut *const CK_FUNCTION_LIST) -> CK_RV>,
+    pub C_GetSlotList:
+        Option<unsafe extern "C" fn(CK_BBOOL, CK_SLOT_ID_PTR, CK_ULONG_PTR) -> CK_RV>,
+    pub C_GetSlotInfo: Option<unsafe extern "C" fn(CK_SLOT_ID, *mut CK_SLOT_INFO) -> CK_RV>,
+    pub C_GetTokenInfo: Option<unsafe extern "C" fn(CK_SLOT_ID, *mut CK_TOKEN_INFO) -> CK_RV>,
+    pub C_GetMechanismList:
+        Option<unsafe extern "C" fn(CK_SLOT_ID, CK_MECHANISM_TYPE_PTR, CK_ULONG_PTR) -> CK_RV>,
+    pub C_GetMechanismInfo: Option<
+        unsafe extern "C" fn(CK_SLOT_ID, CK_MECHANISM_TYPE, *mut CK_MECHANISM_INFO) -> CK_RV,
+    >,
+    pub C_InitToken: Option<
+        unsafe extern "C" fn(CK_SLOT_ID, CK_UTF8CHAR_PTR, CK_ULONG, CK_UTF8CHAR_PTR) -> CK_RV,
+    >,
+    pub C_InitPIN:
+        Option<unsafe extern "C" fn(CK_SESSION_HANDLE, CK_UTF8CHAR_PTR, CK_ULONG) -> CK_RV>,
+    pub C_SetPIN: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_UTF8CHAR_PTR,
+            CK_ULONG,
+            CK_UTF8CHAR_PTR,
+            CK_ULONG,
+        ) -> CK_RV,
+    >,
+    pub C_OpenSession: Option<
+        unsafe extern "C" fn(
+            CK_SLOT_ID,
+            CK_FLAGS,
+            CK_VOID_PTR,
+            CK_NOTIFY,
+            CK_SESSION_HANDLE_PTR,
+        ) -> CK_RV,
+    >,
+    pub C_CloseSession: Option<unsafe extern "C" fn(CK_SESSION_HANDLE) -> CK_RV>,
+    pub C_CloseAllSessions: Option<unsafe extern "C" fn(CK_SLOT_ID) -> CK_RV>,
+    pub C_GetSessionInfo:
+        Option<unsafe extern "C" fn(CK_SESSION_HANDLE, *mut CK_SESSION_INFO) -> CK_RV>,
+    pub C_GetOperationState:
+        Option<unsafe extern "C" fn(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG_PTR) -> CK_RV>,
+    pub C_SetOperationState: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_BYTE_PTR,
+            CK_ULONG,
+            CK_OBJECT_HANDLE,
+            CK_OBJECT_HANDLE,
+        ) -> CK_RV,
+    >,
+    pub C_Login: Option<
+        unsafe extern "C" fn(CK_SESSION_HANDLE, CK_USER_TYPE, CK_UTF8CHAR_PTR, CK_ULONG) -> CK_RV,
+    >,
+    pub C_Logout: Option<unsafe extern "C" fn(CK_SESSION_HANDLE) -> CK_RV>,
+    pub C_CreateObject: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_ATTRIBUTE_PTR,
+            CK_ULONG,
+            CK_OBJECT_HANDLE_PTR,
+        ) -> CK_RV,
+    >,
+    pub C_CopyObject: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_OBJECT_HANDLE,
+            CK_ATTRIBUTE_PTR,
+            CK_ULONG,
+            CK_OBJECT_HANDLE_PTR,
+        ) -> CK_RV,
+    >,
+    pub C_DestroyObject: Option<unsafe extern "C" fn(CK_SESSION_HANDLE, CK_OBJECT_HANDLE) -> CK_RV>,
+    pub C_GetObjectSize:
+        Option<unsafe extern "C" fn(CK_SESSION_HANDLE, CK_OBJECT_HANDLE, CK_ULONG_PTR) -> CK_RV>,
+    pub C_GetAttributeValue: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_OBJECT_HANDLE,
+            CK_ATTRIBUTE_PTR,
+            CK_ULONG,
+        ) -> CK_RV,
+    >,
+    pub C_SetAttributeValue: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_OBJECT_HANDLE,
+            CK_ATTRIBUTE_PTR,
+            CK_ULONG,
+        ) -> CK_RV,
+    >,
+    pub C_FindObjectsInit:
+        Option<unsafe extern "C" fn(CK_SESSION_HANDLE, CK_ATTRIBUTE_PTR, CK_ULONG) -> CK_RV>,
+    pub C_FindObjects: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_OBJECT_HANDLE_PTR,
+            CK_ULONG,
+            CK_ULONG_PTR,
+        ) -> CK_RV,
+    >,
+    pub C_FindObjectsFinal: Option<unsafe extern "C" fn(CK_SESSION_HANDLE) -> CK_RV>,
+    pub C_EncryptInit: Option<
+        unsafe extern "C" fn(CK_SESSION_HANDLE, CK_MECHANISM_PTR, CK_OBJECT_HANDLE) -> CK_RV,
+    >,
+    pub C_Encrypt: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_BYTE_PTR,
+            CK_ULONG,
+            CK_BYTE_PTR,
+            CK_ULONG_PTR,
+        ) -> CK_RV,
+    >,
+    pub C_EncryptUpdate: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_BYTE_PTR,
+            CK_ULONG,
+            CK_BYTE_PTR,
+            CK_ULONG_PTR,
+        ) -> CK_RV,
+    >,
+    pub C_EncryptFinal:
+        Option<unsafe extern "C" fn(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG_PTR) -> CK_RV>,
+    pub C_DecryptInit: Option<
+        unsafe extern "C" fn(CK_SESSION_HANDLE, CK_MECHANISM_PTR, CK_OBJECT_HANDLE) -> CK_RV,
+    >,
+    pub C_Decrypt: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_BYTE_PTR,
+            CK_ULONG,
+            CK_BYTE_PTR,
+            CK_ULONG_PTR,
+        ) -> CK_RV,
+    >,
+    pub C_DecryptUpdate: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_BYTE_PTR,
+            CK_ULONG,
+            CK_BYTE_PTR,
+            CK_ULONG_PTR,
+        ) -> CK_RV,
+    >,
+    pub C_DecryptFinal:
+        Option<unsafe extern "C" fn(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG_PTR) -> CK_RV>,
+    pub C_DigestInit: Option<unsafe extern "C" fn(CK_SESSION_HANDLE, CK_MECHANISM_PTR) -> CK_RV>,
+    pub C_Digest: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_BYTE_PTR,
+            CK_ULONG,
+            CK_BYTE_PTR,
+            CK_ULONG_PTR,
+        ) -> CK_RV,
+    >,
+    pub C_DigestUpdate:
+        Option<unsafe extern "C" fn(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG) -> CK_RV>,
+    pub C_DigestKey: Option<unsafe extern "C" fn(CK_SESSION_HANDLE, CK_OBJECT_HANDLE) -> CK_RV>,
+    pub C_DigestFinal:
+        Option<unsafe extern "C" fn(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG_PTR) -> CK_RV>,
+    pub C_SignInit: Option<
+        unsafe extern "C" fn(CK_SESSION_HANDLE, CK_MECHANISM_PTR, CK_OBJECT_HANDLE) -> CK_RV,
+    >,
+    pub C_Sign: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_BYTE_PTR,
+            CK_ULONG,
+            CK_BYTE_PTR,
+            CK_ULONG_PTR,
+        ) -> CK_RV,
+    >,
+    pub C_SignUpdate:
+        Option<unsafe extern "C" fn(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG) -> CK_RV>,
+    pub C_SignFinal:
+        Option<unsafe extern "C" fn(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG_PTR) -> CK_RV>,
+    pub C_SignRecoverInit: Option<
+        unsafe extern "C" fn(CK_SESSION_HANDLE, CK_MECHANISM_PTR, CK_OBJECT_HANDLE) -> CK_RV,
+    >,
+    pub C_SignRecover: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_BYTE_PTR,
+            CK_ULONG,
+            CK_BYTE_PTR,
+            CK_ULONG_PTR,
+        ) -> CK_RV,
+    >,
+    pub C_VerifyInit: Option<
+        unsafe extern "C" fn(CK_SESSION_HANDLE, CK_MECHANISM_PTR, CK_OBJECT_HANDLE) -> CK_RV,
+    >,
+    pub C_Verify: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_BYTE_PTR,
+            CK_ULONG,
+            CK_BYTE_PTR,
+            CK_ULONG,
+        ) -> CK_RV,
+    >,
+    pub C_VerifyUpdate:
+        Option<unsafe extern "C" fn(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG) -> CK_RV>,
+    pub C_VerifyFinal:
+        Option<unsafe extern "C" fn(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG) -> CK_RV>,
+    pub C_VerifyRecoverInit: Option<
+        unsafe extern "C" fn(CK_SESSION_HANDLE, CK_MECHANISM_PTR, CK_OBJECT_HANDLE) -> CK_RV,
+    >,
+    pub C_VerifyRecover: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_BYTE_PTR,
+            CK_ULONG,
+            CK_BYTE_PTR,
+            CK_ULONG_PTR,
+        ) -> CK_RV,
+    >,
+    pub C_DigestEncryptUpdate: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_BYTE_PTR,
+            CK_ULONG,
+            CK_BYTE_PTR,
+            CK_ULONG_PTR,
+        ) -> CK_RV,
+    >,
+    pub C_DecryptDigestUpdate: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_BYTE_PTR,
+            CK_ULONG,
+            CK_BYTE_PTR,
+            CK_ULONG_PTR,
+        ) -> CK_RV,
+    >,
+    pub C_SignEncryptUpdate: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_BYTE_PTR,
+            CK_ULONG,
+            CK_BYTE_PTR,
+            CK_ULONG_PTR,
+        ) -> CK_RV,
+    >,
+    pub C_DecryptVerifyUpdate: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_BYTE_PTR,
+            CK_ULONG,
+            CK_BYTE_PTR,
+            CK_ULONG_PTR,
+        ) -> CK_RV,
+    >,
+    pub C_GenerateKey: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_MECHANISM_PTR,
+            CK_ATTRIBUTE_PTR,
+            CK_ULONG,
+            CK_OBJECT_HANDLE_PTR,
+        ) -> CK_RV,
+    >,
+    pub C_GenerateKeyPair: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_MECHANISM_PTR,
+            CK_ATTRIBUTE_PTR,
+            CK_ULONG,
+            CK_ATTRIBUTE_PTR,
+            CK_ULONG,
+            CK_OBJECT_HANDLE_PTR,
+            CK_OBJECT_HANDLE_PTR,
+        ) -> CK_RV,
+    >,
+    pub C_WrapKey: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_MECHANISM_PTR,
+            CK_OBJECT_HANDLE,
+            CK_OBJECT_HANDLE,
+            CK_BYTE_PTR,
+            CK_ULONG_PTR,
+        ) -> CK_RV,
+    >,
+    pub C_UnwrapKey: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_MECHANISM_PTR,
+            CK_OBJECT_HANDLE,
+            CK_BYTE_PTR,
+            CK_ULONG,
+            CK_ATTRIBUTE_PTR,
+            CK_ULONG,
+            CK_OBJECT_HANDLE_PTR,
+        ) -> CK_RV,
+    >,
+    pub C_DeriveKey: Option<
+        unsafe extern "C" fn(
+            CK_SESSION_HANDLE,
+            CK_MECHANISM_PTR,
+            CK_OBJECT_HANDLE,
+            CK_ATTRIBUTE_PTR,
+            CK_ULONG,
+            CK_OBJECT_HANDLE_PTR,
+        ) -> CK_RV,
+    >,
+    pub C_SeedRandom:
+        Option<unsafe extern "C" fn(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG) -> CK_RV>,
+    pub C_GenerateRandom:
+        Option<unsafe extern "C" fn(CK_SESSION_HANDLE, CK_BYTE_PTR, CK_ULONG) -> CK_RV>,
+    pub C_GetFunctionStatus: Option<unsafe extern "C" fn(CK_SESSION_HANDLE) -> CK_RV>,
+    pub C_CancelFunction: Option<unsafe extern "C" fn(CK_SESSION_HANDLE) -> CK_RV>,
+    pub C_WaitForSlotEvent:
+        Option<unsafe extern "C" fn(CK_FLAGS, CK_SLOT_ID_PTR, CK_VOID_PTR) -> CK_RV>,
+}
@@ -0,0 +1,251 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Minimal DER encoding and decoding helpers, covering just what's needed to build the structures
+//! this crate emits and to read the ones the device returns.
+
+use failure::Error;
+
+pub(crate) const TAG_BOOLEAN: u8 = 0x01;
+pub(crate) const TAG_INTEGER: u8 = 0x02;
+pub(crate) const TAG_BIT_STRING: u8 = 0x03;
+pub(crate) const TAG_OCTET_STRING: u8 = 0x04;
+pub(crate) const TAG_NULL: u8 = 0x05;
+pub(crate) const TAG_OID: u8 = 0x06;
+pub(crate) const TAG_ENUMERATED: u8 = 0x0a;
+pub(crate) const TAG_UTF8_STRING: u8 = 0x0c;
+pub(crate) const TAG_PRINTABLE_STRING: u8 = 0x13;
+pub(crate) const TAG_UTC_TIME: u8 = 0x17;
+pub(crate) const TAG_GENERALIZED_TIME: u8 = 0x18;
+pub(crate) const TAG_SEQUENCE: u8 = 0x30;
+pub(crate) const TAG_SET: u8 = 0x31;
+
+pub(crate) const OID_RSA_ENCRYPTION: &[u64] = &[1, 2, 840, 113549, 1, 1, 1];
+pub(crate) const OID_EC_PUBLIC_KEY: &[u64] = &[1, 2, 840, 10045, 2, 1];
+pub(crate) const OID_ED25519: &[u64] = &[1, 3, 101, 112];
+
+pub(crate) const OID_SHA1: &[u64] = &[1, 3, 14, 3, 2, 26];
+pub(crate) const OID_SHA256: &[u64] = &[2, 16, 840, 1, 101, 3, 4, 2, 1];
+pub(crate) const OID_SHA384: &[u64] = &[2, 16, 840, 1, 101, 3, 4, 2, 2];
+pub(crate) const OID_SHA512: &[u64] = &[2, 16, 840, 1, 101, 3, 4, 2, 3];
+
+pub(crate) const OID_SHA1_WITH_RSA: &[u64] = &[1, 2, 840, 113549, 1, 1, 5];
+pub(crate) const OID_SHA256_WITH_RSA: &[u64] = &[1, 2, 840, 113549, 1, 1, 11];
+pub(crate) const OID_SHA384_WITH_RSA: &[u64] = &[1, 2, 840, 113549, 1, 1, 12];
+pub(crate) const OID_SHA512_WITH_RSA: &[u64] = &[1, 2, 840, 113549, 1, 1, 13];
+pub(crate) const OID_RSASSA_PSS: &[u64] = &[1, 2, 840, 113549, 1, 1, 10];
+pub(crate) const OID_MGF1: &[u64] = &[1, 2, 840, 113549, 1, 1, 8];
+pub(crate) const OID_ECDSA_WITH_SHA1: &[u64] = &[1, 2, 840, 10045, 4, 1];
+pub(crate) const OID_ECDSA_WITH_SHA256: &[u64] = &[1, 2, 840, 10045, 4, 3, 2];
+pub(crate) const OID_ECDSA_WITH_SHA384: &[u64] = &[1, 2, 840, 10045, 4, 3, 3];
+pub(crate) const OID_ECDSA_WITH_SHA512: &[u64] = &[1, 2, 840, 10045, 4, 3, 4];
+
+pub(crate) const OID_SECP224R1: &[u64] = &[1, 3, 132, 0, 33];
+pub(crate) const OID_SECP256R1: &[u64] = &[1, 2, 840, 10045, 3, 1, 7];
+pub(crate) const OID_SECP384R1: &[u64] = &[1, 3, 132, 0, 34];
+pub(crate) const OID_SECP521R1: &[u64] = &[1, 3, 132, 0, 35];
+pub(crate) const OID_SECP256K1: &[u64] = &[1, 3, 132, 0, 10];
+pub(crate) const OID_BRAINPOOL_P256R1: &[u64] = &[1, 3, 36, 3, 3, 2, 8, 1, 1, 7];
+pub(crate) const OID_BRAINPOOL_P384R1: &[u64] = &[1, 3, 36, 3, 3, 2, 8, 1, 1, 11];
+pub(crate) const OID_BRAINPOOL_P512R1: &[u64] = &[1, 3, 36, 3, 3, 2, 8, 1, 1, 13];
+
+pub(crate) const OID_COMMON_NAME: &[u64] = &[2, 5, 4, 3];
+pub(crate) const OID_SERIAL_NUMBER: &[u64] = &[2, 5, 4, 5];
+pub(crate) const OID_COUNTRY_NAME: &[u64] = &[2, 5, 4, 6];
+pub(crate) const OID_LOCALITY_NAME: &[u64] = &[2, 5, 4, 7];
+pub(crate) const OID_STATE_OR_PROVINCE_NAME: &[u64] = &[2, 5, 4, 8];
+pub(crate) const OID_ORGANIZATION_NAME: &[u64] = &[2, 5, 4, 10];
+pub(crate) const OID_ORGANIZATIONAL_UNIT_NAME: &[u64] = &[2, 5, 4, 11];
+
+pub(crate) const OID_SUBJECT_KEY_IDENTIFIER: &[u64] = &[2, 5, 29, 14];
+pub(crate) const OID_KEY_USAGE: &[u64] = &[2, 5, 29, 15];
+pub(crate) const OID_SUBJECT_ALT_NAME: &[u64] = &[2, 5, 29, 17];
+pub(crate) const OID_BASIC_CONSTRAINTS: &[u64] = &[2, 5, 29, 19];
+pub(crate) const OID_AUTHORITY_KEY_IDENTIFIER: &[u64] = &[2, 5, 29, 35];
+pub(crate) const OID_EXTENDED_KEY_USAGE: &[u64] = &[2, 5, 29, 37];
+pub(crate) const OID_CRL_NUMBER: &[u64] = &[2, 5, 29, 20];
+pub(crate) const OID_CRL_REASON: &[u64] = &[2, 5, 29, 21];
+pub(crate) const OID_EXTENSION_REQUEST: &[u64] = &[1, 2, 840, 113549, 1, 9, 14];
+
+pub(crate) const OID_KP_SERVER_AUTH: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 3, 1];
+pub(crate) const OID_KP_CLIENT_AUTH: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 3, 2];
+pub(crate) const OID_KP_CODE_SIGNING: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 3, 3];
+pub(crate) const OID_KP_EMAIL_PROTECTION: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 3, 4];
+pub(crate) const OID_KP_TIME_STAMPING: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 3, 8];
+pub(crate) const OID_KP_OCSP_SIGNING: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 3, 9];
+
+pub(crate) const OID_OCSP_BASIC: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 48, 1, 1];
+pub(crate) const OID_OCSP_NONCE: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 48, 1, 2];
+
+/// Encode a tag-length-value triple.
+pub(crate) fn tlv(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = contents.len();
+
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let len_bytes = (len as u64).to_be_bytes();
+        let skip = len_bytes.iter().take_while(|b| **b == 0).count();
+        out.push(0x80 | (len_bytes.len() - skip) as u8);
+        out.extend_from_slice(&len_bytes[skip..]);
+    }
+
+    out.extend_from_slice(contents);
+    out
+}
+
+/// Encode a SEQUENCE from already-encoded elements.
+pub(crate) fn sequence(elements: &[&[u8]]) -> Vec<u8> {
+    tlv(TAG_SEQUENCE, &elements.concat())
+}
+
+/// Encode a non-negative INTEGER from its unsigned big-endian representation.
+pub(crate) fn unsigned_integer(bytes: &[u8]) -> Vec<u8> {
+    let skip = bytes.iter().take_while(|b| **b == 0).count();
+    let bytes = &bytes[skip..];
+
+    let mut contents = Vec::with_capacity(bytes.len() + 1);
+    if bytes.is_empty() || bytes[0] & 0x80 != 0 {
+        contents.push(0);
+    }
+    contents.extend_from_slice(bytes);
+
+    tlv(TAG_INTEGER, &contents)
+}
+
+/// Encode a BIT STRING with no unused bits.
+pub(crate) fn bit_string(bytes: &[u8]) -> Vec<u8> {
+    let mut contents = Vec::with_capacity(bytes.len() + 1);
+    contents.push(0);
+    contents.extend_from_slice(bytes);
+
+    tlv(TAG_BIT_STRING, &contents)
+}
+
+pub(crate) fn octet_string(bytes: &[u8]) -> Vec<u8> {
+    tlv(TAG_OCTET_STRING, bytes)
+}
+
+pub(crate) fn null() -> Vec<u8> {
+    tlv(TAG_NULL, &[])
+}
+
+pub(crate) fn boolean(value: bool) -> Vec<u8> {
+    tlv(TAG_BOOLEAN, &[if value { 0xff } else { 0 }])
+}
+
+/// Encode an ENUMERATED with a value below 128.
+pub(crate) fn enumerated(value: u8) -> Vec<u8> {
+    tlv(TAG_ENUMERATED, &[value])
+}
+
+/// Encode a SET OF from already-encoded elements, sorting them as DER requires.
+pub(crate) fn set_of(elements: &[&[u8]]) -> Vec<u8> {
+    let mut elements = elements.to_vec();
+    elements.sort();
+
+    tlv(TAG_SET, &elements.concat())
+}
+
+/// Encode a constructed, context-specific tag `[number]` around already-encoded contents, as used
+/// for EXPLICIT tagging.
+pub(crate) fn explicit(number: u8, contents: &[u8]) -> Vec<u8> {
+    tlv(0xa0 | number, contents)
+}
+
+/// Encode a primitive, context-specific tag `[number]`, as used for IMPLICIT tagging of primitive
+/// types.
+pub(crate) fn implicit(number: u8, contents: &[u8]) -> Vec<u8> {
+    tlv(0x80 | number, contents)
+}
+
+/// Encode an OBJECT IDENTIFIER from its arcs.
+pub(crate) fn oid(arcs: &[u64]) -> Vec<u8> {
+    let mut contents = vec![(arcs[0] * 40 + arcs[1]) as u8];
+
+    for &arc in &arcs[2..] {
+        let mut base128 = vec![(arc & 0x7f) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            base128.push(0x80 | (rest & 0x7f) as u8);
+            rest >>= 7;
+        }
+        contents.extend(base128.iter().rev());
+    }
+
+    tlv(TAG_OID, &contents)
+}
+
+/// Decode a single tag-length-value triple from the front of `input`, returning the tag, the
+/// contents and whatever follows.
+pub(crate) fn read_tlv(input: &[u8]) -> Result<(u8, &[u8], &[u8]), Error> {
+    if input.len() < 2 {
+        bail!("truncated DER element");
+    }
+
+    let tag = input[0];
+    let (len, header_len) = if input[1] < 0x80 {
+        (input[1] as usize, 2)
+    } else {
+        let len_len = (input[1] & 0x7f) as usize;
+        if len_len == 0 || len_len > 4 || input.len() < 2 + len_len {
+            bail!("invalid DER length");
+        }
+
+        let len = input[2..2 + len_len]
+            .iter()
+            .fold(0, |len, b| (len << 8) | *b as usize);
+        (len, 2 + len_len)
+    };
+
+    if input.len() - header_len < len {
+        bail!("truncated DER element");
+    }
+
+    let (contents, rest) = input[header_len..].split_at(len);
+    Ok((tag, contents, rest))
+}
+
+/// Decode a single element with the given tag from the front of `input`, returning its contents
+/// and whatever follows.
+pub(crate) fn read_element(tag: u8, input: &[u8]) -> Result<(&[u8], &[u8]), Error> {
+    let (found, contents, rest) = read_tlv(input)?;
+    if found != tag {
+        bail!("expected DER tag {:#04x}, found {:#04x}", tag, found);
+    }
+
+    Ok((contents, rest))
+}
+
+/// Split the first element, including its tag and length, from the front of `input`.
+pub(crate) fn split_element(input: &[u8]) -> Result<(&[u8], &[u8]), Error> {
+    let (_, _, rest) = read_tlv(input)?;
+    Ok(input.split_at(input.len() - rest.len()))
+}
+
+/// Decode a non-negative INTEGER from the front of `input`, returning its unsigned big-endian
+/// representation without leading zeroes, and whatever follows.
+pub(crate) fn read_unsigned_integer(input: &[u8]) -> Result<(&[u8], &[u8]), Error> {
+    let (tag, contents, rest) = read_tlv(input)?;
+    if tag != TAG_INTEGER {
+        bail!("expected DER INTEGER, found tag {:#04x}", tag);
+    }
+    if contents.is_empty() || contents[0] & 0x80 != 0 {
+        bail!("expected non-negative DER INTEGER");
+    }
+
+    let skip = contents.iter().take_while(|b| **b == 0).count();
+    Ok((&contents[skip..], rest))
+}
@@ -0,0 +1,340 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! First-boot hardening of a factory-fresh device, e.g. one just reset with `Session::reset`.
+//!
+//! `Connector::bootstrap` logs in with the default AuthKey and password, creates an admin AuthKey,
+//! checks that the new key can log in, deletes the default AuthKey and enables force audit. It
+//! returns a `BootstrapSummary` of what it did, signed by an Ed25519 key on the device.
+//!
+//! Every step first checks whether it has already been done, so if the connector drops partway
+//! through, running `bootstrap` again with the same `BootstrapConfig` finishes the job.
+
+use connector::Connector;
+use policy::{default_password, DEFAULT_AUTH_KEY_ID};
+use session::Session;
+use types::*;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use failure::Error;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Capabilities the admin AuthKey needs for `bootstrap` to finish through its session.
+pub const REQUIRED_CAPABILITIES: &[Capability] = &[
+    Capability::DeleteAuthkey,
+    Capability::PutOption,
+    Capability::GetOption,
+    Capability::AsymmetricGen,
+    Capability::AsymmetricSignEddsa,
+];
+
+/// Capabilities the admin AuthKey must be able to delegate, so it can generate the summary key.
+pub const REQUIRED_DELEGATED_CAPABILITIES: &[Capability] = &[Capability::AsymmetricSignEddsa];
+
+/// The admin AuthKey and summary key `bootstrap` creates.
+#[derive(Clone, Debug)]
+pub struct BootstrapConfig {
+    pub admin_key_id: u16,
+    pub admin_label: String,
+    pub admin_password: String,
+    /// The domains of both the admin AuthKey and the summary key.
+    pub domains: Vec<Domain>,
+    /// Must include `REQUIRED_CAPABILITIES`.
+    pub capabilities: Vec<Capability>,
+    /// Must include `REQUIRED_DELEGATED_CAPABILITIES`.
+    pub delegated_capabilities: Vec<Capability>,
+    /// The ID of the Ed25519 key which signs the summary.
+    pub summary_key_id: u16,
+}
+
+impl BootstrapConfig {
+    /// Check that the config can be carried out, and that it won't leave the device as open as it
+    /// was.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.admin_key_id == 0 || self.admin_key_id == DEFAULT_AUTH_KEY_ID {
+            bail!(
+                "bootstrap: admin AuthKey ID must not be 0 or the default AuthKey's ID {}",
+                DEFAULT_AUTH_KEY_ID
+            );
+        }
+        if self.summary_key_id == 0 {
+            bail!("bootstrap: summary key ID must not be 0");
+        }
+        if self.admin_password.is_empty() || self.admin_password == default_password()? {
+            bail!("bootstrap: admin password must not be empty or the default password");
+        }
+        if self.domains.is_empty() {
+            bail!("bootstrap: admin AuthKey must be in at least one domain");
+        }
+
+        let missing: Vec<String> = REQUIRED_CAPABILITIES
+            .iter()
+            .filter(|&c| !self.capabilities.contains(c))
+            .map(Capability::to_string)
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "bootstrap: admin AuthKey is missing capabilities {}",
+                missing.join(",")
+            );
+        }
+
+        let missing: Vec<String> = REQUIRED_DELEGATED_CAPABILITIES
+            .iter()
+            .filter(|&c| !self.delegated_capabilities.contains(c))
+            .map(Capability::to_string)
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "bootstrap: admin AuthKey can't delegate capabilities {}",
+                missing.join(",")
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// The steps `bootstrap` takes, in order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum Step {
+    CreateAdminKey,
+    VerifyAdminKey,
+    CreateSummaryKey,
+    DeleteDefaultKey,
+    EnableForceAudit,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match *self {
+            Step::CreateAdminKey => "create-admin-key",
+            Step::VerifyAdminKey => "verify-admin-key",
+            Step::CreateSummaryKey => "create-summary-key",
+            Step::DeleteDefaultKey => "delete-default-key",
+            Step::EnableForceAudit => "enable-force-audit",
+        })
+    }
+}
+
+/// Whether a step changed the device, or found it already done by an earlier run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum Outcome {
+    Done,
+    AlreadyDone,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match *self {
+            Outcome::Done => "done",
+            Outcome::AlreadyDone => "already-done",
+        })
+    }
+}
+
+/// A signed record of a `bootstrap` run.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BootstrapSummary {
+    pub serial: u32,
+    pub admin_key_id: u16,
+    pub summary_key_id: u16,
+    pub steps: Vec<(Step, Outcome)>,
+    /// The summary key's public key.
+    pub public_key: PublicKey,
+    /// An Ed25519 signature over `message()`.
+    pub signature: Vec<u8>,
+}
+
+impl BootstrapSummary {
+    /// The signed text: a header line, then one `name value` line each for the serial number, the
+    /// admin and summary key IDs and every step.
+    pub fn message(&self) -> Vec<u8> {
+        let mut lines = vec![
+            "yubihsm-bootstrap-v1".to_string(),
+            format!("serial {}", self.serial),
+            format!("admin-key 0x{:04x}", self.admin_key_id),
+            format!("summary-key 0x{:04x}", self.summary_key_id),
+        ];
+        for &(step, outcome) in &self.steps {
+            lines.push(format!("{} {}", step, outcome));
+        }
+
+        let mut message = lines.join("\n").into_bytes();
+        message.push(b'\n');
+        message
+    }
+
+    /// Check `signature` against `public_key`. The caller should also check that `public_key` is
+    /// the summary key it expects, e.g. by comparing it with `Session::get_pubkey`.
+    pub fn verify(&self) -> Result<(), Error> {
+        self.public_key
+            .verify(Algorithm::EcEd25519, self.message(), &self.signature)
+    }
+}
+
+impl fmt::Display for BootstrapSummary {
+    /// The signed message followed by a `signature` line holding the base64 signature.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}signature {}",
+            String::from_utf8_lossy(&self.message()),
+            STANDARD.encode(&self.signature)
+        )
+    }
+}
+
+fn object_exists(session: &Session, id: u16, object_type: ObjectType) -> Result<bool, Error> {
+    // Listing, unlike `get_object_info`, tells a missing object apart from a failed command.
+    Ok(!session
+        .list_objects()
+        .id(id)
+        .object_type(object_type)
+        .execute()?
+        .is_empty())
+}
+
+impl Connector {
+    /// Harden a factory-fresh device as described by `config`; see the module documentation.
+    ///
+    /// The default AuthKey's session is only used to create the admin AuthKey; every later step
+    /// goes through a new session with the admin AuthKey, so a key that can't log in is caught
+    /// before the default AuthKey is deleted. If the default password no longer works (e.g. an
+    /// earlier run already deleted the default AuthKey), bootstrap continues with the admin
+    /// AuthKey if it can log in, and fails otherwise.
+    ///
+    /// Once force audit is enabled, the device refuses commands while its log is full, so the log
+    /// must be read with `Session::get_logs` and acknowledged with `Session::set_log_index`.
+    pub fn bootstrap(&self, config: &BootstrapConfig) -> Result<BootstrapSummary, Error> {
+        config.validate()?;
+        let mut steps = Vec::new();
+
+        let create = match self.create_session_from_password(
+            DEFAULT_AUTH_KEY_ID,
+            default_password()?,
+            false,
+        ) {
+            Ok(session) => {
+                if object_exists(&session, config.admin_key_id, ObjectType::AuthKey)? {
+                    Outcome::AlreadyDone
+                } else {
+                    session.create_authkey(
+                        config.admin_key_id,
+                        &config.admin_label,
+                        &config.domains,
+                        &config.capabilities,
+                        &config.delegated_capabilities,
+                        &config.admin_password,
+                    )?;
+                    Outcome::Done
+                }
+            }
+            Err(_) => Outcome::AlreadyDone,
+        };
+        steps.push((Step::CreateAdminKey, create));
+
+        let admin = self
+            .create_session_from_password(config.admin_key_id, &config.admin_password, false)
+            .map_err(|e| {
+                format_err!(
+                    "bootstrap: couldn't log in with admin AuthKey 0x{:04x}: {}",
+                    config.admin_key_id,
+                    e
+                )
+            })?;
+        // An admin AuthKey left by an earlier run may not match `config`.
+        let info = admin.get_object_info(config.admin_key_id, ObjectType::AuthKey)?;
+        if let Some(c) = REQUIRED_CAPABILITIES
+            .iter()
+            .find(|&c| !info.capabilities.contains(c))
+        {
+            bail!(
+                "bootstrap: admin AuthKey 0x{:04x} is missing capability {}",
+                config.admin_key_id,
+                c
+            );
+        }
+        if let Some(c) = REQUIRED_DELEGATED_CAPABILITIES
+            .iter()
+            .find(|&c| !info.delegated_capabilities.contains(c))
+        {
+            bail!(
+                "bootstrap: admin AuthKey 0x{:04x} can't delegate capability {}",
+                config.admin_key_id,
+                c
+            );
+        }
+        steps.push((Step::VerifyAdminKey, Outcome::Done));
+
+        let summary_key = if object_exists(&admin, config.summary_key_id, ObjectType::Asymmetric)? {
+            let info = admin.get_object_info(config.summary_key_id, ObjectType::Asymmetric)?;
+            if info.algorithm != Some(Algorithm::EcEd25519) {
+                bail!(
+                    "bootstrap: summary key 0x{:04x} exists and is not an Ed25519 key",
+                    config.summary_key_id
+                );
+            }
+            Outcome::AlreadyDone
+        } else {
+            admin.generate_key_ed(
+                config.summary_key_id,
+                "bootstrap-summary",
+                &config.domains,
+                &[Capability::AsymmetricSignEddsa],
+                Algorithm::EcEd25519,
+            )?;
+            Outcome::Done
+        };
+        steps.push((Step::CreateSummaryKey, summary_key));
+
+        let delete = if object_exists(&admin, DEFAULT_AUTH_KEY_ID, ObjectType::AuthKey)? {
+            admin.delete_object(DEFAULT_AUTH_KEY_ID, ObjectType::AuthKey)?;
+            Outcome::Done
+        } else {
+            Outcome::AlreadyDone
+        };
+        steps.push((Step::DeleteDefaultKey, delete));
+
+        let force_audit = match admin.get_option(DeviceOptionType::ForceAudit)? {
+            DeviceOption::ForceAudit(DeviceOptionValue::Enabled)
+            | DeviceOption::ForceAudit(DeviceOptionValue::Fixed) => Outcome::AlreadyDone,
+            _ => {
+                admin.put_option(DeviceOption::ForceAudit(DeviceOptionValue::Enabled))?;
+                Outcome::Done
+            }
+        };
+        steps.push((Step::EnableForceAudit, force_audit));
+
+        let mut summary = BootstrapSummary {
+            serial: self.get_device_info()?.serial,
+            admin_key_id: config.admin_key_id,
+            summary_key_id: config.summary_key_id,
+            steps,
+            public_key: admin.get_pubkey(config.summary_key_id)?,
+            signature: Vec::new(),
+        };
+        summary.signature = admin.sign_eddsa(config.summary_key_id, summary.message())?;
+
+        Ok(summary)
+    }
+}
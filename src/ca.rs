@@ -0,0 +1,991 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An X.509 certificate authority whose signing key is an asymmetric key on the device.
+//!
+//! `CertificateAuthority` issues self-signed roots, intermediates and leaf certificates, either
+//! for a known public key or from a PKCS#10 certificate signing request. What goes into each
+//! certificate (validity period, basic constraints and key usages) is described by a
+//! `CertificateProfile`, and serial numbers come from a `SerialNumberGenerator`. CRLs and OCSP
+//! responses signed by the CA are built in the `revocation` module.
+
+use asn1;
+use public_key::pem_encode;
+use session::Session;
+use state_file;
+use types::*;
+use x509;
+
+use failure::Error;
+
+use std::fmt::Debug;
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// An X.509 distinguished name, built up one attribute at a time.
+///
+/// Attributes are encoded in the order they're added, each in its own relative distinguished name,
+/// so the conventional order is most general (country) to most specific (common name).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DistinguishedName {
+    attributes: Vec<(&'static [u64], u8, String)>,
+}
+
+impl DistinguishedName {
+    /// An empty name, which is only valid as the subject of a certificate with a subject
+    /// alternative name.
+    pub fn new() -> DistinguishedName {
+        DistinguishedName::default()
+    }
+
+    fn attribute(mut self, oid: &'static [u64], tag: u8, value: &str) -> DistinguishedName {
+        self.attributes.push((oid, tag, value.to_string()));
+        self
+    }
+
+    /// Add a two-letter ISO 3166 country code (C).
+    pub fn country(self, country: &str) -> DistinguishedName {
+        self.attribute(asn1::OID_COUNTRY_NAME, asn1::TAG_PRINTABLE_STRING, country)
+    }
+
+    /// Add a state or province name (ST).
+    pub fn state_or_province(self, state: &str) -> DistinguishedName {
+        self.attribute(
+            asn1::OID_STATE_OR_PROVINCE_NAME,
+            asn1::TAG_UTF8_STRING,
+            state,
+        )
+    }
+
+    /// Add a locality name (L).
+    pub fn locality(self, locality: &str) -> DistinguishedName {
+        self.attribute(asn1::OID_LOCALITY_NAME, asn1::TAG_UTF8_STRING, locality)
+    }
+
+    /// Add an organization name (O).
+    pub fn organization(self, organization: &str) -> DistinguishedName {
+        self.attribute(
+            asn1::OID_ORGANIZATION_NAME,
+            asn1::TAG_UTF8_STRING,
+            organization,
+        )
+    }
+
+    /// Add an organizational unit name (OU).
+    pub fn organizational_unit(self, unit: &str) -> DistinguishedName {
+        self.attribute(
+            asn1::OID_ORGANIZATIONAL_UNIT_NAME,
+            asn1::TAG_UTF8_STRING,
+            unit,
+        )
+    }
+
+    /// Add a common name (CN).
+    pub fn common_name(self, name: &str) -> DistinguishedName {
+        self.attribute(asn1::OID_COMMON_NAME, asn1::TAG_UTF8_STRING, name)
+    }
+
+    /// Add a serial number attribute. This is not the certificate serial number.
+    pub fn serial_number(self, serial: &str) -> DistinguishedName {
+        self.attribute(asn1::OID_SERIAL_NUMBER, asn1::TAG_PRINTABLE_STRING, serial)
+    }
+
+    /// Encode the name as a DER `Name`.
+    pub fn to_der(&self) -> Vec<u8> {
+        let rdns: Vec<Vec<u8>> = self.attributes
+            .iter()
+            .map(|&(oid, tag, ref value)| {
+                let attribute = asn1::sequence(&[&asn1::oid(oid), &asn1::tlv(tag, value.as_bytes())]);
+                asn1::set_of(&[&attribute])
+            })
+            .collect();
+        let rdns: Vec<&[u8]> = rdns.iter().map(|rdn| &rdn[..]).collect();
+
+        asn1::sequence(&rdns)
+    }
+}
+
+/// An entry in a certificate's subject alternative name extension.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SubjectAltName {
+    DnsName(String),
+    IpAddress(IpAddr),
+    Email(String),
+    Uri(String),
+}
+
+impl SubjectAltName {
+    fn to_der(&self) -> Result<Vec<u8>, Error> {
+        let (number, value) = match *self {
+            SubjectAltName::Email(ref email) => (1, email.as_bytes().to_vec()),
+            SubjectAltName::DnsName(ref name) => (2, name.as_bytes().to_vec()),
+            SubjectAltName::Uri(ref uri) => (6, uri.as_bytes().to_vec()),
+            SubjectAltName::IpAddress(IpAddr::V4(ip)) => return Ok(asn1::implicit(7, &ip.octets())),
+            SubjectAltName::IpAddress(IpAddr::V6(ip)) => return Ok(asn1::implicit(7, &ip.octets())),
+        };
+
+        // The string forms are all IA5String.
+        if !value.is_ascii() {
+            bail!("subject alternative name {:?} is not ASCII", self);
+        }
+
+        Ok(asn1::implicit(number, &value))
+    }
+}
+
+/// The value of a subject alternative name extension.
+fn subject_alt_names_extension_value(names: &[SubjectAltName]) -> Result<Vec<u8>, Error> {
+    let names = names
+        .iter()
+        .map(SubjectAltName::to_der)
+        .collect::<Result<Vec<_>, _>>()?;
+    let names: Vec<&[u8]> = names.iter().map(|name| &name[..]).collect();
+
+    Ok(asn1::sequence(&names))
+}
+
+/// A bit in a certificate's key usage extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyUsage {
+    DigitalSignature,
+    NonRepudiation,
+    KeyEncipherment,
+    DataEncipherment,
+    KeyAgreement,
+    KeyCertSign,
+    CrlSign,
+}
+
+impl KeyUsage {
+    fn bit(&self) -> u8 {
+        match *self {
+            KeyUsage::DigitalSignature => 0,
+            KeyUsage::NonRepudiation => 1,
+            KeyUsage::KeyEncipherment => 2,
+            KeyUsage::DataEncipherment => 3,
+            KeyUsage::KeyAgreement => 4,
+            KeyUsage::KeyCertSign => 5,
+            KeyUsage::CrlSign => 6,
+        }
+    }
+}
+
+/// The value of a key usage extension: a BIT STRING with the named bits set, and trailing zero
+/// bits removed as DER requires.
+pub(crate) fn key_usage_extension_value(usages: &[KeyUsage]) -> Vec<u8> {
+    let bits = usages.iter().fold(0u8, |bits, usage| bits | 0x80 >> usage.bit());
+    if bits == 0 {
+        // With no bits set, nothing is left once trailing zeros are removed.
+        return asn1::tlv(asn1::TAG_BIT_STRING, &[0]);
+    }
+    let unused_bits = bits.trailing_zeros() as u8;
+
+    asn1::tlv(asn1::TAG_BIT_STRING, &[unused_bits, bits])
+}
+
+/// A purpose in a certificate's extended key usage extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExtendedKeyUsage {
+    ServerAuth,
+    ClientAuth,
+    CodeSigning,
+    EmailProtection,
+    TimeStamping,
+    OcspSigning,
+}
+
+impl ExtendedKeyUsage {
+    fn oid(&self) -> &'static [u64] {
+        match *self {
+            ExtendedKeyUsage::ServerAuth => asn1::OID_KP_SERVER_AUTH,
+            ExtendedKeyUsage::ClientAuth => asn1::OID_KP_CLIENT_AUTH,
+            ExtendedKeyUsage::CodeSigning => asn1::OID_KP_CODE_SIGNING,
+            ExtendedKeyUsage::EmailProtection => asn1::OID_KP_EMAIL_PROTECTION,
+            ExtendedKeyUsage::TimeStamping => asn1::OID_KP_TIME_STAMPING,
+            ExtendedKeyUsage::OcspSigning => asn1::OID_KP_OCSP_SIGNING,
+        }
+    }
+}
+
+/// The value of an extended key usage extension.
+fn extended_key_usage_extension_value(usages: &[ExtendedKeyUsage]) -> Vec<u8> {
+    let purposes: Vec<Vec<u8>> = usages.iter().map(|usage| asn1::oid(usage.oid())).collect();
+    let purposes: Vec<&[u8]> = purposes.iter().map(|p| &p[..]).collect();
+
+    asn1::sequence(&purposes)
+}
+
+/// The value of a basic constraints extension.
+fn basic_constraints_extension_value(ca: bool, path_len: Option<u8>) -> Vec<u8> {
+    let mut basic_constraints = vec![];
+    if ca {
+        basic_constraints.push(asn1::boolean(true));
+        if let Some(path_len) = path_len {
+            basic_constraints.push(asn1::unsigned_integer(&[path_len]));
+        }
+    }
+    let basic_constraints: Vec<&[u8]> = basic_constraints.iter().map(|e| &e[..]).collect();
+
+    asn1::sequence(&basic_constraints)
+}
+
+/// What goes into an issued certificate, apart from its subject and public key.
+///
+/// Start from one of the predefined profiles and adjust the fields as needed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CertificateProfile {
+    /// How long the certificate is valid for, counted from the time it's issued.
+    pub validity: Duration,
+    /// How far before the time of issue the validity period starts, to allow for clock skew.
+    pub backdate: Duration,
+    /// Whether the certificate is for a CA.
+    pub ca: bool,
+    /// For CA certificates, the maximum number of intermediate CAs which may follow it.
+    pub path_len: Option<u8>,
+    pub key_usage: Vec<KeyUsage>,
+    /// Omitted from the certificate if empty.
+    pub extended_key_usage: Vec<ExtendedKeyUsage>,
+}
+
+impl CertificateProfile {
+    /// A root CA, valid for 20 years.
+    pub fn root() -> CertificateProfile {
+        CertificateProfile {
+            validity: Duration::from_secs(20 * 365 * DAY),
+            backdate: Duration::from_secs(60 * 60),
+            ca: true,
+            path_len: None,
+            key_usage: vec![KeyUsage::KeyCertSign, KeyUsage::CrlSign],
+            extended_key_usage: vec![],
+        }
+    }
+
+    /// An intermediate CA, valid for 10 years, which may be followed by at most `path_len`
+    /// further intermediates.
+    pub fn intermediate(path_len: Option<u8>) -> CertificateProfile {
+        CertificateProfile {
+            validity: Duration::from_secs(10 * 365 * DAY),
+            path_len,
+            ..CertificateProfile::root()
+        }
+    }
+
+    /// A TLS server certificate, valid for 397 days (the CA/Browser Forum maximum).
+    pub fn tls_server() -> CertificateProfile {
+        CertificateProfile {
+            validity: Duration::from_secs(397 * DAY),
+            backdate: Duration::from_secs(60 * 60),
+            ca: false,
+            path_len: None,
+            key_usage: vec![KeyUsage::DigitalSignature, KeyUsage::KeyEncipherment],
+            extended_key_usage: vec![ExtendedKeyUsage::ServerAuth],
+        }
+    }
+
+    /// A TLS client certificate, valid for 397 days.
+    pub fn tls_client() -> CertificateProfile {
+        CertificateProfile {
+            extended_key_usage: vec![ExtendedKeyUsage::ClientAuth],
+            ..CertificateProfile::tls_server()
+        }
+    }
+
+    /// A code signing certificate, valid for 3 years.
+    pub fn code_signing() -> CertificateProfile {
+        CertificateProfile {
+            validity: Duration::from_secs(3 * 365 * DAY),
+            key_usage: vec![KeyUsage::DigitalSignature],
+            extended_key_usage: vec![ExtendedKeyUsage::CodeSigning],
+            ..CertificateProfile::tls_server()
+        }
+    }
+
+    /// An OCSP responder certificate, valid for 90 days.
+    pub fn ocsp_signing() -> CertificateProfile {
+        CertificateProfile {
+            validity: Duration::from_secs(90 * DAY),
+            key_usage: vec![KeyUsage::DigitalSignature],
+            extended_key_usage: vec![ExtendedKeyUsage::OcspSigning],
+            ..CertificateProfile::tls_server()
+        }
+    }
+
+    fn extensions(
+        &self,
+        subject_is_empty: bool,
+        subject_key_identifier: &[u8],
+        authority_key_identifier: &[u8],
+        subject_alt_names: Option<&[u8]>,
+    ) -> Vec<Vec<u8>> {
+        let mut extensions = vec![x509::extension(
+            asn1::OID_BASIC_CONSTRAINTS,
+            true,
+            &basic_constraints_extension_value(self.ca, self.path_len),
+        )];
+
+        if !self.key_usage.is_empty() {
+            extensions.push(x509::extension(
+                asn1::OID_KEY_USAGE,
+                true,
+                &key_usage_extension_value(&self.key_usage),
+            ));
+        }
+
+        if !self.extended_key_usage.is_empty() {
+            extensions.push(x509::extension(
+                asn1::OID_EXTENDED_KEY_USAGE,
+                false,
+                &extended_key_usage_extension_value(&self.extended_key_usage),
+            ));
+        }
+
+        extensions.push(x509::extension(
+            asn1::OID_SUBJECT_KEY_IDENTIFIER,
+            false,
+            &asn1::octet_string(subject_key_identifier),
+        ));
+        extensions.push(x509::authority_key_identifier(authority_key_identifier));
+
+        if let Some(subject_alt_names) = subject_alt_names {
+            // RFC 5280 requires the extension to be critical when the subject is empty.
+            extensions.push(x509::extension(
+                asn1::OID_SUBJECT_ALT_NAME,
+                subject_is_empty,
+                subject_alt_names,
+            ));
+        }
+
+        extensions
+    }
+}
+
+/// A source of certificate serial numbers.
+pub trait SerialNumberGenerator: Debug {
+    /// The next serial number, as an unsigned big-endian integer of at most 20 bytes.
+    fn next_serial_number(&mut self, session: &Session) -> Result<Vec<u8>, Error>;
+}
+
+/// Random 16-byte serial numbers from the device's random number generator, as recommended by the
+/// CA/Browser Forum baseline requirements. No state needs to be kept between runs.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RandomSerialNumbers;
+
+impl SerialNumberGenerator for RandomSerialNumbers {
+    fn next_serial_number(&mut self, session: &Session) -> Result<Vec<u8>, Error> {
+        let mut serial = session.get_random(16)?;
+        // Keep the serial number positive without needing a padding byte, and non-zero.
+        serial[0] = (serial[0] & 0x7f) | 0x40;
+
+        Ok(serial)
+    }
+}
+
+/// Sequential serial numbers, with the next one kept as a decimal number in a text file.
+///
+/// The file is created, starting at 1, if it doesn't exist, and is replaced atomically and synced
+/// to disk before each serial number is handed out, so a crash can't make a number be reissued.
+#[derive(Debug)]
+pub struct FileSerialNumbers {
+    path: PathBuf,
+    _lock: File,
+}
+
+impl FileSerialNumbers {
+    /// Use the file at `path`, locking it for as long as the generator lives.
+    ///
+    /// Fails if another generator, in this or another process, is using `path`.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Result<FileSerialNumbers, Error> {
+        let path = path.into();
+        let lock = state_file::lock(&path)?;
+        Ok(FileSerialNumbers { path, _lock: lock })
+    }
+}
+
+impl SerialNumberGenerator for FileSerialNumbers {
+    fn next_serial_number(&mut self, _session: &Session) -> Result<Vec<u8>, Error> {
+        let serial: u64 = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents.trim().parse().map_err(|e| {
+                format_err!("invalid serial number in {}: {}", self.path.display(), e)
+            })?,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => 1,
+            Err(e) => return Err(e.into()),
+        };
+
+        state_file::replace(&self.path, format!("{}\n", serial + 1).as_bytes())?;
+
+        Ok(serial.to_be_bytes().to_vec())
+    }
+}
+
+/// An extension to request in a certificate signing request made with `Session::create_csr`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RequestedExtension {
+    SubjectAltNames(Vec<SubjectAltName>),
+    KeyUsage(Vec<KeyUsage>),
+    ExtendedKeyUsage(Vec<ExtendedKeyUsage>),
+    BasicConstraints { ca: bool, path_len: Option<u8> },
+}
+
+impl RequestedExtension {
+    fn to_der(&self, subject_is_empty: bool) -> Result<Vec<u8>, Error> {
+        Ok(match *self {
+            RequestedExtension::SubjectAltNames(ref names) => x509::extension(
+                asn1::OID_SUBJECT_ALT_NAME,
+                subject_is_empty,
+                &subject_alt_names_extension_value(names)?,
+            ),
+            RequestedExtension::KeyUsage(ref usages) => x509::extension(
+                asn1::OID_KEY_USAGE,
+                true,
+                &key_usage_extension_value(usages),
+            ),
+            RequestedExtension::ExtendedKeyUsage(ref usages) => x509::extension(
+                asn1::OID_EXTENDED_KEY_USAGE,
+                false,
+                &extended_key_usage_extension_value(usages),
+            ),
+            RequestedExtension::BasicConstraints { ca, path_len } => x509::extension(
+                asn1::OID_BASIC_CONSTRAINTS,
+                true,
+                &basic_constraints_extension_value(ca, path_len),
+            ),
+        })
+    }
+}
+
+/// A PKCS#10 certificate signing request (RFC 2986), either parsed and verified with `from_der`
+/// or created on the device with `Session::create_csr`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CertificateRequest {
+    der: Vec<u8>,
+    subject: Vec<u8>,
+    spki: Vec<u8>,
+    public_key: PublicKey,
+    subject_alt_names: Option<Vec<u8>>,
+}
+
+impl CertificateRequest {
+    /// Parse a DER-encoded certificate signing request and check its signature.
+    ///
+    /// Of the extensions requested, only the subject alternative names are kept, since the rest of
+    /// the certificate contents come from the `CertificateProfile`.
+    pub fn from_der<T: AsRef<[u8]>>(der: T) -> Result<CertificateRequest, Error> {
+        let der = der.as_ref();
+        let signed = x509::Signed::parse(der)?;
+
+        let (info, _) = asn1::read_element(asn1::TAG_SEQUENCE, signed.tbs)?;
+        let (version, info) = asn1::read_unsigned_integer(info)?;
+        if !version.is_empty() {
+            bail!("unsupported certificate request version");
+        }
+        let (subject, info) = asn1::split_element(info)?;
+        let (spki, info) = asn1::split_element(info)?;
+        let (attributes, _) = asn1::read_element(0xa0, info)?;
+
+        let public_key = PublicKey::from_spki_der(spki)?;
+        signed
+            .verify(&public_key)
+            .map_err(|e| format_err!("certificate request signature is invalid: {}", e))?;
+
+        Ok(CertificateRequest {
+            der: der.to_vec(),
+            subject: subject.to_vec(),
+            spki: spki.to_vec(),
+            public_key,
+            subject_alt_names: requested_subject_alt_names(attributes)?,
+        })
+    }
+
+    /// Parse a PEM `CERTIFICATE REQUEST` block and check its signature.
+    pub fn from_pem(pem: &str) -> Result<CertificateRequest, Error> {
+        CertificateRequest::from_der(pem_decode("CERTIFICATE REQUEST", pem)?)
+    }
+
+    /// The request, DER-encoded.
+    pub fn to_der(&self) -> &[u8] {
+        &self.der
+    }
+
+    /// The request as a PEM `CERTIFICATE REQUEST` block.
+    pub fn to_pem(&self) -> String {
+        pem_encode("CERTIFICATE REQUEST", &self.der)
+    }
+
+    /// The requested subject, as a DER `Name`.
+    pub fn subject_der(&self) -> &[u8] {
+        &self.subject
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+}
+
+impl Session {
+    /// Create a certificate signing request for the asymmetric key `key_id`, e.g. to have a key
+    /// made with `generate_key_ec` or `generate_key_rsa` certified by an external CA.
+    ///
+    /// The request is signed with the key itself, using the scheme `SignatureScheme::for_key`
+    /// picks for the key's algorithm.
+    pub fn create_csr(
+        &self,
+        key_id: u16,
+        subject: &DistinguishedName,
+        extensions: &[RequestedExtension],
+    ) -> Result<CertificateRequest, Error> {
+        let public_key = self.get_pubkey(key_id)?;
+        let key_algorithm = public_key.algorithm();
+        let scheme = match SignatureScheme::for_key(key_algorithm) {
+            Some(scheme) => scheme,
+            None => bail!("create_csr: can't sign with a {} key", key_algorithm),
+        };
+
+        let subject = subject.to_der();
+        let subject_is_empty = subject == asn1::sequence(&[]);
+        let spki = public_key.to_spki_der()?;
+
+        let mut subject_alt_names = None;
+        let mut requested = Vec::with_capacity(extensions.len());
+        for extension in extensions {
+            if let RequestedExtension::SubjectAltNames(ref names) = *extension {
+                subject_alt_names = Some(subject_alt_names_extension_value(names)?);
+            }
+            requested.push(extension.to_der(subject_is_empty)?);
+        }
+        let requested: Vec<&[u8]> = requested.iter().map(|e| &e[..]).collect();
+
+        let attributes = if extensions.is_empty() {
+            vec![]
+        } else {
+            asn1::sequence(&[
+                &asn1::oid(asn1::OID_EXTENSION_REQUEST),
+                &asn1::set_of(&[&asn1::sequence(&requested)]),
+            ])
+        };
+
+        let info = asn1::sequence(&[
+            &asn1::unsigned_integer(&[0]),
+            &subject,
+            &spki,
+            &asn1::explicit(0, &attributes),
+        ]);
+        let der = x509::sign(self, key_id, key_algorithm, scheme, &info)?;
+
+        Ok(CertificateRequest {
+            der,
+            subject,
+            spki,
+            public_key,
+            subject_alt_names,
+        })
+    }
+}
+
+/// Find the subject alternative name extension in a CSR's extension request attribute, if any.
+fn requested_subject_alt_names(mut attributes: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    let extension_request = asn1::oid(asn1::OID_EXTENSION_REQUEST);
+
+    while !attributes.is_empty() {
+        let (attribute, rest) = asn1::read_element(asn1::TAG_SEQUENCE, attributes)?;
+        attributes = rest;
+
+        let (attribute_type, values) = asn1::split_element(attribute)?;
+        if attribute_type != &extension_request[..] {
+            continue;
+        }
+
+        let (values, _) = asn1::read_element(asn1::TAG_SET, values)?;
+        let (mut extensions, _) = asn1::read_element(asn1::TAG_SEQUENCE, values)?;
+
+        while !extensions.is_empty() {
+            let (extension, rest) = asn1::read_element(asn1::TAG_SEQUENCE, extensions)?;
+            extensions = rest;
+
+            let (extension_id, mut extension) = asn1::split_element(extension)?;
+            if extension_id != &asn1::oid(asn1::OID_SUBJECT_ALT_NAME)[..] {
+                continue;
+            }
+
+            if let Ok((_, rest)) = asn1::read_element(asn1::TAG_BOOLEAN, extension) {
+                extension = rest;
+            }
+            let (value, _) = asn1::read_element(asn1::TAG_OCTET_STRING, extension)?;
+            return Ok(Some(value.to_vec()));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Strip the PEM armor with the given label from `pem` and decode the contents.
+fn pem_decode(label: &str, pem: &str) -> Result<Vec<u8>, Error> {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+
+    let start = match pem.find(&begin) {
+        Some(start) => start + begin.len(),
+        None => bail!("no {} PEM block found", label),
+    };
+    let stop = match pem[start..].find(&end) {
+        Some(stop) => start + stop,
+        None => bail!("unterminated {} PEM block", label),
+    };
+
+    let base64: String = pem[start..stop]
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    STANDARD
+        .decode(base64)
+        .map_err(|e| format_err!("invalid {} PEM block: {}", label, e))
+}
+
+/// A certificate authority whose signing key is the asymmetric key `key_id` on the device.
+#[derive(Debug)]
+pub struct CertificateAuthority {
+    session: Session,
+    key_id: u16,
+    key_algorithm: Algorithm,
+    scheme: SignatureScheme,
+    subject: Vec<u8>,
+    /// The CA certificate's subject key identifier, named by the authority key identifier of
+    /// everything the CA signs.
+    key_identifier: Vec<u8>,
+    /// The SHA-1 hash of the CA's public key, which identifies it in OCSP responses.
+    key_hash: Vec<u8>,
+    certificate: Vec<u8>,
+    serial_numbers: Box<dyn SerialNumberGenerator>,
+}
+
+impl CertificateAuthority {
+    /// Use an existing CA certificate, whose key is `key_id`. Certificates will be signed with
+    /// `scheme`.
+    ///
+    /// The certificate's public key is checked against the key on the device.
+    pub fn new(
+        session: &Session,
+        key_id: u16,
+        scheme: SignatureScheme,
+        certificate: Vec<u8>,
+        serial_numbers: Box<dyn SerialNumberGenerator>,
+    ) -> Result<CertificateAuthority, Error> {
+        let public_key = session.get_pubkey(key_id)?;
+        if !scheme.supports_key(public_key.algorithm()) {
+            bail!(
+                "{:?} can't be used with a {} key",
+                scheme,
+                public_key.algorithm()
+            );
+        }
+
+        let fields = ca_certificate_fields(&certificate)?;
+        if PublicKey::from_spki_der(&fields.spki)? != public_key {
+            bail!("CA certificate doesn't match key {}", key_id);
+        }
+        let key_hash = x509::key_identifier(&fields.spki)?;
+
+        Ok(CertificateAuthority {
+            session: session.clone(),
+            key_id,
+            key_algorithm: public_key.algorithm(),
+            scheme,
+            subject: fields.subject,
+            // Certificates made elsewhere may use another method, and the authority key
+            // identifier has to match.
+            key_identifier: fields
+                .subject_key_identifier
+                .unwrap_or_else(|| key_hash.clone()),
+            key_hash,
+            certificate,
+            serial_numbers,
+        })
+    }
+
+    /// Use the CA certificate stored in the opaque object `certificate_id`, whose key is `key_id`.
+    pub fn from_opaque(
+        session: &Session,
+        key_id: u16,
+        scheme: SignatureScheme,
+        certificate_id: u16,
+        serial_numbers: Box<dyn SerialNumberGenerator>,
+    ) -> Result<CertificateAuthority, Error> {
+        let certificate = session.get_opaque_object(certificate_id)?;
+        CertificateAuthority::new(session, key_id, scheme, certificate, serial_numbers)
+    }
+
+    /// Create a root CA with a new self-signed certificate for `key_id`.
+    pub fn self_signed(
+        session: &Session,
+        key_id: u16,
+        scheme: SignatureScheme,
+        subject: &DistinguishedName,
+        profile: &CertificateProfile,
+        mut serial_numbers: Box<dyn SerialNumberGenerator>,
+    ) -> Result<CertificateAuthority, Error> {
+        let public_key = session.get_pubkey(key_id)?;
+        if !scheme.supports_key(public_key.algorithm()) {
+            bail!(
+                "{:?} can't be used with a {} key",
+                scheme,
+                public_key.algorithm()
+            );
+        }
+
+        let subject = subject.to_der();
+        let spki = public_key.to_spki_der()?;
+        let key_identifier = x509::key_identifier(&spki)?;
+        let serial_number = serial_numbers.next_serial_number(session)?;
+
+        let tbs = tbs_certificate(
+            SystemTime::now(),
+            &serial_number,
+            scheme,
+            &subject,
+            &key_identifier,
+            &subject,
+            &spki,
+            None,
+            profile,
+        )?;
+        let certificate = x509::sign(session, key_id, public_key.algorithm(), scheme, &tbs)?;
+
+        Ok(CertificateAuthority {
+            session: session.clone(),
+            key_id,
+            key_algorithm: public_key.algorithm(),
+            scheme,
+            subject,
+            key_identifier: key_identifier.clone(),
+            key_hash: key_identifier,
+            certificate,
+            serial_numbers,
+        })
+    }
+
+    /// The ID of the CA's key on the device.
+    pub fn key_id(&self) -> u16 {
+        self.key_id
+    }
+
+    /// The CA's own certificate, DER-encoded.
+    pub fn certificate(&self) -> &[u8] {
+        &self.certificate
+    }
+
+    /// The CA's own certificate, as a PEM `CERTIFICATE` block.
+    pub fn certificate_pem(&self) -> String {
+        pem_encode("CERTIFICATE", &self.certificate)
+    }
+
+    /// Store the CA's own certificate on the device as an `Algorithm::OpaqueX509Cert` object, so
+    /// it can later be loaded with `from_opaque`.
+    pub fn store_certificate(
+        &self,
+        object_id: u16,
+        label: &str,
+        domains: &[Domain],
+    ) -> Result<(), Error> {
+        self.session.put_opaque_object(
+            object_id,
+            label,
+            domains,
+            &[],
+            Algorithm::OpaqueX509Cert,
+            &self.certificate,
+        )
+    }
+
+    /// Issue a certificate for `public_key`, e.g. one read from the device with
+    /// `Session::get_pubkey`.
+    pub fn issue(
+        &mut self,
+        subject: &DistinguishedName,
+        public_key: &PublicKey,
+        subject_alt_names: &[SubjectAltName],
+        profile: &CertificateProfile,
+    ) -> Result<Vec<u8>, Error> {
+        let subject_alt_names = if subject_alt_names.is_empty() {
+            None
+        } else {
+            Some(subject_alt_names_extension_value(subject_alt_names)?)
+        };
+
+        self.issue_raw(
+            &subject.to_der(),
+            &public_key.to_spki_der()?,
+            subject_alt_names.as_ref().map(|names| &names[..]),
+            profile,
+        )
+    }
+
+    /// Issue a certificate for the subject, public key and subject alternative names in a
+    /// certificate signing request.
+    pub fn issue_from_csr(
+        &mut self,
+        request: &CertificateRequest,
+        profile: &CertificateProfile,
+    ) -> Result<Vec<u8>, Error> {
+        self.issue_raw(
+            &request.subject,
+            &request.spki,
+            request.subject_alt_names.as_ref().map(|names| &names[..]),
+            profile,
+        )
+    }
+
+    fn issue_raw(
+        &mut self,
+        subject: &[u8],
+        spki: &[u8],
+        subject_alt_names: Option<&[u8]>,
+        profile: &CertificateProfile,
+    ) -> Result<Vec<u8>, Error> {
+        let serial_number = self.serial_numbers.next_serial_number(&self.session)?;
+
+        let tbs = tbs_certificate(
+            SystemTime::now(),
+            &serial_number,
+            self.scheme,
+            &self.subject,
+            &self.key_identifier,
+            subject,
+            spki,
+            subject_alt_names,
+            profile,
+        )?;
+
+        self.sign(&tbs)
+    }
+
+    pub(crate) fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub(crate) fn scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
+
+    /// The CA's subject, DER-encoded, which is the issuer of everything it signs.
+    pub(crate) fn subject_der(&self) -> &[u8] {
+        &self.subject
+    }
+
+    pub(crate) fn key_identifier(&self) -> &[u8] {
+        &self.key_identifier
+    }
+
+    pub(crate) fn key_hash(&self) -> &[u8] {
+        &self.key_hash
+    }
+
+    /// Sign a DER-encoded structure with the CA's key, as in `x509::sign`.
+    pub(crate) fn sign(&self, tbs: &[u8]) -> Result<Vec<u8>, Error> {
+        x509::sign(
+            &self.session,
+            self.key_id,
+            self.key_algorithm,
+            self.scheme,
+            tbs,
+        )
+    }
+}
+
+/// Encode a version 3 `TBSCertificate` (RFC 5280) issued at `now`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn tbs_certificate(
+    now: SystemTime,
+    serial_number: &[u8],
+    scheme: SignatureScheme,
+    issuer: &[u8],
+    issuer_key_identifier: &[u8],
+    subject: &[u8],
+    spki: &[u8],
+    subject_alt_names: Option<&[u8]>,
+    profile: &CertificateProfile,
+) -> Result<Vec<u8>, Error> {
+    let validity = asn1::sequence(&[
+        &x509::time(now - profile.backdate)?,
+        &x509::time(now + profile.validity)?,
+    ]);
+
+    let subject_is_empty = subject == &asn1::sequence(&[])[..];
+    if subject_is_empty && subject_alt_names.is_none() {
+        bail!("a certificate with an empty subject needs subject alternative names");
+    }
+
+    let extensions = profile.extensions(
+        subject_is_empty,
+        &x509::key_identifier(spki)?,
+        issuer_key_identifier,
+        subject_alt_names,
+    );
+    let extensions: Vec<&[u8]> = extensions.iter().map(|e| &e[..]).collect();
+
+    Ok(asn1::sequence(&[
+        &asn1::explicit(0, &asn1::unsigned_integer(&[2])),
+        &asn1::unsigned_integer(serial_number),
+        &x509::algorithm_identifier(scheme),
+        issuer,
+        &validity,
+        subject,
+        spki,
+        &asn1::explicit(3, &asn1::sequence(&extensions)),
+    ]))
+}
+
+/// The fields of a CA certificate a `CertificateAuthority` needs.
+#[derive(Debug, PartialEq)]
+pub(crate) struct CaCertificateFields {
+    pub(crate) subject: Vec<u8>,
+    pub(crate) spki: Vec<u8>,
+    pub(crate) subject_key_identifier: Option<Vec<u8>>,
+}
+
+pub(crate) fn ca_certificate_fields(certificate: &[u8]) -> Result<CaCertificateFields, Error> {
+    let signed = x509::Signed::parse(certificate)?;
+    let (tbs, _) = asn1::read_element(asn1::TAG_SEQUENCE, signed.tbs)?;
+
+    // Skip the version, serial number, signature algorithm, issuer and validity.
+    let (_, tbs) = asn1::read_element(0xa0, tbs)?;
+    let (_, tbs) = asn1::split_element(tbs)?;
+    let (_, tbs) = asn1::split_element(tbs)?;
+    let (_, tbs) = asn1::split_element(tbs)?;
+    let (_, tbs) = asn1::split_element(tbs)?;
+    let (subject, tbs) = asn1::split_element(tbs)?;
+    let (spki, mut tbs) = asn1::split_element(tbs)?;
+
+    // The extensions may follow the issuer and subject unique IDs.
+    let mut subject_key_identifier = None;
+    while !tbs.is_empty() {
+        let (tag, contents, rest) = asn1::read_tlv(tbs)?;
+        if tag == 0xa3 {
+            subject_key_identifier = x509::subject_key_identifier(contents)?;
+        }
+        tbs = rest;
+    }
+
+    Ok(CaCertificateFields {
+        subject: subject.to_vec(),
+        spki: spki.to_vec(),
+        subject_key_identifier,
+    })
+}
@@ -0,0 +1,105 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Minimal CBOR encoding helpers (RFC 8949), covering just what's needed to build COSE structures.
+//!
+//! Everything is encoded deterministically as described in RFC 8949 section 4.2.1: lengths and
+//! integers use the shortest form, only definite lengths are used, and map entries are sorted by
+//! their encoded keys.
+
+const MAJOR_UNSIGNED: u8 = 0;
+const MAJOR_NEGATIVE: u8 = 1;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+const MAJOR_TAG: u8 = 6;
+
+/// Encode the initial byte and argument of a data item.
+fn head(major: u8, value: u64) -> Vec<u8> {
+    let major = major << 5;
+
+    if value < 24 {
+        vec![major | value as u8]
+    } else if value <= 0xff {
+        vec![major | 24, value as u8]
+    } else if value <= 0xffff {
+        let mut out = vec![major | 25];
+        out.extend_from_slice(&(value as u16).to_be_bytes());
+        out
+    } else if value <= 0xffff_ffff {
+        let mut out = vec![major | 26];
+        out.extend_from_slice(&(value as u32).to_be_bytes());
+        out
+    } else {
+        let mut out = vec![major | 27];
+        out.extend_from_slice(&value.to_be_bytes());
+        out
+    }
+}
+
+/// Encode an integer.
+pub(crate) fn int(value: i64) -> Vec<u8> {
+    if value >= 0 {
+        head(MAJOR_UNSIGNED, value as u64)
+    } else {
+        // -1 - value, without overflowing for i64::MIN.
+        head(MAJOR_NEGATIVE, !value as u64)
+    }
+}
+
+/// Encode a byte string.
+pub(crate) fn bytes(bytes: &[u8]) -> Vec<u8> {
+    let mut out = head(MAJOR_BYTES, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+    out
+}
+
+/// Encode a text string.
+pub(crate) fn text(text: &str) -> Vec<u8> {
+    let mut out = head(MAJOR_TEXT, text.len() as u64);
+    out.extend_from_slice(text.as_bytes());
+    out
+}
+
+/// Encode an array from already-encoded items.
+pub(crate) fn array(items: &[&[u8]]) -> Vec<u8> {
+    let mut out = head(MAJOR_ARRAY, items.len() as u64);
+    out.extend_from_slice(&items.concat());
+    out
+}
+
+/// Encode a map from already-encoded keys and values, sorting the entries by key.
+pub(crate) fn map(entries: &[(&[u8], &[u8])]) -> Vec<u8> {
+    let mut entries = entries.to_vec();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut out = head(MAJOR_MAP, entries.len() as u64);
+    for (key, value) in entries {
+        out.extend_from_slice(key);
+        out.extend_from_slice(value);
+    }
+    out
+}
+
+/// Encode a tagged data item.
+pub(crate) fn tag(number: u64, item: &[u8]) -> Vec<u8> {
+    let mut out = head(MAJOR_TAG, number);
+    out.extend_from_slice(item);
+    out
+}
+
+pub(crate) fn null() -> Vec<u8> {
+    vec![0xf6]
+}
@@ -94,6 +94,15 @@ pub struct Connector {
 }
 
 impl Connector {
+    /// A connector which was never connected, for tests which need one but don't use the device.
+    /// Every command sent through it fails.
+    #[cfg(test)]
+    pub(crate) fn disconnected() -> Connector {
+        Connector {
+            this: Arc::new(ConnectorPtr(AtomicPtr::new(ptr::null_mut()))),
+        }
+    }
+
     fn new(url: CString) -> Result<Connector, Error> {
         let mut connector_ptr: *mut yh_connector = ptr::null_mut();
 
@@ -188,7 +197,7 @@ impl Connector {
             }
         }
 
-        Ok(Session::new(session_ptr))
+        Ok(Session::new(session_ptr, auth_key_id, self.clone()))
     }
 
     pub fn get_device_info(&self) -> Result<DeviceInfo, Error> {
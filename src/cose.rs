@@ -0,0 +1,332 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! COSE_Sign1 messages (RFC 9052) signed by keys on the device, and COSE_Key encodings of public
+//! keys so verifiers can be provisioned with them.
+//!
+//! All CBOR is encoded deterministically, so the same inputs always produce the same protected
+//! header and Sig_structure bytes.
+
+use cbor;
+use ecdsa::EcdsaSignature;
+use public_key::{pad_to, RSA_PUBLIC_EXPONENT};
+use session::Session;
+use types::*;
+
+use failure::Error;
+
+use std::fmt;
+
+/// COSE header parameter labels (RFC 9052 section 3.1).
+const HEADER_ALG: i64 = 1;
+const HEADER_KID: i64 = 4;
+
+/// COSE_Key parameter labels (RFC 9052 section 7.1, RFC 9053 section 7).
+const KEY_KTY: i64 = 1;
+const KEY_KID: i64 = 2;
+const KEY_ALG: i64 = 3;
+const KEY_CRV: i64 = -1;
+const KEY_X: i64 = -2;
+const KEY_Y: i64 = -3;
+const KEY_RSA_N: i64 = -1;
+const KEY_RSA_E: i64 = -2;
+
+const KTY_OKP: i64 = 1;
+const KTY_EC2: i64 = 2;
+const KTY_RSA: i64 = 3;
+
+const CRV_P256: i64 = 1;
+const CRV_P384: i64 = 2;
+const CRV_P521: i64 = 3;
+const CRV_ED25519: i64 = 6;
+const CRV_SECP256K1: i64 = 8;
+
+/// The CBOR tag for a COSE_Sign1 message.
+const TAG_COSE_SIGN1: u64 = 18;
+
+/// A COSE signature algorithm (RFC 9053, RFC 8230) which can be computed on the device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CoseAlgorithm {
+    /// ECDSA on P-256 with SHA-256.
+    ES256,
+    /// ECDSA on P-384 with SHA-384.
+    ES384,
+    /// ECDSA on P-521 with SHA-512.
+    ES512,
+    /// Ed25519.
+    EdDSA,
+    /// RSASSA-PSS with SHA-256 and MGF1 with SHA-256.
+    PS256,
+}
+
+impl CoseAlgorithm {
+    /// The algorithm's identifier in the IANA COSE Algorithms registry.
+    pub fn id(&self) -> i64 {
+        match *self {
+            CoseAlgorithm::ES256 => -7,
+            CoseAlgorithm::ES384 => -35,
+            CoseAlgorithm::ES512 => -36,
+            CoseAlgorithm::EdDSA => -8,
+            CoseAlgorithm::PS256 => -37,
+        }
+    }
+
+    /// The default algorithm for a key: the ECDSA variant matching the curve, EdDSA for Ed25519
+    /// keys, and PS256 for RSA keys. Other curves have no COSE algorithm here.
+    pub fn for_key(public_key: &PublicKey) -> Option<CoseAlgorithm> {
+        match public_key.algorithm() {
+            Algorithm::EcP256 => Some(CoseAlgorithm::ES256),
+            Algorithm::EcP384 => Some(CoseAlgorithm::ES384),
+            Algorithm::EcP521 => Some(CoseAlgorithm::ES512),
+            Algorithm::EcEd25519 => Some(CoseAlgorithm::EdDSA),
+            Algorithm::Rsa2048 | Algorithm::Rsa3072 | Algorithm::Rsa4096 => {
+                Some(CoseAlgorithm::PS256)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether this algorithm can be used with a key of algorithm `key_algorithm`. ECDSA
+    /// algorithms are tied to a single curve.
+    pub fn supports_key(&self, key_algorithm: Algorithm) -> bool {
+        match *self {
+            CoseAlgorithm::ES256 => key_algorithm == Algorithm::EcP256,
+            CoseAlgorithm::ES384 => key_algorithm == Algorithm::EcP384,
+            CoseAlgorithm::ES512 => key_algorithm == Algorithm::EcP521,
+            CoseAlgorithm::EdDSA => key_algorithm == Algorithm::EcEd25519,
+            CoseAlgorithm::PS256 => matches!(
+                key_algorithm,
+                Algorithm::Rsa2048 | Algorithm::Rsa3072 | Algorithm::Rsa4096
+            ),
+        }
+    }
+
+    fn scheme(&self) -> SignatureScheme {
+        match *self {
+            CoseAlgorithm::ES256 => SignatureScheme::EcdsaSha256,
+            CoseAlgorithm::ES384 => SignatureScheme::EcdsaSha384,
+            CoseAlgorithm::ES512 => SignatureScheme::EcdsaSha512,
+            CoseAlgorithm::EdDSA => SignatureScheme::Ed25519,
+            CoseAlgorithm::PS256 => SignatureScheme::RsaPssSha256,
+        }
+    }
+}
+
+impl fmt::Display for CoseAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            CoseAlgorithm::ES256 => "ES256",
+            CoseAlgorithm::ES384 => "ES384",
+            CoseAlgorithm::ES512 => "ES512",
+            CoseAlgorithm::EdDSA => "EdDSA",
+            CoseAlgorithm::PS256 => "PS256",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Signs COSE_Sign1 messages with an asymmetric key on the device.
+#[derive(Clone, Debug)]
+pub struct CoseSigner {
+    session: Session,
+    key_id: u16,
+    public_key: PublicKey,
+    algorithm: CoseAlgorithm,
+    kid: Option<Vec<u8>>,
+}
+
+impl CoseSigner {
+    /// Create a signer for the asymmetric key `key_id`, checking that `algorithm` can be used
+    /// with it.
+    pub fn new(
+        session: &Session,
+        key_id: u16,
+        algorithm: CoseAlgorithm,
+    ) -> Result<CoseSigner, Error> {
+        let public_key = session.get_pubkey(key_id)?;
+        if !algorithm.supports_key(public_key.algorithm()) {
+            bail!(
+                "{} can't be used with a {} key",
+                algorithm,
+                public_key.algorithm()
+            );
+        }
+
+        Ok(CoseSigner {
+            session: session.clone(),
+            key_id,
+            public_key,
+            algorithm,
+            kid: None,
+        })
+    }
+
+    /// Put `kid` in the unprotected header of each message, and in the key's `cose_key`.
+    pub fn kid(self, kid: &[u8]) -> CoseSigner {
+        CoseSigner {
+            kid: Some(kid.to_vec()),
+            ..self
+        }
+    }
+
+    /// The ID of the key on the device.
+    pub fn key_id(&self) -> u16 {
+        self.key_id
+    }
+
+    pub fn algorithm(&self) -> CoseAlgorithm {
+        self.algorithm
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    /// Sign `payload`, returning a tagged COSE_Sign1 message which carries it.
+    ///
+    /// `external_aad` is application data covered by the signature but not included in the
+    /// message; pass an empty slice if there is none.
+    pub fn sign1(&self, payload: &[u8], external_aad: &[u8]) -> Result<Vec<u8>, Error> {
+        self.sign1_inner(payload, external_aad, false)
+    }
+
+    /// Sign `payload` as with `sign1`, but leave it out of the message, which the verifier must
+    /// then be given separately.
+    pub fn sign1_detached(&self, payload: &[u8], external_aad: &[u8]) -> Result<Vec<u8>, Error> {
+        self.sign1_inner(payload, external_aad, true)
+    }
+
+    /// The key as a COSE_Key carrying its `alg` and, if set, `kid` parameters.
+    pub fn cose_key(&self) -> Result<Vec<u8>, Error> {
+        cose_key(
+            &self.public_key,
+            Some(self.algorithm),
+            self.kid.as_ref().map(|kid| &kid[..]),
+        )
+    }
+
+    fn sign1_inner(
+        &self,
+        payload: &[u8],
+        external_aad: &[u8],
+        detached: bool,
+    ) -> Result<Vec<u8>, Error> {
+        let protected = cbor::map(&[(&cbor::int(HEADER_ALG), &cbor::int(self.algorithm.id()))]);
+        let to_be_signed = sig_structure(&protected, external_aad, payload);
+
+        let mut signature = self.session.sign_message_with_key_algorithm(
+            self.key_id,
+            self.public_key.algorithm(),
+            self.algorithm.scheme(),
+            &to_be_signed,
+        )?;
+
+        // COSE uses fixed-width r || s rather than DER for ECDSA (RFC 9053 section 2.1).
+        if let PublicKey::Ecc(curve, _, _) = self.public_key {
+            signature = EcdsaSignature::from_der(curve, signature)?.to_bytes();
+        }
+
+        let unprotected = match self.kid {
+            Some(ref kid) => cbor::map(&[(&cbor::int(HEADER_KID), &cbor::bytes(kid))]),
+            None => cbor::map(&[]),
+        };
+        let payload = if detached {
+            cbor::null()
+        } else {
+            cbor::bytes(payload)
+        };
+
+        Ok(cbor::tag(
+            TAG_COSE_SIGN1,
+            &cbor::array(&[
+                &cbor::bytes(&protected),
+                &unprotected,
+                &payload,
+                &cbor::bytes(&signature),
+            ]),
+        ))
+    }
+}
+
+/// The Sig_structure signed for a COSE_Sign1 message (RFC 9052 section 4.4), with the encoded
+/// protected header.
+fn sig_structure(protected: &[u8], external_aad: &[u8], payload: &[u8]) -> Vec<u8> {
+    cbor::array(&[
+        &cbor::text("Signature1"),
+        &cbor::bytes(protected),
+        &cbor::bytes(external_aad),
+        &cbor::bytes(payload),
+    ])
+}
+
+/// Encode `public_key` as a COSE_Key, optionally restricted to `algorithm` and labelled with
+/// `kid`.
+fn cose_key(
+    public_key: &PublicKey,
+    algorithm: Option<CoseAlgorithm>,
+    kid: Option<&[u8]>,
+) -> Result<Vec<u8>, Error> {
+    let mut entries: Vec<(Vec<u8>, Vec<u8>)> = match *public_key {
+        PublicKey::Rsa(_, ref n) => {
+            let skip = n.iter().take_while(|b| **b == 0).count();
+            vec![
+                (cbor::int(KEY_KTY), cbor::int(KTY_RSA)),
+                (cbor::int(KEY_RSA_N), cbor::bytes(&n[skip..])),
+                (cbor::int(KEY_RSA_E), cbor::bytes(RSA_PUBLIC_EXPONENT)),
+            ]
+        }
+        PublicKey::Ecc(curve, ref x, ref y) => {
+            let crv = match curve {
+                Algorithm::EcP256 => CRV_P256,
+                Algorithm::EcP384 => CRV_P384,
+                Algorithm::EcP521 => CRV_P521,
+                Algorithm::EcK256 => CRV_SECP256K1,
+                a => bail!("to_cose_key: no COSE curve for {}", a),
+            };
+            // Checked above that this is a supported curve.
+            let field_len = curve.ec_field_len().unwrap();
+
+            vec![
+                (cbor::int(KEY_KTY), cbor::int(KTY_EC2)),
+                (cbor::int(KEY_CRV), cbor::int(crv)),
+                (cbor::int(KEY_X), cbor::bytes(&pad_to(x, field_len))),
+                (cbor::int(KEY_Y), cbor::bytes(&pad_to(y, field_len))),
+            ]
+        }
+        PublicKey::Edc(_, ref a) => vec![
+            (cbor::int(KEY_KTY), cbor::int(KTY_OKP)),
+            (cbor::int(KEY_CRV), cbor::int(CRV_ED25519)),
+            (cbor::int(KEY_X), cbor::bytes(a)),
+        ],
+    };
+
+    if let Some(kid) = kid {
+        entries.push((cbor::int(KEY_KID), cbor::bytes(kid)));
+    }
+    if let Some(algorithm) = algorithm {
+        entries.push((cbor::int(KEY_ALG), cbor::int(algorithm.id())));
+    }
+
+    let entries: Vec<(&[u8], &[u8])> = entries.iter().map(|(k, v)| (&k[..], &v[..])).collect();
+    Ok(cbor::map(&entries))
+}
+
+impl PublicKey {
+    /// Encode the key as a COSE_Key (RFC 9052 section 7) with only its key type and key
+    /// parameters. Curves without a registered COSE identifier (P-224 and the Brainpool curves)
+    /// are an error.
+    pub fn to_cose_key(&self) -> Result<Vec<u8>, Error> {
+        cose_key(self, None, None)
+    }
+}
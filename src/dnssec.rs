@@ -0,0 +1,610 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! DNSSEC zone signing (RFC 4034) with KSKs and ZSKs kept on the device.
+//!
+//! `DnssecSigner` computes a key's DNSKEY record, key tag and DS records, and signs RRsets,
+//! producing RRSIG records. RSA (RFC 5702), ECDSA P-256 and P-384 (RFC 6605) and Ed25519
+//! (RFC 8080) keys are supported.
+
+use ecdsa::EcdsaSignature;
+use hash::HashAlgorithm;
+use public_key::RSA_PUBLIC_EXPONENT;
+use session::Session;
+use types::*;
+use x509::civil_from_days;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use failure::Error;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The DNSKEY Zone Key flag, which is set on every key that signs zone data.
+pub const DNSKEY_FLAG_ZONE: u16 = 0x0100;
+
+/// The DNSKEY Secure Entry Point flag, which is conventionally set on key signing keys.
+pub const DNSKEY_FLAG_SEP: u16 = 0x0001;
+
+/// The `IN` class.
+pub const CLASS_IN: u16 = 1;
+
+const DNSKEY_PROTOCOL: u8 = 3;
+
+/// A DNSSEC signing algorithm, with its number from the IANA registry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DnssecAlgorithm {
+    RsaSha256 = 8,
+    RsaSha512 = 10,
+    EcdsaP256Sha256 = 13,
+    EcdsaP384Sha384 = 14,
+    Ed25519 = 15,
+}
+
+impl DnssecAlgorithm {
+    /// The algorithm number used in DNSKEY, DS and RRSIG records.
+    pub fn number(&self) -> u8 {
+        *self as u8
+    }
+
+    /// The usual algorithm for a key: RSASHA256 for RSA keys, and the only choice for the others.
+    pub fn for_key(public_key: &PublicKey) -> Result<DnssecAlgorithm, Error> {
+        Ok(match public_key.algorithm() {
+            Algorithm::Rsa2048 | Algorithm::Rsa3072 | Algorithm::Rsa4096 => {
+                DnssecAlgorithm::RsaSha256
+            }
+            Algorithm::EcP256 => DnssecAlgorithm::EcdsaP256Sha256,
+            Algorithm::EcP384 => DnssecAlgorithm::EcdsaP384Sha384,
+            Algorithm::EcEd25519 => DnssecAlgorithm::Ed25519,
+            algorithm => bail!("{} keys can't be used for DNSSEC", algorithm),
+        })
+    }
+
+    fn scheme(&self) -> SignatureScheme {
+        match *self {
+            DnssecAlgorithm::RsaSha256 => SignatureScheme::RsaPkcs1v15Sha256,
+            DnssecAlgorithm::RsaSha512 => SignatureScheme::RsaPkcs1v15Sha512,
+            DnssecAlgorithm::EcdsaP256Sha256 => SignatureScheme::EcdsaSha256,
+            DnssecAlgorithm::EcdsaP384Sha384 => SignatureScheme::EcdsaSha384,
+            DnssecAlgorithm::Ed25519 => SignatureScheme::Ed25519,
+        }
+    }
+}
+
+/// The digest used in a DS record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DigestType {
+    Sha256 = 2,
+    Sha384 = 4,
+}
+
+impl DigestType {
+    fn hash(&self) -> HashAlgorithm {
+        match *self {
+            DigestType::Sha256 => HashAlgorithm::Sha256,
+            DigestType::Sha384 => HashAlgorithm::Sha384,
+        }
+    }
+}
+
+/// Encode a domain name in canonical wire format (RFC 4034 section 6.2): uncompressed and with
+/// ASCII letters lowercased. Names are always treated as fully qualified, so the trailing dot is
+/// optional. `\X` and `\DDD` escapes are accepted.
+pub fn name_to_wire(name: &str) -> Result<Vec<u8>, Error> {
+    if name == "." {
+        return Ok(vec![0]);
+    }
+
+    let mut wire = Vec::new();
+    let mut label = Vec::new();
+    let mut bytes = name.bytes();
+
+    loop {
+        let byte = bytes.next();
+        match byte {
+            Some(b'.') | None => {
+                // Only a trailing dot may leave an empty label behind it.
+                if label.is_empty() && (byte.is_some() || wire.is_empty()) {
+                    bail!("{:?} has an empty label", name);
+                }
+                if label.len() > 63 {
+                    bail!("{:?} has a label longer than 63 bytes", name);
+                }
+                if !label.is_empty() {
+                    wire.push(label.len() as u8);
+                    wire.append(&mut label);
+                }
+                if byte.is_none() {
+                    break;
+                }
+            }
+            Some(b'\\') => match bytes.next() {
+                Some(digit @ b'0'..=b'9') => {
+                    let mut value = u32::from(digit - b'0');
+                    for _ in 0..2 {
+                        match bytes.next() {
+                            Some(digit @ b'0'..=b'9') => {
+                                value = value * 10 + u32::from(digit - b'0')
+                            }
+                            _ => bail!("{:?} has a malformed \\DDD escape", name),
+                        }
+                    }
+                    if value > 255 {
+                        bail!("{:?} has a malformed \\DDD escape", name);
+                    }
+                    label.push((value as u8).to_ascii_lowercase());
+                }
+                Some(byte) => label.push(byte.to_ascii_lowercase()),
+                None => bail!("{:?} ends with a backslash", name),
+            },
+            Some(byte) => label.push(byte.to_ascii_lowercase()),
+        }
+    }
+
+    wire.push(0);
+    if wire.len() > 255 {
+        bail!("{:?} is longer than 255 bytes", name);
+    }
+    Ok(wire)
+}
+
+/// Format a wire-format name in presentation format, with a trailing dot.
+fn wire_to_name(wire: &[u8]) -> String {
+    let labels = wire_labels(wire);
+    if labels.is_empty() {
+        return ".".to_string();
+    }
+
+    let mut name = String::new();
+    for label in labels {
+        for &byte in label {
+            match byte {
+                b'.' | b'\\' | b'"' | b'(' | b')' | b';' | b'@' | b'$' => {
+                    name.push('\\');
+                    name.push(byte as char);
+                }
+                0x21..=0x7e => name.push(byte as char),
+                _ => name.push_str(&format!("\\{:03}", byte)),
+            }
+        }
+        name.push('.');
+    }
+    name
+}
+
+/// The labels of a wire-format name produced by `name_to_wire`, excluding the root.
+fn wire_labels(wire: &[u8]) -> Vec<&[u8]> {
+    let mut labels = Vec::new();
+    let mut rest = wire;
+    while !rest.is_empty() && rest[0] != 0 {
+        let len = rest[0] as usize;
+        labels.push(&rest[1..=len]);
+        rest = &rest[len + 1..];
+    }
+    labels
+}
+
+/// Whether the wire-format name `name` is `zone` or below it.
+fn is_in_zone(name: &[u8], zone: &[u8]) -> bool {
+    let name = wire_labels(name);
+    let zone = wire_labels(zone);
+    name.len() >= zone.len() && name[name.len() - zone.len()..] == zone[..]
+}
+
+/// Encode the RDATA of a DNSKEY record for `public_key`.
+pub fn dnskey_rdata(
+    public_key: &PublicKey,
+    flags: u16,
+    algorithm: DnssecAlgorithm,
+) -> Result<Vec<u8>, Error> {
+    let mut rdata = vec![
+        (flags >> 8) as u8,
+        flags as u8,
+        DNSKEY_PROTOCOL,
+        algorithm.number(),
+    ];
+
+    match (public_key, algorithm) {
+        (&PublicKey::Rsa(_, ref n), DnssecAlgorithm::RsaSha256)
+        | (&PublicKey::Rsa(_, ref n), DnssecAlgorithm::RsaSha512) => {
+            // RFC 3110 section 2: the exponent's length, the exponent and the modulus.
+            rdata.push(RSA_PUBLIC_EXPONENT.len() as u8);
+            rdata.extend_from_slice(RSA_PUBLIC_EXPONENT);
+            let leading_zeros = n.iter().take_while(|&&b| b == 0).count();
+            rdata.extend_from_slice(&n[leading_zeros..]);
+        }
+        (&PublicKey::Ecc(Algorithm::EcP256, _, _), DnssecAlgorithm::EcdsaP256Sha256)
+        | (&PublicKey::Ecc(Algorithm::EcP384, _, _), DnssecAlgorithm::EcdsaP384Sha384) => {
+            // RFC 6605 section 4: the point's coordinates without the uncompressed prefix.
+            rdata.extend_from_slice(&public_key.ec_point()?[1..]);
+        }
+        (&PublicKey::Edc(Algorithm::EcEd25519, ref a), DnssecAlgorithm::Ed25519) => {
+            rdata.extend_from_slice(a)
+        }
+        _ => bail!(
+            "{} keys can't be used with DNSSEC algorithm {:?}",
+            public_key.algorithm(),
+            algorithm
+        ),
+    }
+
+    Ok(rdata)
+}
+
+/// Compute the key tag of a DNSKEY record from its RDATA (RFC 4034 appendix B).
+pub fn key_tag(dnskey_rdata: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    for (i, &byte) in dnskey_rdata.iter().enumerate() {
+        sum += if i % 2 == 0 {
+            u32::from(byte) << 8
+        } else {
+            u32::from(byte)
+        };
+    }
+    sum += (sum >> 16) & 0xffff;
+    (sum & 0xffff) as u16
+}
+
+/// Encode the RDATA of a DS record for the DNSKEY record with owner `owner` and RDATA
+/// `dnskey_rdata` (RFC 4034 section 5).
+pub fn ds_rdata(
+    owner: &str,
+    dnskey_rdata: &[u8],
+    digest_type: DigestType,
+) -> Result<Vec<u8>, Error> {
+    if dnskey_rdata.len() < 4 {
+        bail!("DNSKEY RDATA is too short");
+    }
+
+    let mut digest_input = name_to_wire(owner)?;
+    digest_input.extend_from_slice(dnskey_rdata);
+
+    let tag = key_tag(dnskey_rdata);
+    let mut rdata = vec![
+        (tag >> 8) as u8,
+        tag as u8,
+        dnskey_rdata[3],
+        digest_type as u8,
+    ];
+    rdata.extend_from_slice(&digest_type.hash().digest(&digest_input));
+    Ok(rdata)
+}
+
+/// A set of resource records with the same owner, type and class. `rdata` holds each record's
+/// RDATA in canonical form (RFC 4034 section 6.2): in particular, domain names embedded in the
+/// RDATA of types such as NS, MX and SOA must be uncompressed and lowercased.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rrset {
+    pub name: String,
+    pub rr_type: u16,
+    pub class: u16,
+    pub ttl: u32,
+    pub rdata: Vec<Vec<u8>>,
+}
+
+/// An RRSIG record (RFC 4034 section 3).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rrsig {
+    pub name: String,
+    pub class: u16,
+    pub ttl: u32,
+    pub type_covered: u16,
+    pub algorithm: DnssecAlgorithm,
+    pub labels: u8,
+    pub original_ttl: u32,
+    /// Seconds since the epoch, modulo 2^32 (RFC 4034 section 3.1.5).
+    pub expiration: u32,
+    /// Seconds since the epoch, modulo 2^32.
+    pub inception: u32,
+    pub key_tag: u16,
+    pub signer_name: String,
+    pub signature: Vec<u8>,
+}
+
+impl Rrsig {
+    /// Encode the record's RDATA.
+    pub fn rdata(&self) -> Result<Vec<u8>, Error> {
+        let mut rdata = self.rdata_without_signature()?;
+        rdata.extend_from_slice(&self.signature);
+        Ok(rdata)
+    }
+
+    fn rdata_without_signature(&self) -> Result<Vec<u8>, Error> {
+        let mut rdata = Vec::new();
+        rdata.extend_from_slice(&self.type_covered.to_be_bytes());
+        rdata.push(self.algorithm.number());
+        rdata.push(self.labels);
+        rdata.extend_from_slice(&self.original_ttl.to_be_bytes());
+        rdata.extend_from_slice(&self.expiration.to_be_bytes());
+        rdata.extend_from_slice(&self.inception.to_be_bytes());
+        rdata.extend_from_slice(&self.key_tag.to_be_bytes());
+        rdata.extend_from_slice(&name_to_wire(&self.signer_name)?);
+        Ok(rdata)
+    }
+
+    /// The data covered by the signature (RFC 4034 section 3.1.8.1): the RRSIG RDATA without the
+    /// signature, followed by the RRset's records in canonical form and order.
+    pub(crate) fn signed_data(&self, rrset: &Rrset) -> Result<Vec<u8>, Error> {
+        let owner = name_to_wire(&rrset.name)?;
+
+        let mut rdata: Vec<&Vec<u8>> = rrset.rdata.iter().collect();
+        rdata.sort();
+        rdata.dedup();
+
+        let mut data = self.rdata_without_signature()?;
+        for rdata in rdata {
+            if rdata.len() > 0xffff {
+                bail!("RDATA is longer than 65535 bytes");
+            }
+            data.extend_from_slice(&owner);
+            data.extend_from_slice(&rrset.rr_type.to_be_bytes());
+            data.extend_from_slice(&rrset.class.to_be_bytes());
+            data.extend_from_slice(&self.original_ttl.to_be_bytes());
+            data.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            data.extend_from_slice(rdata);
+        }
+        Ok(data)
+    }
+}
+
+impl fmt::Display for Rrsig {
+    /// Format the record in zone file presentation format.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = name_to_wire(&self.name).map_err(|_| fmt::Error)?;
+        let signer_name = name_to_wire(&self.signer_name).map_err(|_| fmt::Error)?;
+        write!(
+            f,
+            "{} {} {} RRSIG {} {} {} {} {} {} {} {} {}",
+            wire_to_name(&name),
+            self.ttl,
+            class_name(self.class),
+            type_name(self.type_covered),
+            self.algorithm.number(),
+            self.labels,
+            self.original_ttl,
+            format_time(self.expiration),
+            format_time(self.inception),
+            self.key_tag,
+            wire_to_name(&signer_name),
+            STANDARD.encode(&self.signature)
+        )
+    }
+}
+
+/// The mnemonic of a record type, or `TYPEn` (RFC 3597) for types without one here.
+fn type_name(rr_type: u16) -> String {
+    let name = match rr_type {
+        1 => "A",
+        2 => "NS",
+        5 => "CNAME",
+        6 => "SOA",
+        12 => "PTR",
+        15 => "MX",
+        16 => "TXT",
+        28 => "AAAA",
+        33 => "SRV",
+        35 => "NAPTR",
+        43 => "DS",
+        44 => "SSHFP",
+        46 => "RRSIG",
+        47 => "NSEC",
+        48 => "DNSKEY",
+        50 => "NSEC3",
+        51 => "NSEC3PARAM",
+        52 => "TLSA",
+        59 => "CDS",
+        60 => "CDNSKEY",
+        64 => "SVCB",
+        65 => "HTTPS",
+        257 => "CAA",
+        _ => return format!("TYPE{}", rr_type),
+    };
+    name.to_string()
+}
+
+fn class_name(class: u16) -> String {
+    match class {
+        CLASS_IN => "IN".to_string(),
+        _ => format!("CLASS{}", class),
+    }
+}
+
+/// Format an RRSIG time as `YYYYMMDDHHmmSS` in UTC.
+fn format_time(time: u32) -> String {
+    let seconds = u64::from(time);
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    let seconds_of_day = seconds % 86_400;
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60
+    )
+}
+
+/// The number of labels in a wire-format owner name, not counting the root or a leading wildcard
+/// (RFC 4034 section 3.1.3).
+fn label_count(wire: &[u8]) -> u8 {
+    let labels = wire_labels(wire);
+    let wildcard = labels.first() == Some(&&b"*"[..]);
+    labels.len() as u8 - if wildcard { 1 } else { 0 }
+}
+
+fn rrsig_time(time: SystemTime) -> Result<u32, Error> {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| format_err!("times before 1970 are not supported"))?
+        .as_secs();
+    // Serial number arithmetic (RFC 1982) makes the wrapped value meaningful.
+    Ok(seconds as u32)
+}
+
+/// Signs the RRsets of a zone with a key on the device.
+#[derive(Clone, Debug)]
+pub struct DnssecSigner {
+    session: Session,
+    key_id: u16,
+    public_key: PublicKey,
+    algorithm: DnssecAlgorithm,
+    zone: String,
+    flags: u16,
+    dnskey_rdata: Vec<u8>,
+}
+
+impl DnssecSigner {
+    /// Create a signer for the zone `zone` with the key `key_id`, using `DnssecAlgorithm::for_key`.
+    /// `flags` are the DNSKEY flags, usually `DNSKEY_FLAG_ZONE` for a ZSK and
+    /// `DNSKEY_FLAG_ZONE | DNSKEY_FLAG_SEP` for a KSK.
+    pub fn new(
+        session: &Session,
+        key_id: u16,
+        zone: &str,
+        flags: u16,
+    ) -> Result<DnssecSigner, Error> {
+        let public_key = session.get_pubkey(key_id)?;
+        let algorithm = DnssecAlgorithm::for_key(&public_key)?;
+        let zone = wire_to_name(&name_to_wire(zone)?);
+        let dnskey_rdata = dnskey_rdata(&public_key, flags, algorithm)?;
+
+        Ok(DnssecSigner {
+            session: session.clone(),
+            key_id,
+            public_key,
+            algorithm,
+            zone,
+            flags,
+            dnskey_rdata,
+        })
+    }
+
+    /// Use `algorithm` instead of the key's default, e.g. `DnssecAlgorithm::RsaSha512` for an RSA
+    /// key.
+    pub fn with_algorithm(mut self, algorithm: DnssecAlgorithm) -> Result<DnssecSigner, Error> {
+        self.dnskey_rdata = dnskey_rdata(&self.public_key, self.flags, algorithm)?;
+        self.algorithm = algorithm;
+        Ok(self)
+    }
+
+    /// The ID of the key on the device.
+    pub fn key_id(&self) -> u16 {
+        self.key_id
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    pub fn algorithm(&self) -> DnssecAlgorithm {
+        self.algorithm
+    }
+
+    /// The zone's name, in presentation format with a trailing dot.
+    pub fn zone(&self) -> &str {
+        &self.zone
+    }
+
+    pub fn dnskey_rdata(&self) -> &[u8] {
+        &self.dnskey_rdata
+    }
+
+    pub fn key_tag(&self) -> u16 {
+        key_tag(&self.dnskey_rdata)
+    }
+
+    /// The key's DNSKEY record in presentation format.
+    pub fn dnskey_record(&self, ttl: u32) -> String {
+        format!(
+            "{} {} IN DNSKEY {} {} {} {}",
+            self.zone,
+            ttl,
+            self.flags,
+            DNSKEY_PROTOCOL,
+            self.algorithm.number(),
+            STANDARD.encode(&self.dnskey_rdata[4..])
+        )
+    }
+
+    /// The RDATA of a DS record for the key, for the parent zone.
+    pub fn ds_rdata(&self, digest_type: DigestType) -> Result<Vec<u8>, Error> {
+        ds_rdata(&self.zone, &self.dnskey_rdata, digest_type)
+    }
+
+    /// A DS record for the key in presentation format, for the parent zone.
+    pub fn ds_record(&self, ttl: u32, digest_type: DigestType) -> Result<String, Error> {
+        let rdata = self.ds_rdata(digest_type)?;
+        let digest: String = rdata[4..].iter().map(|b| format!("{:02X}", b)).collect();
+        Ok(format!(
+            "{} {} IN DS {} {} {} {}",
+            self.zone,
+            ttl,
+            self.key_tag(),
+            self.algorithm.number(),
+            digest_type as u8,
+            digest
+        ))
+    }
+
+    /// Sign `rrset`, which must belong to the zone, producing an RRSIG record valid from
+    /// `inception` to `expiration`.
+    pub fn sign_rrset(
+        &self,
+        rrset: &Rrset,
+        inception: SystemTime,
+        expiration: SystemTime,
+    ) -> Result<Rrsig, Error> {
+        let owner = name_to_wire(&rrset.name)?;
+        if !is_in_zone(&owner, &name_to_wire(&self.zone)?) {
+            bail!("{} is not in the zone {}", rrset.name, self.zone);
+        }
+        if rrset.rdata.is_empty() {
+            bail!("can't sign an empty RRset");
+        }
+        if expiration <= inception {
+            bail!("RRSIG expiration must be after its inception");
+        }
+
+        let mut rrsig = Rrsig {
+            name: wire_to_name(&owner),
+            class: rrset.class,
+            ttl: rrset.ttl,
+            type_covered: rrset.rr_type,
+            algorithm: self.algorithm,
+            labels: label_count(&owner),
+            original_ttl: rrset.ttl,
+            expiration: rrsig_time(expiration)?,
+            inception: rrsig_time(inception)?,
+            key_tag: self.key_tag(),
+            signer_name: self.zone.clone(),
+            signature: Vec::new(),
+        };
+
+        let data = rrsig.signed_data(rrset)?;
+        let signature = self.session.sign_message_with_key_algorithm(
+            self.key_id,
+            self.public_key.algorithm(),
+            self.algorithm.scheme(),
+            &data,
+        )?;
+
+        rrsig.signature = match self.public_key {
+            // RFC 6605 section 4: ECDSA signatures are r || s.
+            PublicKey::Ecc(curve, _, _) => EcdsaSignature::from_der(curve, signature)?.to_bytes(),
+            _ => signature,
+        };
+        Ok(rrsig)
+    }
+}
@@ -0,0 +1,185 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decoding and re-encoding of the DER ECDSA signatures returned by `Session::sign_ecdsa`.
+
+use asn1;
+use types::*;
+
+use failure::Error;
+use k256;
+
+/// An ECDSA signature, held as its `r` and `s` components.
+///
+/// Both components are stored left-padded to the field length of the curve, so `to_bytes` gives
+/// the fixed-width `r || s` form used by IEEE P1363, JOSE and COSE.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EcdsaSignature {
+    curve: Algorithm,
+    r: Vec<u8>,
+    s: Vec<u8>,
+}
+
+impl EcdsaSignature {
+    /// Parse a DER-encoded `Ecdsa-Sig-Value` (RFC 3279) made with a key on `curve`, as returned by
+    /// `Session::sign_ecdsa`.
+    ///
+    /// For `Algorithm::EcK256` the signature is normalized to low-S form, since the device doesn't
+    /// do so and most secp256k1 consumers reject high-S signatures.
+    pub fn from_der<T: AsRef<[u8]>>(curve: Algorithm, der: T) -> Result<EcdsaSignature, Error> {
+        let (tag, contents, rest) = asn1::read_tlv(der.as_ref())?;
+        if tag != asn1::TAG_SEQUENCE || !rest.is_empty() {
+            bail!("invalid ECDSA signature: expected a single DER SEQUENCE");
+        }
+
+        let (r, contents) = asn1::read_unsigned_integer(contents)?;
+        let (s, contents) = asn1::read_unsigned_integer(contents)?;
+        if !contents.is_empty() {
+            bail!("invalid ECDSA signature: trailing data in SEQUENCE");
+        }
+
+        EcdsaSignature::from_components(curve, r, s)
+    }
+
+    /// Parse a fixed-width `r || s` signature made with a key on `curve`.
+    ///
+    /// As with `from_der`, `Algorithm::EcK256` signatures are normalized to low-S form.
+    pub fn from_bytes<T: AsRef<[u8]>>(curve: Algorithm, bytes: T) -> Result<EcdsaSignature, Error> {
+        let bytes = bytes.as_ref();
+        let field_len = match curve.ec_field_len() {
+            Some(len) => len,
+            None => bail!("{} is not an elliptic curve", curve),
+        };
+
+        if bytes.len() != 2 * field_len {
+            bail!(
+                "invalid ECDSA signature: {} bytes, expected {} for {}",
+                bytes.len(),
+                2 * field_len,
+                curve
+            );
+        }
+
+        let (r, s) = bytes.split_at(field_len);
+        EcdsaSignature::from_components(curve, r, s)
+    }
+
+    fn from_components(curve: Algorithm, r: &[u8], s: &[u8]) -> Result<EcdsaSignature, Error> {
+        let field_len = match curve.ec_field_len() {
+            Some(len) => len,
+            None => bail!("{} is not an elliptic curve", curve),
+        };
+
+        let r = pad_component(r, field_len)?;
+        let s = pad_component(s, field_len)?;
+
+        let mut signature = EcdsaSignature { curve, r, s };
+        if curve == Algorithm::EcK256 {
+            signature.normalize_k256()?;
+        }
+
+        Ok(signature)
+    }
+
+    fn normalize_k256(&mut self) -> Result<(), Error> {
+        let signature = self.k256_signature()?;
+
+        if let Some(normalized) = signature.normalize_s() {
+            self.s = normalized.s().to_bytes().to_vec();
+        }
+
+        Ok(())
+    }
+
+    fn k256_signature(&self) -> Result<k256::ecdsa::Signature, Error> {
+        k256::ecdsa::Signature::from_slice(&self.to_bytes())
+            .map_err(|e| format_err!("invalid ECDSA signature: {}", e))
+    }
+
+    /// The curve the signing key is on.
+    pub fn curve(&self) -> Algorithm {
+        self.curve
+    }
+
+    /// The `r` component, big-endian and padded to the field length of the curve.
+    pub fn r(&self) -> &[u8] {
+        &self.r
+    }
+
+    /// The `s` component, big-endian and padded to the field length of the curve.
+    pub fn s(&self) -> &[u8] {
+        &self.s
+    }
+
+    /// Encode the signature in fixed-width `r || s` form.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&self.r[..], &self.s[..]].concat()
+    }
+
+    /// Encode the signature as a DER `Ecdsa-Sig-Value`.
+    pub fn to_der(&self) -> Vec<u8> {
+        asn1::sequence(&[
+            &asn1::unsigned_integer(&self.r),
+            &asn1::unsigned_integer(&self.s),
+        ])
+    }
+
+    /// Compute the recovery id (0 to 3) which, together with this signature and `digest`, recovers
+    /// `public_key`, as used by Bitcoin compact signatures and Ethereum's `v` value.
+    ///
+    /// Only `Algorithm::EcK256` signatures are supported. `public_key` is the key returned by
+    /// `Session::get_pubkey` for the signing key.
+    pub fn recovery_id<T: AsRef<[u8]>>(
+        &self,
+        public_key: &PublicKey,
+        digest: T,
+    ) -> Result<u8, Error> {
+        if self.curve != Algorithm::EcK256 {
+            bail!("recovery_id: unsupported curve {}", self.curve);
+        }
+
+        match *public_key {
+            PublicKey::Ecc(Algorithm::EcK256, _, _) => (),
+            ref key => bail!("recovery_id: expected a {} key, got {}", self.curve, key.algorithm()),
+        }
+
+        let key = k256::ecdsa::VerifyingKey::from_sec1_bytes(&public_key.ec_point()?)
+            .map_err(|e| format_err!("invalid public key: {}", e))?;
+
+        k256::ecdsa::RecoveryId::trial_recovery_from_prehash(
+            &key,
+            digest.as_ref(),
+            &self.k256_signature()?,
+        ).map(|id| id.to_byte())
+            .map_err(|_| format_err!("recovery_id: signature doesn't match public key"))
+    }
+}
+
+/// Left-pad a big-endian integer with no leading zeroes to `len` bytes, rejecting zero and
+/// integers that don't fit.
+fn pad_component(bytes: &[u8], len: usize) -> Result<Vec<u8>, Error> {
+    let skip = bytes.iter().take_while(|b| **b == 0).count();
+    let bytes = &bytes[skip..];
+
+    if bytes.is_empty() {
+        bail!("invalid ECDSA signature: zero component");
+    }
+    if bytes.len() > len {
+        bail!("invalid ECDSA signature: component longer than {} bytes", len);
+    }
+
+    let mut out = vec![0; len - bytes.len()];
+    out.extend_from_slice(bytes);
+    Ok(out)
+}
@@ -0,0 +1,107 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use asn1;
+use types::Algorithm;
+
+use sha1::Sha1;
+use sha2::digest::DynDigest;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+/// A message digest used by one of the device's signature algorithms.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum HashAlgorithm {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    /// The digest used by a hash-specific signature algorithm, e.g. SHA-256 for
+    /// `Algorithm::RsaPssSha256`.
+    pub(crate) fn from_signature_algorithm(algorithm: Algorithm) -> Option<HashAlgorithm> {
+        match algorithm {
+            Algorithm::RsaPkcs1Sha1 | Algorithm::RsaPssSha1 | Algorithm::EcEcdsaSha1 => {
+                Some(HashAlgorithm::Sha1)
+            }
+            Algorithm::RsaPkcs1Sha256 | Algorithm::RsaPssSha256 | Algorithm::EcEcdsaSha256 => {
+                Some(HashAlgorithm::Sha256)
+            }
+            Algorithm::RsaPkcs1Sha384 | Algorithm::RsaPssSha384 | Algorithm::EcEcdsaSha384 => {
+                Some(HashAlgorithm::Sha384)
+            }
+            Algorithm::RsaPkcs1Sha512 | Algorithm::RsaPssSha512 | Algorithm::EcEcdsaSha512 => {
+                Some(HashAlgorithm::Sha512)
+            }
+            _ => None,
+        }
+    }
+
+    /// The length in bytes of this digest's output.
+    pub(crate) fn output_len(&self) -> usize {
+        match *self {
+            HashAlgorithm::Sha1 => 20,
+            HashAlgorithm::Sha256 => 32,
+            HashAlgorithm::Sha384 => 48,
+            HashAlgorithm::Sha512 => 64,
+        }
+    }
+
+    pub(crate) fn digest(&self, data: &[u8]) -> Vec<u8> {
+        match *self {
+            HashAlgorithm::Sha1 => Sha1::digest(data).to_vec(),
+            HashAlgorithm::Sha256 => Sha256::digest(data).to_vec(),
+            HashAlgorithm::Sha384 => Sha384::digest(data).to_vec(),
+            HashAlgorithm::Sha512 => Sha512::digest(data).to_vec(),
+        }
+    }
+
+    /// A hasher for computing this digest incrementally.
+    pub(crate) fn hasher(&self) -> Box<dyn DynDigest> {
+        match *self {
+            HashAlgorithm::Sha1 => Box::new(Sha1::new()),
+            HashAlgorithm::Sha256 => Box::new(Sha256::new()),
+            HashAlgorithm::Sha384 => Box::new(Sha384::new()),
+            HashAlgorithm::Sha512 => Box::new(Sha512::new()),
+        }
+    }
+
+    /// The MGF1 algorithm using this digest, for RSA-PSS.
+    pub(crate) fn mgf1_algorithm(&self) -> Algorithm {
+        match *self {
+            HashAlgorithm::Sha1 => Algorithm::Mgf1Sha1,
+            HashAlgorithm::Sha256 => Algorithm::Mgf1Sha256,
+            HashAlgorithm::Sha384 => Algorithm::Mgf1Sha384,
+            HashAlgorithm::Sha512 => Algorithm::Mgf1Sha512,
+        }
+    }
+
+    /// The DER AlgorithmIdentifier for this digest, with NULL parameters.
+    pub(crate) fn algorithm_identifier(&self) -> Vec<u8> {
+        let oid = match *self {
+            HashAlgorithm::Sha1 => asn1::OID_SHA1,
+            HashAlgorithm::Sha256 => asn1::OID_SHA256,
+            HashAlgorithm::Sha384 => asn1::OID_SHA384,
+            HashAlgorithm::Sha512 => asn1::OID_SHA512,
+        };
+
+        asn1::sequence(&[&asn1::oid(oid), &asn1::null()])
+    }
+
+    /// Wrap `digest` in the DER DigestInfo structure signed by RSASSA-PKCS1-v1_5 (RFC 8017).
+    pub(crate) fn digest_info(&self, digest: &[u8]) -> Vec<u8> {
+        asn1::sequence(&[&self.algorithm_identifier(), &asn1::octet_string(digest)])
+    }
+}
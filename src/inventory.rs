@@ -0,0 +1,383 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Snapshots of everything on a device, and reports of what changed between two snapshots.
+//!
+//! `Session::inventory` collects an `Inventory`. With the `serde` feature it can be stored (e.g. as
+//! JSON) and compared with a later one using `Inventory::diff`, whose `Display` output lists each
+//! added, removed and changed object.
+
+use session::Session;
+use types::*;
+
+use failure::Error;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A snapshot of a device.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Inventory {
+    pub device_info: DeviceInfo,
+    pub storage: StorageStats,
+    pub options: Vec<DeviceOption>,
+    pub objects: Vec<InventoryObject>,
+}
+
+/// An object on the device, with its public key if it is an asymmetric key.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct InventoryObject {
+    pub info: ObjectInfo,
+    pub public_key: Option<PublicKey>,
+}
+
+impl Session {
+    /// Collect a snapshot of the device: its device info, storage usage and options, and every
+    /// object this session can see.
+    pub fn inventory(&self) -> Result<Inventory, Error> {
+        let mut objects = Vec::new();
+        // `list_objects` only returns IDs and types.
+        for object in self.list_objects().execute()? {
+            let info = self.get_object_info(object.id, object.object_type)?;
+            let public_key = if info.object_type == ObjectType::Asymmetric {
+                Some(self.get_pubkey(info.id)?)
+            } else {
+                None
+            };
+            objects.push(InventoryObject { info, public_key });
+        }
+
+        Ok(Inventory {
+            device_info: self.get_device_info()?,
+            storage: self.get_storage_stats()?,
+            options: vec![
+                self.get_option(DeviceOptionType::ForceAudit)?,
+                self.get_option(DeviceOptionType::CommandAudit)?,
+            ],
+            objects,
+        })
+    }
+}
+
+impl Inventory {
+    fn find(&self, id: u16, object_type: ObjectType) -> Option<&InventoryObject> {
+        self.objects
+            .iter()
+            .find(|object| object.info.id == id && object.info.object_type == object_type)
+    }
+
+    /// Compare this snapshot with a later one. Storage usage and the log counters in the device
+    /// info change in normal use and aren't reported.
+    pub fn diff(&self, later: &Inventory) -> InventoryDiff {
+        let mut diff = InventoryDiff::default();
+
+        let (before, after) = (&self.device_info, &later.device_info);
+        if before.serial != after.serial {
+            diff.device
+                .push(format!("serial {} -> {}", before.serial, after.serial));
+        }
+        let version = |info: &DeviceInfo| {
+            format!(
+                "{}.{}.{}",
+                info.major_version, info.minor_version, info.patch_version
+            )
+        };
+        if version(before) != version(after) {
+            diff.device.push(format!(
+                "firmware {} -> {}",
+                version(before),
+                version(after)
+            ));
+        }
+
+        for option in &later.options {
+            let previous = self
+                .options
+                .iter()
+                .find(|previous| previous.option_type() == option.option_type());
+            if previous != Some(option) {
+                diff.options.push((previous.cloned(), option.clone()));
+            }
+        }
+
+        for object in &self.objects {
+            if later
+                .find(object.info.id, object.info.object_type)
+                .is_none()
+            {
+                diff.removed.push(object.info.clone());
+            }
+        }
+
+        for object in &later.objects {
+            match self.find(object.info.id, object.info.object_type) {
+                None => diff.added.push(object.info.clone()),
+                Some(previous) => {
+                    let changes = object_changes(previous, object);
+                    if !changes.is_empty() {
+                        diff.changed.push(ObjectChange {
+                            id: object.info.id,
+                            object_type: object.info.object_type,
+                            label: object.info.label.clone(),
+                            changes,
+                        });
+                    }
+                }
+            }
+        }
+
+        diff
+    }
+}
+
+/// The differences between two `Inventory` snapshots.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct InventoryDiff {
+    /// Changes to the device's serial number or firmware version.
+    pub device: Vec<String>,
+    /// Options which changed, as (before, after). `before` is `None` if the earlier snapshot
+    /// didn't record the option.
+    pub options: Vec<(Option<DeviceOption>, DeviceOption)>,
+    pub added: Vec<ObjectInfo>,
+    pub removed: Vec<ObjectInfo>,
+    pub changed: Vec<ObjectChange>,
+}
+
+/// An object present in both snapshots whose attributes changed.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ObjectChange {
+    pub id: u16,
+    pub object_type: ObjectType,
+    pub label: String,
+    pub changes: Vec<Change>,
+}
+
+/// A change to one attribute of an object.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Change {
+    Label(String, String),
+    Algorithm(Option<Algorithm>, Option<Algorithm>),
+    Domains {
+        added: Vec<Domain>,
+        removed: Vec<Domain>,
+    },
+    Capabilities {
+        added: Vec<Capability>,
+        removed: Vec<Capability>,
+    },
+    DelegatedCapabilities {
+        added: Vec<Capability>,
+        removed: Vec<Capability>,
+    },
+    /// Changes to capabilities this crate has no `Capability` for, by their libyubihsm names.
+    UnknownCapabilities {
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+    UnknownDelegatedCapabilities {
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+    /// The object was deleted and a new one created with the same ID and type.
+    Sequence(u8, u8),
+    Origin(u8, u8),
+    PublicKey,
+}
+
+fn object_changes(before: &InventoryObject, after: &InventoryObject) -> Vec<Change> {
+    let (old, new) = (&before.info, &after.info);
+    let mut changes = Vec::new();
+
+    if old.label != new.label {
+        changes.push(Change::Label(old.label.clone(), new.label.clone()));
+    }
+    if old.algorithm != new.algorithm {
+        changes.push(Change::Algorithm(old.algorithm, new.algorithm));
+    }
+    let (added, removed) = set_changes(&old.domains, &new.domains);
+    if !added.is_empty() || !removed.is_empty() {
+        changes.push(Change::Domains { added, removed });
+    }
+    let (added, removed) = set_changes(&old.capabilities, &new.capabilities);
+    if !added.is_empty() || !removed.is_empty() {
+        changes.push(Change::Capabilities { added, removed });
+    }
+    let (added, removed) = set_changes(&old.delegated_capabilities, &new.delegated_capabilities);
+    if !added.is_empty() || !removed.is_empty() {
+        changes.push(Change::DelegatedCapabilities { added, removed });
+    }
+    let (added, removed) = set_changes(&old.unknown_capabilities, &new.unknown_capabilities);
+    if !added.is_empty() || !removed.is_empty() {
+        changes.push(Change::UnknownCapabilities { added, removed });
+    }
+    let (added, removed) = set_changes(
+        &old.unknown_delegated_capabilities,
+        &new.unknown_delegated_capabilities,
+    );
+    if !added.is_empty() || !removed.is_empty() {
+        changes.push(Change::UnknownDelegatedCapabilities { added, removed });
+    }
+    if old.sequence != new.sequence {
+        changes.push(Change::Sequence(old.sequence, new.sequence));
+    }
+    if old.origin != new.origin {
+        changes.push(Change::Origin(old.origin, new.origin));
+    }
+    if before.public_key != after.public_key {
+        changes.push(Change::PublicKey);
+    }
+
+    changes
+}
+
+/// The elements of `after` missing from `before`, and of `before` missing from `after`.
+fn set_changes<T: Clone + PartialEq>(before: &[T], after: &[T]) -> (Vec<T>, Vec<T>) {
+    let added = after
+        .iter()
+        .filter(|item| !before.contains(item))
+        .cloned()
+        .collect();
+    let removed = before
+        .iter()
+        .filter(|item| !after.contains(item))
+        .cloned()
+        .collect();
+    (added, removed)
+}
+
+impl InventoryDiff {
+    /// Whether nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.device.is_empty()
+            && self.options.is_empty()
+            && self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+    }
+}
+
+fn list<T: fmt::Display>(prefix: &str, items: &[T]) -> String {
+    items
+        .iter()
+        .map(|item| format!("{}{}", prefix, item))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let domains = |domains: &[Domain]| domains.iter().map(|d| d.0).collect::<Vec<_>>();
+        match *self {
+            Change::Label(ref old, ref new) => write!(f, "label {:?} -> {:?}", old, new),
+            Change::Algorithm(old, new) => write!(f, "algorithm {:?} -> {:?}", old, new),
+            Change::Domains {
+                ref added,
+                ref removed,
+            } => write!(
+                f,
+                "domains {}",
+                [list("+", &domains(added)), list("-", &domains(removed))]
+                    .join(" ")
+                    .trim()
+            ),
+            Change::Capabilities {
+                ref added,
+                ref removed,
+            } => write!(
+                f,
+                "capabilities {}",
+                [list("+", added), list("-", removed)].join(" ").trim()
+            ),
+            Change::DelegatedCapabilities {
+                ref added,
+                ref removed,
+            } => write!(
+                f,
+                "delegated capabilities {}",
+                [list("+", added), list("-", removed)].join(" ").trim()
+            ),
+            Change::UnknownCapabilities {
+                ref added,
+                ref removed,
+            } => write!(
+                f,
+                "unknown capabilities {}",
+                [list("+", added), list("-", removed)].join(" ").trim()
+            ),
+            Change::UnknownDelegatedCapabilities {
+                ref added,
+                ref removed,
+            } => write!(
+                f,
+                "unknown delegated capabilities {}",
+                [list("+", added), list("-", removed)].join(" ").trim()
+            ),
+            Change::Sequence(old, new) => write!(f, "replaced (sequence {} -> {})", old, new),
+            Change::Origin(old, new) => write!(f, "origin {} -> {}", old, new),
+            Change::PublicKey => write!(f, "public key changed"),
+        }
+    }
+}
+
+impl fmt::Display for InventoryDiff {
+    /// One line per change, e.g. `+ asymmetric 0x0010 "signing"` for an added object or
+    /// `~ authkey 0x0002 "ops": capabilities +export_wrapped` for a changed one.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut lines = Vec::new();
+
+        for change in &self.device {
+            lines.push(format!("device: {}", change));
+        }
+        for (before, after) in &self.options {
+            match before {
+                Some(before) => lines.push(format!("option: {:?} -> {:?}", before, after)),
+                None => lines.push(format!("option: {:?}", after)),
+            }
+        }
+        for info in &self.added {
+            lines.push(format!(
+                "+ {} 0x{:04x} {:?}",
+                info.object_type, info.id, info.label
+            ));
+        }
+        for info in &self.removed {
+            lines.push(format!(
+                "- {} 0x{:04x} {:?}",
+                info.object_type, info.id, info.label
+            ));
+        }
+        for object in &self.changed {
+            lines.push(format!(
+                "~ {} 0x{:04x} {:?}: {}",
+                object.object_type,
+                object.id,
+                object.label,
+                object
+                    .changes
+                    .iter()
+                    .map(Change::to_string)
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            ));
+        }
+
+        write!(f, "{}", lines.join("\n"))
+    }
+}
@@ -0,0 +1,274 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compact JSON Web Signatures (RFC 7515) and JSON Web Tokens (RFC 7519) signed by keys on the
+//! device, and the JSON Web Key Set (RFC 7517) that lets relying parties verify them.
+//!
+//! Claims are passed in already serialized, so any JSON library can be used to build them.
+
+use ecdsa::EcdsaSignature;
+use session::Session;
+use types::*;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use failure::Error;
+use sha2::{Digest, Sha256};
+
+use std::fmt;
+
+/// A JWS signature algorithm (RFC 7518 section 3, RFC 8037) which can be computed on the device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum JwsAlgorithm {
+    /// ECDSA on P-256 with SHA-256.
+    ES256,
+    /// ECDSA on P-384 with SHA-384.
+    ES384,
+    /// ECDSA on P-521 with SHA-512.
+    ES512,
+    /// Ed25519.
+    EdDSA,
+    /// RSASSA-PKCS1-v1_5 with SHA-256.
+    RS256,
+    /// RSASSA-PSS with SHA-256 and MGF1 with SHA-256.
+    PS256,
+}
+
+impl JwsAlgorithm {
+    /// The algorithm's `alg` header parameter value.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            JwsAlgorithm::ES256 => "ES256",
+            JwsAlgorithm::ES384 => "ES384",
+            JwsAlgorithm::ES512 => "ES512",
+            JwsAlgorithm::EdDSA => "EdDSA",
+            JwsAlgorithm::RS256 => "RS256",
+            JwsAlgorithm::PS256 => "PS256",
+        }
+    }
+
+    /// The default algorithm for a key: the ECDSA variant matching the curve, EdDSA for Ed25519
+    /// keys, and RS256 for RSA keys. Other curves have no JWS algorithm.
+    pub fn for_key(public_key: &PublicKey) -> Option<JwsAlgorithm> {
+        match public_key.algorithm() {
+            Algorithm::EcP256 => Some(JwsAlgorithm::ES256),
+            Algorithm::EcP384 => Some(JwsAlgorithm::ES384),
+            Algorithm::EcP521 => Some(JwsAlgorithm::ES512),
+            Algorithm::EcEd25519 => Some(JwsAlgorithm::EdDSA),
+            Algorithm::Rsa2048 | Algorithm::Rsa3072 | Algorithm::Rsa4096 => {
+                Some(JwsAlgorithm::RS256)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether this algorithm can be used with a key of algorithm `key_algorithm`. ECDSA
+    /// algorithms are tied to a single curve.
+    pub fn supports_key(&self, key_algorithm: Algorithm) -> bool {
+        match *self {
+            JwsAlgorithm::ES256 => key_algorithm == Algorithm::EcP256,
+            JwsAlgorithm::ES384 => key_algorithm == Algorithm::EcP384,
+            JwsAlgorithm::ES512 => key_algorithm == Algorithm::EcP521,
+            JwsAlgorithm::EdDSA => key_algorithm == Algorithm::EcEd25519,
+            JwsAlgorithm::RS256 | JwsAlgorithm::PS256 => matches!(
+                key_algorithm,
+                Algorithm::Rsa2048 | Algorithm::Rsa3072 | Algorithm::Rsa4096
+            ),
+        }
+    }
+
+    fn scheme(&self) -> SignatureScheme {
+        match *self {
+            JwsAlgorithm::ES256 => SignatureScheme::EcdsaSha256,
+            JwsAlgorithm::ES384 => SignatureScheme::EcdsaSha384,
+            JwsAlgorithm::ES512 => SignatureScheme::EcdsaSha512,
+            JwsAlgorithm::EdDSA => SignatureScheme::Ed25519,
+            JwsAlgorithm::RS256 => SignatureScheme::RsaPkcs1v15Sha256,
+            JwsAlgorithm::PS256 => SignatureScheme::RsaPssSha256,
+        }
+    }
+}
+
+impl fmt::Display for JwsAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The RFC 7638 thumbprint of a key: the unpadded base64url SHA-256 hash of its JWK.
+pub fn thumbprint(public_key: &PublicKey) -> Result<String, Error> {
+    Ok(URL_SAFE_NO_PAD.encode(Sha256::digest(public_key.to_jwk()?.as_bytes())))
+}
+
+/// Signs JWSs and JWTs with an asymmetric key on the device.
+///
+/// The `kid` header parameter defaults to the key's RFC 7638 thumbprint, and the same value is
+/// used for the key's entry in the `jwks` document.
+#[derive(Clone, Debug)]
+pub struct JwsSigner {
+    session: Session,
+    key_id: u16,
+    public_key: PublicKey,
+    algorithm: JwsAlgorithm,
+    kid: String,
+}
+
+impl JwsSigner {
+    /// Create a signer for the asymmetric key `key_id`, checking that `algorithm` can be used
+    /// with it.
+    pub fn new(
+        session: &Session,
+        key_id: u16,
+        algorithm: JwsAlgorithm,
+    ) -> Result<JwsSigner, Error> {
+        let public_key = session.get_pubkey(key_id)?;
+        if !algorithm.supports_key(public_key.algorithm()) {
+            bail!(
+                "{} can't be used with a {} key",
+                algorithm,
+                public_key.algorithm()
+            );
+        }
+
+        Ok(JwsSigner {
+            session: session.clone(),
+            key_id,
+            kid: thumbprint(&public_key)?,
+            public_key,
+            algorithm,
+        })
+    }
+
+    /// Use `kid` as the key ID instead of the key's thumbprint.
+    pub fn kid(self, kid: &str) -> JwsSigner {
+        JwsSigner {
+            kid: kid.to_string(),
+            ..self
+        }
+    }
+
+    /// The ID of the key on the device.
+    pub fn key_id(&self) -> u16 {
+        self.key_id
+    }
+
+    pub fn algorithm(&self) -> JwsAlgorithm {
+        self.algorithm
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    /// Sign `payload`, returning a compact JWS.
+    pub fn sign(&self, payload: &[u8]) -> Result<String, Error> {
+        let header = format!(
+            r#"{{"alg":"{}","kid":{}}}"#,
+            self.algorithm,
+            json_string(&self.kid)
+        );
+
+        self.sign_with_header(&header, payload)
+    }
+
+    /// Sign a JWT with `claims`, a serialized JSON object such as
+    /// `{"iss":"https://id.example.com","sub":"alice","exp":1700000000}`, returning it in compact
+    /// form.
+    pub fn sign_jwt(&self, claims: &str) -> Result<String, Error> {
+        let claims = claims.trim();
+        if !claims.starts_with('{') || !claims.ends_with('}') {
+            bail!("JWT claims must be a JSON object");
+        }
+
+        let header = format!(
+            r#"{{"alg":"{}","kid":{},"typ":"JWT"}}"#,
+            self.algorithm,
+            json_string(&self.kid)
+        );
+
+        self.sign_with_header(&header, claims.as_bytes())
+    }
+
+    /// The key as a JWK carrying its `alg`, `kid` and `use` parameters, as listed in `jwks`.
+    pub fn jwk(&self) -> Result<String, Error> {
+        let jwk = self.public_key.to_jwk()?;
+
+        // `to_jwk` always returns a non-empty object, so splice the extra members in after the
+        // opening brace.
+        Ok(format!(
+            r#"{{"alg":"{}","kid":{},"use":"sig",{}"#,
+            self.algorithm,
+            json_string(&self.kid),
+            &jwk[1..]
+        ))
+    }
+
+    fn sign_with_header(&self, header: &str, payload: &[u8]) -> Result<String, Error> {
+        let signing_input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header),
+            URL_SAFE_NO_PAD.encode(payload)
+        );
+
+        let mut signature = self.session.sign_message_with_key_algorithm(
+            self.key_id,
+            self.public_key.algorithm(),
+            self.algorithm.scheme(),
+            signing_input.as_bytes(),
+        )?;
+
+        // JWS uses fixed-width r || s rather than DER for ECDSA (RFC 7518 section 3.4).
+        if let PublicKey::Ecc(curve, _, _) = self.public_key {
+            signature = EcdsaSignature::from_der(curve, signature)?.to_bytes();
+        }
+
+        Ok(format!(
+            "{}.{}",
+            signing_input,
+            URL_SAFE_NO_PAD.encode(signature)
+        ))
+    }
+}
+
+/// A JSON Web Key Set document listing the keys of `signers`, for publishing at e.g. a
+/// `jwks_uri`.
+pub fn jwks(signers: &[JwsSigner]) -> Result<String, Error> {
+    let keys = signers
+        .iter()
+        .map(JwsSigner::jwk)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(format!(r#"{{"keys":[{}]}}"#, keys.join(",")))
+}
+
+/// Encode `s` as a JSON string literal.
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
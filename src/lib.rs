@@ -14,8 +14,28 @@
 
 #![allow(unknown_lints)]
 
+extern crate base64;
+extern crate ed25519_dalek;
 #[macro_use]
 extern crate failure;
+extern crate k256;
+extern crate p256;
+extern crate p384;
+extern crate p521;
+extern crate rsa;
+#[cfg(feature = "rustls")]
+extern crate rustls;
+#[cfg(feature = "serde")]
+extern crate serde;
+extern crate sha1;
+extern crate sha2;
+extern crate sha3;
+#[cfg(feature = "rustcrypto")]
+extern crate signature;
+#[cfg(feature = "provision")]
+extern crate toml;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 
 mod yubihsm_sys {
     #![allow(non_upper_case_globals)]
@@ -28,17 +48,55 @@ mod yubihsm_sys {
     include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 }
 
+mod asn1;
+mod cbor;
+mod ecdsa;
+mod hash;
 mod types;
 mod yubihsm;
 mod connector;
 mod session;
+mod public_key;
+mod verify;
+mod pool;
+mod socket;
+mod state_file;
+mod x509;
+pub mod bootstrap;
+pub mod ca;
+pub mod cose;
+pub mod dnssec;
+pub mod inventory;
+pub mod jose;
+pub mod openpgp;
+pub mod policy;
+#[cfg(feature = "provision")]
+pub mod provision;
+pub mod revocation;
+pub mod secp256k1;
+pub mod sshsig;
+#[cfg(unix)]
+pub mod ssh_agent;
+pub mod validator;
+#[cfg(feature = "rustcrypto")]
+mod rustcrypto;
+#[cfg(feature = "serde")]
+mod serialization;
+#[cfg(feature = "rustls")]
+mod tls;
 #[cfg(test)]
 mod tests;
 
 pub use types::*;
+pub use ecdsa::EcdsaSignature;
 pub use yubihsm::*;
 pub use connector::*;
 pub use session::*;
+pub use pool::*;
+#[cfg(feature = "rustcrypto")]
+pub use rustcrypto::*;
+#[cfg(feature = "rustls")]
+pub use tls::*;
 
 // Re-exports from the bindgen bindings
 pub use yubihsm_sys::yh_capabilities;
@@ -0,0 +1,454 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! OpenPGP version 4 signatures (RFC 4880) made with keys on the device: detached signatures,
+//! e.g. for release artifacts and `Release.gpg`/`repomd.xml.asc`, cleartext signed messages, e.g.
+//! for Debian `InRelease` files, and the transferable public key verifiers import.
+//!
+//! An OpenPGP key's fingerprint covers its creation time, which the device doesn't record, so the
+//! same creation time has to be given every time a key is used.
+
+use asn1;
+use ecdsa::EcdsaSignature;
+use hash::HashAlgorithm;
+use public_key::RSA_PUBLIC_EXPONENT;
+use session::Session;
+use types::*;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use failure::Error;
+use sha1::Sha1;
+use sha2::digest::DynDigest;
+use sha2::Digest;
+
+use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const PACKET_SIGNATURE: u8 = 2;
+const PACKET_PUBLIC_KEY: u8 = 6;
+const PACKET_USER_ID: u8 = 13;
+
+const SIGNATURE_BINARY: u8 = 0x00;
+const SIGNATURE_TEXT: u8 = 0x01;
+const SIGNATURE_POSITIVE_CERTIFICATION: u8 = 0x13;
+
+const PUBLIC_KEY_RSA: u8 = 1;
+const PUBLIC_KEY_ECDSA: u8 = 19;
+const PUBLIC_KEY_EDDSA: u8 = 22;
+
+const SUBPACKET_CREATION_TIME: u8 = 2;
+const SUBPACKET_ISSUER: u8 = 16;
+const SUBPACKET_KEY_FLAGS: u8 = 27;
+const SUBPACKET_ISSUER_FINGERPRINT: u8 = 33;
+
+/// Key flags for a primary key which certifies its user IDs and makes signatures.
+const KEY_FLAGS_CERTIFY_SIGN: u8 = 0x03;
+
+/// The curve OID for Ed25519 keys with the EdDSA algorithm in version 4 keys.
+const OID_ED25519_LEGACY: &[u64] = &[1, 3, 6, 1, 4, 1, 11591, 15, 1];
+
+/// Signs OpenPGP messages with an asymmetric key on the device.
+///
+/// Supported keys are RSA, ECDSA on P-256, P-384 and P-521, and Ed25519. Signatures use SHA-256,
+/// except that ECDSA on the larger curves uses SHA-384 and SHA-512 respectively.
+#[derive(Clone, Debug)]
+pub struct OpenPgpSigner {
+    session: Session,
+    key_id: u16,
+    public_key: PublicKey,
+    hash: HashAlgorithm,
+    key_packet: Vec<u8>,
+    fingerprint: Vec<u8>,
+}
+
+impl OpenPgpSigner {
+    /// Create a signer for the asymmetric key `key_id`, whose OpenPGP key was created at
+    /// `created`.
+    pub fn new(session: &Session, key_id: u16, created: SystemTime) -> Result<OpenPgpSigner, Error> {
+        let public_key = session.get_pubkey(key_id)?;
+        OpenPgpSigner::from_public_key(session, key_id, public_key, created)
+    }
+
+    fn from_public_key(
+        session: &Session,
+        key_id: u16,
+        public_key: PublicKey,
+        created: SystemTime,
+    ) -> Result<OpenPgpSigner, Error> {
+        let hash = match public_key.algorithm() {
+            Algorithm::EcP384 => HashAlgorithm::Sha384,
+            Algorithm::EcP521 => HashAlgorithm::Sha512,
+            _ => HashAlgorithm::Sha256,
+        };
+
+        let key_packet = public_key_packet_body(&public_key, timestamp(created)?)?;
+        let fingerprint = fingerprint(&key_packet);
+
+        Ok(OpenPgpSigner {
+            session: session.clone(),
+            key_id,
+            public_key,
+            hash,
+            key_packet,
+            fingerprint,
+        })
+    }
+
+    /// The ID of the key on the device.
+    pub fn key_id(&self) -> u16 {
+        self.key_id
+    }
+
+    /// The OpenPGP v4 fingerprint of the key.
+    pub fn fingerprint(&self) -> &[u8] {
+        &self.fingerprint
+    }
+
+    /// The fingerprint as uppercase hex, as shown by `gpg --list-keys`.
+    pub fn fingerprint_hex(&self) -> String {
+        self.fingerprint
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect()
+    }
+
+    /// The OpenPGP key ID: the last 8 bytes of the fingerprint.
+    pub fn openpgp_key_id(&self) -> &[u8] {
+        &self.fingerprint[12..]
+    }
+
+    /// The transferable public key for `user_id` (e.g. `Release Signing <release@example.com>`):
+    /// the public key packet, the user ID packet and a self-signature binding them.
+    pub fn public_key_packets(&self, user_id: &str) -> Result<Vec<u8>, Error> {
+        let mut hasher = self.hash.hasher();
+        hasher.update(&[0x99]);
+        hasher.update(&(self.key_packet.len() as u16).to_be_bytes());
+        hasher.update(&self.key_packet);
+        hasher.update(&[0xb4]);
+        hasher.update(&(user_id.len() as u32).to_be_bytes());
+        hasher.update(user_id.as_bytes());
+
+        let signature = self.finish_signature(
+            hasher,
+            SIGNATURE_POSITIVE_CERTIFICATION,
+            &[subpacket(SUBPACKET_KEY_FLAGS, &[KEY_FLAGS_CERTIFY_SIGN])],
+        )?;
+
+        Ok([
+            packet(PACKET_PUBLIC_KEY, &self.key_packet),
+            packet(PACKET_USER_ID, user_id.as_bytes()),
+            signature,
+        ].concat())
+    }
+
+    /// The transferable public key for `user_id`, ASCII-armored.
+    pub fn public_key_armored(&self, user_id: &str) -> Result<String, Error> {
+        Ok(armor(
+            "PGP PUBLIC KEY BLOCK",
+            &self.public_key_packets(user_id)?,
+        ))
+    }
+
+    /// A detached binary signature over `data`.
+    pub fn sign_detached(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        self.sign_detached_reader(data)
+    }
+
+    /// A detached binary signature over everything read from `reader`, for data too large to hold
+    /// in memory.
+    pub fn sign_detached_reader<R: Read>(&self, mut reader: R) -> Result<Vec<u8>, Error> {
+        let mut hasher = self.hash.hasher();
+        let mut buf = [0; 8192];
+        loop {
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+
+        self.finish_signature(hasher, SIGNATURE_BINARY, &[])
+    }
+
+    /// A detached signature over `data`, ASCII-armored, as in `.asc` files.
+    pub fn sign_detached_armored(&self, data: &[u8]) -> Result<String, Error> {
+        Ok(armor("PGP SIGNATURE", &self.sign_detached(data)?))
+    }
+
+    /// A cleartext signed message (RFC 4880 section 7) containing `text`.
+    ///
+    /// Trailing whitespace on each line isn't covered by the signature, so it is removed from the
+    /// output too.
+    pub fn sign_cleartext(&self, text: &str) -> Result<String, Error> {
+        let lines: Vec<&str> = text.split('\n')
+            .map(|line| line.trim_end_matches(&[' ', '\t', '\r'][..]))
+            .collect();
+
+        let mut hasher = self.hash.hasher();
+        hasher.update(lines.join("\r\n").as_bytes());
+        let signature = self.finish_signature(hasher, SIGNATURE_TEXT, &[])?;
+
+        let mut out = format!(
+            "-----BEGIN PGP SIGNED MESSAGE-----\nHash: {}\n\n",
+            hash_name(self.hash)
+        );
+        for line in &lines {
+            // Dash-escape lines which could be mistaken for armor headers.
+            if line.starts_with('-') {
+                out.push_str("- ");
+            }
+            out.push_str(line);
+            out.push('\n');
+        }
+        out.push_str(&armor("PGP SIGNATURE", &signature));
+
+        Ok(out)
+    }
+
+    /// Complete a signature of type `signature_type` whose data has been fed to `hasher`, adding
+    /// the creation time and issuer subpackets to `subpackets`, and return the signature packet.
+    fn finish_signature(
+        &self,
+        mut hasher: Box<dyn DynDigest>,
+        signature_type: u8,
+        subpackets: &[Vec<u8>],
+    ) -> Result<Vec<u8>, Error> {
+        let mut hashed_subpackets = vec![
+            subpacket(
+                SUBPACKET_CREATION_TIME,
+                &timestamp(SystemTime::now())?.to_be_bytes(),
+            ),
+            subpacket(
+                SUBPACKET_ISSUER_FINGERPRINT,
+                &[&[4][..], &self.fingerprint].concat(),
+            ),
+        ];
+        hashed_subpackets.extend_from_slice(subpackets);
+        let hashed_subpackets = hashed_subpackets.concat();
+        let unhashed_subpackets = subpacket(SUBPACKET_ISSUER, self.openpgp_key_id());
+
+        let mut hashed = vec![
+            4,
+            signature_type,
+            public_key_algorithm(&self.public_key),
+            hash_algorithm_id(self.hash),
+        ];
+        hashed.extend_from_slice(&(hashed_subpackets.len() as u16).to_be_bytes());
+        hashed.extend_from_slice(&hashed_subpackets);
+
+        hasher.update(&hashed);
+        hasher.update(&[4, 0xff]);
+        hasher.update(&(hashed.len() as u32).to_be_bytes());
+        let digest = hasher.finalize();
+
+        let mut body = hashed;
+        body.extend_from_slice(&(unhashed_subpackets.len() as u16).to_be_bytes());
+        body.extend_from_slice(&unhashed_subpackets);
+        body.extend_from_slice(&digest[..2]);
+        for value in self.sign_digest(&digest)? {
+            body.extend_from_slice(&mpi(&value));
+        }
+
+        Ok(packet(PACKET_SIGNATURE, &body))
+    }
+
+    /// Sign `digest` on the device, returning the integers which make up the signature.
+    fn sign_digest(&self, digest: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+        match self.public_key {
+            PublicKey::Rsa(_, _) => {
+                let signature =
+                    self.session
+                        .sign_pkcs1v1_5(self.key_id, false, self.hash.digest_info(digest))?;
+                Ok(vec![signature])
+            }
+            PublicKey::Ecc(curve, _, _) => {
+                // Checked when the public key packet was built that this is a supported curve.
+                let field_len = curve.ec_field_len().unwrap();
+                let digest = &digest[..digest.len().min(field_len)];
+                let signature =
+                    EcdsaSignature::from_der(curve, self.session.sign_ecdsa(self.key_id, digest)?)?;
+                Ok(vec![signature.r().to_vec(), signature.s().to_vec()])
+            }
+            PublicKey::Edc(_, _) => {
+                // Version 4 EdDSA signatures are made over the digest rather than the data.
+                let signature = self.session.sign_eddsa(self.key_id, digest)?;
+                if signature.len() != 64 {
+                    bail!("unexpected Ed25519 signature length {}", signature.len());
+                }
+                Ok(vec![signature[..32].to_vec(), signature[32..].to_vec()])
+            }
+        }
+    }
+}
+
+fn timestamp(time: SystemTime) -> Result<u32, Error> {
+    let seconds = time.duration_since(UNIX_EPOCH)
+        .map_err(|_| format_err!("times before 1970 are not supported"))?
+        .as_secs();
+    if seconds > u64::from(u32::MAX) {
+        bail!("times after 2106 are not supported");
+    }
+
+    Ok(seconds as u32)
+}
+
+fn public_key_algorithm(public_key: &PublicKey) -> u8 {
+    match *public_key {
+        PublicKey::Rsa(_, _) => PUBLIC_KEY_RSA,
+        PublicKey::Ecc(_, _, _) => PUBLIC_KEY_ECDSA,
+        PublicKey::Edc(_, _) => PUBLIC_KEY_EDDSA,
+    }
+}
+
+fn hash_algorithm_id(hash: HashAlgorithm) -> u8 {
+    match hash {
+        HashAlgorithm::Sha1 => 2,
+        HashAlgorithm::Sha256 => 8,
+        HashAlgorithm::Sha384 => 9,
+        HashAlgorithm::Sha512 => 10,
+    }
+}
+
+fn hash_name(hash: HashAlgorithm) -> &'static str {
+    match hash {
+        HashAlgorithm::Sha1 => "SHA1",
+        HashAlgorithm::Sha256 => "SHA256",
+        HashAlgorithm::Sha384 => "SHA384",
+        HashAlgorithm::Sha512 => "SHA512",
+    }
+}
+
+/// The body of a version 4 public key packet (RFC 4880 section 5.5.2, RFC 6637 section 9).
+pub(crate) fn public_key_packet_body(public_key: &PublicKey, created: u32) -> Result<Vec<u8>, Error> {
+    let mut body = vec![4];
+    body.extend_from_slice(&created.to_be_bytes());
+    body.push(public_key_algorithm(public_key));
+
+    match *public_key {
+        PublicKey::Rsa(_, ref n) => {
+            body.extend_from_slice(&mpi(n));
+            body.extend_from_slice(&mpi(RSA_PUBLIC_EXPONENT));
+        }
+        PublicKey::Ecc(curve, _, _) => {
+            let oid = match curve {
+                Algorithm::EcP256 => asn1::OID_SECP256R1,
+                Algorithm::EcP384 => asn1::OID_SECP384R1,
+                Algorithm::EcP521 => asn1::OID_SECP521R1,
+                a => bail!("OpenPGP signing with {} keys is not supported", a),
+            };
+            body.extend_from_slice(&curve_oid(oid));
+            body.extend_from_slice(&mpi(&public_key.ec_point()?));
+        }
+        PublicKey::Edc(_, ref a) => {
+            body.extend_from_slice(&curve_oid(OID_ED25519_LEGACY));
+            body.extend_from_slice(&mpi(&[&[0x40][..], a].concat()));
+        }
+    }
+
+    Ok(body)
+}
+
+/// The version 4 fingerprint of a key, given the body of its public key packet (RFC 4880 section
+/// 12.2).
+pub(crate) fn fingerprint(key_packet: &[u8]) -> Vec<u8> {
+    Sha1::digest(
+        [
+            &[0x99][..],
+            &(key_packet.len() as u16).to_be_bytes(),
+            key_packet,
+        ].concat(),
+    ).to_vec()
+}
+
+/// A curve OID as it appears in key packets: its DER contents prefixed by their length.
+fn curve_oid(arcs: &[u64]) -> Vec<u8> {
+    // The DER encoding of these OIDs is always shorter than 128 bytes, so the length is one byte.
+    asn1::oid(arcs)[1..].to_vec()
+}
+
+/// Encode an unsigned big-endian integer as a multiprecision integer (RFC 4880 section 3.2).
+fn mpi(value: &[u8]) -> Vec<u8> {
+    let skip = value.iter().take_while(|b| **b == 0).count();
+    let value = &value[skip..];
+    let bits = match value.first() {
+        Some(first) => value.len() * 8 - first.leading_zeros() as usize,
+        None => 0,
+    };
+
+    let mut out = (bits as u16).to_be_bytes().to_vec();
+    out.extend_from_slice(value);
+    out
+}
+
+/// Encode a new-format packet or subpacket length (RFC 4880 section 4.2.2).
+fn length(len: usize) -> Vec<u8> {
+    if len < 192 {
+        vec![len as u8]
+    } else if len < 8384 {
+        let len = len - 192;
+        vec![(len >> 8) as u8 + 192, len as u8]
+    } else {
+        let mut out = vec![0xff];
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+        out
+    }
+}
+
+fn packet(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![0xc0 | tag];
+    out.extend_from_slice(&length(body.len()));
+    out.extend_from_slice(body);
+    out
+}
+
+fn subpacket(subpacket_type: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = length(body.len() + 1);
+    out.push(subpacket_type);
+    out.extend_from_slice(body);
+    out
+}
+
+/// The CRC-24 checksum used by ASCII armor (RFC 4880 section 6.1).
+pub(crate) fn crc24(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0x00b7_04ce;
+    for byte in data {
+        crc ^= u32::from(*byte) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x0100_0000 != 0 {
+                crc ^= 0x0186_4cfb;
+            }
+        }
+    }
+
+    crc & 0x00ff_ffff
+}
+
+/// ASCII-armor `data` as a `label` block (RFC 4880 section 6.2).
+fn armor(label: &str, data: &[u8]) -> String {
+    let encoded = STANDARD.encode(data);
+    let mut out = format!("-----BEGIN {}-----\n\n", label);
+
+    for line in encoded.as_bytes().chunks(64) {
+        // base64 output is always ASCII, so this can't split a character.
+        out.push_str(&String::from_utf8_lossy(line));
+        out.push('\n');
+    }
+
+    let crc = crc24(data).to_be_bytes();
+    out.push_str(&format!("={}\n", STANDARD.encode(&crc[1..])));
+    out.push_str(&format!("-----END {}-----\n", label));
+    out
+}
@@ -0,0 +1,355 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A least-privilege linter for the objects on a device.
+//!
+//! `lint` checks a list of objects against a `LintConfig` and returns `Finding`s, each tagged with
+//! a stable rule ID and a severity. `lint_device` lints every object a session can see, and also
+//! checks whether the factory default AuthKey still accepts the default password. Findings
+//! `Display` as tab-separated `severity rule object message` lines, and with the `serde` feature
+//! can be serialized (e.g. as JSON) for audit evidence.
+
+use session::Session;
+use types::*;
+use yubihsm_sys;
+
+use failure::Error;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::ffi::CStr;
+use std::fmt;
+
+/// Capabilities which let an AuthKey reset the device, extract keys or manage other AuthKeys.
+pub const DANGEROUS_CAPABILITIES: &[Capability] = &[
+    Capability::Reset,
+    Capability::ExportWrapped,
+    Capability::PutAuthKey,
+    Capability::DeleteAuthkey,
+];
+
+/// Capabilities which create objects, and so make an AuthKey's delegated capabilities useful.
+const CREATE_CAPABILITIES: &[Capability] = &[
+    Capability::PutAuthKey,
+    Capability::PutAsymmetric,
+    Capability::AsymmetricGen,
+    Capability::ImportWrapped,
+    Capability::PutWrapkey,
+    Capability::GenerateWrapkey,
+    Capability::PutHmackey,
+    Capability::HmackeyGenerate,
+    Capability::PutOpaque,
+    Capability::PutTemplate,
+    Capability::PutOtpAeadKey,
+    Capability::GenerateOtpAeadKey,
+];
+
+/// The ID of the AuthKey a device ships with.
+pub const DEFAULT_AUTH_KEY_ID: u16 = 1;
+
+/// The password the default AuthKey ships with.
+pub(crate) fn default_password() -> Result<&'static str, Error> {
+    Ok(CStr::from_bytes_with_nul(yubihsm_sys::YH_DEFAULT_PASSWORD)?.to_str()?)
+}
+
+/// Settings for `lint`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct LintConfig {
+    /// The most domains an object should be in.
+    pub max_domains: usize,
+    /// AuthKeys which are expected to hold dangerous capabilities and span domains. Findings about
+    /// them are reported at `Severity::Info` rather than suppressed, so they still appear as
+    /// evidence.
+    pub admin_keys: Vec<u16>,
+    /// The types and IDs of objects which are backed up under a wrap key, and so should be
+    /// exportable.
+    pub backup: Vec<(ObjectType, u16)>,
+}
+
+impl Default for LintConfig {
+    fn default() -> LintConfig {
+        LintConfig {
+            max_domains: 1,
+            admin_keys: Vec::new(),
+            backup: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match *self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        })
+    }
+}
+
+/// The checks `lint` makes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum Rule {
+    /// An AuthKey holds, or can delegate, one of `DANGEROUS_CAPABILITIES`.
+    DangerousCapability,
+    /// An AuthKey or wrap key delegates capabilities it can't use, or which no object in its
+    /// domains holds.
+    UnneededDelegation,
+    /// An object is in more than `LintConfig::max_domains` domains.
+    TooManyDomains,
+    /// The factory default AuthKey is still present.
+    DefaultAuthKey,
+    /// An object is exportable under wrap but isn't listed in `LintConfig::backup`.
+    ExportableNotBackedUp,
+    /// An object listed in `LintConfig::backup` isn't exportable under wrap.
+    BackupNotExportable,
+}
+
+impl Rule {
+    /// The rule's stable identifier, e.g. `dangerous-capability`.
+    pub fn id(&self) -> &'static str {
+        match *self {
+            Rule::DangerousCapability => "dangerous-capability",
+            Rule::UnneededDelegation => "unneeded-delegation",
+            Rule::TooManyDomains => "too-many-domains",
+            Rule::DefaultAuthKey => "default-auth-key",
+            Rule::ExportableNotBackedUp => "exportable-not-backed-up",
+            Rule::BackupNotExportable => "backup-not-exportable",
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+/// A problem found by `lint`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Finding {
+    pub rule: Rule,
+    pub severity: Severity,
+    pub object_id: u16,
+    pub object_type: ObjectType,
+    pub label: String,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{} 0x{:04x}\t{}",
+            self.severity, self.rule, self.object_type, self.object_id, self.message
+        )
+    }
+}
+
+fn finding(rule: Rule, severity: Severity, info: &ObjectInfo, message: String) -> Finding {
+    Finding {
+        rule,
+        severity,
+        object_id: info.id,
+        object_type: info.object_type,
+        label: info.label.clone(),
+        message,
+    }
+}
+
+fn capability_list(capabilities: &[Capability]) -> String {
+    capabilities
+        .iter()
+        .map(Capability::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn shares_domain(a: &ObjectInfo, b: &ObjectInfo) -> bool {
+    a.domains.iter().any(|domain| b.domains.contains(domain))
+}
+
+/// Check `objects` for over-privileged AuthKeys and keys, returning findings ordered by object.
+pub fn lint(objects: &[ObjectInfo], config: &LintConfig) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    for info in objects {
+        let is_admin =
+            info.object_type == ObjectType::AuthKey && config.admin_keys.contains(&info.id);
+        let exempt = |severity| if is_admin { Severity::Info } else { severity };
+
+        if info.object_type == ObjectType::AuthKey {
+            if info.id == DEFAULT_AUTH_KEY_ID {
+                findings.push(finding(
+                    Rule::DefaultAuthKey,
+                    Severity::Warning,
+                    info,
+                    "the factory default AuthKey is still present".to_string(),
+                ));
+            }
+
+            let held: Vec<Capability> = DANGEROUS_CAPABILITIES
+                .iter()
+                .filter(|&c| info.capabilities.contains(c))
+                .cloned()
+                .collect();
+            if !held.is_empty() {
+                findings.push(finding(
+                    Rule::DangerousCapability,
+                    exempt(Severity::Warning),
+                    info,
+                    format!("holds {}", capability_list(&held)),
+                ));
+            }
+
+            // An AuthKey which can create AuthKeys can hand its delegated capabilities to one.
+            if info.capabilities.contains(&Capability::PutAuthKey) {
+                let delegable: Vec<Capability> = DANGEROUS_CAPABILITIES
+                    .iter()
+                    .filter(|&c| info.delegated_capabilities.contains(c))
+                    .cloned()
+                    .collect();
+                if !delegable.is_empty() {
+                    findings.push(finding(
+                        Rule::DangerousCapability,
+                        exempt(Severity::Warning),
+                        info,
+                        format!("can delegate {}", capability_list(&delegable)),
+                    ));
+                }
+            }
+        }
+
+        if matches!(info.object_type, ObjectType::AuthKey | ObjectType::WrapKey)
+            && !info.delegated_capabilities.is_empty()
+        {
+            let can_create = info.object_type == ObjectType::WrapKey
+                || CREATE_CAPABILITIES
+                    .iter()
+                    .any(|c| info.capabilities.contains(c));
+
+            if !can_create {
+                findings.push(finding(
+                    Rule::UnneededDelegation,
+                    exempt(Severity::Warning),
+                    info,
+                    format!(
+                        "delegates {} but can't create objects",
+                        capability_list(&info.delegated_capabilities)
+                    ),
+                ));
+            } else {
+                let unused: Vec<Capability> = info
+                    .delegated_capabilities
+                    .iter()
+                    .filter(|&c| {
+                        !objects.iter().any(|other| {
+                            !(other.id == info.id && other.object_type == info.object_type)
+                                && shares_domain(info, other)
+                                && (other.capabilities.contains(c)
+                                    || other.delegated_capabilities.contains(c))
+                        })
+                    })
+                    .cloned()
+                    .collect();
+                if !unused.is_empty() {
+                    findings.push(finding(
+                        Rule::UnneededDelegation,
+                        exempt(Severity::Info),
+                        info,
+                        format!(
+                            "delegates {}, which no object in its domains holds",
+                            capability_list(&unused)
+                        ),
+                    ));
+                }
+            }
+        }
+
+        if info.domains.len() > config.max_domains {
+            findings.push(finding(
+                Rule::TooManyDomains,
+                exempt(Severity::Warning),
+                info,
+                format!(
+                    "is in {} domains (at most {} expected)",
+                    info.domains.len(),
+                    config.max_domains
+                ),
+            ));
+        }
+
+        let exportable = info.capabilities.contains(&Capability::ExportUnderWrap);
+        let backed_up = config.backup.contains(&(info.object_type, info.id));
+        if exportable && !backed_up {
+            findings.push(finding(
+                Rule::ExportableNotBackedUp,
+                Severity::Warning,
+                info,
+                "is exportable under wrap but not marked for backup".to_string(),
+            ));
+        } else if backed_up && !exportable {
+            findings.push(finding(
+                Rule::BackupNotExportable,
+                Severity::Warning,
+                info,
+                "is marked for backup but isn't exportable under wrap".to_string(),
+            ));
+        }
+    }
+
+    findings
+}
+
+/// Lint every object `session` can see. If the factory default AuthKey is present, this also
+/// tries to open a session with it and the default password, and reports a critical finding if
+/// that succeeds.
+pub fn lint_device(session: &Session, config: &LintConfig) -> Result<Vec<Finding>, Error> {
+    let mut objects = Vec::new();
+    // `list_objects` only returns IDs and types.
+    for object in session.list_objects().execute()? {
+        objects.push(session.get_object_info(object.id, object.object_type)?);
+    }
+
+    let mut findings = lint(&objects, config);
+
+    let default_password = default_password()?;
+    for finding in &mut findings {
+        if finding.rule == Rule::DefaultAuthKey
+            && session
+                .connector()
+                .create_session_from_password(DEFAULT_AUTH_KEY_ID, default_password, false)
+                .is_ok()
+        {
+            finding.severity = Severity::Critical;
+            finding.message =
+                "the factory default AuthKey is still present with the default password"
+                    .to_string();
+        }
+    }
+
+    Ok(findings)
+}
@@ -0,0 +1,152 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use connector::Connector;
+use session::Session;
+
+use failure::Error;
+
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Mutex;
+
+/// A pool of sessions authenticated with the same AuthKey, which can be shared between threads.
+///
+/// A `Session` can only be used by one thread at a time, and the device processes one command per
+/// session at a time, so callers that sign concurrently (e.g. TLS handshakes) should each take
+/// their own session from a pool rather than sharing one.
+pub struct SessionPool {
+    connector: Connector,
+    auth_key_id: u16,
+    password: String,
+    max_idle: usize,
+    idle: Mutex<Vec<Session>>,
+}
+
+impl fmt::Debug for SessionPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Leave out the password.
+        f.debug_struct("SessionPool")
+            .field("connector", &self.connector)
+            .field("auth_key_id", &self.auth_key_id)
+            .field("max_idle", &self.max_idle)
+            .finish()
+    }
+}
+
+impl SessionPool {
+    /// Create a pool of sessions authenticated with the given AuthKey and password.
+    ///
+    /// `size` sessions are opened immediately, so bad credentials are reported here rather than
+    /// on first use. More sessions are opened on demand when all of them are in use, but at most
+    /// `size` are kept open once they're returned. The device supports at most 16 open sessions.
+    pub fn new(
+        connector: &Connector,
+        auth_key_id: u16,
+        password: &str,
+        size: usize,
+    ) -> Result<SessionPool, Error> {
+        let pool = SessionPool::empty(connector, auth_key_id, password, size);
+
+        for _ in 0..size {
+            let session = pool.create_session()?;
+            pool.put(session);
+        }
+
+        Ok(pool)
+    }
+
+    /// A pool which keeps at most `max_idle` sessions, without opening any yet.
+    pub(crate) fn empty(
+        connector: &Connector,
+        auth_key_id: u16,
+        password: &str,
+        max_idle: usize,
+    ) -> SessionPool {
+        SessionPool {
+            connector: connector.clone(),
+            auth_key_id,
+            password: password.to_string(),
+            max_idle,
+            idle: Mutex::new(Vec::with_capacity(max_idle)),
+        }
+    }
+
+    /// The ID of the AuthKey the pool's sessions are authenticated with.
+    pub fn auth_key_id(&self) -> u16 {
+        self.auth_key_id
+    }
+
+    /// Take a session from the pool, opening a new one if none are idle. The session goes back to
+    /// the pool when the returned guard is dropped.
+    pub fn get(&self) -> Result<PooledSession<'_>, Error> {
+        let idle = self.idle.lock().unwrap_or_else(|e| e.into_inner()).pop();
+
+        let session = match idle {
+            Some(session) => session,
+            None => self.create_session()?,
+        };
+
+        Ok(PooledSession {
+            pool: self,
+            session: Some(session),
+        })
+    }
+
+    fn create_session(&self) -> Result<Session, Error> {
+        self.connector
+            .create_session_from_password(self.auth_key_id, &self.password, true)
+    }
+
+    pub(crate) fn put(&self, session: Session) {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Extra sessions are closed when dropped.
+        if idle.len() < self.max_idle {
+            idle.push(session);
+        }
+    }
+}
+
+/// A session borrowed from a `SessionPool`. Derefs to `Session`.
+#[derive(Debug)]
+pub struct PooledSession<'a> {
+    pool: &'a SessionPool,
+    session: Option<Session>,
+}
+
+impl<'a> PooledSession<'a> {
+    /// Drop the session instead of returning it to the pool, e.g. after an error which suggests
+    /// the session is no longer usable.
+    pub fn discard(mut self) {
+        self.session = None;
+    }
+}
+
+impl<'a> Deref for PooledSession<'a> {
+    type Target = Session;
+
+    fn deref(&self) -> &Session {
+        // Only `discard` and `drop` take the session, and both consume the guard.
+        self.session.as_ref().unwrap()
+    }
+}
+
+impl<'a> Drop for PooledSession<'a> {
+    fn drop(&mut self) {
+        if let Some(session) = self.session.take() {
+            self.pool.put(session);
+        }
+    }
+}
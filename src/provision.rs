@@ -0,0 +1,690 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Declarative provisioning: bringing a device to the state described by a manifest.
+//!
+//! A `Manifest` lists the objects a device should hold and the options it should have. `plan`
+//! compares it with the device, producing a `Plan` whose `Display` output is a dry run of what
+//! `apply` would do. Objects are identified by their ID and type. Objects can't be modified once
+//! created, so an object whose attributes differ from the manifest is reported as drifted and left
+//! alone.
+//!
+//! Manifests are TOML. Types, algorithms and capabilities use this crate's names for them, as
+//! parsed by their `FromStr` implementations (e.g. `authkey` and `asymmetric_sign_ecdsa`):
+//!
+//! ```toml
+//! delete_unmanaged = false
+//!
+//! [[options]]
+//! force_audit = "enabled"
+//!
+//! [[objects]]
+//! id = 2
+//! type = "authkey"
+//! label = "signer"
+//! algorithm = "yubico-aes-auth"
+//! domains = [1]
+//! capabilities = ["asymmetric_sign_ecdsa"]
+//! source = "import"
+//! password_env = "SIGNER_PASSWORD"
+//!
+//! [[objects]]
+//! id = 0x10
+//! type = "asymmetric"
+//! label = "release signing"
+//! algorithm = "ecp256"
+//! domains = [1]
+//! capabilities = ["asymmetric_sign_ecdsa"]
+//! source = "generate"
+//! ```
+
+use session::Session;
+use types::*;
+
+use failure::Error;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml;
+
+/// The desired state of a device.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Delete objects on the device which the manifest doesn't list. The AuthKey used to make the
+    /// plan is never deleted.
+    #[serde(default)]
+    pub delete_unmanaged: bool,
+    #[serde(default)]
+    pub options: Vec<DeviceOption>,
+    #[serde(default)]
+    pub objects: Vec<ObjectSpec>,
+}
+
+/// How an object listed in a manifest is created.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Source {
+    /// Generate the key on the device.
+    Generate,
+    /// Import the key from `key_file`, or an AuthKey's password from `password_env`.
+    Import,
+    /// Import an object exported under a wrap key from `wrapped_file`.
+    ImportWrapped,
+}
+
+impl fmt::Display for Source {
+    /// The source's spelling in a manifest, e.g. `import-wrapped`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match *self {
+            Source::Generate => "generate",
+            Source::Import => "import",
+            Source::ImportWrapped => "import-wrapped",
+        })
+    }
+}
+
+/// An object listed in a manifest.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ObjectSpec {
+    pub id: u16,
+    #[serde(rename = "type")]
+    pub object_type: ObjectType,
+    #[serde(default)]
+    pub label: String,
+    pub algorithm: Algorithm,
+    pub domains: Vec<Domain>,
+    #[serde(default)]
+    pub capabilities: Vec<Capability>,
+    #[serde(default)]
+    pub delegated_capabilities: Vec<Capability>,
+    pub source: Source,
+    /// For `Source::Import`: the raw key (an EC private scalar, an Ed25519 seed, the RSA primes
+    /// `p || q`, or an HMAC or wrap key), or the contents of an opaque object.
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
+    /// For `Source::Import` of an AuthKey: the environment variable holding its password.
+    #[serde(default)]
+    pub password_env: Option<String>,
+    /// For `Source::ImportWrapped`: the output of Export Wrapped.
+    #[serde(default)]
+    pub wrapped_file: Option<PathBuf>,
+    /// For `Source::ImportWrapped`: the wrap key the object was exported under.
+    #[serde(default)]
+    pub wrapkey_id: Option<u16>,
+}
+
+impl Manifest {
+    /// Parse and validate a manifest.
+    pub fn from_toml(manifest: &str) -> Result<Manifest, Error> {
+        let manifest: Manifest = toml::from_str(manifest)?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    /// Read, parse and validate the manifest at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Manifest, Error> {
+        let path = path.as_ref();
+        let manifest = fs::read_to_string(path)
+            .map_err(|e| format_err!("couldn't read {}: {}", path.display(), e))?;
+        Manifest::from_toml(&manifest).map_err(|e| format_err!("{}: {}", path.display(), e))
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let mut option_types = Vec::new();
+        for option in &self.options {
+            if option_types.contains(&option.option_type()) {
+                bail!("{:?} is set more than once", option.option_type());
+            }
+            option_types.push(option.option_type());
+        }
+
+        let mut objects = HashSet::new();
+        for spec in &self.objects {
+            if !objects.insert((spec.id, u32::from(spec.object_type))) {
+                bail!("{} is listed more than once", spec.name());
+            }
+            spec.validate()
+                .map_err(|e| format_err!("{}: {}", spec.name(), e))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ObjectSpec {
+    fn name(&self) -> String {
+        format!("{} 0x{:04x}", self.object_type, self.id)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.domains.is_empty() {
+            bail!("no domains");
+        }
+        if !self.delegated_capabilities.is_empty()
+            && !matches!(self.object_type, ObjectType::AuthKey | ObjectType::WrapKey)
+        {
+            bail!("only AuthKeys and wrap keys have delegated capabilities");
+        }
+
+        let supported = match (self.source, self.object_type) {
+            (Source::Generate, ObjectType::Asymmetric) => asymmetric_kind(self.algorithm).is_some(),
+            (Source::Generate, ObjectType::HmacKey) | (Source::Generate, ObjectType::WrapKey) => {
+                true
+            }
+            (Source::Import, ObjectType::AuthKey) => {
+                if self.password_env.is_none() {
+                    bail!("importing an AuthKey needs password_env");
+                }
+                true
+            }
+            (Source::Import, ObjectType::Asymmetric)
+            | (Source::Import, ObjectType::HmacKey)
+            | (Source::Import, ObjectType::WrapKey)
+            | (Source::Import, ObjectType::Opaque) => {
+                if self.key_file.is_none() {
+                    bail!("importing needs key_file");
+                }
+                self.object_type != ObjectType::Asymmetric
+                    || asymmetric_kind(self.algorithm).is_some()
+            }
+            (Source::ImportWrapped, _) => {
+                if self.wrapped_file.is_none() || self.wrapkey_id.is_none() {
+                    bail!("import-wrapped needs wrapped_file and wrapkey_id");
+                }
+                true
+            }
+            _ => false,
+        };
+        if !supported {
+            bail!(
+                "{} objects with algorithm {} can't be created with {:?}",
+                self.object_type,
+                self.algorithm,
+                self.source
+            );
+        }
+
+        Ok(())
+    }
+
+    /// The ways `info` differs from this spec.
+    fn differences(&self, info: &ObjectInfo) -> Vec<String> {
+        let mut differences = Vec::new();
+
+        if info.label != self.label {
+            differences.push(format!("label is {:?}, not {:?}", info.label, self.label));
+        }
+        if info.algorithm != Some(self.algorithm) {
+            differences.push(format!(
+                "algorithm is {}, not {}",
+                info.algorithm
+                    .map_or_else(|| "unknown".to_string(), |a| a.to_string()),
+                self.algorithm
+            ));
+        }
+        if DomainParam::from(&info.domains).0 != DomainParam::from(&self.domains).0 {
+            differences.push(format!(
+                "domains are {}, not {}",
+                domain_list(&info.domains),
+                domain_list(&self.domains)
+            ));
+        }
+        differences.extend(capability_difference(
+            "capabilities",
+            &info.capabilities,
+            &self.capabilities,
+        ));
+        differences.extend(capability_difference(
+            "delegated capabilities",
+            &info.delegated_capabilities,
+            &self.delegated_capabilities,
+        ));
+
+        differences
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AsymmetricKind {
+    Rsa,
+    Ec,
+    Ed,
+}
+
+fn asymmetric_kind(algorithm: Algorithm) -> Option<AsymmetricKind> {
+    match algorithm {
+        Algorithm::Rsa2048 | Algorithm::Rsa3072 | Algorithm::Rsa4096 => Some(AsymmetricKind::Rsa),
+        Algorithm::EcEd25519 => Some(AsymmetricKind::Ed),
+        algorithm if algorithm.ec_field_len().is_some() => Some(AsymmetricKind::Ec),
+        _ => None,
+    }
+}
+
+fn domain_list(domains: &[Domain]) -> String {
+    let mut domains: Vec<u8> = domains.iter().map(|domain| domain.0).collect();
+    domains.sort();
+    domains
+        .iter()
+        .map(|domain| domain.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn capability_difference(
+    what: &str,
+    device: &[Capability],
+    manifest: &[Capability],
+) -> Option<String> {
+    let device: HashSet<String> = device.iter().map(Capability::to_string).collect();
+    let manifest: HashSet<String> = manifest.iter().map(Capability::to_string).collect();
+
+    let mut missing: Vec<&String> = manifest.difference(&device).collect();
+    let mut unexpected: Vec<&String> = device.difference(&manifest).collect();
+    missing.sort();
+    unexpected.sort();
+
+    let mut problems = Vec::new();
+    if !missing.is_empty() {
+        problems.push(format!(
+            "missing {}",
+            missing
+                .iter()
+                .map(|c| c.as_str())
+                .collect::<Vec<_>>()
+                .join(",")
+        ));
+    }
+    if !unexpected.is_empty() {
+        problems.push(format!(
+            "unexpected {}",
+            unexpected
+                .iter()
+                .map(|c| c.as_str())
+                .collect::<Vec<_>>()
+                .join(",")
+        ));
+    }
+
+    if problems.is_empty() {
+        None
+    } else {
+        Some(format!("{}: {}", what, problems.join("; ")))
+    }
+}
+
+/// One step of a `Plan`.
+#[derive(Clone, Debug)]
+pub enum Action {
+    /// Change a device option.
+    SetOption {
+        current: DeviceOption,
+        desired: DeviceOption,
+    },
+    /// Delete an object the manifest doesn't list.
+    Delete(ObjectInfo),
+    /// Create an object listed in the manifest.
+    Create(ObjectSpec),
+    /// An object listed in the manifest exists with different attributes, and is left alone.
+    Drifted {
+        spec: ObjectSpec,
+        differences: Vec<String>,
+    },
+    /// An object listed in the manifest exists as described.
+    Unchanged(ObjectSpec),
+    /// An object the manifest doesn't list is left alone.
+    Unmanaged(ObjectInfo),
+}
+
+impl Action {
+    /// Whether `apply` does anything for this action.
+    pub fn is_change(&self) -> bool {
+        matches!(
+            *self,
+            Action::SetOption { .. } | Action::Delete(_) | Action::Create(_)
+        )
+    }
+}
+
+fn object_name(object_type: ObjectType, id: u16, label: &str) -> String {
+    format!("{} 0x{:04x} {:?}", object_type, id, label)
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Action::SetOption {
+                ref current,
+                ref desired,
+            } => write!(f, "set option: {:?} -> {:?}", current, desired),
+            Action::Delete(ref info) => write!(
+                f,
+                "delete {}",
+                object_name(info.object_type, info.id, &info.label)
+            ),
+            Action::Create(ref spec) => write!(
+                f,
+                "create {} ({} {})",
+                object_name(spec.object_type, spec.id, &spec.label),
+                spec.algorithm,
+                spec.source
+            ),
+            Action::Drifted {
+                ref spec,
+                ref differences,
+            } => write!(
+                f,
+                "drifted {}: {}",
+                object_name(spec.object_type, spec.id, &spec.label),
+                differences.join("; ")
+            ),
+            Action::Unchanged(ref spec) => write!(
+                f,
+                "unchanged {}",
+                object_name(spec.object_type, spec.id, &spec.label)
+            ),
+            Action::Unmanaged(ref info) => write!(
+                f,
+                "unmanaged {}",
+                object_name(info.object_type, info.id, &info.label)
+            ),
+        }
+    }
+}
+
+/// The actions needed to bring a device to the state described by a manifest.
+#[derive(Clone, Debug)]
+pub struct Plan {
+    pub actions: Vec<Action>,
+}
+
+impl Plan {
+    /// Whether the device already matches the manifest, apart from any drifted or unmanaged
+    /// objects.
+    pub fn is_empty(&self) -> bool {
+        !self.actions.iter().any(Action::is_change)
+    }
+
+    /// Objects which differ from the manifest and can't be fixed without deleting them.
+    pub fn drifted(&self) -> Vec<&Action> {
+        self.actions
+            .iter()
+            .filter(|action| matches!(**action, Action::Drifted { .. }))
+            .collect()
+    }
+}
+
+impl fmt::Display for Plan {
+    /// One line per action other than `Action::Unchanged`, followed by a summary.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (mut create, mut delete, mut options, mut drifted, mut unchanged, mut unmanaged) =
+            (0, 0, 0, 0, 0, 0);
+
+        for action in &self.actions {
+            match *action {
+                Action::SetOption { .. } => options += 1,
+                Action::Delete(_) => delete += 1,
+                Action::Create(_) => create += 1,
+                Action::Drifted { .. } => drifted += 1,
+                Action::Unchanged(_) => {
+                    unchanged += 1;
+                    continue;
+                }
+                Action::Unmanaged(_) => unmanaged += 1,
+            }
+            writeln!(f, "{}", action)?;
+        }
+
+        write!(
+            f,
+            "{} to create, {} to delete, {} option(s) to set, {} drifted, {} unchanged, {} \
+             unmanaged",
+            create, delete, options, drifted, unchanged, unmanaged
+        )
+    }
+}
+
+/// Compare `manifest` with the device, returning the plan `apply` would carry out.
+pub fn plan(session: &Session, manifest: &Manifest) -> Result<Plan, Error> {
+    // `list_objects` only returns IDs and types.
+    let mut objects = Vec::new();
+    for object in session.list_objects().execute()? {
+        objects.push(session.get_object_info(object.id, object.object_type)?);
+    }
+
+    let mut options = Vec::new();
+    for option in &manifest.options {
+        options.push(session.get_option(option.option_type())?);
+    }
+
+    Ok(diff(manifest, &objects, &options, session.auth_key_id()))
+}
+
+/// The planner: compare `manifest` with the device's `objects` and current `options` (in the
+/// order of `manifest.options`). `auth_key_id` is never deleted.
+pub(crate) fn diff(
+    manifest: &Manifest,
+    objects: &[ObjectInfo],
+    options: &[DeviceOption],
+    auth_key_id: u16,
+) -> Plan {
+    let mut actions = Vec::new();
+
+    for (desired, current) in manifest.options.iter().zip(options) {
+        let current = match (desired, current) {
+            // Only the commands the manifest lists are compared.
+            (DeviceOption::CommandAudit(desired), DeviceOption::CommandAudit(current)) => {
+                DeviceOption::CommandAudit(
+                    desired
+                        .iter()
+                        .filter_map(|&(command, _)| {
+                            current.iter().find(|&&(c, _)| c == command).cloned()
+                        })
+                        .collect(),
+                )
+            }
+            _ => current.clone(),
+        };
+
+        if current != *desired {
+            actions.push(Action::SetOption {
+                current,
+                desired: desired.clone(),
+            });
+        }
+    }
+
+    let find_spec = |info: &ObjectInfo| {
+        manifest
+            .objects
+            .iter()
+            .find(|spec| spec.id == info.id && spec.object_type == info.object_type)
+    };
+
+    let mut unmanaged = Vec::new();
+    for info in objects {
+        if find_spec(info).is_some() {
+            continue;
+        }
+
+        let is_session_key = info.object_type == ObjectType::AuthKey && info.id == auth_key_id;
+        if manifest.delete_unmanaged && !is_session_key {
+            actions.push(Action::Delete(info.clone()));
+        } else {
+            unmanaged.push(Action::Unmanaged(info.clone()));
+        }
+    }
+
+    for spec in &manifest.objects {
+        let info = objects
+            .iter()
+            .find(|info| info.id == spec.id && info.object_type == spec.object_type);
+
+        actions.push(match info {
+            None => Action::Create(spec.clone()),
+            Some(info) => {
+                let differences = spec.differences(info);
+                if differences.is_empty() {
+                    Action::Unchanged(spec.clone())
+                } else {
+                    Action::Drifted {
+                        spec: spec.clone(),
+                        differences,
+                    }
+                }
+            }
+        });
+    }
+
+    actions.extend(unmanaged);
+    Plan { actions }
+}
+
+/// Carry out `plan`: set options, then delete objects, then create objects in manifest order.
+pub fn apply(session: &Session, plan: &Plan) -> Result<(), Error> {
+    for action in &plan.actions {
+        let result = match *action {
+            Action::SetOption { ref desired, .. } => session.put_option(desired.clone()),
+            Action::Delete(ref info) => session.delete_object(info.id, info.object_type),
+            Action::Create(ref spec) => create(session, spec),
+            _ => Ok(()),
+        };
+        result.map_err(|e| format_err!("{}: {}", action, e))?;
+    }
+
+    Ok(())
+}
+
+fn read_file(path: &Option<PathBuf>) -> Result<Vec<u8>, Error> {
+    let path = path
+        .as_ref()
+        .ok_or_else(|| format_err!("no file to import from"))?;
+    fs::read(path).map_err(|e| format_err!("couldn't read {}: {}", path.display(), e))
+}
+
+/// Check that the object imported from `spec`'s wrapped file is the one `spec` describes. The
+/// wrapped object carries its own type and ID, so this catches a manifest pointing at the wrong
+/// file.
+pub(crate) fn check_unwrapped(
+    spec: &ObjectSpec,
+    object_type: ObjectType,
+    object_id: u16,
+) -> Result<(), Error> {
+    if object_type != spec.object_type || object_id != spec.id {
+        bail!(
+            "{} contained {} 0x{:04x}, not {} 0x{:04x}",
+            spec.wrapped_file
+                .as_ref()
+                .map_or_else(String::new, |path| path.display().to_string()),
+            object_type,
+            object_id,
+            spec.object_type,
+            spec.id
+        );
+    }
+
+    Ok(())
+}
+
+fn create(session: &Session, spec: &ObjectSpec) -> Result<(), Error> {
+    let (id, label, algorithm) = (spec.id, spec.label.as_str(), spec.algorithm);
+    let (domains, capabilities, delegated) = (
+        &spec.domains[..],
+        &spec.capabilities[..],
+        &spec.delegated_capabilities[..],
+    );
+
+    match (spec.source, spec.object_type) {
+        (Source::Generate, ObjectType::Asymmetric) => match asymmetric_kind(algorithm) {
+            Some(AsymmetricKind::Rsa) => {
+                session.generate_key_rsa(id, label, domains, capabilities, algorithm)
+            }
+            Some(AsymmetricKind::Ec) => {
+                session.generate_key_ec(id, label, domains, capabilities, algorithm)
+            }
+            Some(AsymmetricKind::Ed) => {
+                session.generate_key_ed(id, label, domains, capabilities, algorithm)
+            }
+            None => bail!("can't generate {} keys", algorithm),
+        },
+        (Source::Generate, ObjectType::HmacKey) => {
+            session.generate_key_hmac(id, label, domains, capabilities, algorithm)
+        }
+        (Source::Generate, ObjectType::WrapKey) => {
+            session.generate_wrapkey(id, label, domains, capabilities, delegated, algorithm)
+        }
+        (Source::Import, ObjectType::AuthKey) => {
+            let name = spec
+                .password_env
+                .as_ref()
+                .ok_or_else(|| format_err!("no password_env"))?;
+            let password =
+                env::var(name).map_err(|e| format_err!("couldn't read ${}: {}", name, e))?;
+            session.create_authkey(id, label, domains, capabilities, delegated, &password)
+        }
+        (Source::Import, ObjectType::Asymmetric) => {
+            let key = read_file(&spec.key_file)?;
+            match asymmetric_kind(algorithm) {
+                Some(AsymmetricKind::Rsa) => {
+                    let (p, q) = key.split_at(key.len() / 2);
+                    session.put_key_rsa(id, label, domains, capabilities, algorithm, p, q)
+                }
+                Some(AsymmetricKind::Ec) => {
+                    session.put_key_ec(id, label, domains, capabilities, algorithm, key)
+                }
+                Some(AsymmetricKind::Ed) => {
+                    session.put_key_ed(id, label, domains, capabilities, algorithm, key)
+                }
+                None => bail!("can't import {} keys", algorithm),
+            }
+        }
+        (Source::Import, ObjectType::HmacKey) => {
+            let key = read_file(&spec.key_file)?;
+            session.put_key_hmac(id, label, domains, capabilities, algorithm, key)
+        }
+        (Source::Import, ObjectType::WrapKey) => {
+            let key = read_file(&spec.key_file)?;
+            session.put_wrapkey(id, label, domains, capabilities, delegated, algorithm, key)
+        }
+        (Source::Import, ObjectType::Opaque) => {
+            let contents = read_file(&spec.key_file)?;
+            session.put_opaque_object(id, label, domains, capabilities, algorithm, &contents)
+        }
+        (Source::ImportWrapped, _) => {
+            let wrapped = read_file(&spec.wrapped_file)?;
+            let wrapkey_id = spec
+                .wrapkey_id
+                .ok_or_else(|| format_err!("no wrapkey_id"))?;
+            let (object_type, object_id) = session.import_wrapped(wrapkey_id, &wrapped)?;
+
+            // Don't leave behind an object the manifest doesn't describe.
+            if let Err(e) = check_unwrapped(spec, object_type, object_id) {
+                match session.delete_object(object_id, object_type) {
+                    Ok(()) => bail!("{}; deleted it", e),
+                    Err(delete_error) => bail!(
+                        "{}; couldn't delete it, so it's still on the device: {}",
+                        e,
+                        delete_error
+                    ),
+                }
+            }
+            Ok(())
+        }
+        (source, object_type) => bail!("can't create {} objects with {:?}", object_type, source),
+    }
+}
@@ -0,0 +1,452 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Encoders for `PublicKey` into the common public key interchange formats.
+
+use asn1;
+use types::*;
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use failure::Error;
+
+/// The public exponent of every RSA key generated or imported by the YubiHSM2.
+pub(crate) const RSA_PUBLIC_EXPONENT: &[u8] = &[0x01, 0x00, 0x01];
+
+/// Wrap `der` in PEM armor with the given label.
+pub(crate) fn pem_encode(label: &str, der: &[u8]) -> String {
+    let encoded = STANDARD.encode(der);
+    let mut out = format!("-----BEGIN {}-----\n", label);
+
+    for line in encoded.as_bytes().chunks(64) {
+        // base64 output is always ASCII, so this can't split a character.
+        out.push_str(&String::from_utf8_lossy(line));
+        out.push('\n');
+    }
+
+    out.push_str(&format!("-----END {}-----\n", label));
+    out
+}
+
+/// Append an SSH wire-format `string` to `out`.
+pub(crate) fn ssh_string(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+/// Append an SSH wire-format `mpint` holding the unsigned big-endian integer `data` to `out`.
+pub(crate) fn ssh_mpint(out: &mut Vec<u8>, data: &[u8]) {
+    let skip = data.iter().take_while(|b| **b == 0).count();
+    let data = &data[skip..];
+
+    if !data.is_empty() && data[0] & 0x80 != 0 {
+        out.extend_from_slice(&(data.len() as u32 + 1).to_be_bytes());
+        out.push(0);
+        out.extend_from_slice(data);
+    } else {
+        ssh_string(out, data);
+    }
+}
+
+/// Read an SSH wire-format `string` from the start of `input`, returning its contents and the
+/// rest of the input.
+pub(crate) fn ssh_read_string(input: &[u8]) -> Result<(&[u8], &[u8]), Error> {
+    if input.len() < 4 {
+        bail!("truncated SSH string");
+    }
+    let (len, input) = input.split_at(4);
+    let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+    if input.len() < len {
+        bail!("truncated SSH string");
+    }
+
+    Ok(input.split_at(len))
+}
+
+/// Read a non-negative SSH wire-format `mpint` from the start of `input`, returning its unsigned
+/// big-endian value without leading zeroes, and the rest of the input.
+pub(crate) fn ssh_read_mpint(input: &[u8]) -> Result<(&[u8], &[u8]), Error> {
+    let (value, rest) = ssh_read_string(input)?;
+    if value.iter().take(1).any(|b| b & 0x80 != 0) {
+        bail!("negative SSH mpint");
+    }
+
+    let skip = value.iter().take_while(|b| **b == 0).count();
+    Ok((&value[skip..], rest))
+}
+
+/// Left-pad `data` with zeroes to `len` bytes.
+pub(crate) fn pad_to(data: &[u8], len: usize) -> Vec<u8> {
+    let mut out = vec![0; len.saturating_sub(data.len())];
+    out.extend_from_slice(data);
+    out
+}
+
+/// The elliptic curves the device supports for ECC keys.
+const EC_CURVES: &[Algorithm] = &[
+    Algorithm::EcP224,
+    Algorithm::EcP256,
+    Algorithm::EcP384,
+    Algorithm::EcP521,
+    Algorithm::EcK256,
+    Algorithm::EcBp256,
+    Algorithm::EcBp384,
+    Algorithm::EcBp512,
+];
+
+impl Algorithm {
+    /// The length in bytes of a field element (and so of each public point coordinate) for
+    /// elliptic curve algorithms.
+    pub(crate) fn ec_field_len(&self) -> Option<usize> {
+        match *self {
+            Algorithm::EcP224 => Some(28),
+            Algorithm::EcP256 | Algorithm::EcK256 | Algorithm::EcBp256 => Some(32),
+            Algorithm::EcP384 | Algorithm::EcBp384 => Some(48),
+            Algorithm::EcBp512 => Some(64),
+            Algorithm::EcP521 => Some(66),
+            _ => None,
+        }
+    }
+
+    fn ec_curve_oid(&self) -> Option<&'static [u64]> {
+        match *self {
+            Algorithm::EcP224 => Some(asn1::OID_SECP224R1),
+            Algorithm::EcP256 => Some(asn1::OID_SECP256R1),
+            Algorithm::EcP384 => Some(asn1::OID_SECP384R1),
+            Algorithm::EcP521 => Some(asn1::OID_SECP521R1),
+            Algorithm::EcK256 => Some(asn1::OID_SECP256K1),
+            Algorithm::EcBp256 => Some(asn1::OID_BRAINPOOL_P256R1),
+            Algorithm::EcBp384 => Some(asn1::OID_BRAINPOOL_P384R1),
+            Algorithm::EcBp512 => Some(asn1::OID_BRAINPOOL_P512R1),
+            _ => None,
+        }
+    }
+}
+
+impl PublicKey {
+    /// The public point of an ECC key in uncompressed SEC1 form (`0x04 || x || y`).
+    pub fn ec_point(&self) -> Result<Vec<u8>, Error> {
+        match *self {
+            PublicKey::Ecc(algorithm, ref x, ref y) => {
+                let field_len = match algorithm.ec_field_len() {
+                    Some(len) => len,
+                    None => bail!("ec_point: unexpected algorithm {}", algorithm),
+                };
+
+                let mut point = vec![0x04];
+                point.extend(pad_to(x, field_len));
+                point.extend(pad_to(y, field_len));
+                Ok(point)
+            }
+            _ => bail!("ec_point: not an ECC key"),
+        }
+    }
+
+    /// The public point of an ECC key in compressed SEC1 form (`0x02` or `0x03` for an even or
+    /// odd `y`, followed by `x`), as used for Bitcoin public keys.
+    pub fn ec_point_compressed(&self) -> Result<Vec<u8>, Error> {
+        let point = self.ec_point()?;
+        let field_len = (point.len() - 1) / 2;
+
+        let mut compressed = vec![0x02 | (point[2 * field_len] & 1)];
+        compressed.extend_from_slice(&point[1..=field_len]);
+        Ok(compressed)
+    }
+
+    /// Encode the key as a DER SubjectPublicKeyInfo structure (RFC 5280, RFC 5480, RFC 8410).
+    pub fn to_spki_der(&self) -> Result<Vec<u8>, Error> {
+        let (algorithm_identifier, public_key) = match *self {
+            PublicKey::Rsa(_, ref n) => {
+                let rsa_public_key = asn1::sequence(&[
+                    &asn1::unsigned_integer(n),
+                    &asn1::unsigned_integer(RSA_PUBLIC_EXPONENT),
+                ]);
+
+                (
+                    asn1::sequence(&[&asn1::oid(asn1::OID_RSA_ENCRYPTION), &asn1::null()]),
+                    rsa_public_key,
+                )
+            }
+            PublicKey::Ecc(algorithm, _, _) => {
+                let curve = match algorithm.ec_curve_oid() {
+                    Some(curve) => curve,
+                    None => bail!("to_spki_der: unexpected algorithm {}", algorithm),
+                };
+
+                (
+                    asn1::sequence(&[&asn1::oid(asn1::OID_EC_PUBLIC_KEY), &asn1::oid(curve)]),
+                    self.ec_point()?,
+                )
+            }
+            PublicKey::Edc(_, ref a) => {
+                (asn1::sequence(&[&asn1::oid(asn1::OID_ED25519)]), a.clone())
+            }
+        };
+
+        Ok(asn1::sequence(&[
+            &algorithm_identifier,
+            &asn1::bit_string(&public_key),
+        ]))
+    }
+
+    /// Decode a DER SubjectPublicKeyInfo structure, the inverse of `to_spki_der`.
+    ///
+    /// Only the key types the device supports can be decoded: RSA keys must be 2048, 3072 or 4096
+    /// bits with the public exponent 65537, and EC points must be uncompressed.
+    pub fn from_spki_der<T: AsRef<[u8]>>(der: T) -> Result<PublicKey, Error> {
+        let (spki, rest) = asn1::read_element(asn1::TAG_SEQUENCE, der.as_ref())?;
+        if !rest.is_empty() {
+            bail!("from_spki_der: trailing data after SubjectPublicKeyInfo");
+        }
+
+        let (algorithm_identifier, spki) = asn1::read_element(asn1::TAG_SEQUENCE, spki)?;
+        let (public_key, _) = asn1::read_element(asn1::TAG_BIT_STRING, spki)?;
+        if public_key.first() != Some(&0) {
+            bail!("from_spki_der: public key has unused bits");
+        }
+        let public_key = &public_key[1..];
+
+        let (key_type, parameters) = asn1::split_element(algorithm_identifier)?;
+
+        if key_type == &asn1::oid(asn1::OID_RSA_ENCRYPTION)[..] {
+            let (rsa_public_key, _) = asn1::read_element(asn1::TAG_SEQUENCE, public_key)?;
+            let (n, rsa_public_key) = asn1::read_unsigned_integer(rsa_public_key)?;
+            let (e, _) = asn1::read_unsigned_integer(rsa_public_key)?;
+
+            if e != RSA_PUBLIC_EXPONENT {
+                bail!("from_spki_der: unsupported RSA public exponent");
+            }
+
+            let algorithm = match n.len() {
+                256 => Algorithm::Rsa2048,
+                384 => Algorithm::Rsa3072,
+                512 => Algorithm::Rsa4096,
+                len => bail!("from_spki_der: unsupported {}-bit RSA key", len * 8),
+            };
+
+            Ok(PublicKey::Rsa(algorithm, n.to_vec()))
+        } else if key_type == &asn1::oid(asn1::OID_EC_PUBLIC_KEY)[..] {
+            let curve = EC_CURVES
+                .iter()
+                .find(|a| a.ec_curve_oid().map(asn1::oid) == Some(parameters.to_vec()));
+            let algorithm = match curve {
+                Some(&algorithm) => algorithm,
+                None => bail!("from_spki_der: unsupported elliptic curve"),
+            };
+            // Every curve in EC_CURVES has a field length.
+            let field_len = algorithm.ec_field_len().unwrap();
+
+            if public_key.len() != 1 + 2 * field_len || public_key[0] != 0x04 {
+                bail!("from_spki_der: expected an uncompressed {} point", algorithm);
+            }
+
+            let (x, y) = public_key[1..].split_at(field_len);
+            Ok(PublicKey::Ecc(algorithm, x.to_vec(), y.to_vec()))
+        } else if key_type == &asn1::oid(asn1::OID_ED25519)[..] {
+            if public_key.len() != 32 {
+                bail!("from_spki_der: invalid ed25519 public key length {}", public_key.len());
+            }
+
+            Ok(PublicKey::Edc(Algorithm::EcEd25519, public_key.to_vec()))
+        } else {
+            bail!("from_spki_der: unsupported key type")
+        }
+    }
+
+    /// Encode the key as a PEM `PUBLIC KEY` block containing its SubjectPublicKeyInfo.
+    pub fn to_pem(&self) -> Result<String, Error> {
+        Ok(pem_encode("PUBLIC KEY", &self.to_spki_der()?))
+    }
+
+    /// Encode the key as a JSON Web Key (RFC 7517, RFC 7518, RFC 8037).
+    ///
+    /// Only the required members are included, in lexicographic order, so the output can also be
+    /// used to compute an RFC 7638 thumbprint. Curves without a registered JWK name (P-224 and the
+    /// Brainpool curves) are an error.
+    pub fn to_jwk(&self) -> Result<String, Error> {
+        match *self {
+            PublicKey::Rsa(_, ref n) => {
+                let skip = n.iter().take_while(|b| **b == 0).count();
+
+                Ok(format!(
+                    r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#,
+                    URL_SAFE_NO_PAD.encode(RSA_PUBLIC_EXPONENT),
+                    URL_SAFE_NO_PAD.encode(&n[skip..])
+                ))
+            }
+            PublicKey::Ecc(algorithm, ref x, ref y) => {
+                let crv = match algorithm {
+                    Algorithm::EcP256 => "P-256",
+                    Algorithm::EcP384 => "P-384",
+                    Algorithm::EcP521 => "P-521",
+                    Algorithm::EcK256 => "secp256k1",
+                    a => bail!("to_jwk: no JWK curve name for {}", a),
+                };
+                // Checked above that this is a supported curve.
+                let field_len = algorithm.ec_field_len().unwrap();
+
+                Ok(format!(
+                    r#"{{"crv":"{}","kty":"EC","x":"{}","y":"{}"}}"#,
+                    crv,
+                    URL_SAFE_NO_PAD.encode(pad_to(x, field_len)),
+                    URL_SAFE_NO_PAD.encode(pad_to(y, field_len))
+                ))
+            }
+            PublicKey::Edc(_, ref a) => Ok(format!(
+                r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#,
+                URL_SAFE_NO_PAD.encode(a)
+            )),
+        }
+    }
+
+    /// The OpenSSH key type name for this key, e.g. `ssh-ed25519`.
+    pub fn openssh_key_type(&self) -> Result<&'static str, Error> {
+        match *self {
+            PublicKey::Rsa(_, _) => Ok("ssh-rsa"),
+            PublicKey::Ecc(Algorithm::EcP256, _, _) => Ok("ecdsa-sha2-nistp256"),
+            PublicKey::Ecc(Algorithm::EcP384, _, _) => Ok("ecdsa-sha2-nistp384"),
+            PublicKey::Ecc(Algorithm::EcP521, _, _) => Ok("ecdsa-sha2-nistp521"),
+            PublicKey::Ecc(a, _, _) => bail!("openssh_key_type: OpenSSH doesn't support {}", a),
+            PublicKey::Edc(_, _) => Ok("ssh-ed25519"),
+        }
+    }
+
+    /// Encode the key as an OpenSSH public key blob (RFC 4253, RFC 5656, RFC 8709), as used inside
+    /// `authorized_keys` lines and the SSH agent protocol.
+    pub fn to_openssh_blob(&self) -> Result<Vec<u8>, Error> {
+        let key_type = self.openssh_key_type()?;
+        let mut blob = Vec::new();
+        ssh_string(&mut blob, key_type.as_bytes());
+
+        match *self {
+            PublicKey::Rsa(_, ref n) => {
+                ssh_mpint(&mut blob, RSA_PUBLIC_EXPONENT);
+                ssh_mpint(&mut blob, n);
+            }
+            PublicKey::Ecc(_, _, _) => {
+                // The curve identifier is the suffix of the key type, e.g. "nistp256".
+                ssh_string(&mut blob, &key_type.as_bytes()["ecdsa-sha2-".len()..]);
+                ssh_string(&mut blob, &self.ec_point()?);
+            }
+            PublicKey::Edc(_, ref a) => ssh_string(&mut blob, a),
+        }
+
+        Ok(blob)
+    }
+
+    /// Decode an OpenSSH public key blob, the inverse of `to_openssh_blob`.
+    ///
+    /// As with `from_spki_der`, RSA keys must be 2048, 3072 or 4096 bits with the public exponent
+    /// 65537.
+    pub fn from_openssh_blob<T: AsRef<[u8]>>(blob: T) -> Result<PublicKey, Error> {
+        let (key_type, blob) = ssh_read_string(blob.as_ref())?;
+
+        let (public_key, rest) = match key_type {
+            b"ssh-rsa" => {
+                let (e, blob) = ssh_read_mpint(blob)?;
+                let (n, blob) = ssh_read_mpint(blob)?;
+
+                if e != RSA_PUBLIC_EXPONENT {
+                    bail!("from_openssh_blob: unsupported RSA public exponent");
+                }
+
+                let algorithm = match n.len() {
+                    256 => Algorithm::Rsa2048,
+                    384 => Algorithm::Rsa3072,
+                    512 => Algorithm::Rsa4096,
+                    len => bail!("from_openssh_blob: unsupported {}-bit RSA key", len * 8),
+                };
+
+                (PublicKey::Rsa(algorithm, n.to_vec()), blob)
+            }
+            b"ecdsa-sha2-nistp256" | b"ecdsa-sha2-nistp384" | b"ecdsa-sha2-nistp521" => {
+                let (curve, blob) = ssh_read_string(blob)?;
+                let (point, blob) = ssh_read_string(blob)?;
+
+                let algorithm = match curve {
+                    b"nistp256" => Algorithm::EcP256,
+                    b"nistp384" => Algorithm::EcP384,
+                    b"nistp521" => Algorithm::EcP521,
+                    _ => bail!("from_openssh_blob: unsupported elliptic curve"),
+                };
+                if &key_type["ecdsa-sha2-".len()..] != curve {
+                    bail!("from_openssh_blob: key type doesn't match curve");
+                }
+                // All three curves have a field length.
+                let field_len = algorithm.ec_field_len().unwrap();
+
+                if point.len() != 1 + 2 * field_len || point[0] != 0x04 {
+                    bail!("from_openssh_blob: expected an uncompressed {} point", algorithm);
+                }
+
+                let (x, y) = point[1..].split_at(field_len);
+                (PublicKey::Ecc(algorithm, x.to_vec(), y.to_vec()), blob)
+            }
+            b"ssh-ed25519" => {
+                let (a, blob) = ssh_read_string(blob)?;
+                if a.len() != 32 {
+                    bail!("from_openssh_blob: invalid ed25519 public key length {}", a.len());
+                }
+
+                (PublicKey::Edc(Algorithm::EcEd25519, a.to_vec()), blob)
+            }
+            _ => bail!(
+                "from_openssh_blob: unsupported key type {}",
+                String::from_utf8_lossy(key_type)
+            ),
+        };
+
+        if !rest.is_empty() {
+            bail!("from_openssh_blob: trailing data after public key");
+        }
+
+        Ok(public_key)
+    }
+
+    /// Decode an OpenSSH public key line (`<key type> <base64 blob> [comment]`), as found in
+    /// `.pub` files, ignoring the comment.
+    pub fn from_openssh(line: &str) -> Result<PublicKey, Error> {
+        let mut fields = line.split_whitespace();
+        let (key_type, blob) = match (fields.next(), fields.next()) {
+            (Some(key_type), Some(blob)) => (key_type, blob),
+            _ => bail!("from_openssh: expected a key type and base64 public key"),
+        };
+
+        let blob = STANDARD
+            .decode(blob)
+            .map_err(|e| format_err!("from_openssh: invalid base64: {}", e))?;
+        let public_key = PublicKey::from_openssh_blob(&blob)?;
+        if public_key.openssh_key_type()? != key_type {
+            bail!("from_openssh: key type doesn't match key");
+        }
+
+        Ok(public_key)
+    }
+
+    /// Encode the key as an OpenSSH `authorized_keys` line, with an optional trailing comment.
+    pub fn to_openssh(&self, comment: Option<&str>) -> Result<String, Error> {
+        let mut line = format!(
+            "{} {}",
+            self.openssh_key_type()?,
+            STANDARD.encode(self.to_openssh_blob()?)
+        );
+
+        if let Some(comment) = comment {
+            line.push(' ');
+            line.push_str(comment);
+        }
+
+        Ok(line)
+    }
+}
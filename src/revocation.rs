@@ -0,0 +1,417 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Certificate revocation lists (RFC 5280) and OCSP responses (RFC 6960) signed by a
+//! `CertificateAuthority`'s key on the device.
+//!
+//! Both are signed directly by the CA key: CRLs are issued by the CA itself, and OCSP responses
+//! identify the responder by the CA's key hash, so no delegated responder certificate is needed.
+
+use asn1;
+use ca::CertificateAuthority;
+use public_key::pem_encode;
+use session::Session;
+use types::*;
+use x509;
+
+use failure::Error;
+use sha1::Sha1;
+use sha2::Digest;
+
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Serializes `OpaqueCrlNumber::next` within the process.
+static NEXT_LOCK: Mutex<()> = Mutex::new(());
+
+/// The reason a certificate was revoked (RFC 5280 section 5.3.1).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RevocationReason {
+    Unspecified,
+    KeyCompromise,
+    CaCompromise,
+    AffiliationChanged,
+    Superseded,
+    CessationOfOperation,
+    CertificateHold,
+    RemoveFromCrl,
+    PrivilegeWithdrawn,
+    AaCompromise,
+}
+
+impl RevocationReason {
+    /// The `CRLReason` value.
+    pub fn code(&self) -> u8 {
+        match *self {
+            RevocationReason::Unspecified => 0,
+            RevocationReason::KeyCompromise => 1,
+            RevocationReason::CaCompromise => 2,
+            RevocationReason::AffiliationChanged => 3,
+            RevocationReason::Superseded => 4,
+            RevocationReason::CessationOfOperation => 5,
+            RevocationReason::CertificateHold => 6,
+            // 7 is not used.
+            RevocationReason::RemoveFromCrl => 8,
+            RevocationReason::PrivilegeWithdrawn => 9,
+            RevocationReason::AaCompromise => 10,
+        }
+    }
+}
+
+/// A revoked certificate, as listed in a CRL.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RevokedCertificate {
+    /// The certificate's serial number, as an unsigned big-endian integer.
+    pub serial_number: Vec<u8>,
+    pub revocation_time: SystemTime,
+    /// Left out of the CRL entry when `None`, as RFC 5280 recommends instead of `Unspecified`.
+    pub reason: Option<RevocationReason>,
+}
+
+impl RevokedCertificate {
+    fn to_der(&self) -> Result<Vec<u8>, Error> {
+        let serial_number = asn1::unsigned_integer(&self.serial_number);
+        let revocation_date = x509::time(self.revocation_time)?;
+
+        Ok(match self.reason {
+            Some(reason) => {
+                let reason_code = x509::extension(
+                    asn1::OID_CRL_REASON,
+                    false,
+                    &asn1::enumerated(reason.code()),
+                );
+                asn1::sequence(&[
+                    &serial_number,
+                    &revocation_date,
+                    &asn1::sequence(&[&reason_code]),
+                ])
+            }
+            None => asn1::sequence(&[&serial_number, &revocation_date]),
+        })
+    }
+}
+
+/// A monotonically increasing CRL number kept on the device in an opaque object, so every CRL a
+/// CA publishes from this process has a larger number than the last, and the count survives
+/// restarts.
+///
+/// The number is stored as an 8-byte big-endian integer in an `Algorithm::OpaqueData` object.
+/// Opaque objects can't be modified, so the object is deleted and re-created each time the number
+/// is incremented, keeping its label, domains and capabilities; the authentication key in use needs
+/// the `GetOpaque`, `PutOpaque` and `DeleteOpaque` capabilities.
+///
+/// Increments are serialized within a process, but the device can't make them atomic: two
+/// processes or machines signing CRLs with the same object at the same time may both use the same
+/// number, so only one should sign CRLs at a time. An increment which finds the number changed
+/// under it fails rather than returning a number.
+///
+/// If the process or connector dies between the delete and the re-create, the object is left
+/// missing and `sign_crl` fails. Recreate it with `create`, passing the number of the last CRL
+/// published as `current`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpaqueCrlNumber {
+    object_id: u16,
+}
+
+impl OpaqueCrlNumber {
+    /// Use the CRL number in the existing opaque object `object_id`.
+    pub fn new(object_id: u16) -> OpaqueCrlNumber {
+        OpaqueCrlNumber { object_id }
+    }
+
+    /// Create the opaque object `object_id` holding `current`, the number of the last CRL
+    /// published. The next CRL will be numbered `current + 1`.
+    pub fn create(
+        session: &Session,
+        object_id: u16,
+        label: &str,
+        domains: &[Domain],
+        current: u64,
+    ) -> Result<OpaqueCrlNumber, Error> {
+        session.put_opaque_object(
+            object_id,
+            label,
+            domains,
+            &[],
+            Algorithm::OpaqueData,
+            &current.to_be_bytes(),
+        )?;
+
+        Ok(OpaqueCrlNumber { object_id })
+    }
+
+    /// The ID of the opaque object holding the number.
+    pub fn object_id(&self) -> u16 {
+        self.object_id
+    }
+
+    /// The number of the last CRL published.
+    pub fn current(&self, session: &Session) -> Result<u64, Error> {
+        let contents = session.get_opaque_object(self.object_id)?;
+        if contents.len() != 8 {
+            bail!(
+                "opaque object {} doesn't hold a CRL number",
+                self.object_id
+            );
+        }
+
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&contents);
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    /// Increment the number on the device and return the new value.
+    fn next(&self, session: &Session) -> Result<u64, Error> {
+        // The lock guards no data, so a panic while it was held leaves nothing inconsistent.
+        let _guard = NEXT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let info = session.get_object_info(self.object_id, ObjectType::Opaque)?;
+        let next = match self.current(session)?.checked_add(1) {
+            Some(next) => next,
+            None => bail!("CRL number in opaque object {} overflowed", self.object_id),
+        };
+
+        session.delete_object(self.object_id, ObjectType::Opaque)?;
+        session
+            .put_opaque_object(
+                self.object_id,
+                &info.label,
+                &info.domains,
+                &info.capabilities,
+                Algorithm::OpaqueData,
+                &next.to_be_bytes(),
+            )
+            .map_err(|e| {
+                // Refuse to carry on rather than quietly starting again from zero.
+                format_err!(
+                    "CRL number {} could not be stored and opaque object {} is gone; \
+                     recreate it with OpaqueCrlNumber::create and current = {}: {}",
+                    next,
+                    self.object_id,
+                    next,
+                    e
+                )
+            })?;
+
+        // Another process incrementing the number at the same time may have replaced or removed
+        // the object.
+        if self.current(session)? != next {
+            bail!(
+                "CRL number in opaque object {} was changed by another signer while being \
+                 incremented to {}",
+                self.object_id,
+                next
+            );
+        }
+
+        Ok(next)
+    }
+}
+
+/// The status of a certificate reported in an OCSP response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CertificateStatus {
+    Good,
+    Revoked {
+        revocation_time: SystemTime,
+        reason: Option<RevocationReason>,
+    },
+    /// The responder doesn't know about the certificate, e.g. because the CA didn't issue it.
+    Unknown,
+}
+
+impl CertificateStatus {
+    fn to_der(&self) -> Result<Vec<u8>, Error> {
+        Ok(match *self {
+            CertificateStatus::Good => asn1::implicit(0, &[]),
+            CertificateStatus::Revoked {
+                revocation_time,
+                reason,
+            } => {
+                let revocation_time = x509::generalized_time(revocation_time)?;
+                match reason {
+                    Some(reason) => asn1::explicit(
+                        1,
+                        &[
+                            &revocation_time[..],
+                            &asn1::explicit(0, &asn1::enumerated(reason.code())),
+                        ].concat(),
+                    ),
+                    None => asn1::explicit(1, &revocation_time),
+                }
+            }
+            CertificateStatus::Unknown => asn1::implicit(2, &[]),
+        })
+    }
+}
+
+impl CertificateAuthority {
+    /// Sign a version 2 CRL listing `revoked`, valid from now until `validity` has passed, with
+    /// the next number from `crl_number`.
+    ///
+    /// The CRL number is incremented before signing, so a failed signature skips a number but
+    /// never reuses one.
+    pub fn sign_crl(
+        &self,
+        crl_number: &OpaqueCrlNumber,
+        revoked: &[RevokedCertificate],
+        validity: Duration,
+    ) -> Result<Vec<u8>, Error> {
+        let number = crl_number.next(self.session())?;
+
+        let tbs = tbs_cert_list(
+            self.scheme(),
+            self.subject_der(),
+            self.key_identifier(),
+            number,
+            revoked,
+            validity,
+        )?;
+
+        self.sign(&tbs)
+    }
+
+    /// Sign a CRL as with `sign_crl`, returning it as a PEM `X509 CRL` block.
+    pub fn sign_crl_pem(
+        &self,
+        crl_number: &OpaqueCrlNumber,
+        revoked: &[RevokedCertificate],
+        validity: Duration,
+    ) -> Result<String, Error> {
+        Ok(pem_encode(
+            "X509 CRL",
+            &self.sign_crl(crl_number, revoked, validity)?,
+        ))
+    }
+
+    /// Sign an OCSP response reporting `status` for the certificate with serial number
+    /// `serial_number` issued by this CA, valid from now until `validity` has passed.
+    ///
+    /// `nonce` is the nonce from the request's nonce extension, if it had one, to be echoed back.
+    /// The result is a DER `OCSPResponse` with a successful status, wrapping the signed
+    /// `BasicOCSPResponse`, ready to be served as `application/ocsp-response`.
+    pub fn sign_ocsp_response(
+        &self,
+        serial_number: &[u8],
+        status: &CertificateStatus,
+        validity: Duration,
+        nonce: Option<&[u8]>,
+    ) -> Result<Vec<u8>, Error> {
+        let response_data = ocsp_response_data(
+            self.subject_der(),
+            self.key_hash(),
+            serial_number,
+            status,
+            validity,
+            nonce,
+        )?;
+        let basic_response = self.sign(&response_data)?;
+
+        Ok(asn1::sequence(&[
+            // successful
+            &asn1::enumerated(0),
+            &asn1::explicit(
+                0,
+                &asn1::sequence(&[
+                    &asn1::oid(asn1::OID_OCSP_BASIC),
+                    &asn1::octet_string(&basic_response),
+                ]),
+            ),
+        ]))
+    }
+}
+
+/// Encode a version 2 `TBSCertList` (RFC 5280), valid from now.
+pub(crate) fn tbs_cert_list(
+    scheme: SignatureScheme,
+    issuer: &[u8],
+    issuer_key_identifier: &[u8],
+    crl_number: u64,
+    revoked: &[RevokedCertificate],
+    validity: Duration,
+) -> Result<Vec<u8>, Error> {
+    let now = SystemTime::now();
+
+    let extensions = asn1::sequence(&[
+        &x509::authority_key_identifier(issuer_key_identifier),
+        &x509::extension(
+            asn1::OID_CRL_NUMBER,
+            false,
+            &asn1::unsigned_integer(&crl_number.to_be_bytes()),
+        ),
+    ]);
+
+    let mut tbs = vec![
+        asn1::unsigned_integer(&[1]),
+        x509::algorithm_identifier(scheme),
+        issuer.to_vec(),
+        x509::time(now)?,
+        x509::time(now + validity)?,
+    ];
+    // An empty list of revoked certificates must be left out rather than encoded.
+    if !revoked.is_empty() {
+        let entries = revoked
+            .iter()
+            .map(RevokedCertificate::to_der)
+            .collect::<Result<Vec<_>, _>>()?;
+        let entries: Vec<&[u8]> = entries.iter().map(|e| &e[..]).collect();
+        tbs.push(asn1::sequence(&entries));
+    }
+    tbs.push(asn1::explicit(0, &extensions));
+
+    let tbs: Vec<&[u8]> = tbs.iter().map(|e| &e[..]).collect();
+    Ok(asn1::sequence(&tbs))
+}
+
+/// Encode the `ResponseData` of a `BasicOCSPResponse` (RFC 6960) with a single response, produced
+/// now by the issuer itself.
+pub(crate) fn ocsp_response_data(
+    issuer: &[u8],
+    issuer_key_hash: &[u8],
+    serial_number: &[u8],
+    status: &CertificateStatus,
+    validity: Duration,
+    nonce: Option<&[u8]>,
+) -> Result<Vec<u8>, Error> {
+    let now = SystemTime::now();
+
+    // Identify the certificate by SHA-1 hashes of the issuer name and key, which is what clients
+    // send in requests.
+    let cert_id = asn1::sequence(&[
+        &asn1::sequence(&[&asn1::oid(asn1::OID_SHA1), &asn1::null()]),
+        &asn1::octet_string(&Sha1::digest(issuer)),
+        &asn1::octet_string(issuer_key_hash),
+        &asn1::unsigned_integer(serial_number),
+    ]);
+    let single_response = asn1::sequence(&[
+        &cert_id,
+        &status.to_der()?,
+        &x509::generalized_time(now)?,
+        &asn1::explicit(0, &x509::generalized_time(now + validity)?),
+    ]);
+
+    let mut response_data = vec![
+        // The responder is identified by key hash.
+        asn1::explicit(2, &asn1::octet_string(issuer_key_hash)),
+        x509::generalized_time(now)?,
+        asn1::sequence(&[&single_response]),
+    ];
+    if let Some(nonce) = nonce {
+        let nonce = x509::extension(asn1::OID_OCSP_NONCE, false, &asn1::octet_string(nonce));
+        response_data.push(asn1::explicit(1, &asn1::sequence(&[&nonce])));
+    }
+
+    let response_data: Vec<&[u8]> = response_data.iter().map(|e| &e[..]).collect();
+    Ok(asn1::sequence(&response_data))
+}
@@ -0,0 +1,368 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Implementations of the RustCrypto `signature` traits for keys on the device, so they can be
+//! used by any library built on those traits.
+//!
+//! Each signer is bound to a `Session` and the ID of an asymmetric key, and caches the key's
+//! public half so `Keypair::verifying_key` doesn't need to talk to the device.
+
+use ecdsa::EcdsaSignature;
+use session::Session;
+use types::*;
+
+use ed25519_dalek;
+use failure::Error;
+use k256;
+use p256;
+use p384;
+use rsa;
+use sha1::Sha1;
+use sha2::digest::const_oid::AssociatedOid;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use signature::hazmat::PrehashSigner;
+use signature::{self, Keypair, Signer};
+
+use std::convert::TryFrom;
+use std::marker::PhantomData;
+
+fn signature_error(e: Error) -> signature::Error {
+    signature::Error::from_source(e.compat())
+}
+
+macro_rules! ecdsa_signer {
+    ($name:ident, $curve:ident, $algorithm:expr, $scheme:expr, $verifying_key:ident, $doc:expr) => {
+        #[doc = $doc]
+        #[derive(Clone, Debug)]
+        pub struct $name {
+            session: Session,
+            key_id: u16,
+            verifying_key: $curve::ecdsa::VerifyingKey,
+        }
+
+        impl $name {
+            /// Create a signer for the asymmetric key `key_id`, checking that it is on the
+            /// expected curve.
+            pub fn new(session: &Session, key_id: u16) -> Result<$name, Error> {
+                Ok($name {
+                    session: session.clone(),
+                    key_id,
+                    verifying_key: session.get_pubkey(key_id)?.$verifying_key()?,
+                })
+            }
+
+            /// The ID of the key on the device.
+            pub fn key_id(&self) -> u16 {
+                self.key_id
+            }
+        }
+
+        impl Signer<$curve::ecdsa::Signature> for $name {
+            fn try_sign(&self, msg: &[u8]) -> Result<$curve::ecdsa::Signature, signature::Error> {
+                let der = self.session
+                    .sign_message_with_key_algorithm(self.key_id, $algorithm, $scheme, msg)
+                    .map_err(signature_error)?;
+                let signature = EcdsaSignature::from_der($algorithm, der).map_err(signature_error)?;
+
+                $curve::ecdsa::Signature::from_slice(&signature.to_bytes())
+            }
+        }
+
+        impl PrehashSigner<$curve::ecdsa::Signature> for $name {
+            fn sign_prehash(
+                &self,
+                prehash: &[u8],
+            ) -> Result<$curve::ecdsa::Signature, signature::Error> {
+                let der = self.session
+                    .sign_ecdsa(self.key_id, prehash)
+                    .map_err(signature_error)?;
+                let signature = EcdsaSignature::from_der($algorithm, der).map_err(signature_error)?;
+
+                $curve::ecdsa::Signature::from_slice(&signature.to_bytes())
+            }
+        }
+
+        impl Keypair for $name {
+            type VerifyingKey = $curve::ecdsa::VerifyingKey;
+
+            fn verifying_key(&self) -> $curve::ecdsa::VerifyingKey {
+                self.verifying_key
+            }
+        }
+    };
+}
+
+ecdsa_signer!(
+    P256Signer,
+    p256,
+    Algorithm::EcP256,
+    SignatureScheme::EcdsaSha256,
+    p256_verifying_key,
+    "An ECDSA/SHA-256 signer for a P-256 key on the device."
+);
+
+ecdsa_signer!(
+    P384Signer,
+    p384,
+    Algorithm::EcP384,
+    SignatureScheme::EcdsaSha384,
+    p384_verifying_key,
+    "An ECDSA/SHA-384 signer for a P-384 key on the device."
+);
+
+ecdsa_signer!(
+    K256Signer,
+    k256,
+    Algorithm::EcK256,
+    SignatureScheme::EcdsaSha256,
+    k256_verifying_key,
+    "An ECDSA/SHA-256 signer for a secp256k1 key on the device. Signatures are normalized to \
+     low-S form."
+);
+
+/// An Ed25519 signer for an Ed25519 key on the device.
+#[derive(Clone, Debug)]
+pub struct Ed25519Signer {
+    session: Session,
+    key_id: u16,
+    verifying_key: ed25519_dalek::VerifyingKey,
+}
+
+impl Ed25519Signer {
+    /// Create a signer for the asymmetric key `key_id`, checking that it is an Ed25519 key.
+    pub fn new(session: &Session, key_id: u16) -> Result<Ed25519Signer, Error> {
+        Ok(Ed25519Signer {
+            session: session.clone(),
+            key_id,
+            verifying_key: session.get_pubkey(key_id)?.ed25519_verifying_key()?,
+        })
+    }
+
+    /// The ID of the key on the device.
+    pub fn key_id(&self) -> u16 {
+        self.key_id
+    }
+}
+
+impl Signer<ed25519_dalek::Signature> for Ed25519Signer {
+    fn try_sign(&self, msg: &[u8]) -> Result<ed25519_dalek::Signature, signature::Error> {
+        let signature = self.session
+            .sign_eddsa(self.key_id, msg)
+            .map_err(signature_error)?;
+
+        ed25519_dalek::Signature::from_slice(&signature)
+    }
+}
+
+impl Keypair for Ed25519Signer {
+    type VerifyingKey = ed25519_dalek::VerifyingKey;
+
+    fn verifying_key(&self) -> ed25519_dalek::VerifyingKey {
+        self.verifying_key
+    }
+}
+
+/// A digest which can be used with the device's RSA signature algorithms: one of `sha1::Sha1`,
+/// `sha2::Sha256`, `sha2::Sha384` or `sha2::Sha512`.
+pub trait RsaDigest: Digest + AssociatedOid {
+    /// The RSASSA-PKCS1-v1_5 scheme using this digest.
+    fn pkcs1v15_scheme() -> SignatureScheme;
+
+    /// The RSASSA-PSS scheme using this digest.
+    fn pss_scheme() -> SignatureScheme;
+}
+
+macro_rules! rsa_digest {
+    ($digest:ty, $pkcs1v15:expr, $pss:expr) => {
+        impl RsaDigest for $digest {
+            fn pkcs1v15_scheme() -> SignatureScheme {
+                $pkcs1v15
+            }
+
+            fn pss_scheme() -> SignatureScheme {
+                $pss
+            }
+        }
+    };
+}
+
+rsa_digest!(
+    Sha1,
+    SignatureScheme::RsaPkcs1v15Sha1,
+    SignatureScheme::RsaPssSha1
+);
+rsa_digest!(
+    Sha256,
+    SignatureScheme::RsaPkcs1v15Sha256,
+    SignatureScheme::RsaPssSha256
+);
+rsa_digest!(
+    Sha384,
+    SignatureScheme::RsaPkcs1v15Sha384,
+    SignatureScheme::RsaPssSha384
+);
+rsa_digest!(
+    Sha512,
+    SignatureScheme::RsaPkcs1v15Sha512,
+    SignatureScheme::RsaPssSha512
+);
+
+/// An RSASSA-PKCS1-v1_5 signer using digest `D`, for an RSA key on the device.
+#[derive(Clone, Debug)]
+pub struct RsaPkcs1v15Signer<D: RsaDigest> {
+    session: Session,
+    key_id: u16,
+    key_algorithm: Algorithm,
+    public_key: rsa::RsaPublicKey,
+    _digest: PhantomData<D>,
+}
+
+impl<D: RsaDigest> RsaPkcs1v15Signer<D> {
+    /// Create a signer for the asymmetric key `key_id`, checking that it is an RSA key.
+    pub fn new(session: &Session, key_id: u16) -> Result<RsaPkcs1v15Signer<D>, Error> {
+        let public_key = session.get_pubkey(key_id)?;
+        Ok(RsaPkcs1v15Signer {
+            session: session.clone(),
+            key_id,
+            key_algorithm: public_key.algorithm(),
+            public_key: public_key.rsa_public_key()?,
+            _digest: PhantomData,
+        })
+    }
+
+    /// The ID of the key on the device.
+    pub fn key_id(&self) -> u16 {
+        self.key_id
+    }
+}
+
+impl<D: RsaDigest> Signer<rsa::pkcs1v15::Signature> for RsaPkcs1v15Signer<D> {
+    fn try_sign(&self, msg: &[u8]) -> Result<rsa::pkcs1v15::Signature, signature::Error> {
+        let signature = self.session
+            .sign_message_with_key_algorithm(self.key_id, self.key_algorithm, D::pkcs1v15_scheme(), msg)
+            .map_err(signature_error)?;
+
+        rsa::pkcs1v15::Signature::try_from(&signature[..])
+    }
+}
+
+impl<D: RsaDigest> Keypair for RsaPkcs1v15Signer<D> {
+    type VerifyingKey = rsa::pkcs1v15::VerifyingKey<D>;
+
+    fn verifying_key(&self) -> rsa::pkcs1v15::VerifyingKey<D> {
+        rsa::pkcs1v15::VerifyingKey::new(self.public_key.clone())
+    }
+}
+
+/// An RSASSA-PSS signer using digest `D` for both the message and MGF1, with a salt as long as the
+/// digest output, for an RSA key on the device.
+#[derive(Clone, Debug)]
+pub struct RsaPssSigner<D: RsaDigest> {
+    session: Session,
+    key_id: u16,
+    key_algorithm: Algorithm,
+    public_key: rsa::RsaPublicKey,
+    _digest: PhantomData<D>,
+}
+
+impl<D: RsaDigest> RsaPssSigner<D> {
+    /// Create a signer for the asymmetric key `key_id`, checking that it is an RSA key.
+    pub fn new(session: &Session, key_id: u16) -> Result<RsaPssSigner<D>, Error> {
+        let public_key = session.get_pubkey(key_id)?;
+        Ok(RsaPssSigner {
+            session: session.clone(),
+            key_id,
+            key_algorithm: public_key.algorithm(),
+            public_key: public_key.rsa_public_key()?,
+            _digest: PhantomData,
+        })
+    }
+
+    /// The ID of the key on the device.
+    pub fn key_id(&self) -> u16 {
+        self.key_id
+    }
+}
+
+impl<D: RsaDigest> Signer<rsa::pss::Signature> for RsaPssSigner<D> {
+    fn try_sign(&self, msg: &[u8]) -> Result<rsa::pss::Signature, signature::Error> {
+        let signature = self.session
+            .sign_message_with_key_algorithm(self.key_id, self.key_algorithm, D::pss_scheme(), msg)
+            .map_err(signature_error)?;
+
+        rsa::pss::Signature::try_from(&signature[..])
+    }
+}
+
+impl<D: RsaDigest> Keypair for RsaPssSigner<D> {
+    type VerifyingKey = rsa::pss::VerifyingKey<D>;
+
+    fn verifying_key(&self) -> rsa::pss::VerifyingKey<D> {
+        rsa::pss::VerifyingKey::new(self.public_key.clone())
+    }
+}
+
+macro_rules! ecdsa_verifying_key {
+    ($name:ident, $curve:ident, $algorithm:expr, $doc:expr) => {
+        #[doc = $doc]
+        pub fn $name(&self) -> Result<$curve::ecdsa::VerifyingKey, Error> {
+            if self.algorithm() != $algorithm {
+                bail!("expected a {} key, got {}", $algorithm, self.algorithm());
+            }
+
+            $curve::ecdsa::VerifyingKey::from_sec1_bytes(&self.ec_point()?)
+                .map_err(|e| format_err!("invalid public key: {}", e))
+        }
+    };
+}
+
+impl PublicKey {
+    ecdsa_verifying_key!(
+        p256_verifying_key,
+        p256,
+        Algorithm::EcP256,
+        "Convert a P-256 public key into a RustCrypto `p256::ecdsa::VerifyingKey`."
+    );
+
+    ecdsa_verifying_key!(
+        p384_verifying_key,
+        p384,
+        Algorithm::EcP384,
+        "Convert a P-384 public key into a RustCrypto `p384::ecdsa::VerifyingKey`."
+    );
+
+    ecdsa_verifying_key!(
+        k256_verifying_key,
+        k256,
+        Algorithm::EcK256,
+        "Convert a secp256k1 public key into a RustCrypto `k256::ecdsa::VerifyingKey`."
+    );
+
+    /// Convert an Ed25519 public key into an `ed25519_dalek::VerifyingKey`.
+    pub fn ed25519_verifying_key(&self) -> Result<ed25519_dalek::VerifyingKey, Error> {
+        let a = match *self {
+            PublicKey::Edc(Algorithm::EcEd25519, ref a) => a,
+            ref key => bail!("expected an ed25519 key, got {}", key.algorithm()),
+        };
+
+        let mut key_bytes = [0; ed25519_dalek::PUBLIC_KEY_LENGTH];
+        if a.len() != key_bytes.len() {
+            bail!("invalid ed25519 public key length {}", a.len());
+        }
+        key_bytes.copy_from_slice(a);
+
+        ed25519_dalek::VerifyingKey::from_bytes(&key_bytes)
+            .map_err(|e| format_err!("invalid public key: {}", e))
+    }
+}
@@ -0,0 +1,201 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Signing Bitcoin and Ethereum transactions with `Algorithm::EcK256` keys on the device.
+//!
+//! The caller computes the transaction's 32-byte sighash (e.g. a BIP-143 digest, or the
+//! Keccak-256 hash of an RLP-encoded Ethereum transaction) and `Secp256k1Signer` signs it,
+//! returning a low-S signature with the recovery id that blockchains need to recover the signer.
+
+use ecdsa::EcdsaSignature;
+use session::Session;
+use types::*;
+
+use failure::Error;
+use sha3::{Digest, Keccak256};
+
+/// Signs 32-byte sighashes with a secp256k1 key on the device.
+#[derive(Clone, Debug)]
+pub struct Secp256k1Signer {
+    session: Session,
+    key_id: u16,
+    public_key: PublicKey,
+}
+
+impl Secp256k1Signer {
+    /// Create a signer for `key_id`, which must be an `Algorithm::EcK256` key.
+    pub fn new(session: &Session, key_id: u16) -> Result<Secp256k1Signer, Error> {
+        let public_key = session.get_pubkey(key_id)?;
+        if public_key.algorithm() != Algorithm::EcK256 {
+            bail!(
+                "secp256k1 signing needs a {} key, not {}",
+                Algorithm::EcK256,
+                public_key.algorithm()
+            );
+        }
+
+        Ok(Secp256k1Signer {
+            session: session.clone(),
+            key_id,
+            public_key,
+        })
+    }
+
+    /// The ID of the key on the device.
+    pub fn key_id(&self) -> u16 {
+        self.key_id
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    /// Sign a 32-byte sighash.
+    pub fn sign_prehash(&self, sighash: &[u8]) -> Result<RecoverableSignature, Error> {
+        if sighash.len() != 32 {
+            bail!("sighash must be 32 bytes, not {}", sighash.len());
+        }
+
+        let der = self.session.sign_ecdsa(self.key_id, sighash)?;
+        let signature = EcdsaSignature::from_der(Algorithm::EcK256, der)?;
+        RecoverableSignature::new(signature, &self.public_key, sighash)
+    }
+}
+
+/// A low-S secp256k1 signature with its recovery id.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecoverableSignature {
+    signature: EcdsaSignature,
+    recovery_id: u8,
+}
+
+/// The `v`, `r` and `s` fields of a signed Ethereum transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EthereumSignature {
+    pub v: u64,
+    pub r: Vec<u8>,
+    pub s: Vec<u8>,
+}
+
+impl RecoverableSignature {
+    /// Pair `signature` (of `sighash`, with the key `public_key`) with its recovery id.
+    pub fn new(
+        signature: EcdsaSignature,
+        public_key: &PublicKey,
+        sighash: &[u8],
+    ) -> Result<RecoverableSignature, Error> {
+        let recovery_id = signature.recovery_id(public_key, sighash)?;
+        Ok(RecoverableSignature {
+            signature,
+            recovery_id,
+        })
+    }
+
+    pub fn signature(&self) -> &EcdsaSignature {
+        &self.signature
+    }
+
+    /// The recovery id, from 0 to 3. Bit 0 is the parity of the ephemeral point's `y`, and bit 1
+    /// is set in the (astronomically unlikely) case that its `x` overflowed the curve order.
+    pub fn recovery_id(&self) -> u8 {
+        self.recovery_id
+    }
+
+    /// Encode the signature as `r || s || recovery_id` (65 bytes).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.signature.to_bytes();
+        bytes.push(self.recovery_id);
+        bytes
+    }
+
+    /// Encode the signature in Bitcoin's compact form (`header || r || s`), as used by
+    /// `signmessage`. `compressed` records whether the signer's address uses the compressed
+    /// public key.
+    pub fn to_bitcoin_compact(&self, compressed: bool) -> Vec<u8> {
+        let header = 27 + self.recovery_id + if compressed { 4 } else { 0 };
+
+        let mut bytes = vec![header];
+        bytes.extend_from_slice(&self.signature.to_bytes());
+        bytes
+    }
+
+    /// Ethereum can only encode the parity of the recovery id.
+    fn y_parity(&self) -> Result<u64, Error> {
+        if self.recovery_id > 1 {
+            bail!("recovery id {} can't be used on Ethereum", self.recovery_id);
+        }
+        Ok(u64::from(self.recovery_id))
+    }
+
+    /// The signature fields of a legacy transaction with EIP-155 replay protection, for which
+    /// `v` is `chain_id * 2 + 35 + y_parity`.
+    pub fn eip155(&self, chain_id: u64) -> Result<EthereumSignature, Error> {
+        let y_parity = self.y_parity()?;
+        let v = chain_id
+            .checked_mul(2)
+            .and_then(|v| v.checked_add(35 + y_parity))
+            .ok_or_else(|| format_err!("chain ID {} is too large", chain_id))?;
+        Ok(self.ethereum_signature(v))
+    }
+
+    /// The signature fields of an EIP-1559 (or EIP-2930) typed transaction, for which `v` is the
+    /// `y_parity` field.
+    pub fn eip1559(&self) -> Result<EthereumSignature, Error> {
+        let v = self.y_parity()?;
+        Ok(self.ethereum_signature(v))
+    }
+
+    fn ethereum_signature(&self, v: u64) -> EthereumSignature {
+        EthereumSignature {
+            v,
+            r: self.signature.r().to_vec(),
+            s: self.signature.s().to_vec(),
+        }
+    }
+}
+
+impl PublicKey {
+    /// The Ethereum address of a secp256k1 key: the last 20 bytes of the Keccak-256 hash of its
+    /// uncompressed point without the `0x04` prefix.
+    pub fn ethereum_address(&self) -> Result<[u8; 20], Error> {
+        if self.algorithm() != Algorithm::EcK256 {
+            bail!("Ethereum addresses need a {} key", Algorithm::EcK256);
+        }
+
+        let hash = Keccak256::digest(&self.ec_point()?[1..]);
+        let mut address = [0; 20];
+        address.copy_from_slice(&hash[12..]);
+        Ok(address)
+    }
+}
+
+/// Format an Ethereum address as `0x`-prefixed hex with the EIP-55 mixed-case checksum.
+pub fn eip55_address(address: &[u8; 20]) -> String {
+    let hex: String = address.iter().map(|b| format!("{:02x}", b)).collect();
+    let hash = Keccak256::digest(hex.as_bytes());
+
+    let checksummed: String = hex
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = (hash[i / 2] >> (4 * (1 - i % 2))) & 0xf;
+            if nibble >= 8 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect();
+    format!("0x{}", checksummed)
+}
@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use connector::Connector;
+use hash::HashAlgorithm;
 use types::*;
 
 use failure::Error;
@@ -93,23 +95,83 @@ impl Deref for SessionPtr {
 #[derive(Clone, Debug)]
 pub struct Session {
     this: Arc<SessionPtr>,
+    auth_key_id: u16,
+    // Declared after `this` so the connector outlives the session it carries.
+    connector: Connector,
     _unsync_marker: Cell<()>,
 }
 
 impl Session {
-    pub(crate) fn new(this: *mut yh_session) -> Session {
+    pub(crate) fn new(this: *mut yh_session, auth_key_id: u16, connector: Connector) -> Session {
         Session {
             this: Arc::new(SessionPtr(AtomicPtr::new(this))),
+            auth_key_id,
+            connector,
             _unsync_marker: Cell::new(()),
         }
     }
 
+    /// The connector this session was created through.
+    pub(crate) fn connector(&self) -> &Connector {
+        &self.connector
+    }
+
+    /// The ID of the AuthKey this session was authenticated with.
+    pub fn auth_key_id(&self) -> u16 {
+        self.auth_key_id
+    }
+
+    /// Check whether this session's AuthKey may create an object with the given domains,
+    /// capabilities and delegated capabilities, using a command that requires the `required`
+    /// capability.
+    ///
+    /// The device only reports `ReturnCode::DeviceInvPermission` when an object creation exceeds
+    /// what the AuthKey may delegate. This fetches the AuthKey's `ObjectInfo` and reports exactly
+    /// which of the requested capabilities or domains are not permitted.
+    pub fn explain_permission(
+        &self,
+        required: Capability,
+        domains: &[Domain],
+        capabilities: &[Capability],
+        delegated_capabilities: &[Capability],
+    ) -> Result<PermissionReport, Error> {
+        let auth_key = self.get_object_info(self.auth_key_id, ObjectType::AuthKey)?;
+
+        Ok(PermissionReport::new(
+            &auth_key,
+            required,
+            domains,
+            capabilities,
+            delegated_capabilities,
+        ))
+    }
+
+    fn check_permission(
+        &self,
+        required: Capability,
+        domains: &[Domain],
+        capabilities: &[Capability],
+        delegated_capabilities: &[Capability],
+    ) -> Result<(), Error> {
+        let report =
+            self.explain_permission(required, domains, capabilities, delegated_capabilities)?;
+
+        if !report.is_permitted() {
+            return Err(report.into());
+        }
+
+        Ok(())
+    }
+
     /// Reset the device to factory settings and reboot.
     ///
     /// Note that since the device reboots when this function is called, this function is far more
     /// likely to return a `ReturnCode::NetError` upon success, since it will vanish out from
     /// underneath the connector. However, it is left to library consumers to decide whether or not
     /// this is an acceptable result.
+    ///
+    /// The device comes back with only the default AuthKey and password; `Connector::bootstrap`
+    /// replaces them with an admin AuthKey.
     pub fn reset(self) -> Result<(), Error> {
         match ReturnCode::from(unsafe {
             yubihsm_sys::yh_util_reset(self.this.load(Ordering::Relaxed))
@@ -366,6 +428,147 @@ impl Session {
         Ok(out)
     }
 
+    /// Decrypt RSA PKCS#1 v1.5 encrypted `data` with the asymmetric key `key_id`.
+    pub fn decrypt_pkcs1v1_5<T: AsRef<[u8]>>(&self, key_id: u16, data: T) -> Result<Vec<u8>, Error> {
+        // The plaintext is always shorter than the modulus, and the largest keys are 4096 bits.
+        let mut out_size: usize = 512;
+        let mut out: Vec<u8> = Vec::with_capacity(out_size);
+
+        let data_slice = data.as_ref();
+
+        unsafe {
+            let ret = ReturnCode::from(yubihsm_sys::yh_util_decrypt_pkcs1v1_5(
+                self.this.load(Ordering::Relaxed),
+                key_id,
+                data_slice.as_ptr(),
+                data_slice.len(),
+                out.as_mut_ptr(),
+                &mut out_size,
+            ));
+
+            if ret != ReturnCode::Success {
+                bail!("couldn't decrypt_pkcs1v1_5: {}", ret);
+            }
+
+            out.set_len(out_size);
+        }
+
+        Ok(out)
+    }
+
+    /// Decrypt RSA-OAEP encrypted `data` with the asymmetric key `key_id`.
+    ///
+    /// Note that the device takes the digest of the OAEP label rather than the label itself, so
+    /// `label_hash` must be the label hashed with the OAEP hash function (the hash of the empty
+    /// string, for the usual empty label). The OAEP hash is implied by its length.
+    pub fn decrypt_oaep<T: AsRef<[u8]>>(
+        &self,
+        key_id: u16,
+        label_hash: &[u8],
+        mgf1_algorithm: Algorithm,
+        data: T,
+    ) -> Result<Vec<u8>, Error> {
+        let mut out_size: usize = 512;
+        let mut out: Vec<u8> = Vec::with_capacity(out_size);
+
+        let data_slice = data.as_ref();
+
+        unsafe {
+            let ret = ReturnCode::from(yubihsm_sys::yh_util_decrypt_oaep(
+                self.this.load(Ordering::Relaxed),
+                key_id,
+                data_slice.as_ptr(),
+                data_slice.len(),
+                out.as_mut_ptr(),
+                &mut out_size,
+                label_hash.as_ptr(),
+                label_hash.len(),
+                mgf1_algorithm.into(),
+            ));
+
+            if ret != ReturnCode::Success {
+                bail!("couldn't decrypt_oaep: {}", ret);
+            }
+
+            out.set_len(out_size);
+        }
+
+        Ok(out)
+    }
+
+    /// Sign `message` with the asymmetric key `key_id` using `scheme`.
+    ///
+    /// Unlike the lower-level `sign_*` methods, which expect the caller to hash and frame the
+    /// input, this computes the digest for the scheme, wraps it in a DigestInfo for RSA PKCS#1
+    /// v1.5, and passes the matching MGF1 algorithm and salt length for RSA-PSS. ECDSA digests
+    /// longer than the curve order are truncated, as specified by FIPS 186-4. The key's algorithm
+    /// is looked up first, and schemes which don't apply to it are rejected.
+    pub fn sign_message<T: AsRef<[u8]>>(
+        &self,
+        key_id: u16,
+        scheme: SignatureScheme,
+        message: T,
+    ) -> Result<Vec<u8>, Error> {
+        let message = message.as_ref();
+
+        let key_algorithm = match self.get_object_info(key_id, ObjectType::Asymmetric)?
+            .algorithm
+        {
+            Some(algorithm) => algorithm,
+            None => bail!("sign_message: key {} has no algorithm", key_id),
+        };
+
+        self.sign_message_with_key_algorithm(key_id, key_algorithm, scheme, message)
+    }
+
+    /// `sign_message` for callers which already know the key's algorithm, saving a round trip to
+    /// the device.
+    pub(crate) fn sign_message_with_key_algorithm(
+        &self,
+        key_id: u16,
+        key_algorithm: Algorithm,
+        scheme: SignatureScheme,
+        message: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        if !scheme.supports_key(key_algorithm) {
+            bail!(
+                "sign_message: {:?} can't be used with key {} ({})",
+                scheme,
+                key_id,
+                key_algorithm
+            );
+        }
+
+        if scheme == SignatureScheme::Ed25519 {
+            return self.sign_eddsa(key_id, message);
+        }
+
+        // Every scheme other than Ed25519 names a digest.
+        let hash = HashAlgorithm::from_signature_algorithm(scheme.algorithm()).unwrap();
+        let mut digest = hash.digest(message);
+
+        match scheme {
+            SignatureScheme::RsaPssSha1
+            | SignatureScheme::RsaPssSha256
+            | SignatureScheme::RsaPssSha384
+            | SignatureScheme::RsaPssSha512 => {
+                self.sign_pss(key_id, hash.output_len(), hash.mgf1_algorithm(), digest)
+            }
+            SignatureScheme::EcdsaSha1
+            | SignatureScheme::EcdsaSha256
+            | SignatureScheme::EcdsaSha384
+            | SignatureScheme::EcdsaSha512 => {
+                // All the supported curves have byte-aligned orders, except P-521 whose field is
+                // wider than any digest.
+                if let Some(field_len) = key_algorithm.ec_field_len() {
+                    digest.truncate(field_len);
+                }
+                self.sign_ecdsa(key_id, digest)
+            }
+            _ => self.sign_pkcs1v1_5(key_id, false, hash.digest_info(&digest)),
+        }
+    }
+
     generate_key!(generate_key_ec, yh_util_generate_key_ec);
 
     generate_key!(generate_key_ed, yh_util_generate_key_ed);
@@ -383,6 +586,13 @@ impl Session {
         delegated_capabilities: &[Capability],
         algorithm: Algorithm,
     ) -> Result<(), Error> {
+        self.check_permission(
+            Capability::GenerateWrapkey,
+            domains,
+            capabilities,
+            delegated_capabilities,
+        )?;
+
         let mut key_id_ptr = key_id;
         let c_label = CString::new(label)?;
         let lib_domains = DomainParam::from(domains);
@@ -575,6 +785,13 @@ impl Session {
         delegated_capabilities: &[Capability],
         password: &str,
     ) -> Result<(), Error> {
+        self.check_permission(
+            Capability::PutAuthKey,
+            domains,
+            capabilities,
+            delegated_capabilities,
+        )?;
+
         let mut key_id_ptr = key_id;
         let c_label = CString::new(label)?;
         let c_pass = CString::new(password)?;
@@ -631,6 +848,57 @@ impl Session {
         }
     }
 
+    /// Import an object exported with Export Wrapped under the wrap key `wrapkey_id`, returning the
+    /// type and ID of the imported object.
+    pub fn import_wrapped(
+        &self,
+        wrapkey_id: u16,
+        wrapped: &[u8],
+    ) -> Result<(ObjectType, u16), Error> {
+        let mut object_type: yubihsm_sys::yh_object_type = 0;
+        let mut object_id: u16 = 0;
+
+        unsafe {
+            match ReturnCode::from(yubihsm_sys::yh_util_import_wrapped(
+                self.this.load(Ordering::Relaxed),
+                wrapkey_id,
+                wrapped.as_ptr(),
+                wrapped.len(),
+                &mut object_type,
+                &mut object_id,
+            )) {
+                ReturnCode::Success => Ok((ObjectType::from(object_type), object_id)),
+                e => Err(format_err!("util_import_wrapped failed: {}", e)),
+            }
+        }
+    }
+
+    /// Read the contents of the opaque object `object_id`, e.g. an X.509 certificate stored with
+    /// `Algorithm::OpaqueX509Cert`.
+    pub fn get_opaque_object(&self, object_id: u16) -> Result<Vec<u8>, Error> {
+        // No object can be larger than a single message.
+        let mut data: Vec<u8> = Vec::with_capacity(yubihsm_sys::YH_MSG_BUF_SIZE as usize);
+        let mut data_length = data.capacity();
+
+        let rc = unsafe {
+            ReturnCode::from(yubihsm_sys::yh_util_get_opaque(
+                self.this.load(Ordering::Relaxed),
+                object_id,
+                data.as_mut_ptr(),
+                &mut data_length,
+            ))
+        };
+
+        if rc != ReturnCode::Success {
+            bail!("util_get_opaque failed: {}", rc);
+        }
+
+        unsafe { data.set_len(data_length) };
+        data.shrink_to_fit();
+
+        Ok(data)
+    }
+
     pub fn get_pubkey(&self, key_id: u16) -> Result<PublicKey, Error> {
         // Per the Yubico documentation, the largest type of data is a public key for a 4096-bit
         // RSA key, which is 0x400 bytes long.
@@ -658,25 +926,25 @@ impl Session {
         data.shrink_to_fit();
 
         match Algorithm::from(algorithm) {
-            Algorithm::Rsa2048 | Algorithm::Rsa3072 | Algorithm::Rsa4096 => {
-                Ok(PublicKey::Rsa(data))
+            a @ Algorithm::Rsa2048 | a @ Algorithm::Rsa3072 | a @ Algorithm::Rsa4096 => {
+                Ok(PublicKey::Rsa(a, data))
             }
-            Algorithm::EcP224
-            | Algorithm::EcP256
-            | Algorithm::EcP384
-            | Algorithm::EcP521
-            | Algorithm::EcK256
-            | Algorithm::EcBp256
-            | Algorithm::EcBp384
-            | Algorithm::EcBp512 => {
+            a @ Algorithm::EcP224
+            | a @ Algorithm::EcP256
+            | a @ Algorithm::EcP384
+            | a @ Algorithm::EcP521
+            | a @ Algorithm::EcK256
+            | a @ Algorithm::EcBp256
+            | a @ Algorithm::EcBp384
+            | a @ Algorithm::EcBp512 => {
                 // Yubico documentation claims `data` contains points X and Y here, so we'll trust
                 // it and split down the middle.
                 let split_point = data.len() / 2;
                 let point_y = data.split_off(split_point);
 
-                Ok(PublicKey::Ecc(data, point_y))
+                Ok(PublicKey::Ecc(a, data, point_y))
             }
-            Algorithm::EcEd25519 => Ok(PublicKey::Edc(data)),
+            a @ Algorithm::EcEd25519 => Ok(PublicKey::Edc(a, data)),
             a => bail!("get_pubkey: unexpected algorithm type {}", a),
         }
     }
@@ -742,6 +1010,54 @@ impl Session {
 
         Ok(())
     }
+
+    /// Get the device's firmware version, serial number and supported algorithms through the
+    /// session's connector.
+    pub fn get_device_info(&self) -> Result<DeviceInfo, Error> {
+        self.connector().get_device_info()
+    }
+
+    pub fn get_storage_stats(&self) -> Result<StorageStats, Error> {
+        let mut stats = StorageStats::default();
+
+        let rc = unsafe {
+            ReturnCode::from(yubihsm_sys::yh_util_get_storage_stats(
+                self.this.load(Ordering::Relaxed),
+                &mut stats.total_records,
+                &mut stats.free_records,
+                &mut stats.total_pages,
+                &mut stats.free_pages,
+                &mut stats.page_size,
+            ))
+        };
+
+        if rc != ReturnCode::Success {
+            bail!("util_get_storage_stats failed: {}", rc);
+        }
+
+        Ok(stats)
+    }
+
+    pub fn get_option(&self, option_type: DeviceOptionType) -> Result<DeviceOption, Error> {
+        // Command audit is two bytes for each command.
+        let mut out = vec![0; 512];
+        let mut out_len = out.len();
+
+        let rc = unsafe {
+            ReturnCode::from(yubihsm_sys::yh_util_get_option(
+                self.this.load(Ordering::Relaxed),
+                From::<u8>::from(option_type.into()),
+                out.as_mut_ptr(),
+                &mut out_len,
+            ))
+        };
+
+        if rc != ReturnCode::Success {
+            bail!("util_get_option failed: {}", rc);
+        }
+
+        DeviceOption::from_bytes(option_type, &out[..out_len])
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -0,0 +1,121 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Length-prefixed messages over sockets, and the serve loop, shared by the SSH agent and the
+//! validator signer.
+
+use failure::Error;
+
+#[cfg(unix)]
+use std::fs::{self, DirBuilder};
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(unix)]
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+/// Read one message prefixed with its 4-byte big-endian length, or `None` if the client has
+/// disconnected. Messages longer than `max_len` are an error.
+pub(crate) fn read_message<R: Read>(
+    reader: &mut R,
+    max_len: usize,
+) -> Result<Option<Vec<u8>>, Error> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => (),
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_len {
+        bail!("message too long ({} bytes, at most {})", len, max_len);
+    }
+
+    let mut message = vec![0; len];
+    reader.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+/// Write `message` prefixed with its 4-byte big-endian length.
+pub(crate) fn write_message<W: Write>(writer: &mut W, message: &[u8]) -> Result<(), Error> {
+    writer.write_all(&(message.len() as u32).to_be_bytes())?;
+    writer.write_all(message)?;
+    Ok(())
+}
+
+/// Create a Unix socket at `path`, readable and writable only by the current user. Fails if
+/// `path` already exists.
+///
+/// The socket is bound inside a new directory only the current user can enter, and only linked
+/// to `path` once its permissions are set, so no other user can connect in between.
+#[cfg(unix)]
+pub(crate) fn bind_unix<P: AsRef<Path>>(path: P) -> Result<UnixListener, Error> {
+    let path = path.as_ref();
+    let mut dir = path.as_os_str().to_owned();
+    dir.push(format!(".{}.d", process::id()));
+
+    DirBuilder::new().mode(0o700).create(&dir)?;
+    let temp_path = Path::new(&dir).join("socket");
+
+    let result = (|| -> Result<UnixListener, Error> {
+        let listener = UnixListener::bind(&temp_path)?;
+        fs::set_permissions(&temp_path, fs::Permissions::from_mode(0o600))?;
+        // Unlike a rename, a hard link fails if `path` exists.
+        fs::hard_link(&temp_path, path)?;
+        Ok(listener)
+    })();
+
+    let _ = fs::remove_file(&temp_path);
+    fs::remove_dir(&dir)?;
+    result
+}
+
+/// Accept connections forever, handling each on its own thread with a clone of `handler`.
+///
+/// A client going away or sending garbage only ends its own connection, and a failed accept only
+/// loses that connection. Both are reported on stderr.
+pub(crate) fn serve<I, S, F>(incoming: I, handler: F) -> Result<(), Error>
+where
+    I: Iterator<Item = io::Result<S>>,
+    S: Send + 'static,
+    F: Fn(S) -> Result<(), Error> + Clone + Send + 'static,
+{
+    for stream in incoming {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("accepting connection failed: {}", e);
+                // Out of file descriptors (EMFILE), accept fails straight away until a connection
+                // closes, so don't spin.
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+        };
+        let handler = handler.clone();
+        thread::spawn(move || {
+            if let Err(e) = handler(stream) {
+                eprintln!("connection failed: {}", e);
+            }
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,237 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An `ssh-agent` compatible server which offers asymmetric keys on the device as identities, so
+//! that standard OpenSSH clients can authenticate with them through `SSH_AUTH_SOCK`.
+//!
+//! Only listing identities and signing are supported (see draft-miller-ssh-agent); requests to add,
+//! remove or lock keys are refused.
+
+use pool::SessionPool;
+use public_key::{ssh_read_string, ssh_string};
+use socket;
+use sshsig::ssh_signature;
+use types::*;
+
+use failure::Error;
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+
+const SSH_AGENT_RSA_SHA2_256: u32 = 2;
+const SSH_AGENT_RSA_SHA2_512: u32 = 4;
+
+/// The largest message accepted from a client, as in OpenSSH's `ssh-agent`.
+const MAX_MESSAGE_LEN: usize = 256 * 1024;
+
+/// A key offered by the agent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SshIdentity {
+    pub key_id: u16,
+    pub public_key: PublicKey,
+    /// The key's label, which clients show as the key comment.
+    pub comment: String,
+}
+
+/// An SSH agent backed by the device.
+///
+/// The asymmetric keys visible to the pool's AuthKey are offered as identities if their labels
+/// start with the configured prefix (by default, all of them) and OpenSSH supports them: Ed25519,
+/// ECDSA on P-256, P-384 and P-521, and RSA. Keys are listed afresh whenever a client asks for
+/// identities, so keys added to or deleted from the device are picked up without restarting the
+/// agent. Sign requests look the key up in the last list, and only list the keys again if it
+/// isn't there.
+#[derive(Clone, Debug)]
+pub struct SshAgent {
+    pool: Arc<SessionPool>,
+    label_prefix: String,
+    /// The identities last listed, shared by every connection.
+    identities: Arc<Mutex<Vec<SshIdentity>>>,
+}
+
+impl SshAgent {
+    pub fn new(pool: Arc<SessionPool>) -> SshAgent {
+        SshAgent {
+            pool,
+            label_prefix: String::new(),
+            identities: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Only offer keys whose labels start with `prefix`.
+    pub fn label_prefix(self, prefix: &str) -> SshAgent {
+        SshAgent {
+            label_prefix: prefix.to_string(),
+            ..self
+        }
+    }
+
+    /// The keys currently offered by the agent.
+    pub fn identities(&self) -> Result<Vec<SshIdentity>, Error> {
+        let session = self.pool.get()?;
+        let mut identities = Vec::new();
+
+        let objects = session
+            .list_objects()
+            .object_type(ObjectType::Asymmetric)
+            .execute()?;
+        for object in objects {
+            // Listing objects doesn't return their labels.
+            let info = session.get_object_info(object.id, ObjectType::Asymmetric)?;
+            if !info.label.starts_with(&self.label_prefix) {
+                continue;
+            }
+
+            let public_key = session.get_pubkey(object.id)?;
+            if public_key.openssh_key_type().is_err() {
+                continue;
+            }
+
+            identities.push(SshIdentity {
+                key_id: object.id,
+                public_key,
+                comment: info.label,
+            });
+        }
+
+        Ok(identities)
+    }
+
+    /// Create a Unix socket at `path`, readable and writable only by the current user, and serve
+    /// clients on it forever. Point clients at it by setting `SSH_AUTH_SOCK` to `path`.
+    ///
+    /// Fails if `path` already exists.
+    pub fn bind<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        self.serve(socket::bind_unix(path)?)
+    }
+
+    /// Serve clients connecting to `listener` forever, each on its own thread.
+    pub fn serve(&self, listener: UnixListener) -> Result<(), Error> {
+        let agent = self.clone();
+        socket::serve(listener.incoming(), move |stream| {
+            agent.handle_connection(stream)
+        })
+    }
+
+    /// Answer requests from a single client until it disconnects.
+    pub fn handle_connection<S: Read + Write>(&self, mut stream: S) -> Result<(), Error> {
+        while let Some(request) = socket::read_message(&mut stream, MAX_MESSAGE_LEN)? {
+            let response = self.handle_request(&request);
+            socket::write_message(&mut stream, &response)?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_request(&self, request: &[u8]) -> Vec<u8> {
+        let response = match request.split_first() {
+            Some((&SSH_AGENTC_REQUEST_IDENTITIES, _)) => self.identities_answer(),
+            Some((&SSH_AGENTC_SIGN_REQUEST, body)) => self.sign_response(body),
+            _ => Err(format_err!("unsupported SSH agent request")),
+        };
+
+        response.unwrap_or_else(|_| vec![SSH_AGENT_FAILURE])
+    }
+
+    /// List the identities and remember them for sign requests.
+    fn refresh_identities(&self) -> Result<Vec<SshIdentity>, Error> {
+        let identities = self.identities()?;
+        *self.identities.lock().unwrap_or_else(|e| e.into_inner()) = identities.clone();
+        Ok(identities)
+    }
+
+    fn find_identity(&self, public_key: &PublicKey) -> Option<SshIdentity> {
+        self.identities
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .find(|identity| identity.public_key == *public_key)
+            .cloned()
+    }
+
+    fn identities_answer(&self) -> Result<Vec<u8>, Error> {
+        let identities = self.refresh_identities()?;
+
+        let mut response = vec![SSH_AGENT_IDENTITIES_ANSWER];
+        response.extend_from_slice(&(identities.len() as u32).to_be_bytes());
+        for identity in identities {
+            ssh_string(&mut response, &identity.public_key.to_openssh_blob()?);
+            ssh_string(&mut response, identity.comment.as_bytes());
+        }
+
+        Ok(response)
+    }
+
+    fn sign_response(&self, body: &[u8]) -> Result<Vec<u8>, Error> {
+        let (key_blob, data, flags) = parse_sign_request(body)?;
+        let public_key = PublicKey::from_openssh_blob(key_blob)?;
+
+        // Only sign with keys the agent offers.
+        let identity = match self.find_identity(&public_key) {
+            Some(identity) => identity,
+            None => self
+                .refresh_identities()?
+                .into_iter()
+                .find(|identity| identity.public_key == public_key)
+                .ok_or_else(|| format_err!("SSH agent has no such key"))?,
+        };
+
+        let signature = ssh_signature(
+            &*self.pool.get()?,
+            identity.key_id,
+            &identity.public_key,
+            rsa_scheme(flags),
+            data,
+        )?;
+
+        let mut response = vec![SSH_AGENT_SIGN_RESPONSE];
+        ssh_string(&mut response, &signature);
+        Ok(response)
+    }
+}
+
+/// Split the body of a sign request into the key blob, the data to sign and the flags.
+pub(crate) fn parse_sign_request(body: &[u8]) -> Result<(&[u8], &[u8], u32), Error> {
+    let (key_blob, rest) = ssh_read_string(body)?;
+    let (data, rest) = ssh_read_string(rest)?;
+    if rest.len() != 4 {
+        bail!("malformed SSH agent sign request");
+    }
+
+    Ok((
+        key_blob,
+        data,
+        u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]),
+    ))
+}
+
+/// The RSA signature scheme asked for by a sign request's flags. Without either flag, clients
+/// expect a SHA-1 `ssh-rsa` signature.
+pub(crate) fn rsa_scheme(flags: u32) -> SignatureScheme {
+    if flags & SSH_AGENT_RSA_SHA2_512 != 0 {
+        SignatureScheme::RsaPkcs1v15Sha512
+    } else if flags & SSH_AGENT_RSA_SHA2_256 != 0 {
+        SignatureScheme::RsaPkcs1v15Sha256
+    } else {
+        SignatureScheme::RsaPkcs1v15Sha1
+    }
+}
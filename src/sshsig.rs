@@ -0,0 +1,563 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! OpenSSH signatures in the `SSHSIG` format, as made by `ssh-keygen -Y sign` and used by git
+//! with `gpg.format=ssh`, and their verification against `allowed_signers` files.
+//!
+//! The format is described in `PROTOCOL.sshsig` in the OpenSSH sources, and `allowed_signers` in
+//! the ALLOWED SIGNERS section of `ssh-keygen(1)`.
+
+use ecdsa::EcdsaSignature;
+use public_key::{pad_to, ssh_mpint, ssh_read_mpint, ssh_read_string, ssh_string};
+use session::Session;
+use types::*;
+use x509;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use failure::Error;
+use sha2::{Digest, Sha256, Sha512};
+
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MAGIC: &[u8] = b"SSHSIG";
+const VERSION: u32 = 1;
+
+const BEGIN: &str = "-----BEGIN SSH SIGNATURE-----";
+const END: &str = "-----END SSH SIGNATURE-----";
+
+/// Makes `SSHSIG` signatures with an asymmetric key on the device.
+///
+/// Ed25519 keys, ECDSA keys on P-256, P-384 and P-521, and RSA keys (signing with
+/// `rsa-sha2-512`) are supported. Messages are hashed with SHA-512, as `ssh-keygen` does.
+#[derive(Clone, Debug)]
+pub struct SshSigner {
+    session: Session,
+    key_id: u16,
+    public_key: PublicKey,
+}
+
+impl SshSigner {
+    /// Create a signer for the asymmetric key `key_id`, checking that OpenSSH supports it.
+    pub fn new(session: &Session, key_id: u16) -> Result<SshSigner, Error> {
+        let public_key = session.get_pubkey(key_id)?;
+        public_key.openssh_key_type()?;
+
+        Ok(SshSigner {
+            session: session.clone(),
+            key_id,
+            public_key,
+        })
+    }
+
+    /// The ID of the key on the device.
+    pub fn key_id(&self) -> u16 {
+        self.key_id
+    }
+
+    /// The key, e.g. for adding to an `allowed_signers` file with `PublicKey::to_openssh`.
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    /// Sign `message` for use in `namespace` (e.g. `git` or `file`), returning an armored
+    /// signature as written by `ssh-keygen -Y sign`.
+    pub fn sign(&self, namespace: &str, message: &[u8]) -> Result<String, Error> {
+        self.sign_reader(namespace, message)
+    }
+
+    /// Sign everything read from `reader`, as with `sign`.
+    pub fn sign_reader<R: Read>(&self, namespace: &str, mut reader: R) -> Result<String, Error> {
+        if namespace.is_empty() {
+            bail!("SSH signatures need a namespace");
+        }
+
+        let mut hasher = Sha512::new();
+        let mut buf = [0; 8192];
+        loop {
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+
+        let signed_data = signed_data(namespace, "sha512", &hasher.finalize());
+        let signature = ssh_signature(
+            &self.session,
+            self.key_id,
+            &self.public_key,
+            SignatureScheme::RsaPkcs1v15Sha512,
+            &signed_data,
+        )?;
+
+        let mut blob = MAGIC.to_vec();
+        blob.extend_from_slice(&VERSION.to_be_bytes());
+        ssh_string(&mut blob, &self.public_key.to_openssh_blob()?);
+        ssh_string(&mut blob, namespace.as_bytes());
+        ssh_string(&mut blob, b"");
+        ssh_string(&mut blob, b"sha512");
+        ssh_string(&mut blob, &signature);
+
+        Ok(armor(&blob))
+    }
+}
+
+/// Sign `data` with the key `key_id`, returning an SSH signature blob (RFC 4253 section 6.6, RFC
+/// 5656 section 3.1.2, RFC 8709 section 6) as used in `SSHSIG` and the SSH agent protocol.
+///
+/// RSA keys sign with `rsa_scheme`, which must be PKCS#1 v1.5 with SHA-1 (`ssh-rsa`), SHA-256
+/// (`rsa-sha2-256`) or SHA-512 (`rsa-sha2-512`).
+pub(crate) fn ssh_signature(
+    session: &Session,
+    key_id: u16,
+    public_key: &PublicKey,
+    rsa_scheme: SignatureScheme,
+    data: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let key_type = public_key.openssh_key_type()?;
+    let key_algorithm = public_key.algorithm();
+
+    let mut signature = Vec::new();
+    match *public_key {
+        PublicKey::Rsa(_, _) => {
+            let signature_type: &[u8] = match rsa_scheme {
+                SignatureScheme::RsaPkcs1v15Sha1 => b"ssh-rsa",
+                SignatureScheme::RsaPkcs1v15Sha256 => b"rsa-sha2-256",
+                SignatureScheme::RsaPkcs1v15Sha512 => b"rsa-sha2-512",
+                s => bail!("{:?} is not an SSH RSA signature scheme", s),
+            };
+            let rsa_signature =
+                session.sign_message_with_key_algorithm(key_id, key_algorithm, rsa_scheme, data)?;
+            ssh_string(&mut signature, signature_type);
+            ssh_string(&mut signature, &rsa_signature);
+        }
+        PublicKey::Ecc(curve, _, _) => {
+            let der = session.sign_message_with_key_algorithm(
+                key_id,
+                key_algorithm,
+                ecdsa_scheme(curve)?,
+                data,
+            )?;
+            let ecdsa_signature = EcdsaSignature::from_der(curve, der)?;
+
+            let mut integers = Vec::new();
+            ssh_mpint(&mut integers, ecdsa_signature.r());
+            ssh_mpint(&mut integers, ecdsa_signature.s());
+            ssh_string(&mut signature, key_type.as_bytes());
+            ssh_string(&mut signature, &integers);
+        }
+        PublicKey::Edc(_, _) => {
+            let eddsa_signature = session.sign_eddsa(key_id, data)?;
+            ssh_string(&mut signature, key_type.as_bytes());
+            ssh_string(&mut signature, &eddsa_signature);
+        }
+    }
+
+    Ok(signature)
+}
+
+/// The ECDSA scheme OpenSSH uses for keys on `curve` (RFC 5656 section 6.2.1).
+fn ecdsa_scheme(curve: Algorithm) -> Result<SignatureScheme, Error> {
+    match curve {
+        Algorithm::EcP256 => Ok(SignatureScheme::EcdsaSha256),
+        Algorithm::EcP384 => Ok(SignatureScheme::EcdsaSha384),
+        Algorithm::EcP521 => Ok(SignatureScheme::EcdsaSha512),
+        a => bail!("OpenSSH doesn't support {}", a),
+    }
+}
+
+/// The data actually signed for a message with digest `hash`.
+fn signed_data(namespace: &str, hash_algorithm: &str, hash: &[u8]) -> Vec<u8> {
+    let mut data = MAGIC.to_vec();
+    ssh_string(&mut data, namespace.as_bytes());
+    ssh_string(&mut data, b"");
+    ssh_string(&mut data, hash_algorithm.as_bytes());
+    ssh_string(&mut data, hash);
+    data
+}
+
+fn armor(blob: &[u8]) -> String {
+    let encoded = STANDARD.encode(blob);
+    let mut out = format!("{}\n", BEGIN);
+
+    for line in encoded.as_bytes().chunks(70) {
+        // base64 output is always ASCII, so this can't split a character.
+        out.push_str(&String::from_utf8_lossy(line));
+        out.push('\n');
+    }
+
+    out.push_str(END);
+    out.push('\n');
+    out
+}
+
+fn dearmor(signature: &str) -> Result<Vec<u8>, Error> {
+    let signature = signature.trim();
+    if !signature.starts_with(BEGIN) || !signature.ends_with(END) {
+        bail!("not an armored SSH signature");
+    }
+
+    let base64: String = signature[BEGIN.len()..signature.len() - END.len()]
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    STANDARD
+        .decode(base64)
+        .map_err(|e| format_err!("invalid SSH signature: {}", e))
+}
+
+/// Check the armored `signature` over `message` in `namespace`, without checking who made it, and
+/// return the key which made it.
+///
+/// Use `AllowedSigners::verify` to also check that the key belongs to an expected signer.
+pub fn verify_signature(
+    signature: &str,
+    message: &[u8],
+    namespace: &str,
+) -> Result<PublicKey, Error> {
+    let blob = dearmor(signature)?;
+
+    if !blob.starts_with(MAGIC) || blob.len() < MAGIC.len() + 4 {
+        bail!("not an SSH signature");
+    }
+    let (version, blob) = blob[MAGIC.len()..].split_at(4);
+    if version != VERSION.to_be_bytes() {
+        bail!("unsupported SSH signature version");
+    }
+
+    let (public_key, blob) = ssh_read_string(blob)?;
+    let (signed_namespace, blob) = ssh_read_string(blob)?;
+    let (_reserved, blob) = ssh_read_string(blob)?;
+    let (hash_algorithm, blob) = ssh_read_string(blob)?;
+    let (signature, blob) = ssh_read_string(blob)?;
+    if !blob.is_empty() {
+        bail!("trailing data after SSH signature");
+    }
+
+    if signed_namespace != namespace.as_bytes() {
+        bail!(
+            "SSH signature is for namespace {:?}, not {:?}",
+            String::from_utf8_lossy(signed_namespace),
+            namespace
+        );
+    }
+
+    let hash = match hash_algorithm {
+        b"sha256" => Sha256::digest(message).to_vec(),
+        b"sha512" => Sha512::digest(message).to_vec(),
+        _ => bail!("unsupported SSH signature hash algorithm"),
+    };
+    let signed_data = signed_data(namespace, &String::from_utf8_lossy(hash_algorithm), &hash);
+
+    let public_key = PublicKey::from_openssh_blob(public_key)?;
+    let (signature_type, signature) = ssh_read_string(signature)?;
+    let (signature, _) = ssh_read_string(signature)?;
+
+    match public_key {
+        PublicKey::Rsa(_, _) => {
+            let algorithm = match signature_type {
+                b"rsa-sha2-256" => Algorithm::RsaPkcs1Sha256,
+                b"rsa-sha2-512" => Algorithm::RsaPkcs1Sha512,
+                _ => bail!("unsupported RSA SSH signature type"),
+            };
+            public_key.verify(algorithm, &signed_data, signature)?;
+        }
+        PublicKey::Ecc(curve, _, _) => {
+            if signature_type != public_key.openssh_key_type()?.as_bytes() {
+                bail!("SSH signature type doesn't match key");
+            }
+
+            // Checked by `from_openssh_blob` that this is one of the NIST curves.
+            let field_len = curve.ec_field_len().unwrap();
+            let (r, integers) = ssh_read_mpint(signature)?;
+            let (s, _) = ssh_read_mpint(integers)?;
+            if r.len() > field_len || s.len() > field_len {
+                bail!("invalid ECDSA signature");
+            }
+            let signature = EcdsaSignature::from_bytes(
+                curve,
+                [pad_to(r, field_len), pad_to(s, field_len)].concat(),
+            )?;
+
+            let algorithm = ecdsa_scheme(curve)?.algorithm();
+            public_key.verify(algorithm, &signed_data, signature.to_der())?;
+        }
+        PublicKey::Edc(_, _) => {
+            if signature_type != b"ssh-ed25519" {
+                bail!("SSH signature type doesn't match key");
+            }
+            public_key.verify(Algorithm::EcEd25519, &signed_data, signature)?;
+        }
+    }
+
+    Ok(public_key)
+}
+
+/// The keys trusted to make SSH signatures, as listed in an `allowed_signers` file.
+///
+/// Entries with the `cert-authority` option are skipped, since signatures made with certificates
+/// are not supported. Times in `valid-after` and `valid-before` are always taken as UTC.
+#[derive(Clone, Debug, Default)]
+pub struct AllowedSigners {
+    entries: Vec<AllowedSigner>,
+}
+
+#[derive(Clone, Debug)]
+struct AllowedSigner {
+    principals: String,
+    namespaces: Option<String>,
+    valid_after: Option<SystemTime>,
+    valid_before: Option<SystemTime>,
+    public_key: PublicKey,
+}
+
+impl AllowedSigners {
+    /// Parse the contents of an `allowed_signers` file.
+    pub fn parse(contents: &str) -> Result<AllowedSigners, Error> {
+        let mut entries = Vec::new();
+
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(entry) = AllowedSigner::parse(line)
+                .map_err(|e| format_err!("allowed_signers line {}: {}", number + 1, e))?
+            {
+                entries.push(entry);
+            }
+        }
+
+        Ok(AllowedSigners { entries })
+    }
+
+    /// Read and parse an `allowed_signers` file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<AllowedSigners, Error> {
+        AllowedSigners::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Check the armored `signature` over `message` in `namespace`, and that it was made by a key
+    /// listed for `principal` (e.g. an email address) which is valid for the namespace now, as
+    /// `ssh-keygen -Y verify` does.
+    pub fn verify(
+        &self,
+        signature: &str,
+        message: &[u8],
+        namespace: &str,
+        principal: &str,
+    ) -> Result<(), Error> {
+        let public_key = verify_signature(signature, message, namespace)?;
+        let now = SystemTime::now();
+
+        let allowed = self.entries.iter().any(|entry| {
+            entry.public_key == public_key
+                && match_pattern_list(&entry.principals, principal)
+                && entry
+                    .namespaces
+                    .iter()
+                    .all(|namespaces| match_pattern_list(namespaces, namespace))
+                && entry.valid_after.iter().all(|after| now >= *after)
+                && entry.valid_before.iter().all(|before| now <= *before)
+        });
+
+        if !allowed {
+            bail!(
+                "SSH signature key is not allowed for {} in namespace {}",
+                principal,
+                namespace
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl AllowedSigner {
+    /// Parse an `allowed_signers` line, returning `None` for certificate authority entries.
+    fn parse(line: &str) -> Result<Option<AllowedSigner>, Error> {
+        let fields = split_fields(line);
+        let principals = match fields.first() {
+            Some(principals) => unquote(principals),
+            None => bail!("missing principals"),
+        };
+
+        // The options are optional, so the second field is either them or the key type.
+        let (options, key) = match fields.get(1) {
+            Some(field) if is_key_type(field) => (None, &fields[1..]),
+            Some(field) => (Some(field), &fields[2..]),
+            None => bail!("missing public key"),
+        };
+
+        let mut entry = AllowedSigner {
+            principals,
+            namespaces: None,
+            valid_after: None,
+            valid_before: None,
+            public_key: PublicKey::from_openssh(&key.join(" "))?,
+        };
+
+        for option in options.map_or(vec![], |options| split_options(options)) {
+            let (name, value) = match option.find('=') {
+                Some(i) => (&option[..i], Some(unquote(&option[i + 1..]))),
+                None => (&option[..], None),
+            };
+
+            match (name.to_ascii_lowercase().as_str(), value) {
+                ("cert-authority", None) => return Ok(None),
+                ("namespaces", Some(value)) => entry.namespaces = Some(value),
+                ("valid-after", Some(value)) => entry.valid_after = Some(parse_time(&value)?),
+                ("valid-before", Some(value)) => entry.valid_before = Some(parse_time(&value)?),
+                _ => bail!("unsupported option {}", option),
+            }
+        }
+
+        Ok(Some(entry))
+    }
+}
+
+fn is_key_type(field: &str) -> bool {
+    field.starts_with("ssh-") || field.starts_with("ecdsa-") || field.starts_with("sk-")
+}
+
+/// Split a line into whitespace-separated fields, keeping double-quoted spans together.
+fn split_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+
+    for c in line.chars() {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        if c.is_whitespace() && !quoted {
+            if !field.is_empty() {
+                fields.push(field.clone());
+                field.clear();
+            }
+        } else {
+            field.push(c);
+        }
+    }
+    if !field.is_empty() {
+        fields.push(field);
+    }
+
+    fields
+}
+
+/// Split an options field on commas outside double quotes.
+fn split_options(options: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut option = String::new();
+    let mut quoted = false;
+
+    for c in options.chars() {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        if c == ',' && !quoted {
+            out.push(option.clone());
+            option.clear();
+        } else {
+            option.push(c);
+        }
+    }
+    out.push(option);
+
+    out
+}
+
+fn unquote(s: &str) -> String {
+    s.trim_matches('"').to_string()
+}
+
+/// Parse a `YYYYMMDD[HHMM[SS]][Z]` time.
+fn parse_time(s: &str) -> Result<SystemTime, Error> {
+    let digits = s.trim_end_matches(&['Z', 'z'][..]);
+    if !digits.chars().all(|c| c.is_ascii_digit()) || ![8, 12, 14].contains(&digits.len()) {
+        bail!("invalid time {:?}", s);
+    }
+
+    // Checked above that these are all ASCII digits.
+    let field = |range: ::std::ops::Range<usize>| -> u32 {
+        digits.get(range).map_or(0, |d| d.parse().unwrap())
+    };
+    let (year, month, day) = (field(0..4), field(4..6), field(6..8));
+    let (hour, minute, second) = (field(8..10), field(10..12), field(12..14));
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        bail!("invalid time {:?}", s);
+    }
+
+    let days = x509::days_from_civil(i64::from(year), month, day);
+    if days < 0 {
+        bail!("times before 1970 are not supported");
+    }
+    let seconds = days as u64 * 86_400 + u64::from(hour * 3600 + minute * 60 + second);
+
+    Ok(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// Match `s` against a comma-separated list of `*`/`?` wildcard patterns, where patterns starting
+/// with `!` exclude matches, as OpenSSH's `match_pattern_list` does.
+fn match_pattern_list(patterns: &str, s: &str) -> bool {
+    let mut matched = false;
+
+    for pattern in patterns.split(',') {
+        if let Some(negated) = pattern.strip_prefix('!') {
+            if match_pattern(negated, s) {
+                return false;
+            }
+        } else if match_pattern(pattern, s) {
+            matched = true;
+        }
+    }
+
+    matched
+}
+
+fn match_pattern(pattern: &str, s: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let s: Vec<char> = s.chars().collect();
+
+    // Iterative wildcard matching, backtracking to the last `*`.
+    let (mut p, mut i) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while i < s.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == s[i]) {
+            p += 1;
+            i += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, i));
+            p += 1;
+        } else if let Some((star_p, star_i)) = star {
+            p = star_p + 1;
+            i = star_i + 1;
+            star = Some((star_p, star_i + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
@@ -0,0 +1,72 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Small files of state which must survive a crash and must only be used by one process at a time,
+//! shared by the validator signer and `ca::FileSerialNumbers`.
+
+use failure::Error;
+
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// `path` with `suffix` appended to its file name.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Take an exclusive lock on `<path>.lock`, failing if another process (or another user in this
+/// one) holds it. The lock is released when the returned file is closed, including when the
+/// process dies.
+pub(crate) fn lock<P: AsRef<Path>>(path: P) -> Result<File, Error> {
+    // The state file itself is replaced on every write, so it can't carry the lock.
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(with_suffix(path.as_ref(), ".lock"))?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => bail!(
+            "state file {} is in use by another process",
+            path.as_ref().display()
+        ),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+/// Replace the contents of `path` atomically, returning only once the new contents are on disk.
+pub(crate) fn replace<P: AsRef<Path>>(path: P, contents: &[u8]) -> Result<(), Error> {
+    let path = path.as_ref();
+    let temp_path = with_suffix(path, ".tmp");
+
+    let mut file = File::create(&temp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)?;
+
+    // The rename is only durable once the directory is synced.
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+    }
+
+    Ok(())
+}
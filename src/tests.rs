@@ -12,7 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use ca::{CertificateRequest, DistinguishedName};
+use ecdsa::EcdsaSignature;
 use types::*;
+use yubihsm_sys::{yh_capabilities, yh_object_descriptor};
 
 #[test]
 fn new_domain() {
@@ -36,3 +39,1655 @@ fn domains_to_domainparam() {
     assert_eq!(new_domains.len(), 2);
     assert_eq!(orig_domains, new_domains);
 }
+
+fn auth_key_info(
+    capabilities: &[Capability],
+    delegated_capabilities: &[Capability],
+    domains: &[Domain],
+) -> ObjectInfo {
+    let descriptor = yh_object_descriptor {
+        capabilities: yh_capabilities::from(capabilities),
+        id: 2,
+        len: 40,
+        domains: DomainParam::from(domains).0,
+        type_: ObjectType::AuthKey.into(),
+        algorithm: Algorithm::YubicoAesAuth.into(),
+        sequence: 0,
+        origin: 0,
+        label: [0; 41],
+        delegated_capabilities: yh_capabilities::from(delegated_capabilities),
+    };
+
+    ObjectInfo::try_from_yh_object_descriptor(descriptor).unwrap()
+}
+
+#[test]
+fn permission_report_permitted() {
+    let auth_key = auth_key_info(
+        &[Capability::PutAuthKey],
+        &[Capability::AsymmetricSignEcdsa, Capability::GetOpaque],
+        &[Domain(1), Domain(2)],
+    );
+    let report = PermissionReport::new(
+        &auth_key,
+        Capability::PutAuthKey,
+        &[Domain(2)],
+        &[Capability::GetOpaque],
+        &[Capability::AsymmetricSignEcdsa],
+    );
+
+    assert!(report.is_permitted());
+}
+
+#[test]
+fn permission_report_exceeds_delegation() {
+    let auth_key = auth_key_info(
+        &[Capability::GetOpaque],
+        &[Capability::AsymmetricSignEcdsa],
+        &[Domain(1)],
+    );
+    let report = PermissionReport::new(
+        &auth_key,
+        Capability::PutAuthKey,
+        &[Domain(1), Domain(3)],
+        &[Capability::AsymmetricSignEcdsa, Capability::PutOpaque],
+        &[Capability::Reset],
+    );
+
+    assert!(!report.is_permitted());
+    assert_eq!(report.auth_key_id, 2);
+    assert_eq!(report.missing_capabilities, vec![Capability::PutAuthKey]);
+    assert_eq!(report.undelegated_capabilities, vec![Capability::PutOpaque]);
+    assert_eq!(
+        report.undelegated_delegated_capabilities,
+        vec![Capability::Reset]
+    );
+    assert_eq!(report.inaccessible_domains, vec![Domain(3)]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_uses_libyubihsm_names() {
+    use serde_json;
+
+    assert_eq!(
+        serde_json::to_string(&Algorithm::EcP256).unwrap(),
+        "\"ecp256\""
+    );
+    assert_eq!(
+        serde_json::to_string(&ObjectType::WrapKey).unwrap(),
+        "\"wrapkey\""
+    );
+    assert_eq!(
+        serde_json::to_string(&Capability::AsymmetricSignEcdsa).unwrap(),
+        "\"asymmetric_sign_ecdsa\""
+    );
+    assert_eq!(
+        serde_json::from_str::<Algorithm>("\"rsa-pss-sha256\"").unwrap(),
+        Algorithm::RsaPssSha256
+    );
+    assert_eq!(
+        serde_json::from_str::<ReturnCode>("\"DeviceInvPermission\"").unwrap(),
+        ReturnCode::DeviceInvPermission
+    );
+    assert!(serde_json::from_str::<Capability>("\"not_a_capability\"").is_err());
+    assert!(serde_json::from_str::<Domain>("17").is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_object_info_round_trip() {
+    use serde_json;
+
+    let info = auth_key_info(
+        &[Capability::PutAuthKey, Capability::GetOpaque],
+        &[Capability::AsymmetricSignEcdsa],
+        &[Domain(1), Domain(5)],
+    );
+    let json = serde_json::to_string(&info).unwrap();
+    let decoded: ObjectInfo = serde_json::from_str(&json).unwrap();
+
+    assert_eq!(decoded.capabilities, info.capabilities);
+    assert_eq!(decoded.domains, info.domains);
+    assert_eq!(decoded.object_type, ObjectType::AuthKey);
+    assert_eq!(decoded.algorithm, Some(Algorithm::YubicoAesAuth));
+    assert_eq!(decoded.delegated_capabilities, info.delegated_capabilities);
+}
+
+#[test]
+fn algorithm_string_round_trip() {
+    for alg in &[
+        Algorithm::RsaPkcs1Sha256,
+        Algorithm::Rsa4096,
+        Algorithm::EcK256,
+        Algorithm::EcEd25519,
+        Algorithm::OpaqueX509Cert,
+        Algorithm::Aes256CcmWrap,
+    ] {
+        assert_eq!(alg.to_string().parse::<Algorithm>().unwrap(), *alg);
+    }
+
+    assert_eq!("ecp384".parse::<Algorithm>().unwrap(), Algorithm::EcP384);
+    assert!("ecp9000".parse::<Algorithm>().is_err());
+}
+
+#[test]
+fn object_type_string_round_trip() {
+    for object_type in &[
+        ObjectType::Asymmetric,
+        ObjectType::AuthKey,
+        ObjectType::HmacKey,
+        ObjectType::Opaque,
+        ObjectType::OtpAeadKey,
+        ObjectType::Public,
+        ObjectType::Template,
+        ObjectType::WrapKey,
+    ] {
+        assert_eq!(
+            object_type.to_string().parse::<ObjectType>().unwrap(),
+            *object_type
+        );
+    }
+
+    assert_eq!(ObjectType::AuthKey.to_string(), "authkey");
+    assert!("symmetric".parse::<ObjectType>().is_err());
+}
+
+#[test]
+fn capability_string_round_trip() {
+    let cap = Capability::DeleteOtpAeadKey;
+
+    assert_eq!(cap.to_string(), "delete_otp_aead_key");
+    assert_eq!(cap.to_string().parse::<Capability>().unwrap(), cap);
+    assert!("delete_everything".parse::<Capability>().is_err());
+    assert!("unknown".parse::<Capability>().is_err());
+    assert_eq!(
+        Capability::from("delete_everything"),
+        Capability::Unknown
+    );
+}
+
+fn from_hex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+const P256_X: &str = "71b622dc91f9f4101062d05ca4525d03881ffc849f53804752299d2cee5184e2";
+const P256_Y: &str = "3f6f28cd0270c4b23644876735bc794652eaa27512c4f9e41be5617a4be7bf9f";
+const ED25519_A: &str = "474c045aa551ce80540a7c79aa84238ddf7f874a416a532cac1ac5b2d8d2157f";
+
+#[test]
+fn ecc_public_key_encodings() {
+    let key = PublicKey::Ecc(Algorithm::EcP256, from_hex(P256_X), from_hex(P256_Y));
+
+    assert_eq!(key.algorithm(), Algorithm::EcP256);
+    assert_eq!(
+        key.to_spki_der().unwrap(),
+        from_hex(&format!(
+            "3059301306072a8648ce3d020106082a8648ce3d03010703420004{}{}",
+            P256_X, P256_Y
+        ))
+    );
+    assert_eq!(
+        key.to_jwk().unwrap(),
+        r#"{"crv":"P-256","kty":"EC","x":"cbYi3JH59BAQYtBcpFJdA4gf_ISfU4BHUimdLO5RhOI","y":"P28ozQJwxLI2RIdnNbx5RlLqonUSxPnkG-Vhekvnv58"}"#
+    );
+    assert_eq!(
+        key.to_openssh(None).unwrap(),
+        "ecdsa-sha2-nistp256 AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAAAIbmlzdHAyNTYAAABBBHG2ItyR+fQQEGLQXKRSXQOIH/yEn1OAR1IpnSzuUYTiP28ozQJwxLI2RIdnNbx5RlLqonUSxPnkG+Vhekvnv58="
+    );
+}
+
+#[test]
+fn edc_public_key_encodings() {
+    let key = PublicKey::Edc(Algorithm::EcEd25519, from_hex(ED25519_A));
+
+    assert_eq!(
+        key.to_spki_der().unwrap(),
+        from_hex(&format!("302a300506032b6570032100{}", ED25519_A))
+    );
+    assert_eq!(
+        key.to_pem().unwrap(),
+        "-----BEGIN PUBLIC KEY-----\n\
+         MCowBQYDK2VwAyEAR0wEWqVRzoBUCnx5qoQjjd9/h0pBalMsrBrFstjSFX8=\n\
+         -----END PUBLIC KEY-----\n"
+    );
+    assert_eq!(
+        key.to_jwk().unwrap(),
+        r#"{"crv":"Ed25519","kty":"OKP","x":"R0wEWqVRzoBUCnx5qoQjjd9_h0pBalMsrBrFstjSFX8"}"#
+    );
+    assert_eq!(
+        key.to_openssh(Some("release@example.com")).unwrap(),
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIEdMBFqlUc6AVAp8eaqEI43ff4dKQWpTLKwaxbLY0hV/ release@example.com"
+    );
+}
+
+#[test]
+fn rsa_public_key_exponent() {
+    let key = PublicKey::Rsa(Algorithm::Rsa2048, vec![0xc0; 256]);
+
+    assert!(
+        key.to_jwk()
+            .unwrap()
+            .starts_with(r#"{"e":"AQAB","kty":"RSA","n":"wMDA"#)
+    );
+    assert!(
+        key.to_openssh(None)
+            .unwrap()
+            .starts_with("ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQDA")
+    );
+    assert!(
+        PublicKey::Ecc(Algorithm::EcBp256, vec![1; 32], vec![2; 32])
+            .to_jwk()
+            .is_err()
+    );
+}
+
+const SIGNED_MESSAGE: &[u8] = b"hello yubihsm";
+
+#[test]
+fn verify_ecdsa() {
+    let key = PublicKey::Ecc(Algorithm::EcP256, from_hex(P256_X), from_hex(P256_Y));
+    let signature = from_hex(
+        "3045022016404a9412ce57985d5ddec990ad9bc890c3b2363460d7fd3570c8b500e17d0b0221009f53e97234\
+         ca80b55c6ae398b0c9d5da581845ad497a304934848e005cc59112",
+    );
+
+    key.verify(Algorithm::EcEcdsaSha256, SIGNED_MESSAGE, &signature)
+        .unwrap();
+    assert!(
+        key.verify(Algorithm::EcEcdsaSha256, b"goodbye yubihsm", &signature)
+            .is_err()
+    );
+    assert!(
+        key.verify(Algorithm::RsaPkcs1Sha256, SIGNED_MESSAGE, &signature)
+            .is_err()
+    );
+}
+
+#[test]
+fn verify_eddsa() {
+    let key = PublicKey::Edc(Algorithm::EcEd25519, from_hex(ED25519_A));
+    let signature = from_hex(
+        "5cad1be8db72e9b192259535b81f78686090f8aa111c3ddc29d2eda0c2b7fcb265094df0ae75af266a6681f2\
+         f2d49e4021cd62e3ec49c9e37c9268cffd079f0a",
+    );
+
+    key.verify(Algorithm::EcEd25519, SIGNED_MESSAGE, &signature)
+        .unwrap();
+    assert!(
+        key.verify(Algorithm::EcEd25519, b"goodbye yubihsm", &signature)
+            .is_err()
+    );
+}
+
+#[test]
+fn ecdsa_signature_encodings() {
+    let der = from_hex(
+        "3045022016404a9412ce57985d5ddec990ad9bc890c3b2363460d7fd3570c8b500e17d0b0221009f53e97234\
+         ca80b55c6ae398b0c9d5da581845ad497a304934848e005cc59112",
+    );
+    let signature = EcdsaSignature::from_der(Algorithm::EcP256, &der).unwrap();
+
+    assert_eq!(
+        signature.r(),
+        &from_hex("16404a9412ce57985d5ddec990ad9bc890c3b2363460d7fd3570c8b500e17d0b")[..]
+    );
+    assert_eq!(
+        signature.s(),
+        &from_hex("9f53e97234ca80b55c6ae398b0c9d5da581845ad497a304934848e005cc59112")[..]
+    );
+    assert_eq!(signature.to_der(), der);
+    assert_eq!(
+        EcdsaSignature::from_bytes(Algorithm::EcP256, signature.to_bytes()).unwrap(),
+        signature
+    );
+
+    assert!(EcdsaSignature::from_der(Algorithm::EcP256, &der[..der.len() - 1]).is_err());
+    assert!(EcdsaSignature::from_bytes(Algorithm::EcP384, signature.to_bytes()).is_err());
+}
+
+#[test]
+fn ecdsa_signature_k256_low_s_and_recovery() {
+    use hash::HashAlgorithm;
+    use k256::ecdsa::{Signature, SigningKey};
+
+    let signing_key = SigningKey::from_slice(&[0x42; 32]).unwrap();
+    let point = signing_key.verifying_key().to_encoded_point(false);
+    let public_key = PublicKey::Ecc(
+        Algorithm::EcK256,
+        point.x().unwrap().to_vec(),
+        point.y().unwrap().to_vec(),
+    );
+    let digest = HashAlgorithm::Sha256.digest(SIGNED_MESSAGE);
+
+    let (low_s, id) = signing_key.sign_prehash_recoverable(&digest).unwrap();
+    let (r, s) = low_s.split_scalars();
+    let high_s = Signature::from_scalars(r, -*s).unwrap();
+
+    let signature = EcdsaSignature::from_der(Algorithm::EcK256, high_s.to_der()).unwrap();
+    assert_eq!(signature.to_bytes(), low_s.to_bytes().to_vec());
+    assert_eq!(signature.recovery_id(&public_key, &digest).unwrap(), id.to_byte());
+
+    let other_key = PublicKey::Ecc(Algorithm::EcP256, from_hex(P256_X), from_hex(P256_Y));
+    assert!(signature.recovery_id(&other_key, &digest).is_err());
+}
+
+#[test]
+fn signature_scheme_key_support() {
+    assert!(SignatureScheme::RsaPssSha256.supports_key(Algorithm::Rsa3072));
+    assert!(SignatureScheme::EcdsaSha384.supports_key(Algorithm::EcBp384));
+    assert!(SignatureScheme::Ed25519.supports_key(Algorithm::EcEd25519));
+
+    assert!(!SignatureScheme::RsaPkcs1v15Sha256.supports_key(Algorithm::EcP256));
+    assert!(!SignatureScheme::EcdsaSha256.supports_key(Algorithm::EcEd25519));
+    assert!(!SignatureScheme::Ed25519.supports_key(Algorithm::EcP256));
+    assert!(!SignatureScheme::EcdsaSha256.supports_key(Algorithm::HmacSha256));
+
+    assert_eq!(
+        SignatureScheme::for_key(Algorithm::Rsa4096),
+        Some(SignatureScheme::RsaPkcs1v15Sha256)
+    );
+    assert_eq!(
+        SignatureScheme::for_key(Algorithm::EcP521),
+        Some(SignatureScheme::EcdsaSha512)
+    );
+    assert_eq!(SignatureScheme::for_key(Algorithm::HmacSha256), None);
+}
+
+#[test]
+fn pkcs1_digest_info() {
+    use hash::HashAlgorithm;
+
+    let digest = HashAlgorithm::Sha256.digest(SIGNED_MESSAGE);
+    let digest_info = HashAlgorithm::Sha256.digest_info(&digest);
+
+    assert_eq!(
+        &digest_info[..19],
+        &from_hex("3031300d060960864801650304020105000420")[..]
+    );
+    assert_eq!(&digest_info[19..], &digest[..]);
+}
+
+#[cfg(feature = "rustcrypto")]
+#[test]
+fn rustcrypto_verifying_keys() {
+    use signature::Verifier;
+
+    let p256_key = PublicKey::Ecc(Algorithm::EcP256, from_hex(P256_X), from_hex(P256_Y));
+    let signature = ::p256::ecdsa::Signature::from_der(&from_hex(
+        "3045022016404a9412ce57985d5ddec990ad9bc890c3b2363460d7fd3570c8b500e17d0b0221009f53e97234\
+         ca80b55c6ae398b0c9d5da581845ad497a304934848e005cc59112",
+    )).unwrap();
+    p256_key
+        .p256_verifying_key()
+        .unwrap()
+        .verify(SIGNED_MESSAGE, &signature)
+        .unwrap();
+    assert!(p256_key.p384_verifying_key().is_err());
+    assert!(p256_key.ed25519_verifying_key().is_err());
+
+    let ed25519_key = PublicKey::Edc(Algorithm::EcEd25519, from_hex(ED25519_A));
+    assert_eq!(
+        &ed25519_key.ed25519_verifying_key().unwrap().to_bytes()[..],
+        &from_hex(ED25519_A)[..]
+    );
+    assert!(ed25519_key.k256_verifying_key().is_err());
+}
+
+const RSA_N: &str = "b0d357343593016b8d91598e00debff069b9c0431fe4b8865068aeeccd0134abfed6099f0bff04\
+                     6bed1f58d4bf713be81e3cf820fd96bde621e71a3b9c29f7e6b16966a4f9c04bf6867449e31aae\
+                     68cf3d480f8a07d8649da20141019799ea6c0dd0a70571c96ac7193414a96c603d9fcece9d94b7\
+                     de08ecd03961a871eb983533717e0a7c799b0ec019bf4057279ee56e6a46d39f83e48b04c98b0a\
+                     3fddb489efdc8b684f712830588f5ccc8f74d29f5443c9747a3224e975695a34eee0d3ff85d060\
+                     25a98860728df3baa4cdf2e66213cb6f9ca95f865ee37a4df2f28b042da023d886198a15c33fc3\
+                     eb97620bac80dc31631a87205b390edeae24b64eeddb";
+
+#[test]
+fn verify_rsa() {
+    let key = PublicKey::Rsa(Algorithm::Rsa2048, from_hex(RSA_N));
+    let pss_signature = from_hex(
+        "442ee5cadf3edd2713e386b884452a502bffa8629bc96efb4eccebf6e137b665e90379cf062d65918c819eed\
+         d8eb6ea9218e78e3c2963e3dbba0ce7a4641398ede02509b8ef9079d93d422a55c288a3f8f9565280dad171a\
+         f0ea91278f15e808340e85e58575786cf5e5597e74f0fab568695b935aae9abbdefd39fae1eba9d5a918ee72\
+         205631956df32e023decec07aa00e4cfa402134c8c01570cc04405cd9ca9990fffee88a86cf720169a8509bc\
+         2eb467117a66decd09ef31344ed74e4e73954d40a6c3a033370c7252ed8258d8e2df11f34cdd49c0bc669872\
+         0fc4789cec4b01be6ae709c46c571d33d8f5d5ff633a9cf859b39fd10a3ea82aa40804f9",
+    );
+    let pkcs1_signature = from_hex(
+        "4a3d86e88a049654d03ed57caf605ab61fa815055dd9e5b34c3ac583b8427e3db125f02143ad9910072df9e5\
+         ae7ce07ffadd674f015cdb74f018d7335e7b54fa95cb28e8db92f7aae9ac4514126d00ab37a49f8ea966a99a\
+         f1f9f0de36c18d9cd50ffdde47f7afa49938d5bd4f1c3e6eb8ae5ed9aec88555c76debb0fa7e442fa956cece\
+         76484643fada2b483743eb615a1aaad886e2a3e507d2eae959e57cab968093079b7cb308ea5c003db6164b18\
+         832604d5ab5af23cc6c7f1300f289c5cd990862281a98e5749c5317c1aa2e1162f73fd62fd14ec9d4f473406\
+         768c342fcb5a9779ce97a4e1ee2a24186bb4f73cf70910ff93fd34a114c504e535c01672",
+    );
+
+    key.verify(Algorithm::RsaPssSha256, SIGNED_MESSAGE, &pss_signature)
+        .unwrap();
+    key.verify(Algorithm::RsaPkcs1Sha384, SIGNED_MESSAGE, &pkcs1_signature)
+        .unwrap();
+    assert!(
+        key.verify(Algorithm::RsaPkcs1Sha256, SIGNED_MESSAGE, &pkcs1_signature)
+            .is_err()
+    );
+}
+
+#[test]
+fn public_key_spki_round_trip() {
+    let keys = [
+        PublicKey::Ecc(Algorithm::EcP256, from_hex(P256_X), from_hex(P256_Y)),
+        PublicKey::Edc(Algorithm::EcEd25519, from_hex(ED25519_A)),
+        PublicKey::Rsa(Algorithm::Rsa2048, from_hex(RSA_N)),
+    ];
+
+    for key in &keys {
+        assert_eq!(
+            &PublicKey::from_spki_der(key.to_spki_der().unwrap()).unwrap(),
+            key
+        );
+    }
+}
+
+const CERTIFICATE_REQUEST: &str =
+    "308201123081b80201003029310b3009060355040613025553310b3009060355040a0c024578310d300b06035504\
+     030c04686f73743059301306072a8648ce3d020106082a8648ce3d0301070342000471b622dc91f9f4101062d05c\
+     a4525d03881ffc849f53804752299d2cee5184e23f6f28cd0270c4b23644876735bc794652eaa27512c4f9e41be5\
+     617a4be7bf9fa02d302b06092a864886f70d01090e311e301c301a0603551d11041330118209612e6578616d706c\
+     65870401020304300a06082a8648ce3d0403020349003046022100a56877d272b47f7ee41812f8bced3275ee185b\
+     501ff3a91117c3ddefb91561e8022100e891180a03701d2f8006f07621685d7d7e41454e04ca009754b97c80b0e3\
+     1031";
+
+#[test]
+fn certificate_request_from_der() {
+    let der = from_hex(CERTIFICATE_REQUEST);
+    let request = CertificateRequest::from_der(&der).unwrap();
+
+    assert_eq!(
+        request.public_key(),
+        &PublicKey::Ecc(Algorithm::EcP256, from_hex(P256_X), from_hex(P256_Y))
+    );
+    assert_eq!(
+        request.subject_der(),
+        &DistinguishedName::new()
+            .country("US")
+            .organization("Ex")
+            .common_name("host")
+            .to_der()[..]
+    );
+
+    // Change the requested common name from "host" to "hosu".
+    let mut tampered = der.clone();
+    tampered[52] ^= 1;
+    assert!(CertificateRequest::from_der(&tampered).is_err());
+}
+
+#[test]
+fn issued_certificate() {
+    use asn1;
+    use ca::{
+        ca_certificate_fields, key_usage_extension_value, tbs_certificate, CertificateProfile,
+    };
+    use p256::ecdsa::signature::Signer;
+    use p256::ecdsa::{Signature, SigningKey};
+    use std::time::{Duration, UNIX_EPOCH};
+    use x509;
+
+    let signing_key = SigningKey::from_slice(&[0x42; 32]).unwrap();
+    let point = signing_key.verifying_key().to_encoded_point(false);
+    let ca_key = PublicKey::Ecc(
+        Algorithm::EcP256,
+        point.x().unwrap().to_vec(),
+        point.y().unwrap().to_vec(),
+    );
+    let ca_spki = ca_key.to_spki_der().unwrap();
+    let issuer = DistinguishedName::new().common_name("Test CA").to_der();
+    let subject = DistinguishedName::new().common_name("host").to_der();
+    let spki = PublicKey::Edc(Algorithm::EcEd25519, from_hex(ED25519_A))
+        .to_spki_der()
+        .unwrap();
+
+    let issue = |now: u64, profile: &CertificateProfile| {
+        let tbs = tbs_certificate(
+            UNIX_EPOCH + Duration::from_secs(now),
+            &[0x01, 0x02],
+            SignatureScheme::EcdsaSha256,
+            &issuer,
+            &x509::key_identifier(&ca_spki).unwrap(),
+            &subject,
+            &spki,
+            None,
+            profile,
+        )
+        .unwrap();
+        let signature: Signature = signing_key.sign(&tbs);
+        x509::signed(
+            &tbs,
+            SignatureScheme::EcdsaSha256,
+            signature.to_der().as_bytes(),
+        )
+    };
+
+    // 2024-01-01T00:00:00Z.
+    let certificate = issue(1_704_067_200, &CertificateProfile::tls_server());
+    let signed = x509::Signed::parse(&certificate).unwrap();
+    signed.verify(&ca_key).unwrap();
+
+    let (tbs, rest) = asn1::read_element(asn1::TAG_SEQUENCE, signed.tbs).unwrap();
+    assert!(rest.is_empty());
+    let (version, tbs) = asn1::read_element(0xa0, tbs).unwrap();
+    assert_eq!(version, &from_hex("020102")[..]);
+    let (serial_number, tbs) = asn1::read_unsigned_integer(tbs).unwrap();
+    assert_eq!(serial_number, &[0x01, 0x02]);
+    let (algorithm, tbs) = asn1::split_element(tbs).unwrap();
+    assert_eq!(
+        algorithm,
+        &x509::algorithm_identifier(SignatureScheme::EcdsaSha256)[..]
+    );
+    let (name, tbs) = asn1::split_element(tbs).unwrap();
+    assert_eq!(name, &issuer[..]);
+    // Backdated by an hour, and valid for 397 days.
+    let (validity, tbs) = asn1::read_element(asn1::TAG_SEQUENCE, tbs).unwrap();
+    let (not_before, validity) = asn1::read_element(asn1::TAG_UTC_TIME, validity).unwrap();
+    assert_eq!(not_before, b"231231230000Z");
+    let (not_after, _) = asn1::read_element(asn1::TAG_UTC_TIME, validity).unwrap();
+    assert_eq!(not_after, b"250201000000Z");
+    let (name, tbs) = asn1::split_element(tbs).unwrap();
+    assert_eq!(name, &subject[..]);
+    let (public_key_info, tbs) = asn1::split_element(tbs).unwrap();
+    assert_eq!(public_key_info, &spki[..]);
+    let (extensions, rest) = asn1::read_element(0xa3, tbs).unwrap();
+    assert!(rest.is_empty());
+    // Key usage is critical, with digitalSignature and keyEncipherment set.
+    let key_usage = from_hex("300e0603551d0f0101ff0404030205a0");
+    assert!(extensions
+        .windows(key_usage.len())
+        .any(|window| window == &key_usage[..]));
+
+    // A tampered certificate fails verification.
+    let mut tampered = certificate.clone();
+    let len = tampered.len();
+    tampered[len - 1] ^= 1;
+    assert!(x509::Signed::parse(&tampered)
+        .unwrap()
+        .verify(&ca_key)
+        .is_err());
+
+    // Dates from 2050 on are GeneralizedTime. 2040-01-01T00:00:00Z plus 20 years of 365 days.
+    let root = issue(2_208_988_800, &CertificateProfile::root());
+    let (tbs, _) = asn1::read_element(asn1::TAG_SEQUENCE, &root).unwrap();
+    let (tbs, _) = asn1::read_element(asn1::TAG_SEQUENCE, tbs).unwrap();
+    let (_, tbs) = asn1::read_element(0xa0, tbs).unwrap();
+    let (_, tbs) = asn1::split_element(tbs).unwrap();
+    let (_, tbs) = asn1::split_element(tbs).unwrap();
+    let (_, tbs) = asn1::split_element(tbs).unwrap();
+    let (validity, _) = asn1::read_element(asn1::TAG_SEQUENCE, tbs).unwrap();
+    let (not_before, validity) = asn1::read_element(asn1::TAG_UTC_TIME, validity).unwrap();
+    assert_eq!(not_before, b"391231230000Z");
+    let (not_after, _) = asn1::read_element(asn1::TAG_GENERALIZED_TIME, validity).unwrap();
+    assert_eq!(not_after, b"20591227000000Z");
+
+    // An empty key usage is an empty BIT STRING, with no unused bits.
+    assert_eq!(key_usage_extension_value(&[]), from_hex("030100"));
+
+    // A CA certificate's own subject key identifier is used, whatever method made it.
+    let fields = ca_certificate_fields(&certificate).unwrap();
+    assert_eq!((&fields.subject, &fields.spki), (&subject, &spki));
+    assert_eq!(
+        fields.subject_key_identifier,
+        Some(x509::key_identifier(&spki).unwrap())
+    );
+    let extensions = asn1::sequence(&[
+        &x509::extension(asn1::OID_KEY_USAGE, true, &key_usage_extension_value(&[])),
+        &x509::extension(
+            asn1::OID_SUBJECT_KEY_IDENTIFIER,
+            false,
+            &asn1::octet_string(&[1, 2, 3, 4]),
+        ),
+    ]);
+    assert_eq!(
+        x509::subject_key_identifier(&extensions).unwrap(),
+        Some(vec![1, 2, 3, 4])
+    );
+    assert_eq!(
+        x509::subject_key_identifier(&asn1::sequence(&[])).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn file_serial_numbers() {
+    use ca::FileSerialNumbers;
+    use state_file;
+    use std::fs;
+
+    let dir = ::std::env::temp_dir().join(format!("libyubihsm-serial-{}", ::std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("serial");
+
+    // Only one generator may use a file at a time.
+    let serial_numbers = FileSerialNumbers::new(&path).unwrap();
+    assert!(FileSerialNumbers::new(&path).is_err());
+    drop(serial_numbers);
+    assert!(FileSerialNumbers::new(&path).is_ok());
+
+    state_file::replace(&path, b"2\n").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "2\n");
+    assert!(!dir.join("serial.tmp").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn crl_without_revoked_certificates() {
+    use asn1;
+    use revocation::tbs_cert_list;
+    use std::time::Duration;
+
+    let issuer = DistinguishedName::new().common_name("Test CA").to_der();
+    let tbs = tbs_cert_list(
+        SignatureScheme::EcdsaSha256,
+        &issuer,
+        &[0x5e; 20],
+        42,
+        &[],
+        Duration::from_secs(86_400),
+    ).unwrap();
+
+    let (tbs, rest) = asn1::read_element(asn1::TAG_SEQUENCE, &tbs).unwrap();
+    assert!(rest.is_empty());
+    let (version, tbs) = asn1::read_element(asn1::TAG_INTEGER, tbs).unwrap();
+    assert_eq!(version, &[1]);
+    let (_, tbs) = asn1::split_element(tbs).unwrap();
+    let (name, tbs) = asn1::split_element(tbs).unwrap();
+    assert_eq!(name, &issuer[..]);
+    let (_, tbs) = asn1::read_element(asn1::TAG_UTC_TIME, tbs).unwrap();
+    let (_, tbs) = asn1::read_element(asn1::TAG_UTC_TIME, tbs).unwrap();
+
+    // The empty revokedCertificates list is left out entirely.
+    let (extensions, rest) = asn1::read_element(0xa0, tbs).unwrap();
+    assert!(rest.is_empty());
+    let crl_number = from_hex("300a0603551d14040302012a");
+    assert!(extensions.ends_with(&crl_number));
+}
+
+#[test]
+fn jws_algorithms_and_thumbprint() {
+    use jose;
+
+    let key = PublicKey::Ecc(Algorithm::EcP256, from_hex(P256_X), from_hex(P256_Y));
+
+    // The SHA-256 of the JWK checked in `ecc_public_key_encodings`.
+    assert_eq!(
+        jose::thumbprint(&key).unwrap(),
+        "GfErxvHCnmjqorrm3Q1kZ1_QBrJ7kNTnM-AMrHZSMWw"
+    );
+    assert_eq!(
+        jose::JwsAlgorithm::for_key(&key),
+        Some(jose::JwsAlgorithm::ES256)
+    );
+    assert!(!jose::JwsAlgorithm::ES256.supports_key(Algorithm::EcP384));
+    assert!(jose::JwsAlgorithm::PS256.supports_key(Algorithm::Rsa3072));
+    assert_eq!(jose::json_string("a\"b\\c\u{1}"), r#""a\"b\\c\u0001""#);
+}
+
+#[test]
+fn cbor_deterministic_encoding() {
+    use cbor;
+
+    // Examples from RFC 8949 appendix A.
+    assert_eq!(cbor::int(0), from_hex("00"));
+    assert_eq!(cbor::int(23), from_hex("17"));
+    assert_eq!(cbor::int(24), from_hex("1818"));
+    assert_eq!(cbor::int(1_000_000), from_hex("1a000f4240"));
+    assert_eq!(cbor::int(-1), from_hex("20"));
+    assert_eq!(cbor::int(-1000), from_hex("3903e7"));
+    assert_eq!(cbor::bytes(&[1, 2, 3, 4]), from_hex("4401020304"));
+    assert_eq!(cbor::text("IETF"), from_hex("6449455446"));
+    assert_eq!(cbor::tag(1, &cbor::int(1_363_896_240)), from_hex("c11a514b67b0"));
+
+    // Map entries are sorted by their encoded keys, so negative labels come last.
+    assert_eq!(
+        cbor::map(&[
+            (&cbor::int(-1), &cbor::int(1)),
+            (&cbor::int(3), &cbor::array(&[])),
+            (&cbor::int(1), &cbor::null()),
+        ]),
+        from_hex("a301f603802001")
+    );
+}
+
+#[test]
+fn cose_key_encodings() {
+    let key = PublicKey::Edc(Algorithm::EcEd25519, from_hex(ED25519_A));
+    assert_eq!(
+        key.to_cose_key().unwrap(),
+        from_hex(&format!("a301012006215820{}", ED25519_A))
+    );
+
+    let key = PublicKey::Ecc(Algorithm::EcP256, from_hex(P256_X), from_hex(P256_Y));
+    assert_eq!(
+        key.to_cose_key().unwrap(),
+        from_hex(&format!("a401022001215820{}225820{}", P256_X, P256_Y))
+    );
+}
+
+#[test]
+fn openpgp_fingerprint_and_armor_checksum() {
+    use openpgp;
+
+    let key = PublicKey::Ecc(Algorithm::EcP256, from_hex(P256_X), from_hex(P256_Y));
+    let key_packet = openpgp::public_key_packet_body(&key, 1_600_000_000).unwrap();
+
+    assert_eq!(
+        openpgp::fingerprint(&key_packet),
+        from_hex("a1838d02f03bdc04b512474c554ca3426fee652c")
+    );
+
+    // The CRC-24/OPENPGP check value.
+    assert_eq!(openpgp::crc24(b"123456789"), 0x21_cf02);
+}
+
+// Made with `ssh-keygen -Y sign -n git` over "hello world\n".
+const SSHSIG_ED25519_KEY: &str = "ssh-ed25519 \
+    AAAAC3NzaC1lZDI1NTE5AAAAIGNSYwswZ1VpGtEd/fvJDoVbGW/gyjEl259V/vnY/SaQ";
+const SSHSIG_ED25519: &str = "-----BEGIN SSH SIGNATURE-----
+U1NIU0lHAAAAAQAAADMAAAALc3NoLWVkMjU1MTkAAAAgY1JjCzBnVWka0R39+8kOhVsZb+
+DKMSXbn1X++dj9JpAAAAADZ2l0AAAAAAAAAAZzaGE1MTIAAABTAAAAC3NzaC1lZDI1NTE5
+AAAAQGNC4RTXtST13zQBPQhgkFn477ua0SZHNGMF3433skSTpYNCf9TAx9AFOLkX1RLbtc
+mUe5C37B5JhnburRa+BAw=
+-----END SSH SIGNATURE-----
+";
+
+#[test]
+fn sshsig_allowed_signers() {
+    use sshsig::AllowedSigners;
+
+    let key = PublicKey::from_openssh(SSHSIG_ED25519_KEY).unwrap();
+    assert_eq!(key.to_openssh(None).unwrap(), SSHSIG_ED25519_KEY);
+
+    let allowed = AllowedSigners::parse(&format!(
+        "# comment\n\
+         *@example.com,!bad@example.com namespaces=\"git,file\" {key}\n\
+         old@example.net valid-before=20200101 {key}\n\
+         *@example.org cert-authority {key}\n",
+        key = SSHSIG_ED25519_KEY
+    ))
+    .unwrap();
+    let message = b"hello world\n";
+
+    allowed
+        .verify(SSHSIG_ED25519, message, "git", "alice@example.com")
+        .unwrap();
+    assert!(allowed
+        .verify(SSHSIG_ED25519, message, "file", "alice@example.com")
+        .is_err());
+    assert!(allowed
+        .verify(SSHSIG_ED25519, b"hello world", "git", "alice@example.com")
+        .is_err());
+    assert!(allowed
+        .verify(SSHSIG_ED25519, message, "git", "bad@example.com")
+        .is_err());
+    assert!(allowed
+        .verify(SSHSIG_ED25519, message, "git", "old@example.net")
+        .is_err());
+    assert!(allowed
+        .verify(SSHSIG_ED25519, message, "git", "alice@example.org")
+        .is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn ssh_agent_messages() {
+    use socket;
+    use ssh_agent;
+    use std::io::Cursor;
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut stream = Cursor::new(from_hex("000000010b0000000d0d"));
+    assert_eq!(
+        socket::read_message(&mut stream, 16).unwrap(),
+        Some(vec![11])
+    );
+    // A truncated message is an error, but a clean disconnect isn't.
+    assert!(socket::read_message(&mut stream, 16).is_err());
+    assert_eq!(
+        socket::read_message(&mut Cursor::new(vec![]), 16).unwrap(),
+        None
+    );
+
+    let request = from_hex("000000016100000002626300000004");
+    let (key_blob, data, flags) = ssh_agent::parse_sign_request(&request).unwrap();
+    assert_eq!((key_blob, data, flags), (&b"a"[..], &b"bc"[..], 4));
+    assert_eq!(
+        ssh_agent::rsa_scheme(flags),
+        SignatureScheme::RsaPkcs1v15Sha512
+    );
+    assert_eq!(ssh_agent::rsa_scheme(2), SignatureScheme::RsaPkcs1v15Sha256);
+    assert_eq!(ssh_agent::rsa_scheme(0), SignatureScheme::RsaPkcs1v15Sha1);
+
+    let dir = ::std::env::temp_dir().join(format!("libyubihsm-socket-{}", ::std::process::id()));
+    ::std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("agent.sock");
+    let _listener = socket::bind_unix(&path).unwrap();
+    let mode = ::std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert!(::std::os::unix::net::UnixStream::connect(&path).is_ok());
+    // The socket is never bound over an existing file, and the private directory is removed.
+    assert!(socket::bind_unix(&path).is_err());
+    assert_eq!(::std::fs::read_dir(&dir).unwrap().count(), 1);
+    ::std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn socket_serve_skips_failed_accepts() {
+    use socket;
+    use std::io;
+    use std::sync::mpsc;
+
+    let incoming = vec![
+        Err(io::Error::from_raw_os_error(24)),
+        Err(io::Error::from(io::ErrorKind::ConnectionAborted)),
+        Ok(1),
+        Ok(2),
+    ];
+    let (sender, receiver) = mpsc::channel();
+    socket::serve(incoming.into_iter(), move |n: u32| {
+        sender.send(n).unwrap();
+        if n == 1 {
+            bail!("handler failed");
+        }
+        Ok(())
+    })
+    .unwrap();
+
+    let mut served: Vec<u32> = receiver.iter().take(2).collect();
+    served.sort();
+    assert_eq!(served, vec![1, 2]);
+}
+
+/// Length-prefixed sign bytes for a vote (or proposal) with a timestamp, as a node sends them.
+fn canonical_vote(
+    msg_type: u8,
+    height: i64,
+    round: i64,
+    block_hash: Option<u8>,
+    chain_id: &str,
+) -> Vec<u8> {
+    let (block_id_key, timestamp_key, chain_id_key) = if msg_type == 32 {
+        (0x2a, 0x32, 0x3a)
+    } else {
+        (0x22, 0x2a, 0x32)
+    };
+
+    let mut body = vec![0x08, msg_type, 0x11];
+    body.extend_from_slice(&height.to_le_bytes());
+    if round != 0 {
+        body.push(0x19);
+        body.extend_from_slice(&round.to_le_bytes());
+    }
+    if let Some(hash) = block_hash {
+        body.extend_from_slice(&[block_id_key, 0x04, 0x0a, 0x02, hash, hash]);
+    }
+    body.extend_from_slice(&[timestamp_key, 0x02, 0x08, 0x01]);
+    body.push(chain_id_key);
+    body.push(chain_id.len() as u8);
+    body.extend_from_slice(chain_id.as_bytes());
+
+    let mut sign_bytes = vec![body.len() as u8];
+    sign_bytes.extend_from_slice(&body);
+    sign_bytes
+}
+
+#[test]
+fn validator_double_sign_protection() {
+    use state_file;
+    use validator::{SignRequest, SignState, Step};
+
+    let request = SignRequest::parse(&canonical_vote(2, 10, 1, Some(0xaa), "test-chain")).unwrap();
+    assert_eq!(
+        request,
+        SignRequest {
+            chain_id: "test-chain".to_string(),
+            height: 10,
+            round: 1,
+            step: Step::Precommit,
+            block_id: Some(vec![0x0a, 0x02, 0xaa, 0xaa]),
+        }
+    );
+    let proposal =
+        SignRequest::parse(&canonical_vote(32, 10, 0, Some(0xaa), "test-chain")).unwrap();
+    assert_eq!((proposal.step, proposal.round), (Step::Proposal, 0));
+    let nil_vote = SignRequest::parse(&canonical_vote(1, 10, 1, None, "test-chain")).unwrap();
+    assert_eq!(nil_vote.block_id, None);
+
+    // Heartbeats and vote extensions aren't signed, and neither are truncated sign bytes.
+    assert!(SignRequest::parse(&canonical_vote(3, 10, 0, None, "test-chain")).is_err());
+    let sign_bytes = canonical_vote(1, 10, 0, None, "test-chain");
+    assert!(SignRequest::parse(&sign_bytes[..sign_bytes.len() - 1]).is_err());
+
+    let state =
+        SignState::from(&SignRequest::parse(&canonical_vote(1, 10, 1, Some(0xaa), "c")).unwrap());
+    let check = |msg_type, height, round, block_hash| {
+        let request = SignRequest::parse(&canonical_vote(msg_type, height, round, block_hash, "c"));
+        state.check(&request.unwrap()).is_ok()
+    };
+    assert!(SignState::default().check(&request).is_ok());
+    // The same prevote again (with a new timestamp) is fine, but not a conflicting one.
+    assert!(check(1, 10, 1, Some(0xaa)));
+    assert!(!check(1, 10, 1, Some(0xbb)));
+    assert!(!check(1, 10, 1, None));
+    // Earlier steps, rounds and heights are refused, and later ones are fine.
+    assert!(!check(32, 10, 1, Some(0xaa)));
+    assert!(!check(2, 10, 0, Some(0xaa)));
+    assert!(!check(2, 9, 5, Some(0xaa)));
+    assert!(check(2, 10, 1, Some(0xbb)));
+    assert!(check(32, 10, 2, Some(0xbb)));
+    assert!(check(32, 11, 0, None));
+
+    let dir = ::std::env::temp_dir().join(format!("libyubihsm-validator-{}", ::std::process::id()));
+    ::std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("state");
+    let _ = ::std::fs::remove_file(&path);
+
+    SignState::initialize(&path).unwrap();
+    assert_eq!(SignState::load(&path).unwrap(), SignState::default());
+    // An existing state file is never reset.
+    state.save(&path).unwrap();
+    assert!(SignState::initialize(&path).is_err());
+    assert_eq!(SignState::load(&path).unwrap(), state);
+
+    // Only one signer may hold the state file at a time.
+    let lock = state_file::lock(&path).unwrap();
+    assert!(state_file::lock(&path).is_err());
+    drop(lock);
+    assert!(state_file::lock(&path).is_ok());
+
+    ::std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn k256_public_key(signing_key: &::k256::ecdsa::SigningKey) -> PublicKey {
+    let point = signing_key.verifying_key().to_encoded_point(false);
+    PublicKey::Ecc(
+        Algorithm::EcK256,
+        point.x().unwrap().to_vec(),
+        point.y().unwrap().to_vec(),
+    )
+}
+
+#[test]
+fn secp256k1_ethereum_and_bitcoin() {
+    use k256::ecdsa::SigningKey;
+    use secp256k1::{eip55_address, EthereumSignature, RecoverableSignature};
+
+    let mut one = [0; 32];
+    one[31] = 1;
+    let public_key = k256_public_key(&SigningKey::from_slice(&one).unwrap());
+    assert_eq!(
+        public_key.ec_point_compressed().unwrap(),
+        from_hex("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+    );
+    assert_eq!(
+        eip55_address(&public_key.ethereum_address().unwrap()),
+        "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf"
+    );
+    let mut address = [0; 20];
+    address.copy_from_slice(&from_hex("5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"));
+    assert_eq!(
+        eip55_address(&address),
+        "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"
+    );
+
+    // The example transaction from EIP-155, signed with RFC 6979 nonces as Ethereum clients do.
+    let signing_key = SigningKey::from_slice(&[0x46; 32]).unwrap();
+    let public_key = k256_public_key(&signing_key);
+    assert_eq!(
+        eip55_address(&public_key.ethereum_address().unwrap()),
+        "0x9d8A62f656a8d1615C1294fd71e9CFb3E4855A4F"
+    );
+
+    let sighash = from_hex("daf5a779ae972f972197303d7b574746c7ef83eadac0f2791ad23db92e4c8e53");
+    let (signature, _) = signing_key.sign_prehash_recoverable(&sighash).unwrap();
+    let signature = EcdsaSignature::from_bytes(Algorithm::EcK256, signature.to_bytes()).unwrap();
+    let signature = RecoverableSignature::new(signature, &public_key, &sighash).unwrap();
+
+    let r = from_hex("28ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276");
+    let s = from_hex("67cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83");
+    assert_eq!(
+        signature.eip155(1).unwrap(),
+        EthereumSignature {
+            v: 37,
+            r: r.clone(),
+            s: s.clone(),
+        }
+    );
+    assert_eq!(signature.eip1559().unwrap().v, 0);
+    assert_eq!(signature.to_bytes(), [&r[..], &s[..], &[0]].concat());
+    assert_eq!(
+        signature.to_bitcoin_compact(true),
+        [&[31], &r[..], &s[..]].concat()
+    );
+    assert_eq!(signature.to_bitcoin_compact(false)[0], 27);
+
+    let other_key = k256_public_key(&SigningKey::from_slice(&one).unwrap());
+    assert!(
+        RecoverableSignature::new(signature.signature().clone(), &other_key, &sighash).is_err()
+    );
+}
+
+#[test]
+fn dnssec_records() {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use dnssec::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    assert_eq!(
+        name_to_wire("Example.COM").unwrap(),
+        name_to_wire("example.com.").unwrap()
+    );
+    assert_eq!(
+        name_to_wire("a\\.b.\\067").unwrap(),
+        b"\x03a.b\x01c\x00".to_vec()
+    );
+    assert_eq!(name_to_wire(".").unwrap(), vec![0]);
+    assert!(name_to_wire("a..b").is_err());
+    assert!(name_to_wire("").is_err());
+
+    // The examples from RFC 8080 section 6.
+    let signing_key = SigningKey::from_bytes(b"82260384628080122645190204142262");
+    let public_key = PublicKey::Edc(
+        Algorithm::EcEd25519,
+        signing_key.verifying_key().to_bytes().to_vec(),
+    );
+    let dnskey = dnskey_rdata(
+        &public_key,
+        DNSKEY_FLAG_ZONE | DNSKEY_FLAG_SEP,
+        DnssecAlgorithm::Ed25519,
+    )
+    .unwrap();
+    assert_eq!(
+        STANDARD.encode(&dnskey[4..]),
+        "l02Woi0iS8Aa25FQkUd9RMzZHJpBoRQwAQEX1SxZJA4="
+    );
+    assert_eq!(key_tag(&dnskey), 3613);
+    assert_eq!(
+        ds_rdata("example.com.", &dnskey, DigestType::Sha256).unwrap(),
+        [
+            &[0x0e, 0x1d, 15, 2][..],
+            &from_hex("3aa5ab37efce57f737fc1627013fee07bdf241bd10f3b1964ab55c78e79a304b"),
+        ]
+        .concat()
+    );
+
+    let mx = Rrset {
+        name: "example.com.".to_string(),
+        rr_type: 15,
+        class: CLASS_IN,
+        ttl: 3600,
+        rdata: vec![[&[0, 10][..], &name_to_wire("mail.example.com.").unwrap()].concat()],
+    };
+    let mut rrsig = Rrsig {
+        name: "example.com.".to_string(),
+        class: CLASS_IN,
+        ttl: 3600,
+        type_covered: 15,
+        algorithm: DnssecAlgorithm::Ed25519,
+        labels: 2,
+        original_ttl: 3600,
+        expiration: 1_440_021_600,
+        inception: 1_438_207_200,
+        key_tag: 3613,
+        signer_name: "example.com.".to_string(),
+        signature: Vec::new(),
+    };
+    let signed_data = rrsig.signed_data(&mx).unwrap();
+    rrsig.signature = signing_key.sign(&signed_data).to_bytes().to_vec();
+    assert_eq!(
+        rrsig.to_string(),
+        "example.com. 3600 IN RRSIG MX 15 2 3600 20150819220000 20150729220000 3613 \
+         example.com. oL9krJun7xfBOIWcGHi7mag5/hdZrKWw15jPGrHpjQeRAvTdszaPD+QLs3fx8A4M3e23mRZ9V\
+         rbpMngwcrqNAg=="
+    );
+
+    // Duplicate records are signed once, in canonical order.
+    let mut duplicated = mx.clone();
+    duplicated.rdata.push(duplicated.rdata[0].clone());
+    assert_eq!(rrsig.signed_data(&duplicated).unwrap(), signed_data);
+
+    // The P-256 example from RFC 6605 section 6.1.
+    let signing_key = ::p256::ecdsa::SigningKey::from_slice(
+        &STANDARD
+            .decode("GU6SnQ/Ou+xC5RumuIUIuJZteXT2z0O/ok1s38Et6mQ=")
+            .unwrap(),
+    )
+    .unwrap();
+    let point = signing_key.verifying_key().to_encoded_point(false);
+    let public_key = PublicKey::Ecc(
+        Algorithm::EcP256,
+        point.x().unwrap().to_vec(),
+        point.y().unwrap().to_vec(),
+    );
+    let dnskey = dnskey_rdata(&public_key, 257, DnssecAlgorithm::EcdsaP256Sha256).unwrap();
+    assert_eq!(
+        STANDARD.encode(&dnskey[4..]),
+        "GojIhhXUN/u4v54ZQqGSnyhWJwaubCvTmeexv7bR6edbkrSqQpF64cYbcB7wNcP+e+MAnLr+Wi9xMWyQLc8NAA=="
+    );
+    assert_eq!(key_tag(&dnskey), 55648);
+    assert_eq!(
+        ds_rdata("example.net.", &dnskey, DigestType::Sha256).unwrap()[4..].to_vec(),
+        from_hex("b4c8c1fe2e7477127b27115656ad6256f424625bf5c1e2770ce6d6e37df61d17")
+    );
+    assert!(dnskey_rdata(&public_key, 257, DnssecAlgorithm::Ed25519).is_err());
+}
+
+#[cfg(feature = "provision")]
+#[test]
+fn provision_plan() {
+    use provision::{self, Action, Manifest};
+
+    let manifest = Manifest::from_toml(
+        r#"
+        delete_unmanaged = true
+
+        [[options]]
+        force_audit = "enabled"
+
+        [[options]]
+        command_audit = [["SignEcdsa", "enabled"]]
+
+        [[objects]]
+        id = 2
+        type = "authkey"
+        algorithm = "yubico-aes-auth"
+        domains = [1, 5]
+        capabilities = ["put_authkey"]
+        source = "import"
+        password_env = "PROVISION_TEST_PASSWORD"
+
+        [[objects]]
+        id = 0x10
+        type = "asymmetric"
+        label = "signing"
+        algorithm = "ecp256"
+        domains = [1]
+        capabilities = ["asymmetric_sign_ecdsa"]
+        source = "generate"
+        "#,
+    )
+    .unwrap();
+
+    let drifted = auth_key_info(
+        &[Capability::PutAuthKey, Capability::GetOpaque],
+        &[],
+        &[Domain(1), Domain(5)],
+    );
+    let mut session_key = drifted.clone();
+    session_key.id = 1;
+    let mut stale = drifted.clone();
+    stale.id = 0x20;
+    stale.object_type = ObjectType::Asymmetric;
+    stale.algorithm = Some(Algorithm::EcP256);
+
+    let plan = provision::diff(
+        &manifest,
+        &[session_key, drifted, stale],
+        &[
+            DeviceOption::ForceAudit(DeviceOptionValue::Disabled),
+            DeviceOption::CommandAudit(vec![
+                (CommandType::SignEcdsa, DeviceOptionValue::Enabled),
+                (CommandType::SignEddsa, DeviceOptionValue::Disabled),
+            ]),
+        ],
+        1,
+    );
+
+    assert!(!plan.is_empty());
+    assert_eq!(plan.drifted().len(), 1);
+    assert_eq!(
+        plan.to_string(),
+        "set option: ForceAudit(Disabled) -> ForceAudit(Enabled)\n\
+         delete asymmetric 0x0020 \"\"\n\
+         drifted authkey 0x0002 \"\": capabilities: unexpected get_opaque\n\
+         create asymmetric 0x0010 \"signing\" (ecp256 generate)\n\
+         unmanaged authkey 0x0001 \"\"\n\
+         1 to create, 1 to delete, 1 option(s) to set, 1 drifted, 0 unchanged, 1 unmanaged"
+    );
+    match plan.actions[2] {
+        Action::Drifted { ref spec, .. } => assert_eq!(spec.id, 2),
+        ref action => panic!("unexpected action: {}", action),
+    }
+
+    let converged = provision::diff(&Manifest::from_toml("").unwrap(), &[], &[], 1);
+    assert!(converged.is_empty());
+
+    let object = |extra: &str| {
+        format!(
+            "[[objects]]\nid = 3\ntype = \"authkey\"\nalgorithm = \"yubico-aes-auth\"\n\
+             domains = [1]\n{}",
+            extra
+        )
+    };
+    assert!(Manifest::from_toml(&object("source = \"generate\"")).is_err());
+    assert!(Manifest::from_toml(&object("source = \"import\"")).is_err());
+    assert!(Manifest::from_toml(&object("source = \"import\"\ncolour = \"blue\"")).is_err());
+    let duplicated = object("source = \"import\"\npassword_env = \"X\"\n");
+    assert!(Manifest::from_toml(&duplicated).is_ok());
+    assert!(Manifest::from_toml(&format!("{}{}", duplicated, duplicated)).is_err());
+
+    // A wrapped file holding some other object is caught after importing it.
+    let wrapped = Manifest::from_toml(&object(
+        "source = \"import-wrapped\"\nwrapped_file = \"ops.wrap\"\nwrapkey_id = 0x20\n",
+    ))
+    .unwrap();
+    let spec = &wrapped.objects[0];
+    assert!(provision::check_unwrapped(spec, ObjectType::AuthKey, 3).is_ok());
+    assert_eq!(
+        provision::check_unwrapped(spec, ObjectType::AuthKey, 4)
+            .unwrap_err()
+            .to_string(),
+        "ops.wrap contained authkey 0x0004, not authkey 0x0003"
+    );
+    assert!(provision::check_unwrapped(spec, ObjectType::Asymmetric, 3).is_err());
+}
+
+#[test]
+fn inventory_diff() {
+    use inventory::{Change, Inventory, InventoryObject};
+
+    let auth_key = auth_key_info(&[Capability::PutAuthKey], &[], &[Domain(1)]);
+    let mut signing_key = auth_key.clone();
+    signing_key.id = 0x10;
+    signing_key.object_type = ObjectType::Asymmetric;
+    signing_key.algorithm = Some(Algorithm::EcEd25519);
+    let mut wrap_key = auth_key.clone();
+    wrap_key.id = 0x20;
+    wrap_key.object_type = ObjectType::WrapKey;
+
+    let object = |info: &ObjectInfo| InventoryObject {
+        info: info.clone(),
+        public_key: match info.object_type {
+            ObjectType::Asymmetric => {
+                Some(PublicKey::Edc(Algorithm::EcEd25519, from_hex(ED25519_A)))
+            }
+            _ => None,
+        },
+    };
+    let before = Inventory {
+        device_info: DeviceInfo {
+            major_version: 2,
+            minor_version: 0,
+            patch_version: 0,
+            serial: 1234,
+            log_capacity: 62,
+            log_used: 3,
+            algorithms: vec![],
+        },
+        storage: StorageStats::default(),
+        options: vec![DeviceOption::ForceAudit(DeviceOptionValue::Enabled)],
+        objects: vec![object(&auth_key), object(&signing_key), object(&wrap_key)],
+    };
+    assert!(before.diff(&before).is_empty());
+
+    let mut after = before.clone();
+    after.device_info.log_used = 10;
+    after.storage.free_records = 7;
+    assert!(before.diff(&after).is_empty());
+
+    after.device_info.minor_version = 1;
+    after.options = vec![DeviceOption::ForceAudit(DeviceOptionValue::Disabled)];
+    after.objects.retain(|object| object.info.id != 0x20);
+    after.objects[0]
+        .info
+        .capabilities
+        .push(Capability::ExportWrapped);
+    after.objects[0].info.domains = vec![Domain(2)];
+    after.objects[1].info.sequence = 1;
+    after.objects[1].public_key = Some(PublicKey::Edc(Algorithm::EcEd25519, vec![0; 32]));
+    let mut opaque = auth_key.clone();
+    opaque.id = 0x30;
+    opaque.object_type = ObjectType::Opaque;
+    opaque.label = "cert".to_string();
+    after.objects.push(object(&opaque));
+
+    let diff = before.diff(&after);
+    assert_eq!(diff.added[0].id, 0x30);
+    assert_eq!(diff.removed[0].id, 0x20);
+    assert_eq!(
+        diff.changed[0].changes,
+        vec![
+            Change::Domains {
+                added: vec![Domain(2)],
+                removed: vec![Domain(1)],
+            },
+            Change::Capabilities {
+                added: vec![Capability::ExportWrapped],
+                removed: vec![],
+            },
+        ]
+    );
+    assert_eq!(
+        diff.to_string(),
+        "device: firmware 2.0.0 -> 2.1.0\n\
+         option: ForceAudit(Enabled) -> ForceAudit(Disabled)\n\
+         + opaque 0x0030 \"cert\"\n\
+         - wrapkey 0x0020 \"\"\n\
+         ~ authkey 0x0002 \"\": domains +2 -1; capabilities +export_wrapped\n\
+         ~ asymmetric 0x0010 \"\": replaced (sequence 0 -> 1); public key changed"
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn inventory_serde_round_trip() {
+    use inventory::{Change, Inventory, InventoryObject};
+    use serde_json;
+
+    let mut info = auth_key_info(&[Capability::PutAuthKey], &[], &[Domain(1)]);
+    info.unknown_capabilities = vec!["change_authentication_key".to_string()];
+    info.unknown_delegated_capabilities = vec!["sign_attestation_certificate".to_string()];
+    let before = Inventory {
+        device_info: DeviceInfo {
+            major_version: 2,
+            minor_version: 0,
+            patch_version: 0,
+            serial: 1234,
+            log_capacity: 62,
+            log_used: 3,
+            algorithms: vec![],
+        },
+        storage: StorageStats::default(),
+        options: vec![],
+        objects: vec![InventoryObject {
+            info,
+            public_key: None,
+        }],
+    };
+
+    let json = serde_json::to_string(&before).unwrap();
+    let mut after: Inventory = serde_json::from_str(&json).unwrap();
+    assert_eq!(
+        after.objects[0].info.unknown_capabilities,
+        vec!["change_authentication_key".to_string()]
+    );
+    assert!(before.diff(&after).is_empty());
+
+    after.objects[0].info.unknown_capabilities = vec!["export_wrapped_v2".to_string()];
+    let diff = before.diff(&after);
+    assert_eq!(
+        diff.changed[0].changes,
+        vec![Change::UnknownCapabilities {
+            added: vec!["export_wrapped_v2".to_string()],
+            removed: vec!["change_authentication_key".to_string()],
+        }]
+    );
+    assert_eq!(
+        diff.to_string(),
+        "~ authkey 0x0002 \"\": unknown capabilities +export_wrapped_v2 \
+         -change_authentication_key"
+    );
+}
+
+#[test]
+fn policy_lint() {
+    use policy::{lint, LintConfig, Rule, Severity};
+
+    let all_domains: Vec<Domain> = (1..17).map(Domain).collect();
+    let mut factory = auth_key_info(
+        &[Capability::Reset, Capability::PutAuthKey],
+        &[Capability::Reset],
+        &all_domains,
+    );
+    factory.id = 1;
+
+    let mut admin = auth_key_info(
+        &[Capability::PutAuthKey, Capability::AsymmetricGen],
+        &[Capability::AsymmetricSignEcdsa, Capability::ExportWrapped],
+        &[Domain(1), Domain(2)],
+    );
+    admin.id = 2;
+
+    let mut signer = auth_key_info(
+        &[Capability::AsymmetricSignEcdsa],
+        &[Capability::AsymmetricSignEcdsa],
+        &[Domain(1)],
+    );
+    signer.id = 3;
+
+    let mut exportable = auth_key_info(
+        &[Capability::AsymmetricSignEcdsa, Capability::ExportUnderWrap],
+        &[],
+        &[Domain(1)],
+    );
+    exportable.id = 0x10;
+    exportable.object_type = ObjectType::Asymmetric;
+    exportable.algorithm = Some(Algorithm::EcP256);
+    let mut unexportable = exportable.clone();
+    unexportable.id = 0x11;
+    unexportable.capabilities = vec![Capability::AsymmetricSignEcdsa];
+
+    let config = LintConfig {
+        admin_keys: vec![2],
+        // An opaque object with the same ID doesn't cover asymmetric key 0x10.
+        backup: vec![(ObjectType::Asymmetric, 0x11), (ObjectType::Opaque, 0x10)],
+        ..LintConfig::default()
+    };
+    let findings = lint(&[factory, admin, signer, exportable, unexportable], &config);
+    let summary: Vec<(Rule, Severity, u16)> = findings
+        .iter()
+        .map(|f| (f.rule, f.severity, f.object_id))
+        .collect();
+
+    assert_eq!(
+        summary,
+        vec![
+            (Rule::DefaultAuthKey, Severity::Warning, 1),
+            (Rule::DangerousCapability, Severity::Warning, 1),
+            (Rule::DangerousCapability, Severity::Warning, 1),
+            (Rule::UnneededDelegation, Severity::Info, 1),
+            (Rule::TooManyDomains, Severity::Warning, 1),
+            (Rule::DangerousCapability, Severity::Info, 2),
+            (Rule::DangerousCapability, Severity::Info, 2),
+            (Rule::UnneededDelegation, Severity::Info, 2),
+            (Rule::TooManyDomains, Severity::Info, 2),
+            (Rule::UnneededDelegation, Severity::Warning, 3),
+            (Rule::ExportableNotBackedUp, Severity::Warning, 0x10),
+            (Rule::BackupNotExportable, Severity::Warning, 0x11),
+        ]
+    );
+    assert_eq!(
+        findings[1].to_string(),
+        "warning\tdangerous-capability\tauthkey 0x0001\tholds reset,put_authkey"
+    );
+    assert_eq!(
+        findings[7].message,
+        "delegates export_wrapped, which no object in its domains holds"
+    );
+    assert_eq!(
+        findings[9].message,
+        "delegates asymmetric_sign_ecdsa but can't create objects"
+    );
+}
+
+#[test]
+fn bootstrap_summary() {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use bootstrap::{
+        BootstrapConfig, BootstrapSummary, Outcome, Step, REQUIRED_CAPABILITIES,
+        REQUIRED_DELEGATED_CAPABILITIES,
+    };
+    use ed25519_dalek::{Signer, SigningKey};
+
+    let mut config = BootstrapConfig {
+        admin_key_id: 2,
+        admin_label: "admin".to_string(),
+        admin_password: "correct horse battery staple".to_string(),
+        domains: vec![Domain(1)],
+        capabilities: REQUIRED_CAPABILITIES.to_vec(),
+        delegated_capabilities: vec![],
+        summary_key_id: 3,
+    };
+    assert_eq!(
+        config.validate().unwrap_err().to_string(),
+        "bootstrap: admin AuthKey can't delegate capabilities asymmetric_sign_eddsa"
+    );
+    config.delegated_capabilities = REQUIRED_DELEGATED_CAPABILITIES.to_vec();
+    assert!(config.validate().is_ok());
+    config.admin_password = "password".to_string();
+    assert!(config.validate().is_err());
+    config.admin_password = "correct horse battery staple".to_string();
+    config.admin_key_id = 1;
+    assert!(config.validate().is_err());
+    config.admin_key_id = 2;
+    config.capabilities.retain(|&c| c != Capability::PutOption);
+    let err = config.validate().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "bootstrap: admin AuthKey is missing capabilities put_option"
+    );
+    config.capabilities = REQUIRED_CAPABILITIES.to_vec();
+    config.capabilities.retain(|&c| c != Capability::GetOption);
+    assert_eq!(
+        config.validate().unwrap_err().to_string(),
+        "bootstrap: admin AuthKey is missing capabilities get_option"
+    );
+
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let mut summary = BootstrapSummary {
+        serial: 12345678,
+        admin_key_id: 2,
+        summary_key_id: 3,
+        steps: vec![
+            (Step::CreateAdminKey, Outcome::AlreadyDone),
+            (Step::VerifyAdminKey, Outcome::Done),
+            (Step::CreateSummaryKey, Outcome::Done),
+            (Step::DeleteDefaultKey, Outcome::Done),
+            (Step::EnableForceAudit, Outcome::Done),
+        ],
+        public_key: PublicKey::Edc(
+            Algorithm::EcEd25519,
+            key.verifying_key().to_bytes().to_vec(),
+        ),
+        signature: vec![],
+    };
+    assert_eq!(
+        String::from_utf8(summary.message()).unwrap(),
+        "yubihsm-bootstrap-v1\nserial 12345678\nadmin-key 0x0002\nsummary-key 0x0003\n\
+         create-admin-key already-done\nverify-admin-key done\ncreate-summary-key done\n\
+         delete-default-key done\nenable-force-audit done\n"
+    );
+    summary.signature = key.sign(&summary.message()).to_bytes().to_vec();
+    assert!(summary.verify().is_ok());
+    assert!(summary.to_string().ends_with(&format!(
+        "enable-force-audit done\nsignature {}",
+        STANDARD.encode(&summary.signature)
+    )));
+
+    summary.steps[3].1 = Outcome::AlreadyDone;
+    assert!(summary.verify().is_err());
+}
+
+#[test]
+fn session_pool_idle_sessions() {
+    use connector::Connector;
+    use pool::SessionPool;
+    use session::Session;
+    use std::ptr;
+
+    let connector = Connector::disconnected();
+    let pool = SessionPool::empty(&connector, 1, "password", 2);
+    // Sessions beyond `max_idle` are closed rather than kept.
+    for _ in 0..3 {
+        pool.put(Session::new(ptr::null_mut(), 1, connector.clone()));
+    }
+
+    let first = pool.get().unwrap();
+    let second = pool.get().unwrap();
+    // With no idle sessions left, the pool tries (and fails) to open one.
+    assert!(pool.get().is_err());
+
+    // A discarded session isn't returned to the pool, but a dropped one is.
+    first.discard();
+    drop(second);
+    let _session = pool.get().unwrap();
+    assert!(pool.get().is_err());
+}
+
+#[cfg(feature = "rustls")]
+#[test]
+fn tls_signing_key_schemes() {
+    use connector::Connector;
+    use pool::SessionPool;
+    use rustls::sign::SigningKey;
+    use rustls::{SignatureAlgorithm, SignatureScheme as TlsSignatureScheme};
+    use std::sync::Arc;
+    use tls::TlsSigningKey;
+
+    let pool = Arc::new(SessionPool::empty(
+        &Connector::disconnected(),
+        1,
+        "password",
+        0,
+    ));
+    let key = |public_key| TlsSigningKey::from_public_key(pool.clone(), 1, public_key);
+    let chosen = |key: &TlsSigningKey, offered: &[TlsSignatureScheme]| {
+        key.choose_scheme(offered).map(|signer| signer.scheme())
+    };
+
+    let p256 = key(PublicKey::Ecc(
+        Algorithm::EcP256,
+        from_hex(P256_X),
+        from_hex(P256_Y),
+    ))
+    .unwrap();
+    assert_eq!(p256.algorithm(), SignatureAlgorithm::ECDSA);
+    assert_eq!(
+        chosen(
+            &p256,
+            &[
+                TlsSignatureScheme::ECDSA_NISTP384_SHA384,
+                TlsSignatureScheme::ECDSA_NISTP256_SHA256,
+            ]
+        ),
+        Some(TlsSignatureScheme::ECDSA_NISTP256_SHA256)
+    );
+    assert_eq!(
+        chosen(&p256, &[TlsSignatureScheme::ECDSA_NISTP384_SHA384]),
+        None
+    );
+    assert_eq!(
+        &p256.public_key().unwrap()[..],
+        &PublicKey::Ecc(Algorithm::EcP256, from_hex(P256_X), from_hex(P256_Y))
+            .to_spki_der()
+            .unwrap()[..]
+    );
+
+    let ed25519 = key(PublicKey::Edc(Algorithm::EcEd25519, from_hex(ED25519_A))).unwrap();
+    assert_eq!(ed25519.algorithm(), SignatureAlgorithm::ED25519);
+    assert_eq!(
+        chosen(&ed25519, &[TlsSignatureScheme::ED25519]),
+        Some(TlsSignatureScheme::ED25519)
+    );
+
+    // RSA keys prefer the strongest PSS scheme offered, and never use PKCS#1 v1.5.
+    let rsa = key(PublicKey::Rsa(Algorithm::Rsa2048, from_hex(RSA_N))).unwrap();
+    assert_eq!(rsa.algorithm(), SignatureAlgorithm::RSA);
+    assert_eq!(
+        chosen(
+            &rsa,
+            &[
+                TlsSignatureScheme::RSA_PSS_SHA256,
+                TlsSignatureScheme::RSA_PSS_SHA384,
+                TlsSignatureScheme::RSA_PKCS1_SHA512,
+            ]
+        ),
+        Some(TlsSignatureScheme::RSA_PSS_SHA384)
+    );
+    assert_eq!(chosen(&rsa, &[TlsSignatureScheme::RSA_PKCS1_SHA256]), None);
+
+    // secp256k1 isn't used in TLS.
+    let one = from_hex("0000000000000000000000000000000000000000000000000000000000000001");
+    assert!(key(PublicKey::Ecc(Algorithm::EcK256, one.clone(), one)).is_err());
+}
@@ -0,0 +1,198 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A rustls `SigningKey` which keeps the TLS private key on the device.
+
+use pool::SessionPool;
+use types::*;
+
+use failure::Error;
+use rustls;
+use rustls::pki_types::{CertificateDer, SubjectPublicKeyInfoDer};
+use rustls::sign::{CertifiedKey, Signer, SigningKey};
+use rustls::{SignatureAlgorithm, SignatureScheme as TlsSignatureScheme};
+
+use std::sync::Arc;
+
+/// The TLS signature schemes usable with each kind of key, in order of preference, paired with
+/// the device signature scheme which implements them.
+const P256_SCHEMES: &[(TlsSignatureScheme, SignatureScheme)] = &[(
+    TlsSignatureScheme::ECDSA_NISTP256_SHA256,
+    SignatureScheme::EcdsaSha256,
+)];
+const P384_SCHEMES: &[(TlsSignatureScheme, SignatureScheme)] = &[(
+    TlsSignatureScheme::ECDSA_NISTP384_SHA384,
+    SignatureScheme::EcdsaSha384,
+)];
+const ED25519_SCHEMES: &[(TlsSignatureScheme, SignatureScheme)] =
+    &[(TlsSignatureScheme::ED25519, SignatureScheme::Ed25519)];
+const RSA_SCHEMES: &[(TlsSignatureScheme, SignatureScheme)] = &[
+    (TlsSignatureScheme::RSA_PSS_SHA512, SignatureScheme::RsaPssSha512),
+    (TlsSignatureScheme::RSA_PSS_SHA384, SignatureScheme::RsaPssSha384),
+    (TlsSignatureScheme::RSA_PSS_SHA256, SignatureScheme::RsaPssSha256),
+];
+
+/// A rustls `SigningKey` for an asymmetric key on the device.
+///
+/// Supported keys are ECDSA on P-256 and P-384, Ed25519, and RSA, which is only offered with
+/// RSA-PSS (as required by TLS 1.3). Each signature takes a session from the pool for the duration
+/// of the signing operation, so concurrent handshakes don't wait on each other.
+#[derive(Debug)]
+pub struct TlsSigningKey {
+    pool: Arc<SessionPool>,
+    key_id: u16,
+    public_key: PublicKey,
+    spki: Vec<u8>,
+}
+
+impl TlsSigningKey {
+    /// Create a signing key for the asymmetric key `key_id`, checking that it is of a supported
+    /// type.
+    pub fn new(pool: Arc<SessionPool>, key_id: u16) -> Result<TlsSigningKey, Error> {
+        let public_key = pool.get()?.get_pubkey(key_id)?;
+        TlsSigningKey::from_public_key(pool, key_id, public_key)
+    }
+
+    /// Create a signing key for `key_id`, whose public key has already been read from the device.
+    pub(crate) fn from_public_key(
+        pool: Arc<SessionPool>,
+        key_id: u16,
+        public_key: PublicKey,
+    ) -> Result<TlsSigningKey, Error> {
+        match public_key.algorithm() {
+            Algorithm::EcP256
+            | Algorithm::EcP384
+            | Algorithm::EcEd25519
+            | Algorithm::Rsa2048
+            | Algorithm::Rsa3072
+            | Algorithm::Rsa4096 => (),
+            a => bail!("TLS signing with {} keys is not supported", a),
+        }
+
+        let spki = public_key.to_spki_der()?;
+
+        Ok(TlsSigningKey {
+            pool,
+            key_id,
+            public_key,
+            spki,
+        })
+    }
+
+    /// The ID of the key on the device.
+    pub fn key_id(&self) -> u16 {
+        self.key_id
+    }
+
+    /// Pair this key with the DER X.509 certificate stored in the opaque object `cert_id`, which
+    /// must have been stored with `Algorithm::OpaqueX509Cert`.
+    ///
+    /// The certificate's public key is checked against this key. Intermediate certificates can be
+    /// appended to the returned `CertifiedKey`'s `cert` chain.
+    pub fn certified_key(self, cert_id: u16) -> Result<CertifiedKey, Error> {
+        let cert = {
+            let session = self.pool.get()?;
+
+            let info = session.get_object_info(cert_id, ObjectType::Opaque)?;
+            if info.algorithm != Some(Algorithm::OpaqueX509Cert) {
+                bail!("opaque object {} is not an X.509 certificate", cert_id);
+            }
+
+            session.get_opaque_object(cert_id)?
+        };
+
+        let certified_key = CertifiedKey::new(vec![CertificateDer::from(cert)], Arc::new(self));
+        certified_key
+            .keys_match()
+            .map_err(|e| format_err!("certificate doesn't match key: {}", e))?;
+
+        Ok(certified_key)
+    }
+
+    fn schemes(&self) -> &'static [(TlsSignatureScheme, SignatureScheme)] {
+        match self.public_key.algorithm() {
+            Algorithm::EcP256 => P256_SCHEMES,
+            Algorithm::EcP384 => P384_SCHEMES,
+            Algorithm::EcEd25519 => ED25519_SCHEMES,
+            // Checked in `new` that this is an RSA key.
+            _ => RSA_SCHEMES,
+        }
+    }
+}
+
+impl SigningKey for TlsSigningKey {
+    fn choose_scheme(&self, offered: &[TlsSignatureScheme]) -> Option<Box<dyn Signer>> {
+        self.schemes()
+            .iter()
+            .find(|&&(tls_scheme, _)| offered.contains(&tls_scheme))
+            .map(|&(tls_scheme, scheme)| {
+                Box::new(TlsSigner {
+                    pool: self.pool.clone(),
+                    key_id: self.key_id,
+                    key_algorithm: self.public_key.algorithm(),
+                    tls_scheme,
+                    scheme,
+                }) as Box<dyn Signer>
+            })
+    }
+
+    fn public_key(&self) -> Option<SubjectPublicKeyInfoDer<'_>> {
+        Some(SubjectPublicKeyInfoDer::from(&self.spki[..]))
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        match self.public_key {
+            PublicKey::Rsa(_, _) => SignatureAlgorithm::RSA,
+            PublicKey::Ecc(_, _, _) => SignatureAlgorithm::ECDSA,
+            PublicKey::Edc(_, _) => SignatureAlgorithm::ED25519,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TlsSigner {
+    pool: Arc<SessionPool>,
+    key_id: u16,
+    key_algorithm: Algorithm,
+    tls_scheme: TlsSignatureScheme,
+    scheme: SignatureScheme,
+}
+
+impl Signer for TlsSigner {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, rustls::Error> {
+        let session = self.pool
+            .get()
+            .map_err(|e| rustls::Error::General(format!("no HSM session available: {}", e)))?;
+
+        let result = session.sign_message_with_key_algorithm(
+            self.key_id,
+            self.key_algorithm,
+            self.scheme,
+            message,
+        );
+
+        match result {
+            Ok(signature) => Ok(signature),
+            Err(e) => {
+                // The session may have expired or the connection dropped; don't reuse it.
+                session.discard();
+                Err(rustls::Error::General(format!("HSM signing failed: {}", e)))
+            }
+        }
+    }
+
+    fn scheme(&self) -> TlsSignatureScheme {
+        self.tls_scheme
+    }
+}
@@ -15,10 +15,14 @@
 use failure::Error;
 use yubihsm_sys::*;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 use std::ffi::{CStr, CString};
 use std::fmt::{Display, Formatter};
 use std::os::raw::c_char;
 use std::ptr;
+use std::str::FromStr;
 
 /// Wrapper struct for "encoded" Domains. This is the type expected by libyubihsm functions.
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -182,9 +186,10 @@ impl From<ReturnCode> for yh_rc {
     }
 }
 
-impl Display for ReturnCode {
-    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
-        let error_name = match *self {
+impl ReturnCode {
+    /// The name of this return code, as used in its `Display` output.
+    pub(crate) fn name(&self) -> &'static str {
+        match *self {
             ReturnCode::Success => "Success",
             ReturnCode::Memory => "Memory",
             ReturnCode::InitError => "InitError",
@@ -215,11 +220,15 @@ impl Display for ReturnCode {
             ReturnCode::GenericError => "GenericError",
             ReturnCode::DeviceObjectExists => "DeviceObjectExists",
             ReturnCode::ConnectorError => "ConnectorError",
-        };
+        }
+    }
+}
 
+impl Display for ReturnCode {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
         unsafe {
             let error = CStr::from_ptr(yh_strerror(yh_rc::from(*self)));
-            write!(f, "{} (ReturnCode::{})", error.to_string_lossy(), error_name)
+            write!(f, "{} (ReturnCode::{})", error.to_string_lossy(), self.name())
         }
     }
 }
@@ -269,7 +278,51 @@ impl From<ObjectType> for yh_object_type {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+// libyubihsm's type table has no entry for public keys, so `yh_type_to_string` and
+// `yh_string_to_type` can't handle them.
+const PUBLIC_TYPE_NAME: &str = "public";
+
+impl Display for ObjectType {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        if *self == ObjectType::Public {
+            return write!(f, "{}", PUBLIC_TYPE_NAME);
+        }
+
+        let mut string_ptr: *const c_char = ptr::null();
+
+        unsafe {
+            match ReturnCode::from(yh_type_to_string((*self).into(), &mut string_ptr)) {
+                ReturnCode::Success => {
+                    let object_type = CStr::from_ptr(string_ptr);
+                    write!(f, "{}", object_type.to_string_lossy())
+                }
+                _ => Err(::std::fmt::Error),
+            }
+        }
+    }
+}
+
+impl FromStr for ObjectType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ObjectType, Error> {
+        if s == PUBLIC_TYPE_NAME {
+            return Ok(ObjectType::Public);
+        }
+
+        let c_str = CString::new(s)?;
+        let mut object_type: yh_object_type = 0;
+
+        unsafe {
+            match ReturnCode::from(yh_string_to_type(c_str.as_ptr(), &mut object_type)) {
+                ReturnCode::Success => Ok(ObjectType::from(object_type)),
+                _ => bail!("unknown object type: {}", s),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Algorithm {
     RsaPkcs1Sha1,
     RsaPkcs1Sha256,
@@ -447,6 +500,108 @@ impl Display for Algorithm {
     }
 }
 
+impl FromStr for Algorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Algorithm, Error> {
+        let c_str = CString::new(s)?;
+        let mut algorithm: yh_algorithm = 0;
+
+        unsafe {
+            match ReturnCode::from(yh_string_to_algo(c_str.as_ptr(), &mut algorithm)) {
+                ReturnCode::Success => Ok(Algorithm::from(algorithm)),
+                _ => bail!("unknown algorithm: {}", s),
+            }
+        }
+    }
+}
+
+/// A complete signature scheme, for use with `Session::sign_message`.
+///
+/// Each scheme names both the signature algorithm and the digest applied to the message. RSA-PSS
+/// schemes use MGF1 with the same digest, and a salt as long as the digest output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum SignatureScheme {
+    RsaPkcs1v15Sha1,
+    RsaPkcs1v15Sha256,
+    RsaPkcs1v15Sha384,
+    RsaPkcs1v15Sha512,
+    RsaPssSha1,
+    RsaPssSha256,
+    RsaPssSha384,
+    RsaPssSha512,
+    EcdsaSha1,
+    EcdsaSha256,
+    EcdsaSha384,
+    EcdsaSha512,
+    Ed25519,
+}
+
+impl SignatureScheme {
+    /// The device signature algorithm for this scheme, e.g. `Algorithm::RsaPssSha256`. This is
+    /// also the algorithm to pass to `PublicKey::verify`.
+    pub fn algorithm(&self) -> Algorithm {
+        match *self {
+            SignatureScheme::RsaPkcs1v15Sha1 => Algorithm::RsaPkcs1Sha1,
+            SignatureScheme::RsaPkcs1v15Sha256 => Algorithm::RsaPkcs1Sha256,
+            SignatureScheme::RsaPkcs1v15Sha384 => Algorithm::RsaPkcs1Sha384,
+            SignatureScheme::RsaPkcs1v15Sha512 => Algorithm::RsaPkcs1Sha512,
+            SignatureScheme::RsaPssSha1 => Algorithm::RsaPssSha1,
+            SignatureScheme::RsaPssSha256 => Algorithm::RsaPssSha256,
+            SignatureScheme::RsaPssSha384 => Algorithm::RsaPssSha384,
+            SignatureScheme::RsaPssSha512 => Algorithm::RsaPssSha512,
+            SignatureScheme::EcdsaSha1 => Algorithm::EcEcdsaSha1,
+            SignatureScheme::EcdsaSha256 => Algorithm::EcEcdsaSha256,
+            SignatureScheme::EcdsaSha384 => Algorithm::EcEcdsaSha384,
+            SignatureScheme::EcdsaSha512 => Algorithm::EcEcdsaSha512,
+            SignatureScheme::Ed25519 => Algorithm::EcEd25519,
+        }
+    }
+
+    /// A sensible default scheme for a key of algorithm `key_algorithm`: RSASSA-PKCS1-v1_5 with
+    /// SHA-256 for RSA keys, ECDSA with a digest matching the curve size for ECC keys, and Ed25519
+    /// for EDC keys.
+    pub fn for_key(key_algorithm: Algorithm) -> Option<SignatureScheme> {
+        match key_algorithm {
+            Algorithm::Rsa2048 | Algorithm::Rsa3072 | Algorithm::Rsa4096 => {
+                Some(SignatureScheme::RsaPkcs1v15Sha256)
+            }
+            Algorithm::EcP224 | Algorithm::EcP256 | Algorithm::EcK256 | Algorithm::EcBp256 => {
+                Some(SignatureScheme::EcdsaSha256)
+            }
+            Algorithm::EcP384 | Algorithm::EcBp384 => Some(SignatureScheme::EcdsaSha384),
+            Algorithm::EcP521 | Algorithm::EcBp512 => Some(SignatureScheme::EcdsaSha512),
+            Algorithm::EcEd25519 => Some(SignatureScheme::Ed25519),
+            _ => None,
+        }
+    }
+
+    /// Whether this scheme can be used with a key of algorithm `key_algorithm`, e.g.
+    /// `Algorithm::Rsa2048` or `Algorithm::EcP256`.
+    pub fn supports_key(&self, key_algorithm: Algorithm) -> bool {
+        match *self {
+            SignatureScheme::RsaPkcs1v15Sha1
+            | SignatureScheme::RsaPkcs1v15Sha256
+            | SignatureScheme::RsaPkcs1v15Sha384
+            | SignatureScheme::RsaPkcs1v15Sha512
+            | SignatureScheme::RsaPssSha1
+            | SignatureScheme::RsaPssSha256
+            | SignatureScheme::RsaPssSha384
+            | SignatureScheme::RsaPssSha512 => matches!(
+                key_algorithm,
+                Algorithm::Rsa2048 | Algorithm::Rsa3072 | Algorithm::Rsa4096
+            ),
+            SignatureScheme::EcdsaSha1
+            | SignatureScheme::EcdsaSha256
+            | SignatureScheme::EcdsaSha384
+            | SignatureScheme::EcdsaSha512 => key_algorithm.ec_field_len().is_some(),
+            SignatureScheme::Ed25519 => key_algorithm == Algorithm::EcEd25519,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Capability {
     GetOpaque,
@@ -558,6 +713,28 @@ impl<'a> From<&'a Capability> for String {
     }
 }
 
+impl Display for Capability {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        write!(f, "{}", String::from(self))
+    }
+}
+
+impl FromStr for Capability {
+    type Err = Error;
+
+    /// Parse a capability from its libyubihsm name. Unlike `Capability::from`, names which aren't
+    /// known capabilities, including `"unknown"` itself, are an error rather than
+    /// `Capability::Unknown`.
+    fn from_str(s: &str) -> Result<Capability, Error> {
+        match Capability::from(s) {
+            Capability::Unknown => bail!("unknown capability: {}", s),
+            cap => Ok(cap),
+        }
+    }
+}
+
+/// Convert a libyubihsm capability name to a `Capability`, mapping unrecognized names to
+/// `Capability::Unknown`. Use `str::parse` to reject unrecognized names instead.
 impl<T> From<T> for Capability
 where
     T: AsRef<str>,
@@ -662,13 +839,14 @@ where
 }
 
 impl Capability {
-    /// Convert a library-created `yh_capabilities` blob to a Vec<Capability>. The
-    /// `yh_capabilities` layout is opaque and the only other library-provided way of representing
-    /// capabilities is by moving strings around, so we want to avoid that as much as possible.
+    /// Convert a library-created `yh_capabilities` blob to a Vec<Capability>, along with the
+    /// libyubihsm names of any capabilities this crate doesn't know. The `yh_capabilities` layout
+    /// is opaque and the only other library-provided way of representing capabilities is by moving
+    /// strings around, so we want to avoid that as much as possible.
     //TODO(csssuf): move this to std::convert::TryFrom when rustc 1.26.0 is released
     pub(crate) fn try_from_yh_capabilities(
         caps: &yh_capabilities,
-    ) -> Result<Vec<Capability>, Error> {
+    ) -> Result<(Vec<Capability>, Vec<String>), Error> {
         // As there are currently fewer than 64 capabilities, this _should_ be sufficient.
         // Unfortunately the published docs for libyubihsm make no mention of how any of this
         // memory is managed, nor are there constants for maximum sizes, so we have to resort to
@@ -688,15 +866,22 @@ impl Capability {
             }
         }
 
-        lib_cap_strs[..n_cap_strs]
-            .into_iter()
-            .map(|x| unsafe { CStr::from_ptr(*x).to_str() }.map(Capability::from))
-            .collect::<Result<Vec<_>, ::std::str::Utf8Error>>()
-            .map_err(|e| e.into())
+        let mut capabilities = Vec::new();
+        let mut unknown = Vec::new();
+        for x in &lib_cap_strs[..n_cap_strs] {
+            let name = unsafe { CStr::from_ptr(*x) }.to_str()?;
+            match Capability::from(name) {
+                Capability::Unknown => unknown.push(name.to_string()),
+                capability => capabilities.push(capability),
+            }
+        }
+
+        Ok((capabilities, unknown))
     }
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct DeviceInfo {
     pub major_version: u8,
     pub minor_version: u8,
@@ -707,20 +892,47 @@ pub struct DeviceInfo {
     pub algorithms: Vec<Algorithm>,
 }
 
+/// The device's object storage usage, as reported by Get Storage Info.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StorageStats {
+    pub total_records: u16,
+    pub free_records: u16,
+    pub total_pages: u16,
+    pub free_pages: u16,
+    pub page_size: u16,
+}
+
 /// The public component of an asymmetric key stored on the device.
 ///
-/// The contents of each variant correspond to the component(s) necessary to represent a public key
-/// using that algorithm. For RSA, the contents are the public modulus `n`. For ECC, the first
-/// component is the public point `x`, and the second component is the public point `y`. For EDC,
-/// the contents are the public point `a` (compressed, per the Yubico documentation).
+/// The first component of each variant is the key's algorithm, which determines the key size or
+/// curve. The remaining contents correspond to the component(s) necessary to represent a public key
+/// using that algorithm. For RSA, the contents are the public modulus `n` (the public exponent is
+/// always 65537). For ECC, the second component is the public point `x`, and the third component is
+/// the public point `y`. For EDC, the contents are the public point `a` (compressed, per the Yubico
+/// documentation).
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum PublicKey {
-    Rsa(Vec<u8>),
-    Ecc(Vec<u8>, Vec<u8>),
-    Edc(Vec<u8>),
+    Rsa(Algorithm, Vec<u8>),
+    Ecc(Algorithm, Vec<u8>, Vec<u8>),
+    Edc(Algorithm, Vec<u8>),
+}
+
+impl PublicKey {
+    /// The algorithm of the key this public key belongs to, e.g. `Algorithm::Rsa2048` or
+    /// `Algorithm::EcP256`.
+    pub fn algorithm(&self) -> Algorithm {
+        match *self {
+            PublicKey::Rsa(algorithm, _)
+            | PublicKey::Ecc(algorithm, _, _)
+            | PublicKey::Edc(algorithm, _) => algorithm,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Command {
     Request(CommandType),
     Response(CommandType),
@@ -728,6 +940,7 @@ pub enum Command {
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum CommandType {
     Echo,
     CreateSession,
@@ -974,6 +1187,7 @@ impl From<Command> for u8 {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Log {
     pub unlogged_boots: u16,
     pub unlogged_auths: u16,
@@ -987,6 +1201,7 @@ impl Log {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct LogEntry {
     pub index: u16,
     pub command: Command,
@@ -1047,6 +1262,7 @@ impl From<LogEntry> for yh_log_entry {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ObjectInfo {
     pub capabilities: Vec<Capability>,
     pub id: u16,
@@ -1058,6 +1274,13 @@ pub struct ObjectInfo {
     pub origin: u8,
     pub label: String,
     pub delegated_capabilities: Vec<Capability>,
+    /// Capabilities this crate has no `Capability` for, by their libyubihsm names.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub unknown_capabilities: Vec<String>,
+    /// Delegated capabilities this crate has no `Capability` for, by their libyubihsm names.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub unknown_delegated_capabilities: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(skip))]
     _priv: (),
 }
 
@@ -1066,8 +1289,13 @@ impl ObjectInfo {
     pub(crate) fn try_from_yh_object_descriptor(
         o: yh_object_descriptor,
     ) -> Result<ObjectInfo, Error> {
+        let (capabilities, unknown_capabilities) =
+            Capability::try_from_yh_capabilities(&o.capabilities)?;
+        let (delegated_capabilities, unknown_delegated_capabilities) =
+            Capability::try_from_yh_capabilities(&o.delegated_capabilities)?;
+
         Ok(ObjectInfo {
-            capabilities: Capability::try_from_yh_capabilities(&o.capabilities)?,
+            capabilities,
             id: o.id,
             length: o.len,
             domains: DomainParam(o.domains).into(),
@@ -1082,18 +1310,131 @@ impl ObjectInfo {
             label: unsafe { CStr::from_ptr(o.label.as_ptr()) }
                 .to_string_lossy()
                 .to_string(),
-            delegated_capabilities: Capability::try_from_yh_capabilities(
-                &o.delegated_capabilities,
-            )?,
+            delegated_capabilities,
+            unknown_capabilities,
+            unknown_delegated_capabilities,
             _priv: (),
         })
     }
 }
 
+/// The result of checking an object creation request against the delegation of the current
+/// session's AuthKey. See `Session::explain_permission`.
+///
+/// An object can only be created with capabilities the AuthKey may delegate, and only in domains
+/// the AuthKey itself belongs to. The AuthKey also needs the capability for the command being run.
+#[derive(Clone, Debug, PartialEq, Fail)]
+pub struct PermissionReport {
+    /// The AuthKey the request was checked against.
+    pub auth_key_id: u16,
+    /// Capabilities required to run the command which the AuthKey does not hold.
+    pub missing_capabilities: Vec<Capability>,
+    /// Requested capabilities which are not among the AuthKey's delegated capabilities.
+    pub undelegated_capabilities: Vec<Capability>,
+    /// Requested delegated capabilities which are not among the AuthKey's delegated capabilities.
+    pub undelegated_delegated_capabilities: Vec<Capability>,
+    /// Requested domains which the AuthKey does not belong to.
+    pub inaccessible_domains: Vec<Domain>,
+}
+
+impl PermissionReport {
+    pub(crate) fn new(
+        auth_key: &ObjectInfo,
+        required: Capability,
+        domains: &[Domain],
+        capabilities: &[Capability],
+        delegated_capabilities: &[Capability],
+    ) -> PermissionReport {
+        let not_delegated = |caps: &[Capability]| {
+            caps.iter()
+                .filter(|cap| !auth_key.delegated_capabilities.contains(cap))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        PermissionReport {
+            auth_key_id: auth_key.id,
+            missing_capabilities: if auth_key.capabilities.contains(&required) {
+                Vec::new()
+            } else {
+                vec![required]
+            },
+            undelegated_capabilities: not_delegated(capabilities),
+            undelegated_delegated_capabilities: not_delegated(delegated_capabilities),
+            inaccessible_domains: domains
+                .iter()
+                .filter(|domain| !auth_key.domains.contains(domain))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Whether the request is within what the AuthKey is allowed to do.
+    pub fn is_permitted(&self) -> bool {
+        self.missing_capabilities.is_empty() && self.undelegated_capabilities.is_empty()
+            && self.undelegated_delegated_capabilities.is_empty()
+            && self.inaccessible_domains.is_empty()
+    }
+}
+
+impl Display for PermissionReport {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        if self.is_permitted() {
+            return write!(f, "request is permitted by AuthKey {}", self.auth_key_id);
+        }
+
+        let cap_names = |caps: &[Capability]| {
+            caps.iter()
+                .map(String::from)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let mut problems = Vec::new();
+        if !self.missing_capabilities.is_empty() {
+            problems.push(format!(
+                "missing capabilities: {}",
+                cap_names(&self.missing_capabilities)
+            ));
+        }
+        if !self.undelegated_capabilities.is_empty() {
+            problems.push(format!(
+                "capabilities not delegated: {}",
+                cap_names(&self.undelegated_capabilities)
+            ));
+        }
+        if !self.undelegated_delegated_capabilities.is_empty() {
+            problems.push(format!(
+                "delegated capabilities not delegated: {}",
+                cap_names(&self.undelegated_delegated_capabilities)
+            ));
+        }
+        if !self.inaccessible_domains.is_empty() {
+            problems.push(format!(
+                "domains not accessible: {}",
+                self.inaccessible_domains
+                    .iter()
+                    .map(|domain| domain.0.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+
+        write!(
+            f,
+            "request exceeds permissions of AuthKey {} ({})",
+            self.auth_key_id,
+            problems.join("; ")
+        )
+    }
+}
+
 /// A global option for the device. See [Yubico's documentation] for more.
 ///
 /// [Yubico's documentation]: https://developers.yubico.com/YubiHSM2/Commands/Put_Option.html
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum DeviceOption {
     /// Whether or not the device should refuse operations when the log store is full.
     ForceAudit(DeviceOptionValue),
@@ -1103,6 +1444,41 @@ pub enum DeviceOption {
 }
 
 impl DeviceOption {
+    pub fn option_type(&self) -> DeviceOptionType {
+        match *self {
+            DeviceOption::ForceAudit(_) => DeviceOptionType::ForceAudit,
+            DeviceOption::CommandAudit(_) => DeviceOptionType::CommandAudit,
+        }
+    }
+
+    /// Parse the value of an option as returned by the device's Get Option command.
+    pub(crate) fn from_bytes(
+        option_type: DeviceOptionType,
+        bytes: &[u8],
+    ) -> Result<DeviceOption, Error> {
+        match option_type {
+            DeviceOptionType::ForceAudit => match *bytes {
+                [val] => Ok(DeviceOption::ForceAudit(DeviceOptionValue::from_u8(val)?)),
+                _ => bail!("unexpected force_audit value: {:?}", bytes),
+            },
+            DeviceOptionType::CommandAudit => {
+                let pairs = bytes.chunks_exact(2);
+                if !pairs.remainder().is_empty() {
+                    bail!("unexpected command_audit value: {:?}", bytes);
+                }
+
+                let mut vals = Vec::new();
+                for pair in pairs {
+                    // Commands this crate doesn't know about can't be represented, so skip them.
+                    if let Command::Request(cmd) = Command::from(pair[0]) {
+                        vals.push((cmd, DeviceOptionValue::from_u8(pair[1])?));
+                    }
+                }
+                Ok(DeviceOption::CommandAudit(vals))
+            }
+        }
+    }
+
     pub(crate) fn to_bytes(&self) -> Vec<u8> {
         let mut out = Vec::new();
 
@@ -1124,10 +1500,7 @@ impl DeviceOption {
 
 impl<'a> From<&'a DeviceOption> for u8 {
     fn from(opt: &'a DeviceOption) -> u8 {
-        match *opt {
-            DeviceOption::ForceAudit(_) => 0x01,
-            DeviceOption::CommandAudit(_) => 0x03,
-        }
+        opt.option_type().into()
     }
 }
 
@@ -1137,8 +1510,26 @@ impl From<DeviceOption> for u8 {
     }
 }
 
+/// The global options of the device, for use with `Session::get_option`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceOptionType {
+    ForceAudit,
+    CommandAudit,
+}
+
+impl From<DeviceOptionType> for u8 {
+    fn from(option_type: DeviceOptionType) -> u8 {
+        match option_type {
+            DeviceOptionType::ForceAudit => 0x01,
+            DeviceOptionType::CommandAudit => 0x03,
+        }
+    }
+}
+
 /// A value for a global device option.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 #[repr(u8)]
 pub enum DeviceOptionValue {
     /// The option is disabled.
@@ -1148,3 +1539,14 @@ pub enum DeviceOptionValue {
     /// The option is enabled and cannot be disabled.
     Fixed = 0x02,
 }
+
+impl DeviceOptionValue {
+    pub(crate) fn from_u8(val: u8) -> Result<DeviceOptionValue, Error> {
+        match val {
+            0x00 => Ok(DeviceOptionValue::Disabled),
+            0x01 => Ok(DeviceOptionValue::Enabled),
+            0x02 => Ok(DeviceOptionValue::Fixed),
+            _ => bail!("unexpected option value: {}", val),
+        }
+    }
+}
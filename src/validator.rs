@@ -0,0 +1,477 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A consensus signer for Tendermint/CometBFT validators whose Ed25519 keys are on the device,
+//! which refuses to sign anything that could be a double sign.
+//!
+//! The signer reads the height, round and step of each vote or proposal from its canonical sign
+//! bytes, rather than trusting the client to report them, and only signs if they are later than
+//! those it last signed with the key, or the same and for the same block. The new height, round
+//! and step are written to a state file (and synced to disk) before the signature is made, so a
+//! crash can't lose track of a signature.
+
+use pool::SessionPool;
+use socket;
+use state_file;
+use types::*;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use failure::Error;
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+const REQUEST_PUBLIC_KEY: u8 = 1;
+const REQUEST_SIGN: u8 = 2;
+const RESPONSE_OK: u8 = 0;
+const RESPONSE_ERROR: u8 = 1;
+
+/// The largest request accepted from a client; sign bytes are a few hundred bytes.
+const MAX_MESSAGE_LEN: usize = 64 * 1024;
+
+// SignedMsgType values in the sign bytes.
+const PREVOTE_TYPE: u64 = 1;
+const PRECOMMIT_TYPE: u64 = 2;
+const PROPOSAL_TYPE: u64 = 32;
+
+/// The step of a consensus round, in the order the steps happen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Step {
+    Proposal,
+    Prevote,
+    Precommit,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Step::Proposal => "proposal",
+            Step::Prevote => "prevote",
+            Step::Precommit => "precommit",
+        })
+    }
+}
+
+impl Step {
+    fn from_name(name: &str) -> Result<Step, Error> {
+        match name {
+            "proposal" => Ok(Step::Proposal),
+            "prevote" => Ok(Step::Prevote),
+            "precommit" => Ok(Step::Precommit),
+            _ => bail!("unknown consensus step {:?}", name),
+        }
+    }
+}
+
+/// The parts of a vote or proposal that matter for double-sign protection, read from its sign
+/// bytes (a length-prefixed `CanonicalVote` or `CanonicalProposal`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignRequest {
+    pub chain_id: String,
+    pub height: i64,
+    pub round: i64,
+    pub step: Step,
+    /// The encoded `CanonicalBlockID`, or `None` for a nil vote.
+    pub block_id: Option<Vec<u8>>,
+}
+
+enum Field<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+}
+
+fn read_varint(data: &[u8]) -> Result<(u64, &[u8]), Error> {
+    let mut value = 0;
+    for (i, &b) in data.iter().enumerate().take(10) {
+        value |= u64::from(b & 0x7f) << (7 * i);
+        if b & 0x80 == 0 {
+            return Ok((value, &data[i + 1..]));
+        }
+    }
+
+    bail!("malformed varint in sign bytes")
+}
+
+/// Split a protobuf message into its fields, as (field number, value) pairs.
+fn read_fields(mut data: &[u8]) -> Result<Vec<(u64, Field<'_>)>, Error> {
+    let mut fields = Vec::new();
+
+    while !data.is_empty() {
+        let (key, rest) = read_varint(data)?;
+        let (field, rest) = match key & 7 {
+            0 => {
+                let (value, rest) = read_varint(rest)?;
+                (Field::Varint(value), rest)
+            }
+            1 => {
+                if rest.len() < 8 {
+                    bail!("truncated sign bytes");
+                }
+                let mut value = [0; 8];
+                value.copy_from_slice(&rest[..8]);
+                (Field::Fixed64(u64::from_le_bytes(value)), &rest[8..])
+            }
+            2 => {
+                let (len, rest) = read_varint(rest)?;
+                if (rest.len() as u64) < len {
+                    bail!("truncated sign bytes");
+                }
+                let (value, rest) = rest.split_at(len as usize);
+                (Field::Bytes(value), rest)
+            }
+            wire_type => bail!("unexpected protobuf wire type {} in sign bytes", wire_type),
+        };
+
+        fields.push((key >> 3, field));
+        data = rest;
+    }
+
+    Ok(fields)
+}
+
+impl SignRequest {
+    pub fn parse(sign_bytes: &[u8]) -> Result<SignRequest, Error> {
+        let (len, body) = read_varint(sign_bytes)?;
+        if len != body.len() as u64 {
+            bail!("sign bytes length prefix doesn't match their length");
+        }
+
+        // Fields with default values are omitted, and the last occurrence of a field wins.
+        let fields = read_fields(body)?;
+        let field = |number| fields.iter().rev().find(|f| f.0 == number).map(|f| &f.1);
+
+        let msg_type = match field(1) {
+            Some(&Field::Varint(msg_type)) => msg_type,
+            None => 0,
+            _ => bail!("malformed message type in sign bytes"),
+        };
+        let (step, block_id_field, chain_id_field) = match msg_type {
+            PREVOTE_TYPE => (Step::Prevote, 4, 6),
+            PRECOMMIT_TYPE => (Step::Precommit, 4, 6),
+            PROPOSAL_TYPE => (Step::Proposal, 5, 7),
+            _ => bail!("can't sign messages of type {}", msg_type),
+        };
+
+        let fixed64 = |number| match field(number) {
+            Some(&Field::Fixed64(value)) => Ok(value as i64),
+            None => Ok(0),
+            _ => Err(format_err!("malformed height or round in sign bytes")),
+        };
+        let height = fixed64(2)?;
+        let round = fixed64(3)?;
+        if height < 1 || round < 0 {
+            bail!("invalid height {} or round {} in sign bytes", height, round);
+        }
+
+        let block_id = match field(block_id_field) {
+            Some(&Field::Bytes(block_id)) => Some(block_id.to_vec()),
+            None => None,
+            _ => bail!("malformed block ID in sign bytes"),
+        };
+        let chain_id = match field(chain_id_field) {
+            Some(&Field::Bytes(chain_id)) => String::from_utf8(chain_id.to_vec())?,
+            None => String::new(),
+            _ => bail!("malformed chain ID in sign bytes"),
+        };
+
+        Ok(SignRequest {
+            chain_id,
+            height,
+            round,
+            step,
+            block_id,
+        })
+    }
+}
+
+/// The last height, round and step signed with a key, and the block signed for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignState {
+    pub height: i64,
+    pub round: i64,
+    pub step: Step,
+    pub block_id: Option<Vec<u8>>,
+}
+
+impl Default for SignState {
+    /// The state of a key which has never signed, which allows signing at any height.
+    fn default() -> SignState {
+        SignState {
+            height: 0,
+            round: 0,
+            step: Step::Proposal,
+            block_id: None,
+        }
+    }
+}
+
+impl From<&SignRequest> for SignState {
+    fn from(request: &SignRequest) -> SignState {
+        SignState {
+            height: request.height,
+            round: request.round,
+            step: request.step,
+            block_id: request.block_id.clone(),
+        }
+    }
+}
+
+impl SignState {
+    /// Fail unless signing `request` after this state can't be a double sign.
+    pub fn check(&self, request: &SignRequest) -> Result<(), Error> {
+        let last = (self.height, self.round, self.step);
+        let next = (request.height, request.round, request.step);
+
+        if next < last {
+            bail!(
+                "refusing to sign {} at height {} round {}: already signed {} at height {} round {}",
+                request.step,
+                request.height,
+                request.round,
+                self.step,
+                self.height,
+                self.round
+            );
+        }
+        if next == last && request.block_id != self.block_id {
+            bail!(
+                "refusing to sign a conflicting {} at height {} round {}",
+                request.step,
+                request.height,
+                request.round
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Create a state file for a key which has never signed. Fails if the file already exists, so
+    /// that a key's history can't be reset by accident.
+    pub fn initialize<P: AsRef<Path>>(path: P) -> Result<(), Error> {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        SignState::default().save(path)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SignState, Error> {
+        let contents = fs::read_to_string(path)?;
+
+        let mut height = None;
+        let mut round = None;
+        let mut step = None;
+        let mut block_id = None;
+        for line in contents.lines() {
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap().trim();
+            let value = parts
+                .next()
+                .ok_or_else(|| format_err!("malformed state file line {:?}", line))?
+                .trim();
+            match key {
+                "height" => height = Some(value.parse()?),
+                "round" => round = Some(value.parse()?),
+                "step" => step = Some(Step::from_name(value)?),
+                "block_id" if value.is_empty() => block_id = Some(None),
+                "block_id" => block_id = Some(Some(STANDARD.decode(value)?)),
+                _ => bail!("unknown state file setting {:?}", key),
+            }
+        }
+
+        match (height, round, step, block_id) {
+            (Some(height), Some(round), Some(step), Some(block_id)) => Ok(SignState {
+                height,
+                round,
+                step,
+                block_id,
+            }),
+            _ => bail!("incomplete state file"),
+        }
+    }
+
+    /// Replace the state file at `path` with this state, atomically and durably.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let block_id = match self.block_id {
+            Some(ref block_id) => STANDARD.encode(block_id),
+            None => String::new(),
+        };
+        let contents = format!(
+            "height = {}\nround = {}\nstep = {}\nblock_id = {}\n",
+            self.height, self.round, self.step, block_id
+        );
+
+        state_file::replace(path, contents.as_bytes())
+    }
+}
+
+/// Signs votes and proposals for one chain with an Ed25519 key on the device.
+///
+/// Clones share the key's state, and requests are signed one at a time, so any number of
+/// connections can be served safely. A signer holds a lock on its state file for as long as it
+/// (or a clone) is alive, so a second signer can't use the same state file.
+#[derive(Clone, Debug)]
+pub struct ValidatorSigner {
+    pool: Arc<SessionPool>,
+    key_id: u16,
+    public_key: PublicKey,
+    chain_id: String,
+    state_path: PathBuf,
+    state: Arc<Mutex<SignState>>,
+    _state_lock: Arc<File>,
+}
+
+impl ValidatorSigner {
+    /// Create a signer for the Ed25519 key `key_id`, which keeps its state in `state_path`. The
+    /// state file must already exist; create it with `SignState::initialize` for a new key.
+    ///
+    /// Fails if another signer, in this or another process, is using `state_path`.
+    pub fn new<P: AsRef<Path>>(
+        pool: Arc<SessionPool>,
+        key_id: u16,
+        chain_id: &str,
+        state_path: P,
+    ) -> Result<ValidatorSigner, Error> {
+        let state_lock = state_file::lock(&state_path)?;
+        let state = SignState::load(&state_path)?;
+
+        let public_key = pool.get()?.get_pubkey(key_id)?;
+        match public_key {
+            PublicKey::Edc(Algorithm::EcEd25519, _) => (),
+            _ => bail!("validator key {} isn't an Ed25519 key", key_id),
+        }
+
+        Ok(ValidatorSigner {
+            pool,
+            key_id,
+            public_key,
+            chain_id: chain_id.to_string(),
+            state_path: state_path.as_ref().to_path_buf(),
+            state: Arc::new(Mutex::new(state)),
+            _state_lock: Arc::new(state_lock),
+        })
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    /// The last height, round and step signed.
+    pub fn state(&self) -> Result<SignState, Error> {
+        Ok(self.lock_state()?.clone())
+    }
+
+    fn lock_state(&self) -> Result<MutexGuard<'_, SignState>, Error> {
+        // A panic while signing may have left the state file and the device out of step with the
+        // in-memory state, so the signer stops signing.
+        self.state
+            .lock()
+            .map_err(|_| format_err!("validator signer state is poisoned"))
+    }
+
+    /// Sign a vote or proposal's sign bytes, returning the 64-byte Ed25519 signature.
+    pub fn sign(&self, sign_bytes: &[u8]) -> Result<Vec<u8>, Error> {
+        let request = SignRequest::parse(sign_bytes)?;
+        if request.chain_id != self.chain_id {
+            bail!(
+                "refusing to sign for chain {:?} (expected {:?})",
+                request.chain_id,
+                self.chain_id
+            );
+        }
+
+        let mut state = self.lock_state()?;
+        state.check(&request)?;
+
+        let new_state = SignState::from(&request);
+        new_state.save(&self.state_path)?;
+        *state = new_state;
+
+        self.pool.get()?.sign_eddsa(self.key_id, sign_bytes)
+    }
+
+    /// Create a Unix socket at `path`, readable and writable only by the current user, and serve
+    /// clients on it forever.
+    ///
+    /// Fails if `path` already exists.
+    #[cfg(unix)]
+    pub fn bind<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        self.serve_unix(socket::bind_unix(path)?)
+    }
+
+    /// Serve clients connecting to `listener` forever, each on its own thread.
+    #[cfg(unix)]
+    pub fn serve_unix(&self, listener: UnixListener) -> Result<(), Error> {
+        let signer = self.clone();
+        socket::serve(listener.incoming(), move |stream| {
+            signer.handle_connection(stream)
+        })
+    }
+
+    /// Serve clients connecting to `listener` forever, each on its own thread.
+    ///
+    /// Connections aren't authenticated, so the listener should only be reachable by the
+    /// validator node. Double-sign protection doesn't depend on the client.
+    pub fn serve_tcp(&self, listener: TcpListener) -> Result<(), Error> {
+        let signer = self.clone();
+        socket::serve(listener.incoming(), move |stream| {
+            signer.handle_connection(stream)
+        })
+    }
+
+    /// Answer requests from a single client until it disconnects.
+    ///
+    /// Requests and responses are a 4-byte big-endian length followed by the message. A request
+    /// is `1` for the public key, or `2` followed by sign bytes. A response is `0` followed by
+    /// the 32-byte public key or 64-byte signature, or `1` followed by an error message.
+    pub fn handle_connection<S: Read + Write>(&self, mut stream: S) -> Result<(), Error> {
+        while let Some(request) = socket::read_message(&mut stream, MAX_MESSAGE_LEN)? {
+            let response = match self.handle_request(&request) {
+                Ok(data) => {
+                    let mut response = vec![RESPONSE_OK];
+                    response.extend_from_slice(&data);
+                    response
+                }
+                Err(e) => {
+                    let mut response = vec![RESPONSE_ERROR];
+                    response.extend_from_slice(e.to_string().as_bytes());
+                    response
+                }
+            };
+
+            socket::write_message(&mut stream, &response)?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_request(&self, request: &[u8]) -> Result<Vec<u8>, Error> {
+        match request.split_first() {
+            Some((&REQUEST_PUBLIC_KEY, _)) => match self.public_key {
+                PublicKey::Edc(_, ref a) => Ok(a.clone()),
+                _ => bail!("validator key isn't an Ed25519 key"),
+            },
+            Some((&REQUEST_SIGN, sign_bytes)) => self.sign(sign_bytes),
+            _ => bail!("unsupported validator signer request"),
+        }
+    }
+}
@@ -0,0 +1,205 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Local verification of signatures made by keys on the device, using pure-Rust implementations
+//! so that no device is needed.
+
+use hash::HashAlgorithm;
+use public_key::RSA_PUBLIC_EXPONENT;
+use types::*;
+
+use ed25519_dalek;
+use failure::Error;
+use k256;
+use p256;
+use p256::ecdsa::signature::hazmat::PrehashVerifier;
+use p256::ecdsa::signature::Verifier;
+use p384;
+use p521;
+use rsa::{BigUint, Pkcs1v15Sign, Pss, RsaPublicKey};
+use sha1::Sha1;
+use sha2::{Sha256, Sha384, Sha512};
+
+macro_rules! verify_ecdsa_prehash {
+    ($curve:ident, $point:expr, $digest:expr, $signature:expr) => {{
+        let key = $curve::ecdsa::VerifyingKey::from_sec1_bytes($point)
+            .map_err(|e| format_err!("invalid public key: {}", e))?;
+        let signature = $curve::ecdsa::Signature::from_der($signature)
+            .map_err(|e| format_err!("invalid ECDSA signature: {}", e))?;
+
+        key.verify_prehash($digest, &signature)
+            .map_err(|_| format_err!("ECDSA signature verification failed"))
+    }};
+}
+
+impl PublicKey {
+    /// Verify a signature made by this key over `message`.
+    ///
+    /// `algorithm` is the signature algorithm, which also selects the digest: one of the
+    /// `Algorithm::RsaPkcs1*` or `Algorithm::RsaPss*` algorithms for RSA keys, one of the
+    /// `Algorithm::EcEcdsa*` algorithms for ECC keys, or `Algorithm::EcEd25519` for EDC keys.
+    /// RSA-PSS signatures are expected to use MGF1 with the same digest, and a salt as long as the
+    /// digest output. ECDSA signatures are DER-encoded, as returned by `Session::sign_ecdsa`.
+    ///
+    /// ECDSA verification is supported for the P-256, P-384, P-521 and secp256k1 curves.
+    pub fn verify<T: AsRef<[u8]>, U: AsRef<[u8]>>(
+        &self,
+        algorithm: Algorithm,
+        message: T,
+        signature: U,
+    ) -> Result<(), Error> {
+        let message = message.as_ref();
+
+        if algorithm == Algorithm::EcEd25519 {
+            return self.verify_eddsa(message, signature.as_ref());
+        }
+
+        match HashAlgorithm::from_signature_algorithm(algorithm) {
+            Some(hash) => self.verify_prehashed(algorithm, hash.digest(message), signature),
+            None => bail!("verify: {} is not a signature algorithm", algorithm),
+        }
+    }
+
+    /// Verify a signature made by this key over a precomputed digest, as passed to
+    /// `Session::sign_ecdsa`, `Session::sign_pss` or `Session::sign_pkcs1v1_5` with `hashed` set.
+    ///
+    /// See `verify` for the accepted algorithms. Ed25519 signs the message itself rather than a
+    /// digest of it, so `Algorithm::EcEd25519` is not accepted here.
+    pub fn verify_prehashed<T: AsRef<[u8]>, U: AsRef<[u8]>>(
+        &self,
+        algorithm: Algorithm,
+        digest: T,
+        signature: U,
+    ) -> Result<(), Error> {
+        let digest = digest.as_ref();
+        let signature = signature.as_ref();
+
+        let hash = match HashAlgorithm::from_signature_algorithm(algorithm) {
+            Some(hash) => hash,
+            None => bail!("verify_prehashed: {} is not a digest signature algorithm", algorithm),
+        };
+
+        if digest.len() != hash.output_len() {
+            bail!(
+                "verify_prehashed: digest is {} bytes, expected {} for {}",
+                digest.len(),
+                hash.output_len(),
+                algorithm
+            );
+        }
+
+        match (self, algorithm) {
+            (
+                &PublicKey::Rsa(_, _),
+                Algorithm::RsaPkcs1Sha1
+                | Algorithm::RsaPkcs1Sha256
+                | Algorithm::RsaPkcs1Sha384
+                | Algorithm::RsaPkcs1Sha512,
+            ) => {
+                let scheme = match hash {
+                    HashAlgorithm::Sha1 => Pkcs1v15Sign::new::<Sha1>(),
+                    HashAlgorithm::Sha256 => Pkcs1v15Sign::new::<Sha256>(),
+                    HashAlgorithm::Sha384 => Pkcs1v15Sign::new::<Sha384>(),
+                    HashAlgorithm::Sha512 => Pkcs1v15Sign::new::<Sha512>(),
+                };
+
+                self.rsa_public_key()?
+                    .verify(scheme, digest, signature)
+                    .map_err(|_| format_err!("RSA PKCS#1 v1.5 signature verification failed"))
+            }
+            (
+                &PublicKey::Rsa(_, _),
+                Algorithm::RsaPssSha1
+                | Algorithm::RsaPssSha256
+                | Algorithm::RsaPssSha384
+                | Algorithm::RsaPssSha512,
+            ) => {
+                let scheme = match hash {
+                    HashAlgorithm::Sha1 => Pss::new::<Sha1>(),
+                    HashAlgorithm::Sha256 => Pss::new::<Sha256>(),
+                    HashAlgorithm::Sha384 => Pss::new::<Sha384>(),
+                    HashAlgorithm::Sha512 => Pss::new::<Sha512>(),
+                };
+
+                self.rsa_public_key()?
+                    .verify(scheme, digest, signature)
+                    .map_err(|_| format_err!("RSA-PSS signature verification failed"))
+            }
+            (
+                &PublicKey::Ecc(curve, _, _),
+                Algorithm::EcEcdsaSha1
+                | Algorithm::EcEcdsaSha256
+                | Algorithm::EcEcdsaSha384
+                | Algorithm::EcEcdsaSha512,
+            ) => {
+                let point = self.ec_point()?;
+
+                match curve {
+                    Algorithm::EcP256 => verify_ecdsa_prehash!(p256, &point, digest, signature),
+                    Algorithm::EcP384 => verify_ecdsa_prehash!(p384, &point, digest, signature),
+                    Algorithm::EcP521 => verify_ecdsa_prehash!(p521, &point, digest, signature),
+                    Algorithm::EcK256 => {
+                        let key = k256::ecdsa::VerifyingKey::from_sec1_bytes(&point)
+                            .map_err(|e| format_err!("invalid public key: {}", e))?;
+                        let signature = k256::ecdsa::Signature::from_der(signature)
+                            .map_err(|e| format_err!("invalid ECDSA signature: {}", e))?;
+                        // k256 only accepts low-S signatures, but the device doesn't normalize
+                        // the signatures it makes. Both forms are valid ECDSA signatures.
+                        let signature = signature.normalize_s().unwrap_or(signature);
+
+                        key.verify_prehash(digest, &signature)
+                            .map_err(|_| format_err!("ECDSA signature verification failed"))
+                    }
+                    c => bail!("verify_prehashed: ECDSA verification over {} is not supported", c),
+                }
+            }
+            (key, a) => bail!(
+                "verify_prehashed: {} can't be used with a {} key",
+                a,
+                key.algorithm()
+            ),
+        }
+    }
+
+    fn verify_eddsa(&self, message: &[u8], signature: &[u8]) -> Result<(), Error> {
+        let a = match *self {
+            PublicKey::Edc(_, ref a) => a,
+            ref key => bail!("verify: ed25519 can't be used with a {} key", key.algorithm()),
+        };
+
+        let mut key_bytes = [0; ed25519_dalek::PUBLIC_KEY_LENGTH];
+        if a.len() != key_bytes.len() {
+            bail!("invalid ed25519 public key length {}", a.len());
+        }
+        key_bytes.copy_from_slice(a);
+
+        let key = ed25519_dalek::VerifyingKey::from_bytes(&key_bytes)
+            .map_err(|e| format_err!("invalid public key: {}", e))?;
+        let signature = ed25519_dalek::Signature::from_slice(signature)
+            .map_err(|e| format_err!("invalid ed25519 signature: {}", e))?;
+
+        key.verify(message, &signature)
+            .map_err(|_| format_err!("ed25519 signature verification failed"))
+    }
+
+    pub(crate) fn rsa_public_key(&self) -> Result<RsaPublicKey, Error> {
+        match *self {
+            PublicKey::Rsa(_, ref n) => RsaPublicKey::new(
+                BigUint::from_bytes_be(n),
+                BigUint::from_bytes_be(RSA_PUBLIC_EXPONENT),
+            ).map_err(|e| format_err!("invalid RSA public key: {}", e)),
+            ref key => bail!("not an RSA key: {}", key.algorithm()),
+        }
+    }
+}
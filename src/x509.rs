@@ -0,0 +1,303 @@
+// Copyright 2018 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Encoding and decoding helpers shared by the signed X.509 structures this crate builds.
+
+use asn1;
+use hash::HashAlgorithm;
+use session::Session;
+use types::*;
+
+use failure::Error;
+use sha1::Sha1;
+use sha2::Digest;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SIGNATURE_SCHEMES: &[SignatureScheme] = &[
+    SignatureScheme::RsaPkcs1v15Sha1,
+    SignatureScheme::RsaPkcs1v15Sha256,
+    SignatureScheme::RsaPkcs1v15Sha384,
+    SignatureScheme::RsaPkcs1v15Sha512,
+    SignatureScheme::RsaPssSha1,
+    SignatureScheme::RsaPssSha256,
+    SignatureScheme::RsaPssSha384,
+    SignatureScheme::RsaPssSha512,
+    SignatureScheme::EcdsaSha1,
+    SignatureScheme::EcdsaSha256,
+    SignatureScheme::EcdsaSha384,
+    SignatureScheme::EcdsaSha512,
+    SignatureScheme::Ed25519,
+];
+
+/// The DER AlgorithmIdentifier for signatures made with `scheme` (RFC 3279, RFC 4055, RFC 5758,
+/// RFC 8410).
+pub(crate) fn algorithm_identifier(scheme: SignatureScheme) -> Vec<u8> {
+    let oid = match scheme {
+        SignatureScheme::RsaPkcs1v15Sha1 => asn1::OID_SHA1_WITH_RSA,
+        SignatureScheme::RsaPkcs1v15Sha256 => asn1::OID_SHA256_WITH_RSA,
+        SignatureScheme::RsaPkcs1v15Sha384 => asn1::OID_SHA384_WITH_RSA,
+        SignatureScheme::RsaPkcs1v15Sha512 => asn1::OID_SHA512_WITH_RSA,
+        SignatureScheme::RsaPssSha1
+        | SignatureScheme::RsaPssSha256
+        | SignatureScheme::RsaPssSha384
+        | SignatureScheme::RsaPssSha512 => {
+            // PSS schemes always name a digest.
+            let hash = HashAlgorithm::from_signature_algorithm(scheme.algorithm()).unwrap();
+            return asn1::sequence(&[&asn1::oid(asn1::OID_RSASSA_PSS), &pss_parameters(hash)]);
+        }
+        SignatureScheme::EcdsaSha1 => asn1::OID_ECDSA_WITH_SHA1,
+        SignatureScheme::EcdsaSha256 => asn1::OID_ECDSA_WITH_SHA256,
+        SignatureScheme::EcdsaSha384 => asn1::OID_ECDSA_WITH_SHA384,
+        SignatureScheme::EcdsaSha512 => asn1::OID_ECDSA_WITH_SHA512,
+        SignatureScheme::Ed25519 => asn1::OID_ED25519,
+    };
+
+    match scheme {
+        SignatureScheme::RsaPkcs1v15Sha1
+        | SignatureScheme::RsaPkcs1v15Sha256
+        | SignatureScheme::RsaPkcs1v15Sha384
+        | SignatureScheme::RsaPkcs1v15Sha512 => {
+            asn1::sequence(&[&asn1::oid(oid), &asn1::null()])
+        }
+        _ => asn1::sequence(&[&asn1::oid(oid)]),
+    }
+}
+
+/// RSASSA-PSS-params using `hash` for both the message and MGF1, with a salt as long as the digest
+/// output. Fields equal to their defaults (SHA-1, MGF1 with SHA-1, 20 bytes of salt) are omitted.
+fn pss_parameters(hash: HashAlgorithm) -> Vec<u8> {
+    if hash == HashAlgorithm::Sha1 {
+        return asn1::sequence(&[]);
+    }
+
+    let hash_algorithm = hash.algorithm_identifier();
+    let mask_gen_algorithm = asn1::sequence(&[&asn1::oid(asn1::OID_MGF1), &hash_algorithm]);
+
+    asn1::sequence(&[
+        &asn1::explicit(0, &hash_algorithm),
+        &asn1::explicit(1, &mask_gen_algorithm),
+        &asn1::explicit(2, &asn1::unsigned_integer(&[hash.output_len() as u8])),
+    ])
+}
+
+/// The signature scheme named by a DER AlgorithmIdentifier, if it's one the device can produce.
+pub(crate) fn scheme_from_algorithm_identifier(der: &[u8]) -> Option<SignatureScheme> {
+    SIGNATURE_SCHEMES
+        .iter()
+        .cloned()
+        .find(|scheme| algorithm_identifier(*scheme) == der)
+}
+
+/// Sign the DER-encoded `tbs` structure with the key `key_id`, and wrap it together with the
+/// signature algorithm and signature, as in `Certificate`, `CertificationRequest` and
+/// `CertificateList`.
+pub(crate) fn sign(
+    session: &Session,
+    key_id: u16,
+    key_algorithm: Algorithm,
+    scheme: SignatureScheme,
+    tbs: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let signature = session.sign_message_with_key_algorithm(key_id, key_algorithm, scheme, tbs)?;
+
+    Ok(signed(tbs, scheme, &signature))
+}
+
+/// Wrap the DER-encoded `tbs` structure together with the signature algorithm and its signature.
+pub(crate) fn signed(tbs: &[u8], scheme: SignatureScheme, signature: &[u8]) -> Vec<u8> {
+    asn1::sequence(&[
+        tbs,
+        &algorithm_identifier(scheme),
+        &asn1::bit_string(signature),
+    ])
+}
+
+/// The parts of a signed structure, as produced by `sign`.
+pub(crate) struct Signed<'a> {
+    /// The signed structure, including its tag and length.
+    pub(crate) tbs: &'a [u8],
+    pub(crate) algorithm_identifier: &'a [u8],
+    pub(crate) signature: &'a [u8],
+}
+
+impl<'a> Signed<'a> {
+    pub(crate) fn parse(der: &'a [u8]) -> Result<Signed<'a>, Error> {
+        let (signed, rest) = asn1::read_element(asn1::TAG_SEQUENCE, der)?;
+        if !rest.is_empty() {
+            bail!("trailing data after signed structure");
+        }
+
+        let (tbs, signed) = asn1::split_element(signed)?;
+        let (algorithm_identifier, signed) = asn1::split_element(signed)?;
+        let (signature, _) = asn1::read_element(asn1::TAG_BIT_STRING, signed)?;
+        if signature.first() != Some(&0) {
+            bail!("signature has unused bits");
+        }
+
+        Ok(Signed {
+            tbs,
+            algorithm_identifier,
+            signature: &signature[1..],
+        })
+    }
+
+    /// Check the signature against `public_key`.
+    pub(crate) fn verify(&self, public_key: &PublicKey) -> Result<(), Error> {
+        let scheme = match scheme_from_algorithm_identifier(self.algorithm_identifier) {
+            Some(scheme) => scheme,
+            None => bail!("unsupported signature algorithm"),
+        };
+
+        public_key.verify(scheme.algorithm(), self.tbs, self.signature)
+    }
+}
+
+/// Encode an X.509 `Extension`.
+pub(crate) fn extension(oid: &[u64], critical: bool, value: &[u8]) -> Vec<u8> {
+    if critical {
+        asn1::sequence(&[
+            &asn1::oid(oid),
+            &asn1::boolean(true),
+            &asn1::octet_string(value),
+        ])
+    } else {
+        asn1::sequence(&[&asn1::oid(oid), &asn1::octet_string(value)])
+    }
+}
+
+/// Encode an authority key identifier `Extension` naming the issuer's key identifier.
+pub(crate) fn authority_key_identifier(key_identifier: &[u8]) -> Vec<u8> {
+    extension(
+        asn1::OID_AUTHORITY_KEY_IDENTIFIER,
+        false,
+        &asn1::sequence(&[&asn1::implicit(0, key_identifier)]),
+    )
+}
+
+/// The key identifier in the subject key identifier `Extension` among `extensions`, the contents of
+/// a TBSCertificate's `[3]` field, if there is one.
+pub(crate) fn subject_key_identifier(extensions: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    let (mut extensions, _) = asn1::read_element(asn1::TAG_SEQUENCE, extensions)?;
+    let oid = asn1::oid(asn1::OID_SUBJECT_KEY_IDENTIFIER);
+
+    while !extensions.is_empty() {
+        let (extension, rest) = asn1::read_element(asn1::TAG_SEQUENCE, extensions)?;
+        extensions = rest;
+
+        let (id, extension) = asn1::split_element(extension)?;
+        if id != &oid[..] {
+            continue;
+        }
+        // Skip `critical`, which is only present when true.
+        let (tag, _, rest) = asn1::read_tlv(extension)?;
+        let extension = if tag == asn1::TAG_BOOLEAN {
+            rest
+        } else {
+            extension
+        };
+        let (value, _) = asn1::read_element(asn1::TAG_OCTET_STRING, extension)?;
+        let (key_identifier, _) = asn1::read_element(asn1::TAG_OCTET_STRING, value)?;
+        return Ok(Some(key_identifier.to_vec()));
+    }
+
+    Ok(None)
+}
+
+/// The key identifier for a SubjectPublicKeyInfo: the SHA-1 hash of the public key bits, as in
+/// method 1 of RFC 5280 section 4.2.1.2.
+pub(crate) fn key_identifier(spki: &[u8]) -> Result<Vec<u8>, Error> {
+    let (spki, _) = asn1::read_element(asn1::TAG_SEQUENCE, spki)?;
+    let (_, spki) = asn1::split_element(spki)?;
+    let (public_key, _) = asn1::read_element(asn1::TAG_BIT_STRING, spki)?;
+
+    Ok(Sha1::digest(public_key.get(1..).unwrap_or(&[])).to_vec())
+}
+
+/// Encode an X.509 `Time`: UTCTime for dates in 1950 through 2049, GeneralizedTime otherwise.
+pub(crate) fn time(time: SystemTime) -> Result<Vec<u8>, Error> {
+    let (year, clock) = split_time(time)?;
+
+    if year < 2050 {
+        Ok(asn1::tlv(
+            asn1::TAG_UTC_TIME,
+            format!("{:02}{}", year % 100, clock).as_bytes(),
+        ))
+    } else {
+        generalized_time(time)
+    }
+}
+
+/// Encode a GeneralizedTime, which OCSP uses for all dates.
+pub(crate) fn generalized_time(time: SystemTime) -> Result<Vec<u8>, Error> {
+    let (year, clock) = split_time(time)?;
+
+    Ok(asn1::tlv(
+        asn1::TAG_GENERALIZED_TIME,
+        format!("{:04}{}", year, clock).as_bytes(),
+    ))
+}
+
+/// Split a time into its year and the rest of the date formatted as "MMDDhhmmssZ", which UTCTime
+/// and GeneralizedTime share.
+fn split_time(time: SystemTime) -> Result<(i64, String), Error> {
+    let seconds = time.duration_since(UNIX_EPOCH)
+        .map_err(|_| format_err!("times before 1970 are not supported"))?
+        .as_secs();
+
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    let seconds_of_day = seconds % 86_400;
+    let clock = format!(
+        "{:02}{:02}{:02}{:02}{:02}Z",
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60
+    );
+
+    Ok((year, clock))
+}
+
+/// Convert a count of days since 1970-01-01 to a (year, month, day) date in the proleptic Gregorian
+/// calendar.
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+/// Convert a (year, month, day) date in the proleptic Gregorian calendar to a count of days since
+/// 1970-01-01, the inverse of `civil_from_days`.
+pub(crate) fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let mp = i64::from(if month > 2 { month - 3 } else { month + 9 });
+    let day_of_year = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}